@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthAction {
+    Publish,
+    Subscribe,
+}
+
+/// Posted to the auth callout before a publish or subscribe offer is answered
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthRequest {
+    pub action: AuthAction,
+    pub stream: String,
+    pub session: String,
+    pub client_ip: Option<String>,
+    /// Bearer token of the request
+    pub token: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthResponse {
+    pub allow: bool,
+    /// Why the offer is denied, returned to the client
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Subscribers the stream accepts
+    #[serde(default)]
+    pub max_subscribers: Option<usize>,
+    /// Unix timestamp in milliseconds the session is closed at
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
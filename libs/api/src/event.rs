@@ -12,6 +12,9 @@ pub enum Event {
     Stream {
         r#type: StreamEventType,
         stream: Stream,
+        /// The problem of a `healthWarning` or `healthRecovered`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        health: Option<Health>,
     },
 }
 
@@ -26,6 +29,33 @@ pub enum StreamEventType {
     SubscribeDown,
     ReforwardUp,
     ReforwardDown,
+    HealthWarning,
+    HealthRecovered,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthProblem {
+    /// No RTP for a while
+    Stalled,
+    /// No video keyframe for a while
+    NoKeyframe,
+    LowBitrate,
+    /// RTP timestamps jumped away from the arrival time, only warned
+    TimestampJump,
+    /// A publisher sends audio but no video
+    VideoMissing,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    pub problem: HealthProblem,
+    /// `audio` or `video`
+    pub kind: String,
+    /// Simulcast layer of the track, empty without simulcast
+    pub rid: String,
+    pub detail: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -1,3 +1,4 @@
+pub mod callout;
 pub mod event;
 pub mod path;
 pub mod recorder;
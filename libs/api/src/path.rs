@@ -21,6 +21,9 @@ pub fn session(stream: &str, session: &str) -> String {
 pub fn session_layer(stream: &str, session: &str) -> String {
     format!("/session/{stream}/{session}/layer")
 }
+pub fn session_publishers(stream: &str, session: &str) -> String {
+    format!("/session/{stream}/{session}/publishers")
+}
 
 pub fn streams(stream: &str) -> String {
     format!("/api/streams/{stream}")
@@ -30,6 +33,10 @@ pub fn cascade(stream: &str) -> String {
     format!("/api/cascade/{stream}")
 }
 
+pub fn mpegts(stream: &str) -> String {
+    format!("/api/mpegts/{stream}")
+}
+
 pub fn streams_sse() -> &'static str {
     "/api/sse/streams"
 }
@@ -45,3 +52,15 @@ pub fn record(stream: &str) -> String {
 pub fn recordings() -> &'static str {
     "/api/recordings"
 }
+
+pub fn hls(stream: &str) -> String {
+    format!("/hls/{stream}")
+}
+
+pub fn dash(stream: &str) -> String {
+    format!("/dash/{stream}")
+}
+
+pub fn ws(stream: &str) -> String {
+    format!("/ws/{stream}")
+}
@@ -158,4 +158,8 @@ pub struct StartRecordResponse {
     pub record_dir: String,
     /// Absolute path (within storage) to the MPD manifest for this session
     pub mpd_path: String,
+    /// Path (within storage) to the HLS master playlist next to the MPD,
+    /// empty from the nodes recording without HLS playlists
+    #[serde(default)]
+    pub hls_path: String,
 }
@@ -6,6 +6,19 @@ pub struct SelectLayer {
     pub encoding_id: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SelectPublishers {
+    /// Publisher sessions to receive, all of them if absent
+    pub publishers: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WhipQuery {
+    /// Register as the hot standby of the current publisher
+    #[serde(default)]
+    pub standby: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ChangeResource {
     pub kind: String,
@@ -29,6 +42,13 @@ pub struct Cascade {
     pub target_url: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MpegtsOutput {
+    /// `udp://{host}:{port}`, unicast or multicast, `?ttl=` and `?localaddr=` for multicast
+    pub target_url: String,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct StreamSSE {
     #[serde(default)]
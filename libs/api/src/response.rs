@@ -32,6 +32,41 @@ pub struct Session {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cascade: Option<CascadeInfo>,
     pub has_data_channel: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<SessionStats>,
+    /// Active problems of a publish session
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub health: Vec<crate::event::HealthProblem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionStats {
+    /// bits per second
+    pub bitrate_in: u64,
+    pub bitrate_out: u64,
+    pub packets_lost: i64,
+    /// In 1/256
+    pub fraction_lost: u8,
+    /// milliseconds
+    pub jitter: u32,
+    /// milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt: Option<u32>,
+    pub nack_count: u64,
+    pub pli_count: u64,
+    pub fir_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_pair: Option<CandidatePair>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidatePair {
+    pub local: String,
+    pub remote: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
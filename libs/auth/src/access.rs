@@ -29,6 +29,9 @@ pub async fn access_middleware(request: Request, next: Next) -> Response {
             (id, &Method::POST, path) if path == api::path::cascade(&id) => {
                 Access::from(claims.mode).x
             }
+            (id, &Method::POST | &Method::DELETE, path) if path == api::path::mpegts(&id) => {
+                Access::from(claims.mode).x
+            }
             (id, _, _) if id == ANY_ID => true,
             (id, &Method::POST, path) if path == "/token" && id == ANY_ID => {
                 Access::from(claims.mode).r
@@ -9,6 +9,18 @@ pub struct Claims {
     pub mode: Mode,
 }
 
+impl Claims {
+    /// Whether these claims may subscribe to `stream`
+    pub fn can_subscribe(&self, stream: &str) -> bool {
+        self.id == crate::ANY_ID || (self.id == stream && Access::from(self.mode).r)
+    }
+
+    /// Whether these claims may publish to `stream`
+    pub fn can_publish(&self, stream: &str) -> bool {
+        self.id == crate::ANY_ID || (self.id == stream && Access::from(self.mode).w)
+    }
+}
+
 impl Display for Claims {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -47,6 +47,25 @@ impl AuthState {
             decoding: DecodingKey::from_secret(secret.as_bytes()),
         }
     }
+
+    /// Claims of a bearer token, without static tokens everything is allowed
+    pub fn claims(&self, token: Option<&str>) -> Option<Claims> {
+        let any = Claims {
+            id: ANY_ID.to_string(),
+            exp: 0,
+            mode: 7,
+        };
+        if self.tokens.is_empty() {
+            return Some(any);
+        }
+        match token {
+            Some(token) if self.tokens.contains(token) => Some(any),
+            Some(token) => decode::<Claims>(token, &self.decoding, &Validation::default())
+                .ok()
+                .map(|token_data| token_data.claims),
+            None => None,
+        }
+    }
 }
 
 pub async fn validate_middleware(
@@ -54,46 +73,20 @@ pub async fn validate_middleware(
     mut request: Request,
     next: Next,
 ) -> Response {
-    let mut closure = || {
-        if state.tokens.is_empty() {
-            request.extensions_mut().insert(Claims {
-                id: ANY_ID.to_string(),
-                exp: 0,
-                mode: 7,
-            });
-            return true;
-        }
-
-        if let Some(auth_header) = request.headers().get(header::AUTHORIZATION) {
-            match Bearer::decode(auth_header) {
-                Some(bearer) if state.tokens.contains(bearer.token()) => {
-                    request.extensions_mut().insert(Claims {
-                        id: ANY_ID.to_string(),
-                        exp: 0,
-                        mode: 7,
-                    });
-                    return true;
-                }
-                Some(bearer) => {
-                    if let Ok(token_data) =
-                        decode::<Claims>(bearer.token(), &state.decoding, &Validation::default())
-                    {
-                        request.extensions_mut().insert(token_data.claims);
-                        return true;
-                    }
-                }
-                _ => (),
-            }
-        };
-        false
-    };
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(Bearer::decode)
+        .map(|bearer| bearer.token().to_string());
 
-    if closure() {
-        next.run(request).await
-    } else {
-        Response::builder()
+    match state.claims(token.as_deref()) {
+        Some(claims) => {
+            request.extensions_mut().insert(claims);
+            next.run(request).await
+        }
+        None => Response::builder()
             .status(StatusCode::UNAUTHORIZED)
             .body(axum::body::Body::default())
-            .unwrap()
+            .unwrap(),
     }
 }
@@ -10,10 +10,14 @@ use webrtc::{
     peer_connection::sdp::session_description::RTCSessionDescription,
 };
 
+pub mod sdpfrag;
+
 #[derive(Clone)]
 pub struct Client {
     pub url: String,
     pub session_url: Option<String>,
+    /// Entity tag of the ICE session, updated by ICE restarts
+    pub etag: Option<String>,
     pub default_headers: HeaderMap,
 }
 
@@ -43,6 +47,7 @@ impl Client {
         Client {
             url,
             session_url: None,
+            etag: None,
             default_headers: defulat_headers.unwrap_or_default(),
         }
     }
@@ -55,6 +60,7 @@ impl Client {
         Client {
             url,
             session_url,
+            etag: None,
             default_headers: defulat_headers.unwrap_or_default(),
         }
     }
@@ -88,12 +94,72 @@ impl Client {
                 self.session_url = Some(url.into());
             }
         }
+        self.etag = Self::parse_etag(&response);
         let ice_servers = Self::parse_ide_servers(&response)?;
         let sdp =
             RTCSessionDescription::answer(String::from_utf8(response.bytes().await?.to_vec())?)?;
         Ok((sdp, ice_servers))
     }
 
+    fn parse_etag(response: &Response) -> Option<String> {
+        response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_owned())
+    }
+
+    /// Restart ICE of the session, `offer` is the local offer created with ICE restart
+    /// and `answer` the current remote answer.
+    /// Returns `answer` with the new remote ICE credentials and candidates.
+    pub async fn ice_restart(
+        &mut self,
+        offer: &RTCSessionDescription,
+        answer: &RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        let session_url = self
+            .session_url
+            .clone()
+            .ok_or(anyhow::anyhow!("there is no resource url"))?;
+        let mut header_map = self.default_headers.clone();
+        header_map.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str("application/trickle-ice-sdpfrag")?,
+        );
+        header_map.insert(header::IF_MATCH, HeaderValue::from_str("*")?);
+        let fragment = sdpfrag::sdpfrag(&offer.sdp)?;
+        let response = request(session_url, "PATCH", header_map, fragment).await?;
+        if response.status() != StatusCode::OK {
+            return Err(anyhow::anyhow!(get_response_error(response).await));
+        }
+        self.etag = Self::parse_etag(&response);
+        let fragment = response.text().await?;
+        RTCSessionDescription::answer(sdpfrag::restart_description(&answer.sdp, &fragment)?)
+            .map_err(|e| e.into())
+    }
+
+    /// Trickle local candidates in a trickle-ice-sdpfrag
+    pub async fn add_ice_candidates(&self, fragment: String) -> Result<()> {
+        let session_url = self
+            .session_url
+            .clone()
+            .ok_or(anyhow::anyhow!("there is no resource url"))?;
+        let mut header_map = self.default_headers.clone();
+        header_map.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str("application/trickle-ice-sdpfrag")?,
+        );
+        if let Some(etag) = &self.etag {
+            header_map.insert(header::IF_MATCH, HeaderValue::from_str(etag)?);
+        }
+        let response = request(session_url, "PATCH", header_map, fragment).await?;
+        if response.status() != StatusCode::NO_CONTENT {
+            Err(anyhow::anyhow!(get_response_error(response).await))
+        } else {
+            Ok(())
+        }
+    }
+
     fn parse_ide_servers(response: &Response) -> Result<Vec<RTCIceServer>> {
         let links = response.headers().get_all(header::LINK);
         let mut ice_servers = vec![];
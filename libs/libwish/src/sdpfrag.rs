@@ -0,0 +1,175 @@
+//! ICE restart with `application/trickle-ice-sdpfrag` (RFC 8840), as used by WHIP / WHEP
+
+use std::io::Cursor;
+
+use anyhow::{Result, anyhow};
+use webrtc::sdp::SessionDescription;
+use webrtc::sdp::description::common::Attribute;
+
+const ICE_UFRAG: &str = "ice-ufrag";
+const ICE_PWD: &str = "ice-pwd";
+const CANDIDATE: &str = "candidate";
+const END_OF_CANDIDATES: &str = "end-of-candidates";
+
+/// Entity tag of the ICE session of a SDP, changes with every ICE restart
+pub fn etag(sdp: &str) -> Option<String> {
+    ice_credentials(sdp).map(|(ufrag, _)| format!("\"{ufrag}\""))
+}
+
+/// The first ice-ufrag and ice-pwd of a SDP or trickle-ice-sdpfrag
+pub fn ice_credentials(sdp: &str) -> Option<(String, String)> {
+    let value = |key: &str| {
+        sdp.lines()
+            .find_map(|line| line.trim().strip_prefix(&format!("a={key}:")))
+            .map(|value| value.to_string())
+    };
+    Some((value(ICE_UFRAG)?, value(ICE_PWD)?))
+}
+
+/// The trickle-ice-sdpfrag of a SDP: its ICE credentials and candidates
+pub fn sdpfrag(sdp: &str) -> Result<String> {
+    let (ufrag, pwd) = ice_credentials(sdp).ok_or(anyhow!("no ice credentials"))?;
+    let mut lines = vec![
+        format!("a={ICE_UFRAG}:{ufrag}"),
+        format!("a={ICE_PWD}:{pwd}"),
+    ];
+    for line in sdp.lines().map(|line| line.trim()) {
+        if line.starts_with("m=")
+            || line.starts_with("a=mid:")
+            || line.starts_with(&format!("a={CANDIDATE}:"))
+            || line == format!("a={END_OF_CANDIDATES}")
+        {
+            lines.push(line.to_string());
+        }
+    }
+    Ok(lines.join("\r\n") + "\r\n")
+}
+
+/// `sdp` restarted with the ICE credentials and candidates of the trickle-ice-sdpfrag `fragment`,
+/// the previous candidates are dropped
+pub fn restart_description(sdp: &str, fragment: &str) -> Result<String> {
+    let (ufrag, pwd) = ice_credentials(fragment).ok_or(anyhow!("no ice credentials"))?;
+    let candidates = fragment_candidates(fragment);
+    let mut session = SessionDescription::unmarshal(&mut Cursor::new(sdp))?;
+    let restart = |attributes: &mut Vec<Attribute>| {
+        attributes.retain(|attr| !attr.is_ice_candidate() && attr.key != END_OF_CANDIDATES);
+        for attr in attributes.iter_mut() {
+            match attr.key.as_str() {
+                ICE_UFRAG => attr.value = Some(ufrag.clone()),
+                ICE_PWD => attr.value = Some(pwd.clone()),
+                _ => {}
+            }
+        }
+    };
+    restart(&mut session.attributes);
+    for (index, media) in session.media_descriptions.iter_mut().enumerate() {
+        restart(&mut media.attributes);
+        let mid = media
+            .attributes
+            .iter()
+            .find(|attr| attr.key == "mid")
+            .and_then(|attr| attr.value.clone());
+        for (candidate_mid, candidate) in candidates.iter() {
+            // Candidates without a mid belong to the first media
+            let matched = match candidate_mid {
+                Some(candidate_mid) => mid.as_ref() == Some(candidate_mid),
+                None => index == 0,
+            };
+            if matched {
+                media.attributes.push(Attribute::new(
+                    CANDIDATE.to_string(),
+                    Some(candidate.clone()),
+                ));
+            }
+        }
+    }
+    Ok(session.marshal())
+}
+
+/// Candidates of a trickle-ice-sdpfrag with the mid of their media
+fn fragment_candidates(fragment: &str) -> Vec<(Option<String>, String)> {
+    let mut mid = None;
+    let mut candidates = vec![];
+    for line in fragment.lines().map(|line| line.trim()) {
+        if line.starts_with("m=") {
+            mid = None;
+        } else if let Some(value) = line.strip_prefix("a=mid:") {
+            mid = Some(value.to_string());
+        } else if let Some(value) = line.strip_prefix(&format!("a={CANDIDATE}:")) {
+            candidates.push((mid.clone(), value.to_string()));
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{etag, ice_credentials, restart_description, sdpfrag};
+
+    const SDP: &str = "v=0\r
+o=- 0 0 IN IP4 0.0.0.0\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0 1\r
+m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:EsAw\r
+a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r
+a=mid:0\r
+a=candidate:1387637174 1 udp 2122260223 192.0.2.1 61764 typ host\r
+a=end-of-candidates\r
+m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:EsAw\r
+a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r
+a=mid:1\r
+";
+
+    #[test]
+    fn test_ice_credentials() {
+        assert_eq!(
+            ice_credentials(SDP),
+            Some(("EsAw".to_string(), "P2uYro0UCOQ4zxjKXaWCBui1".to_string()))
+        );
+        assert_eq!(etag(SDP), Some("\"EsAw\"".to_string()));
+        assert_eq!(ice_credentials("a=ice-ufrag:EsAw\r\n"), None);
+    }
+
+    #[test]
+    fn test_sdpfrag() {
+        assert_eq!(
+            sdpfrag(SDP).unwrap(),
+            "a=ice-ufrag:EsAw\r
+a=ice-pwd:P2uYro0UCOQ4zxjKXaWCBui1\r
+m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+a=mid:0\r
+a=candidate:1387637174 1 udp 2122260223 192.0.2.1 61764 typ host\r
+a=end-of-candidates\r
+m=video 9 UDP/TLS/RTP/SAVPF 96\r
+a=mid:1\r
+"
+        );
+    }
+
+    #[test]
+    fn test_restart_description() {
+        let fragment = "a=ice-ufrag:Nw1f\r
+a=ice-pwd:0T3jqXzRcN0Lqm4oEuRVPnPk\r
+m=video 9 UDP/TLS/RTP/SAVPF 96\r
+a=mid:1\r
+a=candidate:3471623853 1 udp 2122194687 198.51.100.1 61765 typ host\r
+";
+        let restarted = restart_description(SDP, fragment).unwrap();
+        assert_eq!(
+            ice_credentials(&restarted),
+            Some(("Nw1f".to_string(), "0T3jqXzRcN0Lqm4oEuRVPnPk".to_string()))
+        );
+        assert!(!restarted.contains("EsAw"));
+        assert!(!restarted.contains("192.0.2.1"));
+        assert!(!restarted.contains("a=end-of-candidates"));
+        // the new candidate lands in the media of its mid
+        let video = &restarted[restarted.find("m=video").unwrap()..];
+        assert!(video.contains("a=candidate:3471623853 1 udp 2122194687 198.51.100.1 61765"));
+        assert!(restart_description(SDP, "a=ice-ufrag:Nw1f\r\n").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-pub use rtsp_types::{Message, Method, Request, Response, StatusCode, Url, Version};
+pub use rtsp_types::{Message, Method, ParseError, Request, Response, StatusCode, Url, Version};
 
 pub mod headers {
     pub use rtsp_types::headers::*;
@@ -409,7 +409,7 @@ fn pack_temporal_unit(obus: &[Vec<u8>]) -> BytesMut {
     buf
 }
 
-impl crate::recorder::codec::RtpParser for Av1RtpParser {
+impl crate::codec::RtpParser for Av1RtpParser {
     type Output = BytesMut;
 
     fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
@@ -0,0 +1,36 @@
+use anyhow::{Result, anyhow};
+use bytes::BytesMut;
+use webrtc::rtp::packet::Packet;
+
+/// RTP parser for G.711 (PCMU/PCMA).
+/// Every byte of the payload is one companded sample, so like Opus each RTP packet is
+/// carried as a single MP4 sample, as is, in a `ulaw`/`alaw` sample entry.
+///
+/// Returns the RTP timestamp so that the caller can calculate the duration from
+/// timestamp deltas, the first packet lasts as many ticks as it has bytes.
+#[derive(Default)]
+pub struct G711RtpParser;
+
+impl G711RtpParser {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Push one RTP packet.
+    ///
+    /// Returns the raw payload as a BytesMut together with the original timestamp.
+    pub fn push_packet(&mut self, pkt: &Packet) -> Result<(BytesMut, u32)> {
+        if pkt.payload.is_empty() {
+            return Err(anyhow!("empty G.711 payload"));
+        }
+        Ok((BytesMut::from(pkt.payload.as_ref()), pkt.header.timestamp))
+    }
+}
+
+impl crate::codec::RtpParser for G711RtpParser {
+    type Output = (BytesMut, u32);
+
+    fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
+        G711RtpParser::push_packet(self, pkt).map(Some)
+    }
+}
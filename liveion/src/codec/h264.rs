@@ -1,5 +1,5 @@
+use super::nalu_to_avcc;
 use super::{CodecAdapter, TrackKind};
-use crate::recorder::fmp4::nalu_to_avcc;
 use anyhow::{Result, anyhow};
 use bytes::Bytes;
 use bytes::BytesMut;
@@ -34,11 +34,18 @@ impl H264Adapter {
         }
     }
 
+    /// Keeps a parameter set, true when it changed
+    fn store_nalu(target: &mut Option<Vec<u8>>, data: &[u8]) -> bool {
+        if target.as_deref() != Some(data) {
+            *target = Some(data.to_vec());
+            true
+        } else {
+            false
+        }
+    }
+
     /// Parse SPS bytes to calculate codec string
     fn update_codec_string(&mut self) {
-        if self.codec_string.is_some() {
-            return;
-        }
         if let Some(ref sps) = self.sps {
             if sps.len() >= 4 {
                 let profile_idc = sps[1];
@@ -125,15 +132,13 @@ impl CodecAdapter for H264Adapter {
 
             match nal_type {
                 7 => {
-                    if self.sps.is_none() {
-                        self.sps = Some(nalu[header_idx..].to_vec());
+                    if Self::store_nalu(&mut self.sps, &nalu[header_idx..]) {
                         self.parse_dimensions(&nalu[header_idx..]);
                         cfg_updated = true;
                     }
                 }
                 8 => {
-                    if self.pps.is_none() {
-                        self.pps = Some(nalu[header_idx..].to_vec());
+                    if Self::store_nalu(&mut self.pps, &nalu[header_idx..]) {
                         cfg_updated = true;
                     }
                 }
@@ -240,7 +245,7 @@ impl H264RtpParser {
 }
 
 // Implement unified RTP parser trait
-impl crate::recorder::codec::RtpParser for H264RtpParser {
+impl crate::codec::RtpParser for H264RtpParser {
     type Output = (BytesMut, bool);
 
     fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
@@ -1,7 +1,7 @@
 use std::io::Cursor;
 
+use super::nalu_to_avcc;
 use super::{CodecAdapter, TrackKind};
-use crate::recorder::fmp4::nalu_to_avcc;
 use anyhow::{Result, anyhow};
 use bytes::{Bytes, BytesMut};
 use scuffle_h265::{
@@ -340,7 +340,7 @@ impl H265RtpParser {
     }
 }
 
-impl crate::recorder::codec::RtpParser for H265RtpParser {
+impl crate::codec::RtpParser for H265RtpParser {
     type Output = (BytesMut, bool);
 
     fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
@@ -0,0 +1,220 @@
+pub mod av1;
+pub mod g711;
+pub mod h264;
+pub mod h265;
+pub mod opus;
+pub mod vp8;
+pub mod vp9;
+
+use av1::Av1Adapter;
+pub use av1::Av1RtpParser;
+pub use g711::G711RtpParser;
+use h264::H264Adapter;
+pub use h264::H264RtpParser;
+use h265::H265Adapter;
+pub use h265::H265RtpParser;
+pub use opus::OpusRtpParser;
+use vp8::Vp8Adapter;
+pub use vp8::Vp8RtpParser;
+use vp9::Vp9Adapter;
+pub use vp9::Vp9RtpParser;
+
+use anyhow::Result;
+use bytes::Bytes;
+use webrtc::api::media_engine::{
+    MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_HEVC, MIME_TYPE_OPUS, MIME_TYPE_PCMA, MIME_TYPE_PCMU,
+    MIME_TYPE_VP8, MIME_TYPE_VP9,
+};
+use webrtc::rtp::packet::Packet;
+
+/// Track category for a given adapter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+/// Event emitted by the adapter while parsing a frame
+pub enum CodecEvent {
+    /// Codec config (SPS/PPS, vpcC, etc.) is complete, ready to write init segment
+    ConfigUpdated,
+    /// This frame is a keyframe
+    KeyFrame,
+    /// Regular frame, no special events
+    None,
+}
+
+impl CodecEvent {
+    /// The event of a frame, from the flags returned by [`CodecAdapter::convert_frame`]
+    pub fn of_frame(is_sync: bool, config_updated: bool) -> Self {
+        if config_updated {
+            Self::ConfigUpdated
+        } else if is_sync {
+            Self::KeyFrame
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Unified codec adapter interface that all specific codec implementations must implement.
+/// Design goals:
+/// 1. Eliminate Segmenter/Fmp4Writer dependencies on specific codec details;
+/// 2. Facilitate future support for HEVC/VP9/AV1 and other codecs;
+/// 3. Maintain zero dependencies, pure Rust implementation.
+pub trait CodecAdapter: Send + Sync {
+    /// Track type (audio/video)
+    fn kind(&self) -> TrackKind;
+
+    /// Default timescale, H264 commonly uses 90_000.
+    fn timescale(&self) -> u32;
+
+    /// Whether initialization config has been collected (SPS/PPS, vpcC, etc.).
+    fn ready(&self) -> bool;
+
+    /// Convert a raw access unit frame to BMFF 4-byte length prefix format, returns:
+    /// - Vec<u8>  converted payload
+    /// - bool     whether this frame is a keyframe
+    /// - bool     whether this parsing caused codec config to be complete (first time collecting complete SPS/PPS, etc.)
+    fn convert_frame(&mut self, frame: &Bytes) -> (Vec<u8>, bool, bool);
+
+    /// Return codec config blob (e.g., [sps, pps]).
+    fn codec_config(&self) -> Option<Vec<Vec<u8>>>;
+
+    /// Return codec string like "avc1.42E01E" or "vp08.00.41.08".
+    fn codec_string(&self) -> Option<String>;
+
+    /// Video width, if applicable
+    fn width(&self) -> u32 {
+        0
+    }
+
+    /// Video height, if applicable
+    fn height(&self) -> u32 {
+        0
+    }
+}
+
+/// Supported video codecs for recorder ingestion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+    Vp8,
+    Vp9,
+    Av1,
+}
+
+/// Factory helper to create a concrete codec adapter by codec kind.
+pub fn create_video_adapter(codec: VideoCodec) -> Box<dyn CodecAdapter> {
+    match codec {
+        VideoCodec::H264 => Box::new(H264Adapter::new()),
+        VideoCodec::H265 => Box::new(H265Adapter::new()),
+        VideoCodec::Vp8 => Box::new(Vp8Adapter::new()),
+        VideoCodec::Vp9 => Box::new(Vp9Adapter::new()),
+        VideoCodec::Av1 => Box::new(Av1Adapter::new()),
+    }
+}
+
+/// Supported audio codecs for recorder ingestion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioCodec {
+    Opus,
+    Pcmu,
+    Pcma,
+}
+
+impl AudioCodec {
+    /// The codec of an RTP mime type like "audio/PCMU", None for the unsupported ones
+    pub fn from_mime(mime_type: &str) -> Option<Self> {
+        if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+            Some(Self::Opus)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_PCMU) {
+            Some(Self::Pcmu)
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_PCMA) {
+            Some(Self::Pcma)
+        } else {
+            None
+        }
+    }
+
+    /// Sample entry of the fMP4 track, also the codec string of the manifest
+    pub fn codec_string(self) -> &'static str {
+        match self {
+            Self::Opus => "opus",
+            Self::Pcmu => "ulaw",
+            Self::Pcma => "alaw",
+        }
+    }
+}
+
+/// Unified RTP parser trait so that different codecs (H264/Opus/…) share the same façade.
+///
+/// Associated type `Output` represents the parsed unit – for video it could be
+/// `(BytesMut, bool)` (frame + is_idr), for audio `(BytesMut, u32)` (payload + timestamp).
+/// The method returns `Ok(Some(x))` when a full unit is ready, or `Ok(None)` when the
+/// parser needs more RTP packets.
+pub trait RtpParser {
+    type Output;
+    fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>>;
+}
+
+/// The RTP parser of a codec, giving whole frames
+pub enum Depacketizer {
+    H264(H264RtpParser),
+    H265(H265RtpParser),
+    Vp8(Vp8RtpParser),
+    Vp9(Vp9RtpParser),
+    Av1(Av1RtpParser),
+    Opus(OpusRtpParser),
+}
+
+impl Depacketizer {
+    pub fn new(mime_type: &str) -> Option<(Self, Option<VideoCodec>)> {
+        let is = |mime: &str| mime_type.eq_ignore_ascii_case(mime);
+        if is(MIME_TYPE_H264) {
+            Some((Self::H264(H264RtpParser::new()), Some(VideoCodec::H264)))
+        } else if is(MIME_TYPE_HEVC) {
+            Some((Self::H265(H265RtpParser::new()), Some(VideoCodec::H265)))
+        } else if is(MIME_TYPE_VP8) {
+            Some((Self::Vp8(Vp8RtpParser::new()), Some(VideoCodec::Vp8)))
+        } else if is(MIME_TYPE_VP9) {
+            Some((Self::Vp9(Vp9RtpParser::new()), Some(VideoCodec::Vp9)))
+        } else if is(MIME_TYPE_AV1) {
+            Some((Self::Av1(Av1RtpParser::new()), Some(VideoCodec::Av1)))
+        } else if is(MIME_TYPE_OPUS) {
+            Some((Self::Opus(OpusRtpParser::new()), None))
+        } else {
+            None
+        }
+    }
+
+    /// A whole frame once its last packet is pushed
+    pub fn push(&mut self, packet: &Packet) -> Option<Bytes> {
+        match self {
+            Self::H264(parser) => parser.push_packet(packet).ok()?.map(|(f, _)| f.freeze()),
+            Self::H265(parser) => parser.push_packet(packet).ok()?.map(|(f, _)| f.freeze()),
+            Self::Vp8(parser) => parser.push_packet(packet).ok()?.map(|(f, _)| f.freeze()),
+            Self::Vp9(parser) => parser.push_packet(packet).ok()?.map(|f| f.freeze()),
+            Self::Av1(parser) => parser.push_packet(packet).ok()?.map(|f| f.freeze()),
+            Self::Opus(parser) => parser.push_packet(packet).ok().map(|(f, _)| f.freeze()),
+        }
+    }
+}
+
+/// Convert an Annex-B NALU (with or without start code) to a 4-byte-length-prefixed AVCC buffer.
+pub fn nalu_to_avcc(nalu: &Bytes) -> Vec<u8> {
+    // Determine where the raw payload starts (skip 3- or 4-byte start code if present)
+    let offset = if nalu.len() >= 4 && nalu[..4] == [0, 0, 0, 1][..] {
+        4
+    } else if nalu.len() >= 3 && nalu[..3] == [0, 0, 1][..] {
+        3
+    } else {
+        0
+    };
+    let payload = &nalu[offset..];
+    let mut out = Vec::with_capacity(4 + payload.len());
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+    out
+}
@@ -28,7 +28,7 @@ impl OpusRtpParser {
 }
 
 // Implement unified RTP parser trait (always returns Some because each RTP packet is a full Opus sample)
-impl crate::recorder::codec::RtpParser for OpusRtpParser {
+impl crate::codec::RtpParser for OpusRtpParser {
     type Output = (BytesMut, u32);
 
     fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
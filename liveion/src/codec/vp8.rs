@@ -0,0 +1,208 @@
+use super::{CodecAdapter, TrackKind};
+use anyhow::{Result, anyhow};
+use bytes::{Bytes, BytesMut};
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp::{codecs::vp8::Vp8Packet, packetizer::Depacketizer};
+
+/// Minimal VP8 adapter. For fMP4 we carry raw frame bytes into samples,
+/// the dimensions come from the keyframe header.
+pub struct Vp8Adapter {
+    timescale: u32,
+    width: u32,
+    height: u32,
+    profile: u8,
+}
+
+impl Default for Vp8Adapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vp8Adapter {
+    pub fn new() -> Self {
+        Self {
+            timescale: 90_000,
+            width: 0,
+            height: 0,
+            profile: 0,
+        }
+    }
+}
+
+impl CodecAdapter for Vp8Adapter {
+    fn kind(&self) -> TrackKind {
+        TrackKind::Video
+    }
+    fn timescale(&self) -> u32 {
+        self.timescale
+    }
+    fn ready(&self) -> bool {
+        self.width > 0 && self.height > 0
+    }
+    fn convert_frame(&mut self, frame: &Bytes) -> (Vec<u8>, bool, bool) {
+        let payload = frame.as_ref();
+
+        let mut is_key = false;
+        let mut cfg_updated = false;
+
+        if let Some(header) = Vp8FrameHeader::parse(payload) {
+            is_key = header.key_frame;
+            if is_key {
+                let was_ready = self.ready();
+                let changed = self.profile != header.profile
+                    || self.width != header.width
+                    || self.height != header.height;
+                self.profile = header.profile;
+                self.width = header.width;
+                self.height = header.height;
+                cfg_updated = self.ready() && (!was_ready || changed);
+            }
+        }
+
+        (payload.to_vec(), is_key, cfg_updated)
+    }
+    fn codec_config(&self) -> Option<Vec<Vec<u8>>> {
+        Some(vec![])
+    }
+    fn codec_string(&self) -> Option<String> {
+        // Level is not defined for VP8, bit depth is always 8
+        Some(format!("vp08.{:02}.10.08", self.profile))
+    }
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// The frame tag, and the start code with the dimensions of a keyframe
+/// https://datatracker.ietf.org/doc/html/rfc6386#section-9.1
+#[derive(Debug, Clone, Copy)]
+struct Vp8FrameHeader {
+    key_frame: bool,
+    profile: u8,
+    width: u32,
+    height: u32,
+}
+
+impl Vp8FrameHeader {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let tag = *data.first()?;
+        let key_frame = tag & 0x01 == 0;
+        let profile = (tag >> 1) & 0x07;
+        if !key_frame {
+            return Some(Self {
+                key_frame,
+                profile,
+                width: 0,
+                height: 0,
+            });
+        }
+
+        if data.get(3..6)? != [0x9d, 0x01, 0x2a] {
+            return None;
+        }
+        let width = u16::from_le_bytes([*data.get(6)?, *data.get(7)?]) & 0x3fff;
+        let height = u16::from_le_bytes([*data.get(8)?, *data.get(9)?]) & 0x3fff;
+        Some(Self {
+            key_frame,
+            profile,
+            width: width as u32,
+            height: height as u32,
+        })
+    }
+}
+
+/// Assemble WebRTC RTP (VP8) packets into a complete VP8 frame.
+const MAX_FRAME_SIZE: usize = 2 * 1024 * 1024;
+
+pub struct Vp8RtpParser {
+    depacketizer: Vp8Packet,
+    fragments: Vec<Bytes>,
+    fragments_size: usize,
+    fragment_next_seq: Option<u16>,
+    is_keyframe: bool,
+}
+
+impl Default for Vp8RtpParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vp8RtpParser {
+    pub fn new() -> Self {
+        Self {
+            depacketizer: Vp8Packet::default(),
+            fragments: Vec::new(),
+            fragments_size: 0,
+            fragment_next_seq: None,
+            is_keyframe: false,
+        }
+    }
+
+    /// Returns the frame once the packet with the marker bit is pushed,
+    /// and whether it is a keyframe
+    pub fn push_packet(&mut self, pkt: &Packet) -> Result<Option<(BytesMut, bool)>> {
+        let payload = match self.depacketizer.depacketize(&pkt.payload) {
+            Ok(payload) => payload,
+            Err(err) => {
+                self.reset_fragments();
+                return Err(anyhow!(err));
+            }
+        };
+
+        // The first packet of a frame starts partition 0, its payload starts with the frame tag
+        let is_begin = self.depacketizer.s == 1 && self.depacketizer.pid == 0;
+        if is_begin {
+            self.reset_fragments();
+            self.is_keyframe = payload.first().is_some_and(|tag| tag & 0x01 == 0);
+        } else if self.fragments.is_empty() {
+            return Ok(None);
+        } else if let Some(expected) = self.fragment_next_seq
+            && pkt.header.sequence_number != expected
+        {
+            self.reset_fragments();
+            return Ok(None);
+        }
+
+        self.fragment_next_seq = Some(pkt.header.sequence_number.wrapping_add(1));
+        self.fragments_size += payload.len();
+
+        if self.fragments_size > MAX_FRAME_SIZE {
+            self.reset_fragments();
+            return Ok(None);
+        }
+
+        self.fragments.push(payload);
+
+        if pkt.header.marker {
+            let mut out = BytesMut::with_capacity(self.fragments_size);
+            for fragment in self.fragments.drain(..) {
+                out.extend_from_slice(fragment.as_ref());
+            }
+            self.fragments_size = 0;
+            self.fragment_next_seq = None;
+            return Ok(Some((out, self.is_keyframe)));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Vp8RtpParser {
+    fn reset_fragments(&mut self) {
+        self.fragments.clear();
+        self.fragments_size = 0;
+        self.fragment_next_seq = None;
+    }
+}
+
+impl crate::codec::RtpParser for Vp8RtpParser {
+    type Output = (BytesMut, bool);
+    fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
+        Vp8RtpParser::push_packet(self, pkt)
+    }
+}
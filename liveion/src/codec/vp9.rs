@@ -476,7 +476,7 @@ impl Vp9RtpParser {
     }
 }
 
-impl crate::recorder::codec::RtpParser for Vp9RtpParser {
+impl crate::codec::RtpParser for Vp9RtpParser {
     type Output = BytesMut;
     fn push_packet(&mut self, pkt: &Packet) -> Result<Option<Self::Output>> {
         Vp9RtpParser::push_packet(self, pkt)
@@ -19,6 +19,24 @@ pub struct Config {
     #[serde(default)]
     pub sdp: Sdp,
 
+    #[serde(default)]
+    pub simulcast: Simulcast,
+
+    #[serde(default)]
+    pub gop_cache: GopCache,
+
+    #[serde(default)]
+    pub room: Room,
+
+    #[serde(default)]
+    pub health: Health,
+
+    #[serde(default)]
+    pub webrtc: WebRtc,
+
+    #[serde(default)]
+    pub metrics: Metrics,
+
     #[cfg(feature = "net4mqtt")]
     #[serde(default)]
     pub net4mqtt: Option<Net4mqtt>,
@@ -26,9 +44,24 @@ pub struct Config {
     #[serde(default)]
     pub webhook: Webhook,
 
+    #[serde(default)]
+    pub rtsp: Rtsp,
+
+    #[cfg(feature = "rtmp")]
+    #[serde(default)]
+    pub rtmp: Rtmp,
+
+    #[cfg(feature = "srt")]
+    #[serde(default)]
+    pub srt: Srt,
+
     #[cfg(feature = "recorder")]
     #[serde(default)]
     pub recorder: RecorderConfig,
+
+    #[cfg(feature = "recorder")]
+    #[serde(default)]
+    pub cmaf: Cmaf,
 }
 
 #[cfg(feature = "net4mqtt")]
@@ -49,8 +82,171 @@ impl Net4mqtt {
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Webhook {
+    /// Hooks with only a URL and the default settings
     #[serde(default)]
     pub webhooks: Vec<String>,
+    #[serde(default)]
+    pub hooks: Vec<WebhookTarget>,
+    /// Events published to MQTT brokers
+    #[serde(default)]
+    pub mqtt: Vec<MqttHookTarget>,
+}
+
+impl Webhook {
+    /// Every configured hook, including the `webhooks` URLs
+    pub fn targets(&self) -> Vec<WebhookTarget> {
+        self.webhooks
+            .iter()
+            .map(|url| WebhookTarget {
+                url: url.clone(),
+                ..Default::default()
+            })
+            .chain(self.hooks.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// Signs the body with HMAC-SHA256 in the `X-Live777-Signature` header, empty for no signature
+    #[serde(default)]
+    pub secret: String,
+    /// Event types delivered, e.g. "streamUp" or "publishDown", empty for all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Stream name patterns delivered, supports wildcards, empty for all streams
+    #[serde(default)]
+    pub streams: Vec<String>,
+    /// Milliseconds before a request is given up
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+    /// Retries of a failed delivery before the event is dropped
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+    /// Milliseconds before the first retry, doubled with every retry up to `max_backoff`
+    #[serde(default = "default_webhook_backoff")]
+    pub backoff: u64,
+    #[serde(default = "default_webhook_max_backoff")]
+    pub max_backoff: u64,
+    /// Maximum events waiting for delivery, the oldest are dropped beyond it
+    #[serde(default = "default_webhook_queue_size")]
+    pub queue_size: usize,
+    /// File keeping the undelivered events across restarts
+    #[serde(default)]
+    pub queue_path: Option<String>,
+}
+
+impl Default for WebhookTarget {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            secret: String::new(),
+            events: vec![],
+            streams: vec![],
+            timeout: default_webhook_timeout(),
+            max_retries: default_webhook_max_retries(),
+            backoff: default_webhook_backoff(),
+            max_backoff: default_webhook_max_backoff(),
+            queue_size: default_webhook_queue_size(),
+            queue_path: None,
+        }
+    }
+}
+
+impl WebhookTarget {
+    pub fn matches_stream(&self, stream: &str) -> bool {
+        self.streams.is_empty() || matches_any(&self.streams, stream)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MqttHookTarget {
+    /// Such as `mqtt://localhost:1883/?client_id=live777-{alias}`, `client_id` is required
+    pub url: String,
+    /// Replaces `{alias}` in the URL and topics
+    #[serde(default = "default_mqtt_hook_alias")]
+    pub alias: String,
+    /// Topic of the events, `{stream}` is the stream name
+    #[serde(default = "default_mqtt_hook_topic")]
+    pub topic: String,
+    /// Retained topic holding the state of every live stream, empty to disable
+    #[serde(default = "default_mqtt_hook_presence_topic")]
+    pub presence_topic: String,
+    /// Retained topic of the node, `online` once connected and `offline` as the last will,
+    /// the presence of the node is stale while it is `offline`, empty to disable
+    #[serde(default = "default_mqtt_hook_status_topic")]
+    pub status_topic: String,
+    /// 0, 1 or 2
+    #[serde(default = "default_mqtt_hook_qos")]
+    pub qos: u8,
+    /// Event types published, e.g. "streamUp" or "publishDown", empty for all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Stream name patterns published, supports wildcards, empty for all streams
+    #[serde(default)]
+    pub streams: Vec<String>,
+}
+
+impl Default for MqttHookTarget {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            alias: default_mqtt_hook_alias(),
+            topic: default_mqtt_hook_topic(),
+            presence_topic: default_mqtt_hook_presence_topic(),
+            status_topic: default_mqtt_hook_status_topic(),
+            qos: default_mqtt_hook_qos(),
+            events: vec![],
+            streams: vec![],
+        }
+    }
+}
+
+impl MqttHookTarget {
+    pub fn matches_stream(&self, stream: &str) -> bool {
+        self.streams.is_empty() || matches_any(&self.streams, stream)
+    }
+}
+
+fn default_mqtt_hook_alias() -> String {
+    "live777".to_string()
+}
+
+fn default_mqtt_hook_topic() -> String {
+    "live777/{alias}/events/{stream}".to_string()
+}
+
+fn default_mqtt_hook_presence_topic() -> String {
+    "live777/{alias}/streams/{stream}".to_string()
+}
+
+fn default_mqtt_hook_status_topic() -> String {
+    "live777/{alias}/status".to_string()
+}
+
+fn default_mqtt_hook_qos() -> u8 {
+    1
+}
+
+fn default_webhook_timeout() -> u64 {
+    500
+}
+
+fn default_webhook_max_retries() -> u32 {
+    10
+}
+
+fn default_webhook_backoff() -> u64 {
+    500
+}
+
+fn default_webhook_max_backoff() -> u64 {
+    30_000
+}
+
+fn default_webhook_queue_size() -> usize {
+    1024
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -67,6 +263,24 @@ pub struct Auth {
     pub secret: String,
     #[serde(default)]
     pub tokens: Vec<String>,
+    #[serde(default)]
+    pub callout: Option<AuthCallout>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthCallout {
+    /// Asked to allow or deny every publish and subscribe offer
+    pub url: String,
+    /// Milliseconds before the callout is given up
+    #[serde(default = "default_auth_callout_timeout")]
+    pub timeout: u64,
+    /// Accept the offers while the callout fails, instead of denying them
+    #[serde(default)]
+    pub allow_on_error: bool,
+}
+
+fn default_auth_callout_timeout() -> u64 {
+    1000
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,6 +296,338 @@ pub struct Sdp {
     pub disable_codecs: Vec<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Simulcast {
+    /// Pick the simulcast layer of each subscriber from its REMB/TWCC and loss reports
+    #[serde(default)]
+    pub auto_layer: bool,
+    /// Milliseconds the estimate must allow the next layer before switching up
+    #[serde(default = "default_simulcast_upgrade_hold")]
+    pub upgrade_hold: u64,
+    /// Milliseconds of congestion before switching down
+    #[serde(default = "default_simulcast_downgrade_hold")]
+    pub downgrade_hold: u64,
+    /// Reported fraction lost (0.0 - 1.0) treated as congestion
+    #[serde(default = "default_simulcast_loss_threshold")]
+    pub loss_threshold: f32,
+}
+
+impl Default for Simulcast {
+    fn default() -> Self {
+        Self {
+            auto_layer: false,
+            upgrade_hold: default_simulcast_upgrade_hold(),
+            downgrade_hold: default_simulcast_downgrade_hold(),
+            loss_threshold: default_simulcast_loss_threshold(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GopCache {
+    /// Stream name patterns that keep the packets since the last keyframe, supports wildcards
+    #[serde(default)]
+    pub streams: Vec<String>,
+    /// Maximum packets cached per video track, a longer GOP is not cached
+    #[serde(default = "default_gop_cache_max_packets")]
+    pub max_packets: usize,
+}
+
+impl Default for GopCache {
+    fn default() -> Self {
+        Self {
+            streams: vec![],
+            max_packets: default_gop_cache_max_packets(),
+        }
+    }
+}
+
+impl GopCache {
+    pub fn enabled(&self, stream: &str) -> bool {
+        matches_any(&self.streams, stream)
+    }
+}
+
+fn default_gop_cache_max_packets() -> usize {
+    2048
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Room {
+    /// Stream name patterns that accept several publishers, supports wildcards
+    #[serde(default)]
+    pub streams: Vec<String>,
+    /// Maximum publishers of a room stream
+    #[serde(default = "default_room_max_publishers")]
+    pub max_publishers: usize,
+}
+
+impl Default for Room {
+    fn default() -> Self {
+        Self {
+            streams: vec![],
+            max_publishers: default_room_max_publishers(),
+        }
+    }
+}
+
+impl Room {
+    pub fn enabled(&self, stream: &str) -> bool {
+        matches_any(&self.streams, stream)
+    }
+}
+
+fn default_room_max_publishers() -> usize {
+    16
+}
+
+/// RTSP server playing the streams without a WebRTC peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rtsp {
+    /// Disabled without an address
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// Seconds a client may stay silent before it is disconnected
+    #[serde(default = "default_rtsp_session_timeout")]
+    pub session_timeout: u64,
+}
+
+impl Default for Rtsp {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            session_timeout: default_rtsp_session_timeout(),
+        }
+    }
+}
+
+fn default_rtsp_session_timeout() -> u64 {
+    60
+}
+
+/// RTMP server publishing to the streams, e.g. from OBS or FFmpeg
+#[cfg(feature = "rtmp")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rtmp {
+    /// Disabled without an address
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// Seconds a publisher may stay silent before it is disconnected
+    #[serde(default = "default_rtmp_timeout")]
+    pub timeout: u64,
+}
+
+#[cfg(feature = "rtmp")]
+impl Default for Rtmp {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            timeout: default_rtmp_timeout(),
+        }
+    }
+}
+
+#[cfg(feature = "rtmp")]
+fn default_rtmp_timeout() -> u64 {
+    30
+}
+
+/// SRT over MPEG-TS, publishing to the streams and playing them
+#[cfg(feature = "srt")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Srt {
+    /// Listener mode, disabled without an address
+    #[serde(default)]
+    pub listen: Option<SocketAddr>,
+    /// Encrypts the connections of the listener, 10 to 79 characters, empty for none
+    #[serde(default)]
+    pub passphrase: String,
+    /// Bytes of the key the callers generate, 16, 24 or 32
+    #[serde(default = "default_srt_pbkeylen")]
+    pub pbkeylen: usize,
+    /// Milliseconds, the larger one of both peers is used
+    #[serde(default = "default_srt_latency")]
+    pub latency: u64,
+    /// Caller mode, connections to other listeners
+    #[serde(default)]
+    pub callers: Vec<SrtCaller>,
+}
+
+#[cfg(feature = "srt")]
+impl Default for Srt {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            passphrase: String::new(),
+            pbkeylen: default_srt_pbkeylen(),
+            latency: default_srt_latency(),
+            callers: vec![],
+        }
+    }
+}
+
+#[cfg(feature = "srt")]
+fn default_srt_pbkeylen() -> usize {
+    16
+}
+
+#[cfg(feature = "srt")]
+fn default_srt_latency() -> u64 {
+    120
+}
+
+#[cfg(feature = "srt")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SrtCaller {
+    /// The listener, `host:port`
+    pub addr: String,
+    pub stream: String,
+    #[serde(default)]
+    pub mode: SrtMode,
+    /// Sent to the listener, e.g. `#!::r=name,m=publish`
+    #[serde(default)]
+    pub streamid: Option<String>,
+    /// Empty for none
+    #[serde(default)]
+    pub passphrase: String,
+}
+
+#[cfg(feature = "srt")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SrtMode {
+    /// Receives the stream of the listener
+    #[default]
+    Request,
+    /// Sends the stream to the listener
+    Publish,
+}
+
+/// Watchdog of the publish tracks, the durations are in milliseconds and 0 disables a check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+    /// How often the tracks are checked
+    #[serde(default = "default_health_interval")]
+    pub interval: u64,
+    /// No RTP received for this long
+    #[serde(default = "default_health_stall_timeout")]
+    pub stall_timeout: u64,
+    /// No video keyframe received for this long
+    #[serde(default = "default_health_keyframe_timeout")]
+    pub keyframe_timeout: u64,
+    /// Payload bitrates in bits per second, 0 disables
+    #[serde(default)]
+    pub min_video_bitrate: u64,
+    #[serde(default)]
+    pub min_audio_bitrate: u64,
+    /// The bitrate stays below the minimum for this long
+    #[serde(default = "default_health_low_bitrate_timeout")]
+    pub low_bitrate_timeout: u64,
+    /// RTP timestamps move away from the arrival time by more than this
+    #[serde(default = "default_health_max_timestamp_jump")]
+    pub max_timestamp_jump: u64,
+    /// A publisher sends audio without video for this long
+    #[serde(default = "default_health_video_missing_timeout")]
+    pub video_missing_timeout: u64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            interval: default_health_interval(),
+            stall_timeout: default_health_stall_timeout(),
+            keyframe_timeout: default_health_keyframe_timeout(),
+            min_video_bitrate: 0,
+            min_audio_bitrate: 0,
+            low_bitrate_timeout: default_health_low_bitrate_timeout(),
+            max_timestamp_jump: default_health_max_timestamp_jump(),
+            video_missing_timeout: default_health_video_missing_timeout(),
+        }
+    }
+}
+
+fn default_health_interval() -> u64 {
+    1000
+}
+
+fn default_health_stall_timeout() -> u64 {
+    3000
+}
+
+fn default_health_keyframe_timeout() -> u64 {
+    0
+}
+
+fn default_health_low_bitrate_timeout() -> u64 {
+    5000
+}
+
+fn default_health_max_timestamp_jump() -> u64 {
+    1000
+}
+
+fn default_health_video_missing_timeout() -> u64 {
+    0
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebRtc {
+    /// Single UDP port shared by all peer connections
+    #[serde(default)]
+    pub udp_mux_port: Option<u16>,
+    /// Range of the UDP ports allocated per peer connection, 0 means no limit
+    #[serde(default)]
+    pub port_min: u16,
+    #[serde(default)]
+    pub port_max: u16,
+    /// Public IPs announced instead of the host candidate IPs, e.g. behind 1:1 NAT
+    #[serde(default)]
+    pub nat_1to1_ips: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Streams labeled by name, the streams beyond are summed up as `_overflow`
+    #[serde(default = "default_metrics_max_streams")]
+    pub max_streams: usize,
+    /// Sessions labeled by id in the per-session metrics, 0 disables them
+    #[serde(default)]
+    pub max_sessions: usize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            max_streams: default_metrics_max_streams(),
+            max_sessions: 0,
+        }
+    }
+}
+
+fn default_metrics_max_streams() -> usize {
+    100
+}
+
+fn matches_any(patterns: &[String], stream: &str) -> bool {
+    patterns.iter().any(|p| {
+        glob::Pattern::new(p)
+            .map(|pat| pat.matches(stream))
+            .unwrap_or(false)
+    })
+}
+
+fn default_simulcast_upgrade_hold() -> u64 {
+    5000
+}
+
+fn default_simulcast_downgrade_hold() -> u64 {
+    1000
+}
+
+fn default_simulcast_loss_threshold() -> f32 {
+    0.1
+}
+
 fn default_http_listen() -> SocketAddr {
     SocketAddr::from_str(&format!(
         "0.0.0.0:{}",
@@ -124,6 +670,38 @@ impl Config {
                 .validate()
                 .map_err(|e| anyhow::anyhow!(format!("ice_server error : {}", e)))?;
         }
+        if self.webrtc.port_max != 0 && self.webrtc.port_min > self.webrtc.port_max {
+            return Err(anyhow::anyhow!(
+                "webrtc error : port_min is greater than port_max"
+            ));
+        }
+        if self.webrtc.udp_mux_port.is_some()
+            && (self.webrtc.port_min != 0 || self.webrtc.port_max != 0)
+        {
+            return Err(anyhow::anyhow!(
+                "webrtc error : udp_mux_port can't be used with a port range"
+            ));
+        }
+        #[cfg(feature = "srt")]
+        {
+            let passphrases = std::iter::once(&self.srt.passphrase)
+                .chain(self.srt.callers.iter().map(|caller| &caller.passphrase));
+            for passphrase in passphrases {
+                if !passphrase.is_empty() && !(10..=79).contains(&passphrase.len()) {
+                    return Err(anyhow::anyhow!(
+                        "srt error : passphrase must have 10 to 79 characters"
+                    ));
+                }
+            }
+            if !matches!(self.srt.pbkeylen, 16 | 24 | 32) {
+                return Err(anyhow::anyhow!("srt error : pbkeylen must be 16, 24 or 32"));
+            }
+        }
+        if self.webhook.mqtt.iter().any(|target| target.qos > 2) {
+            return Err(anyhow::anyhow!(
+                "webhook error : mqtt qos must be 0, 1 or 2"
+            ));
+        }
         Ok(())
     }
 }
@@ -169,3 +747,65 @@ impl Default for RecorderConfig {
         }
     }
 }
+
+/// Live CMAF packaging in memory, started by the first player of a stream
+#[cfg(feature = "recorder")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cmaf {
+    /// Serve LL-HLS at `/hls/{stream}/index.m3u8`
+    #[serde(default)]
+    pub hls: bool,
+    /// Serve low-latency DASH at `/dash/{stream}/manifest.mpd`
+    #[serde(default)]
+    pub dash: bool,
+    /// Send fMP4 fragments of one frame each over WebSocket at `/ws/{stream}`, for MSE players
+    #[serde(default)]
+    pub ws: bool,
+    /// Target duration of a part in milliseconds
+    #[serde(default = "default_cmaf_part_duration")]
+    pub part_duration: u64,
+    /// Target duration of a segment in milliseconds, video segments start at keyframes
+    #[serde(default = "default_cmaf_segment_duration")]
+    pub segment_duration: u64,
+    /// Segments listed in the playlists
+    #[serde(default = "default_cmaf_segments")]
+    pub segments: usize,
+    /// Seconds without a request before the packaging of a stream stops
+    #[serde(default = "default_cmaf_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+#[cfg(feature = "recorder")]
+impl Default for Cmaf {
+    fn default() -> Self {
+        Self {
+            hls: false,
+            dash: false,
+            ws: false,
+            part_duration: default_cmaf_part_duration(),
+            segment_duration: default_cmaf_segment_duration(),
+            segments: default_cmaf_segments(),
+            idle_timeout: default_cmaf_idle_timeout(),
+        }
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn default_cmaf_part_duration() -> u64 {
+    500
+}
+
+#[cfg(feature = "recorder")]
+fn default_cmaf_segment_duration() -> u64 {
+    2000
+}
+
+#[cfg(feature = "recorder")]
+fn default_cmaf_segments() -> usize {
+    6
+}
+
+#[cfg(feature = "recorder")]
+fn default_cmaf_idle_timeout() -> u64 {
+    30
+}
@@ -1,2 +1,3 @@
 pub const RID_ENABLE: &str = "RID_ENABLE";
 pub const RID_DISABLE: &str = "RID_DISABLE";
+pub const RID_AUTO: &str = "RID_AUTO";
@@ -16,10 +16,11 @@ impl From<crate::forward::message::ForwardInfo> for api::response::Stream {
             created_at: value.create_at,
             publish: api::response::PubSub {
                 leave_at: value.publish_leave_at,
-                sessions: match value.publish_session_info.map(|session| session.into()) {
-                    Some(session) => vec![session],
-                    None => vec![],
-                },
+                sessions: value
+                    .publish_session_infos
+                    .into_iter()
+                    .map(|session| session.into())
+                    .collect(),
             },
             subscribe: api::response::PubSub {
                 leave_at: value.subscribe_leave_at,
@@ -50,6 +51,33 @@ impl From<crate::forward::message::SessionInfo> for api::response::Session {
             state: convert_connect_state(value.state),
             cascade: value.cascade.map(|reforward| reforward.into()),
             has_data_channel: value.has_data_channel,
+            stats: value.stats.map(|stats| stats.into()),
+            health: value
+                .health
+                .into_iter()
+                .map(|problem| problem.into())
+                .collect(),
+        }
+    }
+}
+
+impl From<crate::forward::message::SessionStats> for api::response::SessionStats {
+    fn from(value: crate::forward::message::SessionStats) -> Self {
+        api::response::SessionStats {
+            bitrate_in: value.bitrate_in,
+            bitrate_out: value.bitrate_out,
+            packets_lost: value.packets_lost,
+            fraction_lost: value.fraction_lost,
+            jitter: value.jitter,
+            rtt: value.rtt,
+            nack_count: value.nack_count,
+            pli_count: value.pli_count,
+            fir_count: value.fir_count,
+            candidate_pair: value
+                .local_candidate
+                .zip(value.remote_candidate)
+                .map(|(local, remote)| api::response::CandidatePair { local, remote }),
+            layer: value.layer,
         }
     }
 }
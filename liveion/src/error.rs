@@ -6,6 +6,8 @@ pub enum AppError {
     StreamNotFound(String),
     StreamAlreadyExists(String),
     SessionNotFound(String),
+    PreconditionFailed(String),
+    Forbidden(String),
     Throw(String),
     InternalServerError(anyhow::Error),
 }
@@ -32,6 +34,20 @@ impl AppError {
         AppError::SessionNotFound(t.to_string())
     }
 
+    pub fn precondition_failed<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::PreconditionFailed(t.to_string())
+    }
+
+    pub fn forbidden<T>(t: T) -> Self
+    where
+        T: ToString,
+    {
+        AppError::Forbidden(t.to_string())
+    }
+
     pub fn throw<T>(t: T) -> Self
     where
         T: ToString,
@@ -46,6 +62,10 @@ impl IntoResponse for AppError {
             AppError::StreamNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
             AppError::StreamAlreadyExists(err) => (StatusCode::CONFLICT, err).into_response(),
             AppError::SessionNotFound(err) => (StatusCode::NOT_FOUND, err).into_response(),
+            AppError::PreconditionFailed(err) => {
+                (StatusCode::PRECONDITION_FAILED, err).into_response()
+            }
+            AppError::Forbidden(err) => (StatusCode::FORBIDDEN, err).into_response(),
             AppError::InternalServerError(err) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
             }
@@ -0,0 +1,131 @@
+use super::keyframe::is_keyframe;
+use super::track::ForwardData;
+
+/// Video packets since the last keyframe, replayed to new subscribers
+/// so they don't have to wait for the publisher's next keyframe.
+pub(crate) struct GopCache {
+    mime_type: String,
+    max_packets: usize,
+    packets: Vec<ForwardData>,
+    /// The GOP outgrew `max_packets`, wait for the next keyframe
+    overflow: bool,
+}
+
+impl GopCache {
+    pub(crate) fn new(mime_type: String, max_packets: usize) -> Self {
+        Self {
+            mime_type,
+            max_packets,
+            packets: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    pub(crate) fn push(&mut self, packet: &ForwardData) {
+        // Parameter sets and the first slice of a keyframe share a timestamp
+        let starts_gop = is_keyframe(&self.mime_type, &packet.payload)
+            && self
+                .packets
+                .first()
+                .is_none_or(|first| first.header.timestamp != packet.header.timestamp);
+        if starts_gop {
+            self.packets.clear();
+            self.overflow = false;
+        } else if self.packets.is_empty() || self.overflow {
+            return;
+        }
+        if self.packets.len() >= self.max_packets {
+            self.packets.clear();
+            self.overflow = true;
+            return;
+        }
+        self.packets.push(packet.clone());
+    }
+
+    pub(crate) fn packets(&self) -> Vec<ForwardData> {
+        self.packets.clone()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.packets.clear();
+        self.overflow = false;
+    }
+}
+
+/// Squeeze the timestamps of cached packets right before the last cached frame,
+/// so the subscriber decodes the whole GOP at once instead of replaying it in real time.
+/// Returns the timestamp to send for each packet.
+pub(crate) fn replay_timestamps(packets: &[ForwardData]) -> Vec<u32> {
+    let Some(last) = packets.last() else {
+        return vec![];
+    };
+    let mut frames_after = 0u32;
+    let mut timestamps = vec![0; packets.len()];
+    let mut next_timestamp = last.header.timestamp;
+    for (i, packet) in packets.iter().enumerate().rev() {
+        if packet.header.timestamp != next_timestamp {
+            next_timestamp = packet.header.timestamp;
+            frames_after += 1;
+        }
+        timestamps[i] = last.header.timestamp.wrapping_sub(frames_after);
+    }
+    timestamps
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use webrtc::rtp::packet::Packet;
+
+    use super::{GopCache, replay_timestamps};
+
+    fn packet(timestamp: u32, payload: &'static [u8]) -> Arc<Packet> {
+        let mut packet = Packet::default();
+        packet.header.timestamp = timestamp;
+        packet.payload = payload.into();
+        Arc::new(packet)
+    }
+
+    // VP8 start of partition, P=0
+    const KEY: &[u8] = &[0x10, 0x00];
+    // VP8 start of partition, P=1
+    const DELTA: &[u8] = &[0x10, 0x01];
+
+    #[test]
+    fn test_gop_cache_starts_at_keyframe() {
+        let mut gop = GopCache::new("video/VP8".to_string(), 16);
+        gop.push(&packet(0, DELTA));
+        assert!(gop.packets().is_empty());
+        gop.push(&packet(3000, KEY));
+        gop.push(&packet(3000, &[0x00]));
+        gop.push(&packet(6000, DELTA));
+        assert_eq!(gop.packets().len(), 3);
+        gop.push(&packet(9000, KEY));
+        assert_eq!(gop.packets().len(), 1);
+    }
+
+    #[test]
+    fn test_gop_cache_overflow() {
+        let mut gop = GopCache::new("video/VP8".to_string(), 2);
+        gop.push(&packet(0, KEY));
+        gop.push(&packet(3000, DELTA));
+        gop.push(&packet(6000, DELTA));
+        assert!(gop.packets().is_empty());
+        gop.push(&packet(9000, DELTA));
+        assert!(gop.packets().is_empty());
+        gop.push(&packet(12000, KEY));
+        assert_eq!(gop.packets().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_timestamps() {
+        let packets = vec![
+            packet(3000, KEY),
+            packet(3000, &[0x00]),
+            packet(6000, DELTA),
+            packet(9000, DELTA),
+        ];
+        assert_eq!(replay_timestamps(&packets), vec![8998, 8998, 8999, 9000]);
+    }
+}
@@ -0,0 +1,302 @@
+use std::time::{Duration, Instant};
+
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+use super::message::HealthProblem;
+use crate::config;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HealthChange {
+    pub(crate) problem: HealthProblem,
+    pub(crate) raised: bool,
+    pub(crate) detail: String,
+}
+
+impl HealthChange {
+    fn raised(problem: HealthProblem, detail: String) -> Self {
+        Self {
+            problem,
+            raised: true,
+            detail,
+        }
+    }
+
+    fn recovered(problem: HealthProblem) -> Self {
+        Self {
+            problem,
+            raised: false,
+            detail: String::new(),
+        }
+    }
+}
+
+fn enabled(millis: u64) -> Option<Duration> {
+    (millis > 0).then(|| Duration::from_millis(millis))
+}
+
+/// Health of a publish track, fed by `track_forward` and checked by the stream watchdog
+pub(crate) struct TrackHealth {
+    cfg: config::Health,
+    video: bool,
+    clock_rate: u32,
+    start: Instant,
+    last_packet: Option<Instant>,
+    last_keyframe: Option<Instant>,
+    low_bitrate_since: Option<Instant>,
+    /// RTP timestamp and arrival of the previous packet
+    previous: Option<(u32, Instant)>,
+    /// Largest timestamp jump in milliseconds since the previous check
+    jump: Option<i64>,
+    active: Vec<HealthProblem>,
+}
+
+impl TrackHealth {
+    pub(crate) fn new(cfg: config::Health, kind: RTPCodecType, clock_rate: u32) -> Self {
+        Self::with_start(cfg, kind, clock_rate, Instant::now())
+    }
+
+    fn with_start(
+        cfg: config::Health,
+        kind: RTPCodecType,
+        clock_rate: u32,
+        start: Instant,
+    ) -> Self {
+        Self {
+            cfg,
+            video: kind == RTPCodecType::Video,
+            clock_rate,
+            start,
+            last_packet: None,
+            last_keyframe: None,
+            low_bitrate_since: None,
+            previous: None,
+            jump: None,
+            active: vec![],
+        }
+    }
+
+    pub(crate) fn packet(&mut self, timestamp: u32, keyframe: bool, arrival: Instant) {
+        if let Some((previous, previous_arrival)) = self.previous
+            && self.clock_rate > 0
+            && self.cfg.max_timestamp_jump > 0
+        {
+            let media =
+                timestamp.wrapping_sub(previous) as i32 as i64 * 1000 / self.clock_rate as i64;
+            let elapsed = arrival.duration_since(previous_arrival).as_millis() as i64;
+            let jump = media - elapsed;
+            if jump.unsigned_abs() > self.cfg.max_timestamp_jump
+                && self.jump.is_none_or(|max| jump.abs() > max.abs())
+            {
+                self.jump = Some(jump);
+            }
+        }
+        self.previous = Some((timestamp, arrival));
+        self.last_packet = Some(arrival);
+        if keyframe {
+            self.last_keyframe = Some(arrival);
+        }
+    }
+
+    /// Problems raised and recovered since the previous check, `bitrate` in bits per second
+    pub(crate) fn check(&mut self, bitrate: u64, now: Instant) -> Vec<HealthChange> {
+        let mut changes = vec![];
+        let start = self.start;
+        let since = move |at: Option<Instant>| now.duration_since(at.unwrap_or(start));
+
+        let stalled = enabled(self.cfg.stall_timeout)
+            .is_some_and(|timeout| since(self.last_packet) >= timeout);
+        self.set(
+            &mut changes,
+            HealthProblem::Stalled,
+            stalled,
+            format!("no RTP for {}ms", since(self.last_packet).as_millis()),
+        );
+
+        // A stalled track has neither keyframes nor bitrate, keep their state until it resumes
+        if !stalled {
+            if self.video
+                && let Some(timeout) = enabled(self.cfg.keyframe_timeout)
+            {
+                let elapsed = since(self.last_keyframe);
+                self.set(
+                    &mut changes,
+                    HealthProblem::NoKeyframe,
+                    elapsed >= timeout,
+                    format!("no keyframe for {}ms", elapsed.as_millis()),
+                );
+            }
+
+            let min_bitrate = if self.video {
+                self.cfg.min_video_bitrate
+            } else {
+                self.cfg.min_audio_bitrate
+            };
+            if min_bitrate > 0 && self.last_packet.is_some() {
+                let low = bitrate < min_bitrate;
+                let low_since = match (low, self.low_bitrate_since) {
+                    (false, _) => None,
+                    (true, None) => Some(now),
+                    (true, since) => since,
+                };
+                self.low_bitrate_since = low_since;
+                let sustained = low_since.is_some_and(|since| {
+                    now.duration_since(since) >= Duration::from_millis(self.cfg.low_bitrate_timeout)
+                });
+                self.set(
+                    &mut changes,
+                    HealthProblem::LowBitrate,
+                    sustained,
+                    format!("{bitrate}bps below {min_bitrate}bps"),
+                );
+            }
+        }
+
+        // Only warned, there is no state to recover from
+        if let Some(jump) = self.jump.take() {
+            changes.push(HealthChange::raised(
+                HealthProblem::TimestampJump,
+                format!("RTP timestamp jumped {jump}ms from the arrival time"),
+            ));
+        }
+        changes
+    }
+
+    fn set(
+        &mut self,
+        changes: &mut Vec<HealthChange>,
+        problem: HealthProblem,
+        active: bool,
+        detail: String,
+    ) {
+        match (active, self.active.contains(&problem)) {
+            (true, false) => {
+                self.active.push(problem);
+                changes.push(HealthChange::raised(problem, detail));
+            }
+            (false, true) => {
+                self.active.retain(|active| *active != problem);
+                changes.push(HealthChange::recovered(problem));
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn problems(&self) -> Vec<HealthProblem> {
+        self.active.clone()
+    }
+
+    pub(crate) fn is_stalled(&self) -> bool {
+        self.last_packet.is_none() || self.active.contains(&HealthProblem::Stalled)
+    }
+
+    pub(crate) fn start(&self) -> Instant {
+        self.start
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+    use super::{HealthChange, TrackHealth};
+    use crate::config;
+    use crate::forward::message::HealthProblem;
+
+    fn raised(changes: &[HealthChange]) -> Vec<HealthProblem> {
+        changes
+            .iter()
+            .filter(|change| change.raised)
+            .map(|change| change.problem)
+            .collect()
+    }
+
+    fn recovered(changes: &[HealthChange]) -> Vec<HealthProblem> {
+        changes
+            .iter()
+            .filter(|change| !change.raised)
+            .map(|change| change.problem)
+            .collect()
+    }
+
+    #[test]
+    fn test_stall_and_keyframe() {
+        let start = Instant::now();
+        let cfg = config::Health {
+            keyframe_timeout: 10_000,
+            ..Default::default()
+        };
+        let mut health = TrackHealth::with_start(cfg, RTPCodecType::Video, 90000, start);
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // 30 fps with a keyframe every second
+        for frame in 0..60u64 {
+            health.packet(frame as u32 * 3000, frame % 30 == 0, at(frame * 1000 / 30));
+        }
+        assert!(health.check(0, at(2000)).is_empty());
+
+        // nothing for 4 seconds
+        assert_eq!(raised(&health.check(0, at(6000))), [HealthProblem::Stalled]);
+        assert!(health.is_stalled());
+
+        // frames resume, without keyframes
+        for frame in 180..600u64 {
+            health.packet(frame as u32 * 3000, false, at(frame * 1000 / 30));
+        }
+        let changes = health.check(0, at(20_000));
+        assert_eq!(recovered(&changes), [HealthProblem::Stalled]);
+        assert_eq!(raised(&changes), [HealthProblem::NoKeyframe]);
+        assert_eq!(health.problems(), [HealthProblem::NoKeyframe]);
+
+        health.packet(600 * 3000, true, at(20_000));
+        assert_eq!(
+            recovered(&health.check(0, at(20_100))),
+            [HealthProblem::NoKeyframe]
+        );
+    }
+
+    #[test]
+    fn test_low_bitrate() {
+        let start = Instant::now();
+        let cfg = config::Health {
+            min_audio_bitrate: 16_000,
+            ..Default::default()
+        };
+        let mut health = TrackHealth::with_start(cfg, RTPCodecType::Audio, 48000, start);
+        let at = |millis| start + Duration::from_millis(millis);
+        health.packet(0, false, at(0));
+
+        health.packet(48_000, false, at(1000));
+        assert!(health.check(8000, at(1000)).is_empty());
+        health.packet(48_000 * 6, false, at(6000));
+        assert_eq!(
+            raised(&health.check(8000, at(6000))),
+            [HealthProblem::LowBitrate]
+        );
+        health.packet(48_000 * 7, false, at(7000));
+        assert_eq!(
+            recovered(&health.check(32_000, at(7000))),
+            [HealthProblem::LowBitrate]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_jump() {
+        let start = Instant::now();
+        let mut health =
+            TrackHealth::with_start(config::Health::default(), RTPCodecType::Audio, 48000, start);
+        let at = |millis| start + Duration::from_millis(millis);
+        health.packet(0, false, at(0));
+        health.packet(960, false, at(20));
+        assert!(health.check(0, at(20)).is_empty());
+
+        // the encoder restarts with a new timestamp base
+        health.packet(960 + 48_000 * 60, false, at(40));
+        let changes = health.check(0, at(40));
+        assert_eq!(raised(&changes), [HealthProblem::TimestampJump]);
+        // one-shot
+        health.packet(960 + 48_000 * 60 + 960, false, at(60));
+        assert!(health.check(0, at(60)).is_empty());
+    }
+}
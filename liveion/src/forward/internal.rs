@@ -1,13 +1,17 @@
 use std::borrow::ToOwned;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use libwish::Client;
+use libwish::{Client, sdpfrag};
 use tokio::sync::{RwLock, broadcast};
 use tracing::trace;
 use tracing::{debug, info};
 use webrtc::api::APIBuilder;
-use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::interceptor_registry::{
+    configure_rtcp_reports, configure_twcc_receiver_only, register_default_interceptors,
+};
 use webrtc::api::media_engine::{MIME_TYPE_OPUS, MIME_TYPE_VP8, MediaEngine};
 use webrtc::api::setting_engine::SettingEngine;
 use webrtc::data::data_channel::DataChannel;
@@ -18,6 +22,8 @@ use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::interceptor::registry::Registry;
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::rtp_transceiver::rtp_codec::{
     RTCRtpCodecCapability, RTCRtpHeaderExtensionCapability, RTPCodecType,
 };
@@ -29,17 +35,21 @@ use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_remote::TrackRemote;
 
 use crate::AppError;
-use crate::forward::get_peer_id;
+use crate::config::{self, Simulcast};
 use crate::forward::message::ForwardInfo;
 use crate::forward::rtcp::RtcpMessage;
+use crate::forward::{Admitted, ForwardConfig, get_peer_id, peer_complete};
 use crate::result::Result;
 use crate::{metrics, new_broadcast_channel};
 
 use super::media::MediaInfo;
-use super::message::{CascadeInfo, ForwardEvent, ForwardEventType};
+use super::message::{CascadeInfo, ForwardEvent, ForwardEventType, HealthProblem, HealthReport};
+use super::nack::LocalStreamWriters;
+use super::network::Network;
 use super::publish::PublishRTCPeerConnection;
-use super::subscribe::SubscribeRTCPeerConnection;
-use super::track::PublishTrackRemote;
+use super::room::slot_publisher;
+use super::subscribe::{SenderSlot, SubscribeRTCPeerConnection};
+use super::track::{OutputTrack, PublishTrackRemote};
 
 const MESSAGE_SIZE: usize = 1024 * 16;
 
@@ -54,33 +64,58 @@ pub(crate) struct PeerForwardInternal {
     create_at: i64,
     publish_leave_at: RwLock<i64>,
     subscribe_leave_at: RwLock<i64>,
-    publish: RwLock<Option<PublishRTCPeerConnection>>,
+    /// In join order, holds more than one publisher only in room mode
+    publish_group: RwLock<Vec<PublishRTCPeerConnection>>,
+    max_publishers: usize,
+    /// Takes over when the publisher leaves, its tracks are kept aside until then
+    standby: RwLock<Option<PublishRTCPeerConnection>>,
+    standby_tracks: RwLock<Vec<PublishTrackRemote>>,
     pub(super) publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
     publish_tracks_change: broadcast::Sender<()>,
     publish_rtcp_channel: broadcast::Sender<(RtcpMessage, u32)>,
     subscribe_group: RwLock<Vec<SubscribeRTCPeerConnection>>,
+    /// Set by the auth callout admitting the publisher
+    max_subscribers: RwLock<Option<usize>>,
+    /// Readers without a WebRTC peer, counted as subscribers against `max_subscribers`
+    output_sessions: Mutex<HashSet<String>>,
     data_channel_forward: DataChannelForward,
     ice_server: Vec<RTCIceServer>,
+    network: Network,
+    pub(super) simulcast: Simulcast,
+    gop_cache: Option<usize>,
+    pub(super) health: config::Health,
+    /// Publishers sending audio without video
+    video_missing: RwLock<Vec<String>>,
     event_sender: broadcast::Sender<ForwardEvent>,
 }
 
 impl PeerForwardInternal {
-    pub(crate) fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub(crate) fn new(stream: impl ToString, config: ForwardConfig) -> Self {
         PeerForwardInternal {
             stream: stream.to_string(),
             create_at: Utc::now().timestamp_millis(),
             publish_leave_at: RwLock::new(0),
             subscribe_leave_at: RwLock::new(Utc::now().timestamp_millis()),
-            publish: RwLock::new(None),
+            publish_group: RwLock::new(Vec::new()),
+            max_publishers: config.max_publishers,
+            standby: RwLock::new(None),
+            standby_tracks: RwLock::new(Vec::new()),
             publish_tracks: Arc::new(RwLock::new(Vec::new())),
             publish_tracks_change: new_broadcast_channel!(16),
             publish_rtcp_channel: new_broadcast_channel!(48),
             subscribe_group: RwLock::new(Vec::new()),
+            max_subscribers: RwLock::new(None),
+            output_sessions: Mutex::new(HashSet::new()),
             data_channel_forward: DataChannelForward {
                 publish: new_broadcast_channel!(1024),
                 subscribe: new_broadcast_channel!(1024),
             },
-            ice_server,
+            ice_server: config.ice_servers,
+            network: config.network,
+            simulcast: config.simulcast,
+            gop_cache: config.gop_cache,
+            health: config.health,
+            video_missing: RwLock::new(Vec::new()),
             event_sender: new_broadcast_channel!(16),
         }
     }
@@ -90,30 +125,55 @@ impl PeerForwardInternal {
     }
 
     pub(crate) async fn info(&self) -> ForwardInfo {
+        self.collect_info(false).await
+    }
+
+    /// `info` with the transport stats of every session, reads the stats report of every peer
+    pub(crate) async fn info_with_stats(&self) -> ForwardInfo {
+        self.collect_info(true).await
+    }
+
+    async fn collect_info(&self, stats: bool) -> ForwardInfo {
+        let publish_tracks = self.publish_tracks.read().await.clone();
         let mut subscribe_session_infos = vec![];
         let subscribe_group = self.subscribe_group.read().await;
         for subscribe in subscribe_group.iter() {
-            subscribe_session_infos.push(subscribe.info().await);
+            let mut info = subscribe.info().await;
+            if stats {
+                info.stats = Some(subscribe.stats().await);
+            }
+            subscribe_session_infos.push(info);
+        }
+        let video_missing = self.video_missing.read().await.clone();
+        let mut publish_session_infos = vec![];
+        for publish in self.publish_group.read().await.iter() {
+            let mut info = publish.info();
+            for track in publish_tracks
+                .iter()
+                .filter(|track| track.publish_id == publish.id)
+            {
+                for problem in track.health() {
+                    if !info.health.contains(&problem) {
+                        info.health.push(problem);
+                    }
+                }
+            }
+            if video_missing.contains(&publish.id) {
+                info.health.push(HealthProblem::VideoMissing);
+            }
+            if stats {
+                info.stats = Some(publish.stats(&publish_tracks).await);
+            }
+            publish_session_infos.push(info);
         }
         ForwardInfo {
             id: self.stream.clone(),
             create_at: self.create_at,
             publish_leave_at: *self.publish_leave_at.read().await,
             subscribe_leave_at: *self.subscribe_leave_at.read().await,
-            publish_session_info: self
-                .publish
-                .read()
-                .await
-                .as_ref()
-                .map(|publish| publish.info()),
+            publish_session_infos,
             subscribe_session_infos,
-            codecs: self
-                .publish_tracks
-                .read()
-                .await
-                .iter()
-                .map(|track| track.codec())
-                .collect(),
+            codecs: publish_tracks.iter().map(|track| track.codec()).collect(),
         }
     }
 
@@ -127,15 +187,22 @@ impl PeerForwardInternal {
             ice_candidates.len(),
             id
         );
-        let publish = self.publish.read().await;
-        if publish.is_some() && publish.as_ref().unwrap().id == id {
-            let publish = publish.as_ref().unwrap();
+        let publish_group = self.publish_group.read().await;
+        if let Some(publish) = publish_group.iter().find(|publish| publish.id == id) {
             for ice_candidate in ice_candidates {
                 publish.peer.add_ice_candidate(ice_candidate).await?;
             }
             return Ok(());
         }
-        drop(publish);
+        drop(publish_group);
+        if let Some(standby) = self.standby.read().await.as_ref()
+            && standby.id == id
+        {
+            for ice_candidate in ice_candidates {
+                standby.peer.add_ice_candidate(ice_candidate).await?;
+            }
+            return Ok(());
+        }
         let subscribe_group = self.subscribe_group.read().await;
         for subscribe in subscribe_group.iter() {
             if subscribe.id == id {
@@ -148,11 +215,76 @@ impl PeerForwardInternal {
         Ok(())
     }
 
+    /// The peer of a publish, standby or subscribe session
+    async fn session_peer(&self, id: &str) -> Option<Arc<RTCPeerConnection>> {
+        let publish_group = self.publish_group.read().await;
+        if let Some(publish) = publish_group.iter().find(|publish| publish.id == id) {
+            return Some(publish.peer.clone());
+        }
+        drop(publish_group);
+        if let Some(standby) = self.standby.read().await.as_ref()
+            && standby.id == id
+        {
+            return Some(standby.peer.clone());
+        }
+        let subscribe_group = self.subscribe_group.read().await;
+        subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .map(|subscribe| subscribe.peer.clone())
+    }
+
+    pub(crate) async fn ice_etag(&self, id: String) -> Result<String> {
+        let peer = self
+            .session_peer(&id)
+            .await
+            .ok_or(AppError::session_not_found("session not exists"))?;
+        let description = peer
+            .local_description()
+            .await
+            .ok_or(AppError::throw("not set local_description"))?;
+        sdpfrag::etag(&description.sdp).ok_or(AppError::throw("no ice credentials"))
+    }
+
+    /// Answer the client offer again with the new ICE credentials of `fragment`, which restarts ICE
+    pub(crate) async fn ice_restart(
+        &self,
+        id: String,
+        fragment: String,
+    ) -> Result<RTCSessionDescription> {
+        let peer = self
+            .session_peer(&id)
+            .await
+            .ok_or(AppError::session_not_found("session not exists"))?;
+        let remote = peer
+            .remote_description()
+            .await
+            .ok_or(AppError::throw("not set remote_description"))?;
+        if remote.sdp_type != RTCSdpType::Offer {
+            return Err(AppError::throw(
+                "ice restart requires a session offered by the client",
+            ));
+        }
+        let offer =
+            RTCSessionDescription::offer(sdpfrag::restart_description(&remote.sdp, &fragment)?)?;
+        let description = peer_complete(offer, peer).await?;
+        info!("[{}] [{}] ice restart", self.stream, id);
+        Ok(description)
+    }
+
+    /// Returns true when the last publisher is removed and no standby takes over
     pub(crate) async fn remove_peer(&self, id: String) -> Result<bool> {
-        let publish = self.publish.read().await;
-        if publish.is_some() && publish.as_ref().unwrap().id == id {
-            publish.as_ref().unwrap().peer.close().await?;
-            return Ok(true);
+        let publish_group = self.publish_group.read().await;
+        if let Some(publish) = publish_group.iter().find(|publish| publish.id == id) {
+            publish.peer.close().await?;
+            return Ok(publish_group.len() == 1 && self.standby.read().await.is_none());
+        }
+        drop(publish_group);
+        if let Some(standby) = self.standby.read().await.as_ref()
+            && standby.id == id
+        {
+            standby.peer.close().await?;
+            return Ok(false);
         }
 
         let subscribe_group = self.subscribe_group.read().await;
@@ -166,14 +298,18 @@ impl PeerForwardInternal {
     }
 
     pub(crate) async fn close(&self) -> Result<()> {
-        let publish = self.publish.read().await;
+        let publish_group = self.publish_group.read().await;
         let subscribe_group = self.subscribe_group.read().await;
-        if publish.is_some() {
-            publish.as_ref().unwrap().peer.close().await?;
+        for publish in publish_group.iter() {
+            publish.peer.close().await?;
+        }
+        if let Some(standby) = self.standby.read().await.as_ref() {
+            standby.peer.close().await?;
         }
         for subscribe in subscribe_group.iter() {
             subscribe.peer.close().await?;
         }
+        metrics::release_stream(&self.stream);
         info!("{} close", self.stream);
         Ok(())
     }
@@ -239,9 +375,13 @@ impl PeerForwardInternal {
 
 // publish
 impl PeerForwardInternal {
-    pub(crate) async fn publish_is_some(&self) -> bool {
-        let publish = self.publish.read().await;
-        publish.is_some()
+    pub(crate) async fn publish_is_full(&self) -> bool {
+        let publish_group = self.publish_group.read().await;
+        publish_group.len() >= self.max_publishers
+    }
+
+    pub(crate) fn is_room(&self) -> bool {
+        self.max_publishers > 1
     }
 
     pub(crate) async fn set_publish(
@@ -250,8 +390,8 @@ impl PeerForwardInternal {
         cascade: Option<CascadeInfo>,
     ) -> Result<()> {
         {
-            let mut publish = self.publish.write().await;
-            if publish.is_some() {
+            let mut publish_group = self.publish_group.write().await;
+            if publish_group.len() >= self.max_publishers {
                 return Err(AppError::stream_already_exists(
                     "A connection has already been established",
                 ));
@@ -264,7 +404,7 @@ impl PeerForwardInternal {
             )
             .await?;
             info!("[{}] [publish] set {}", self.stream, publish_peer.id);
-            *publish = Some(publish_peer);
+            publish_group.push(publish_peer);
         }
         {
             let mut publish_leave_at = self.publish_leave_at.write().await;
@@ -276,54 +416,167 @@ impl PeerForwardInternal {
         Ok(())
     }
 
+    pub(crate) async fn set_max_subscribers(&self, max_subscribers: Option<usize>) {
+        *self.max_subscribers.write().await = max_subscribers;
+    }
+
+    /// Whether another subscriber exceeds `max_subscribers`, or the limit of the stream without it.
+    /// `subscribers` is counted under the write lock of the subscribe group, held until the insert.
+    async fn subscribe_is_full(&self, subscribers: usize, max_subscribers: Option<usize>) -> bool {
+        let Some(max_subscribers) = max_subscribers.or(*self.max_subscribers.read().await) else {
+            return false;
+        };
+        let outputs = self.output_sessions.lock().unwrap().len();
+        subscribers + outputs >= max_subscribers
+    }
+
+    pub(crate) async fn add_output_session(
+        &self,
+        session: String,
+        max_subscribers: Option<usize>,
+    ) -> Result<()> {
+        let subscribe_group = self.subscribe_group.write().await;
+        if self
+            .subscribe_is_full(subscribe_group.len(), max_subscribers)
+            .await
+        {
+            return Err(AppError::forbidden(
+                "the stream has reached max subscribers",
+            ));
+        }
+        if !self.output_sessions.lock().unwrap().insert(session) {
+            return Err(AppError::throw("output session already exists"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove_output_session(&self, session: &str) {
+        self.output_sessions.lock().unwrap().remove(session);
+    }
+
+    pub(crate) async fn has_standby(&self) -> bool {
+        self.standby.read().await.is_some()
+    }
+
+    pub(crate) async fn set_standby(&self, peer: Arc<RTCPeerConnection>) -> Result<()> {
+        let publish_group = self.publish_group.read().await;
+        if publish_group.is_empty() {
+            return Err(AppError::throw("publish is none"));
+        }
+        let mut standby = self.standby.write().await;
+        if standby.is_some() {
+            return Err(AppError::stream_already_exists(
+                "A standby has already been established",
+            ));
+        }
+        let standby_peer = PublishRTCPeerConnection::new(
+            self.stream.clone(),
+            peer,
+            self.publish_rtcp_channel.subscribe(),
+            None,
+        )
+        .await?;
+        info!(
+            "[{}] [publish] set standby {}",
+            self.stream, standby_peer.id
+        );
+        *standby = Some(standby_peer);
+        Ok(())
+    }
+
     pub(crate) async fn remove_publish(&self, peer: Arc<RTCPeerConnection>) -> Result<()> {
+        let id = get_peer_id(&peer);
         {
-            let mut publish = self.publish.write().await;
-            if publish.is_none() {
+            let mut standby = self.standby.write().await;
+            if standby.as_ref().is_some_and(|standby| standby.id == id) {
+                *standby = None;
+                self.standby_tracks.write().await.clear();
+                metrics::release_session(&self.stream, &id);
+                info!("[{}] [publish] remove standby {}", self.stream, id);
+                return Ok(());
+            }
+        }
+        let (empty, promoted) = {
+            let mut publish_group = self.publish_group.write().await;
+            if publish_group.is_empty() {
                 return Err(AppError::throw("publish is none"));
             }
-            if publish.as_ref().unwrap().id != get_peer_id(&peer) {
+            let Some(index) = publish_group.iter().position(|publish| publish.id == id) else {
                 return Err(AppError::throw("publish not myself"));
-            }
-            *publish = None;
-        }
-        {
+            };
+            publish_group.remove(index);
+            // Swap the tracks in one change, so subscribers rebind without a gap
+            let promoted = if publish_group.is_empty() {
+                self.standby.write().await.take()
+            } else {
+                None
+            };
             let mut publish_tracks = self.publish_tracks.write().await;
-            publish_tracks.clear();
+            publish_tracks.retain(|track| track.publish_id != id);
+            if let Some(promoted) = promoted.as_ref() {
+                publish_tracks.extend(self.standby_tracks.write().await.drain(..));
+                info!(
+                    "[{}] [publish] promote standby {}",
+                    self.stream, promoted.id
+                );
+            }
             let _ = self.publish_tracks_change.send(());
+            let promoted = promoted.map(|promoted| {
+                let id = promoted.id.clone();
+                publish_group.push(promoted);
+                id
+            });
+            (publish_group.is_empty(), promoted)
+        };
+        // The limit was set by the admission of the publisher gone
+        if empty || promoted.is_some() {
+            *self.max_subscribers.write().await = None;
         }
-        {
+        if empty {
             let mut publish_leave_at = self.publish_leave_at.write().await;
             *publish_leave_at = Utc::now().timestamp_millis();
+            info!("[{}] [publish] set none", self.stream);
+        } else {
+            info!("[{}] [publish] remove {}", self.stream, id);
         }
-        info!("[{}] [publish] set none", self.stream);
-        metrics::PUBLISH.dec();
+        if promoted.is_none() {
+            metrics::PUBLISH.dec();
+        }
+        metrics::release_session(&self.stream, &id);
         self.send_event(ForwardEventType::PublishDown, get_peer_id(&peer))
             .await;
+        if let Some(promoted) = promoted {
+            self.send_event(ForwardEventType::PublishUp, promoted).await;
+        }
         Ok(())
     }
 
     pub async fn publish_is_svc(&self) -> bool {
-        let publish = self.publish.read().await;
-        if publish.is_none() {
-            return false;
-        }
-        publish.as_ref().unwrap().media_info.video_transceiver.2
+        let publish_group = self.publish_group.read().await;
+        publish_group
+            .iter()
+            .any(|publish| publish.media_info.video_transceiver.2)
     }
 
     pub async fn publish_svc_rids(&self) -> Result<Vec<String>> {
         let publish_tracks = self.publish_tracks.read().await;
-        let rids = publish_tracks
+        let mut rids: Vec<String> = vec![];
+        for t in publish_tracks
             .iter()
             .filter(|t| t.kind == RTPCodecType::Video)
-            .map(|t| t.rid.clone())
-            .collect::<Vec<_>>();
+        {
+            if !rids.contains(&t.rid) {
+                rids.push(t.rid.clone());
+            }
+        }
         Ok(rids)
     }
 
+    /// `loopback` for the publishers in the process, see [`Network::apply_loopback`]
     pub(crate) async fn new_publish_peer(
         &self,
         media_info: MediaInfo,
+        loopback: bool,
     ) -> Result<Arc<RTCPeerConnection>> {
         if media_info.video_transceiver.0 > 1 && media_info.audio_transceiver.0 > 1 {
             return Err(AppError::throw("sendonly is more than 1"));
@@ -354,6 +607,11 @@ impl PeerForwardInternal {
         // But, as a local server, maybe we need this
         // https://github.com/binbat/live777/issues/155
         s.set_ice_multicast_dns_mode(MulticastDnsMode::Disabled);
+        if loopback {
+            Network::apply_loopback(&mut s);
+        } else {
+            self.network.apply(&mut s)?;
+        }
 
         let api = APIBuilder::new()
             .with_media_engine(m)
@@ -361,7 +619,11 @@ impl PeerForwardInternal {
             .with_setting_engine(s)
             .build();
         let config = RTCConfiguration {
-            ice_servers: self.ice_server.clone(),
+            ice_servers: if loopback {
+                vec![]
+            } else {
+                self.ice_server.clone()
+            },
             ..Default::default()
         };
         let peer = Arc::new(api.new_peer_connection(config).await?);
@@ -390,12 +652,43 @@ impl PeerForwardInternal {
         &self,
         peer: Arc<RTCPeerConnection>,
         track: Arc<TrackRemote>,
+        standby: bool,
     ) -> Result<()> {
-        let publish_track_remote =
-            PublishTrackRemote::new(self.stream.clone(), get_peer_id(&peer), track).await;
+        let id = get_peer_id(&peer);
+        let publish_track_remote = PublishTrackRemote::new(
+            self.stream.clone(),
+            id.clone(),
+            track,
+            self.gop_cache,
+            self.health.clone(),
+        )
+        .await;
+        if standby {
+            let publish_group = self.publish_group.read().await;
+            // Unless it has been promoted meanwhile
+            if !publish_group.iter().any(|publish| publish.id == id) {
+                self.standby_tracks.write().await.push(publish_track_remote);
+                return Ok(());
+            }
+        }
+        // Group the tracks by publisher in join order, then by rid
+        // Only the publishers with a video m-line in their offer, audio only streams are fine
+        let publishers: Vec<String> = self
+            .publish_group
+            .read()
+            .await
+            .iter()
+            .filter(|publish| publish.media_info.video_transceiver.0 > 0)
+            .map(|publish| publish.id.clone())
+            .collect();
         let mut publish_tracks = self.publish_tracks.write().await;
         publish_tracks.push(publish_track_remote);
-        publish_tracks.sort_by(|a, b| a.rid.cmp(&b.rid));
+        publish_tracks.sort_by_key(|t| {
+            (
+                publishers.iter().position(|id| *id == t.publish_id),
+                t.rid.clone(),
+            )
+        });
         let _ = self.publish_tracks_change.send(());
         Ok(())
     }
@@ -427,6 +720,23 @@ impl PeerForwardInternal {
         None
     }
 
+    /// The first track of each kind of the first publisher
+    pub(crate) async fn output_tracks(&self) -> Vec<OutputTrack> {
+        let publish_tracks = self.publish_tracks.read().await;
+        let Some(publish_id) = publish_tracks.first().map(|t| t.publish_id.clone()) else {
+            return vec![];
+        };
+        [RTPCodecType::Video, RTPCodecType::Audio]
+            .into_iter()
+            .filter_map(|kind| {
+                publish_tracks
+                    .iter()
+                    .find(|t| t.publish_id == publish_id && t.kind == kind)
+            })
+            .map(|t| OutputTrack::new(t.clone(), self.publish_rtcp_channel.clone()))
+            .collect()
+    }
+
     /// Subscribe to notifications when publish tracks change (e.g., new tracks arrive).
     #[cfg(feature = "recorder")]
     pub(crate) fn subscribe_publish_tracks_change(&self) -> tokio::sync::broadcast::Receiver<()> {
@@ -464,14 +774,24 @@ impl PeerForwardInternal {
     pub(crate) async fn new_subscription_peer(
         &self,
         media_info: MediaInfo,
-    ) -> Result<Arc<RTCPeerConnection>> {
-        if media_info.video_transceiver.1 > 1 && media_info.audio_transceiver.1 > 1 {
+    ) -> Result<(Arc<RTCPeerConnection>, LocalStreamWriters)> {
+        if !self.is_room()
+            && media_info.video_transceiver.1 > 1
+            && media_info.audio_transceiver.1 > 1
+        {
             return Err(AppError::throw("recvonly is more than 1"));
         }
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
+        // NACKs are answered from the publish track history in the subscriber,
+        // so the default interceptors are not used: they include a NACK responder.
+        // The default video codecs already advertise nack feedback.
+        let writers = LocalStreamWriters::default();
         let mut registry = Registry::new();
-        registry = register_default_interceptors(registry, &mut m)?;
+        // Must be first to get the writer closest to the transport
+        registry.add(Box::new(writers.clone()));
+        registry = configure_rtcp_reports(registry);
+        registry = configure_twcc_receiver_only(registry, &mut m)?;
         let mut s = SettingEngine::default();
         s.detach_data_channels();
 
@@ -480,6 +800,7 @@ impl PeerForwardInternal {
         // But, as a local server, maybe we need this
         // https://github.com/binbat/live777/issues/155
         s.set_ice_multicast_dns_mode(MulticastDnsMode::Disabled);
+        self.network.apply(&mut s)?;
 
         let api = APIBuilder::new()
             .with_media_engine(m)
@@ -491,15 +812,54 @@ impl PeerForwardInternal {
             ..Default::default()
         };
         let peer = Arc::new(api.new_peer_connection(config).await?);
-        Self::new_sender(&peer, RTPCodecType::Video, media_info.video_transceiver.1).await?;
-        Self::new_sender(&peer, RTPCodecType::Audio, media_info.audio_transceiver.1).await?;
-        Ok(peer)
+        self.new_senders(
+            &peer,
+            RTPCodecType::Video,
+            0..media_info.video_transceiver.1 as usize,
+        )
+        .await?;
+        self.new_senders(
+            &peer,
+            RTPCodecType::Audio,
+            0..media_info.audio_transceiver.1 as usize,
+        )
+        .await?;
+        Ok((peer, writers))
+    }
+
+    /// One sender per slot index, room mode has a slot per publisher
+    async fn new_senders(
+        &self,
+        peer: &Arc<RTCPeerConnection>,
+        kind: RTPCodecType,
+        slots: std::ops::Range<usize>,
+    ) -> Result<()> {
+        let max_slots = if self.is_room() {
+            self.max_publishers
+        } else {
+            1
+        };
+        let publish_tracks = self.publish_tracks.read().await;
+        for index in slots.take_while(|index| *index < max_slots) {
+            // msid of the initial answer, see `new_local_track` in subscribe
+            let stream_id = slot_publisher(
+                publish_tracks.iter().map(|t| (t.kind, &t.publish_id)),
+                kind,
+                index,
+                None,
+            )
+            .filter(|_| self.is_room())
+            .unwrap_or(format!("{}-{}", "webrtc", kind));
+            Self::new_sender(peer, kind, 1, stream_id).await?;
+        }
+        Ok(())
     }
 
     async fn new_sender(
         peer: &Arc<RTCPeerConnection>,
         kind: RTPCodecType,
         recv_sender: u8,
+        stream_id: String,
     ) -> Result<Option<Arc<RTCRtpSender>>> {
         Ok(if recv_sender > 0 {
             let sender = peer
@@ -549,7 +909,7 @@ impl PeerForwardInternal {
                     }
                 },
                 "webrtc".to_string(),
-                format!("{}-{}", "webrtc", kind),
+                stream_id,
             ));
             // ssrc for sdp
             let _ = sender.replace_track(Some(track)).await;
@@ -571,37 +931,40 @@ impl PeerForwardInternal {
         })
     }
 
+    /// `admitted` counts the subscriber against `max_subscribers`, not the cascade pushes
     pub async fn add_subscribe(
         &self,
-        peer: Arc<RTCPeerConnection>,
+        (peer, writers): (Arc<RTCPeerConnection>, LocalStreamWriters),
         cascade: Option<CascadeInfo>,
         media_info: MediaInfo,
+        admitted: Option<&Admitted>,
     ) -> Result<()> {
-        let transceivers = peer.get_transceivers().await;
-        let mut video_sender = None;
-        let mut audio_sender = None;
-        for transceiver in transceivers {
-            let sender = transceiver.sender().await;
-            match transceiver.kind() {
-                RTPCodecType::Video => video_sender = Some(sender),
-                RTPCodecType::Audio => audio_sender = Some(sender),
-                RTPCodecType::Unspecified => {}
-            }
-        }
+        let slots = Self::sender_slots(&peer, 0).await;
         {
             let s = SubscribeRTCPeerConnection::new(
-                cascade.clone(),
+                (cascade.clone(), self.is_room()),
                 self.stream.clone(),
-                (peer.clone(), media_info),
+                (peer.clone(), writers, media_info),
                 self.publish_rtcp_channel.clone(),
                 (
                     self.publish_tracks.clone(),
                     self.publish_tracks_change.clone(),
                 ),
-                (video_sender, audio_sender),
+                slots,
+                self.simulcast.clone(),
             )
             .await;
-            self.subscribe_group.write().await.push(s);
+            let mut subscribe_group = self.subscribe_group.write().await;
+            if let Some(admitted) = admitted
+                && self
+                    .subscribe_is_full(subscribe_group.len(), admitted.max_subscribers)
+                    .await
+            {
+                return Err(AppError::forbidden(
+                    "the stream has reached max subscribers",
+                ));
+            }
+            subscribe_group.push(s);
             *self.subscribe_leave_at.write().await = 0;
         }
         metrics::SUBSCRIBE.inc();
@@ -615,6 +978,86 @@ impl PeerForwardInternal {
         Ok(())
     }
 
+    /// Senders of the peer from the `skip`-th transceiver on, numbered per kind
+    async fn sender_slots(peer: &Arc<RTCPeerConnection>, skip: usize) -> Vec<SenderSlot> {
+        let mut slots = vec![];
+        let (mut video, mut audio) = (0, 0);
+        for (i, transceiver) in peer.get_transceivers().await.into_iter().enumerate() {
+            let kind = transceiver.kind();
+            let index = match kind {
+                RTPCodecType::Video => &mut video,
+                RTPCodecType::Audio => &mut audio,
+                RTPCodecType::Unspecified => continue,
+            };
+            if i >= skip {
+                slots.push(SenderSlot {
+                    kind,
+                    index: *index,
+                    sender: transceiver.sender().await,
+                });
+            }
+            *index += 1;
+        }
+        slots
+    }
+
+    /// Apply a new offer of a room subscriber, adding senders for the new m-lines
+    pub(crate) async fn renegotiate_subscribe(
+        &self,
+        id: String,
+        offer: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        if !self.is_room() {
+            return Err(AppError::throw("renegotiation requires room mode"));
+        }
+        let media_info = MediaInfo::try_from(offer.unmarshal()?)?;
+        let subscribe_group = self.subscribe_group.read().await;
+        let subscribe = subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .ok_or(AppError::session_not_found("session not exists"))?;
+        let peer = subscribe.peer.clone();
+        let transceivers = peer.get_transceivers().await;
+        let count = |kind| transceivers.iter().filter(|t| t.kind() == kind).count();
+        let (video, audio) = (count(RTPCodecType::Video), count(RTPCodecType::Audio));
+        self.new_senders(
+            &peer,
+            RTPCodecType::Video,
+            video..media_info.video_transceiver.1 as usize,
+        )
+        .await?;
+        self.new_senders(
+            &peer,
+            RTPCodecType::Audio,
+            audio..media_info.audio_transceiver.1 as usize,
+        )
+        .await?;
+        let description = peer_complete(offer, peer.clone()).await?;
+        subscribe.add_slots(Self::sender_slots(&peer, transceivers.len()).await);
+        info!(
+            "[{}] [subscribe] [{}] renegotiated, video: {}, audio: {}",
+            self.stream, id, media_info.video_transceiver.1, media_info.audio_transceiver.1
+        );
+        Ok(description)
+    }
+
+    pub(crate) async fn select_publishers(
+        &self,
+        id: String,
+        publishers: Option<Vec<String>>,
+    ) -> Result<()> {
+        if !self.is_room() {
+            return Err(AppError::throw("select publishers requires room mode"));
+        }
+        let subscribe_group = self.subscribe_group.read().await;
+        let subscribe = subscribe_group
+            .iter()
+            .find(|subscribe| subscribe.id == id)
+            .ok_or(AppError::session_not_found("session not exists"))?;
+        subscribe.select_publishers(publishers).await;
+        Ok(())
+    }
+
     pub async fn remove_subscribe(&self, peer: Arc<RTCPeerConnection>) -> Result<()> {
         let mut flag = false;
         let mut reforward_flat = false;
@@ -626,6 +1069,7 @@ impl PeerForwardInternal {
                 if subscribe.id == session {
                     flag = true;
                     metrics::SUBSCRIBE.dec();
+                    metrics::release_session(&self.stream, &session);
                     if let Some(cascade) = subscribe.cascade.clone() {
                         reforward_flat = true;
                         metrics::REFORWARD.dec();
@@ -682,6 +1126,99 @@ impl PeerForwardInternal {
         Ok(())
     }
 
+    /// Check the publish tracks, then raise and recover their problems as events
+    pub(crate) async fn health_check(&self) {
+        let now = Instant::now();
+        let publish_tracks = self.publish_tracks.read().await.clone();
+        let mut events = vec![];
+        for track in publish_tracks.iter() {
+            for change in track.health_check(now) {
+                events.push((
+                    track.publish_id.clone(),
+                    change.raised,
+                    HealthReport {
+                        problem: change.problem,
+                        kind: track.kind.to_string(),
+                        rid: track.rid.clone(),
+                        detail: change.detail,
+                    },
+                ));
+            }
+        }
+
+        let timeout = Duration::from_millis(self.health.video_missing_timeout);
+        // Only the publishers with a video m-line in their offer, audio only streams are fine
+        let publishers: Vec<String> = self
+            .publish_group
+            .read()
+            .await
+            .iter()
+            .filter(|publish| publish.media_info.video_transceiver.0 > 0)
+            .map(|publish| publish.id.clone())
+            .collect();
+        let mut video_missing = self.video_missing.write().await;
+        video_missing.retain(|id| publishers.contains(id));
+        for publisher in publishers {
+            let tracks: Vec<&PublishTrackRemote> = publish_tracks
+                .iter()
+                .filter(|track| track.publish_id == publisher)
+                .collect();
+            // Audio flowing since the timeout, while no video is
+            let audio_since = tracks
+                .iter()
+                .filter(|track| track.kind == RTPCodecType::Audio)
+                .map(|track| track.health_receiving())
+                .filter(|(receiving, _)| *receiving)
+                .map(|(_, start)| start)
+                .min();
+            let video = tracks
+                .iter()
+                .filter(|track| track.kind == RTPCodecType::Video)
+                .any(|track| track.health_receiving().0);
+            let missing = self.health.video_missing_timeout > 0
+                && !video
+                && audio_since.is_some_and(|start| now.duration_since(start) >= timeout);
+            let active = video_missing.contains(&publisher);
+            if missing == active {
+                continue;
+            }
+            if missing {
+                video_missing.push(publisher.clone());
+            } else {
+                video_missing.retain(|id| *id != publisher);
+            }
+            events.push((
+                publisher,
+                missing,
+                HealthReport {
+                    problem: HealthProblem::VideoMissing,
+                    kind: RTPCodecType::Video.to_string(),
+                    rid: String::new(),
+                    detail: format!("audio without video for {}ms", timeout.as_millis()),
+                },
+            ));
+        }
+        drop(video_missing);
+
+        for (session, raised, report) in events {
+            if raised {
+                info!(
+                    "[{}] [{}] health warning {:?} {} {}: {}",
+                    self.stream, session, report.problem, report.kind, report.rid, report.detail
+                );
+                self.send_event(ForwardEventType::HealthWarning(report), session)
+                    .await;
+            } else {
+                info!(
+                    "[{}] [{}] health recovered {:?} {} {}",
+                    self.stream, session, report.problem, report.kind, report.rid
+                );
+                self.send_event(ForwardEventType::HealthRecovered(report), session)
+                    .await;
+            }
+        }
+    }
+
     async fn send_event(&self, r#type: ForwardEventType, session: String) {
         let _ = self.event_sender.send(ForwardEvent {
             r#type,
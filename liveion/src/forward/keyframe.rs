@@ -0,0 +1,159 @@
+use webrtc::api::media_engine::{
+    MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_HEVC, MIME_TYPE_VP8, MIME_TYPE_VP9,
+};
+
+/// Whether the RTP payload starts a frame that decoders can begin from.
+/// Unknown codecs (and audio) are treated as always decodable.
+pub(crate) fn is_keyframe(mime_type: &str, payload: &[u8]) -> bool {
+    if payload.is_empty() {
+        return false;
+    }
+    if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP8) {
+        vp8_is_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_VP9) {
+        vp9_is_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+        h264_is_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_HEVC) {
+        h265_is_keyframe(payload)
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_AV1) {
+        av1_is_keyframe(payload)
+    } else {
+        true
+    }
+}
+
+// https://datatracker.ietf.org/doc/html/rfc7741#section-4.2
+fn vp8_is_keyframe(payload: &[u8]) -> bool {
+    let start_of_partition = payload[0] & 0x10 != 0;
+    let partition_id = payload[0] & 0x07;
+    if !start_of_partition || partition_id != 0 {
+        return false;
+    }
+    let mut offset = 1;
+    if payload[0] & 0x80 != 0 {
+        let Some(&ext) = payload.get(offset) else {
+            return false;
+        };
+        offset += 1;
+        if ext & 0x80 != 0 {
+            let Some(&picture_id) = payload.get(offset) else {
+                return false;
+            };
+            offset += if picture_id & 0x80 != 0 { 2 } else { 1 };
+        }
+        if ext & 0x40 != 0 {
+            offset += 1;
+        }
+        if ext & 0x30 != 0 {
+            offset += 1;
+        }
+    }
+    payload.get(offset).is_some_and(|b| b & 0x01 == 0)
+}
+
+// https://datatracker.ietf.org/doc/html/draft-ietf-payload-vp9-16#section-4.2
+fn vp9_is_keyframe(payload: &[u8]) -> bool {
+    let inter_predicted = payload[0] & 0x40 != 0;
+    let start_of_frame = payload[0] & 0x08 != 0;
+    !inter_predicted && start_of_frame
+}
+
+fn h264_is_keyframe(payload: &[u8]) -> bool {
+    match payload[0] & 0x1f {
+        5 | 7 => true,
+        // STAP-A
+        24 => {
+            let mut offset = 1;
+            while offset + 2 < payload.len() {
+                let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+                if matches!(payload[offset + 2] & 0x1f, 5 | 7) {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        // FU-A
+        28 => payload
+            .get(1)
+            .is_some_and(|fu| fu & 0x80 != 0 && fu & 0x1f == 5),
+        _ => false,
+    }
+}
+
+fn h265_is_keyframe(payload: &[u8]) -> bool {
+    let is_irap = |nal_type: u8| (16..=21).contains(&nal_type) || nal_type == 32 || nal_type == 33;
+    match (payload[0] >> 1) & 0x3f {
+        // AP
+        48 => {
+            let mut offset = 2;
+            while offset + 2 < payload.len() {
+                let size = u16::from_be_bytes([payload[offset], payload[offset + 1]]) as usize;
+                if is_irap((payload[offset + 2] >> 1) & 0x3f) {
+                    return true;
+                }
+                offset += 2 + size;
+            }
+            false
+        }
+        // FU
+        49 => payload
+            .get(2)
+            .is_some_and(|fu| fu & 0x80 != 0 && is_irap(fu & 0x3f)),
+        nal_type => is_irap(nal_type),
+    }
+}
+
+// https://aomediacodec.github.io/av1-rtp-spec/#44-av1-aggregation-header
+fn av1_is_keyframe(payload: &[u8]) -> bool {
+    payload[0] & 0x08 != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_keyframe;
+
+    #[test]
+    fn test_vp8_keyframe() {
+        // S=1, PID=0, P=0
+        assert!(is_keyframe("video/VP8", &[0x10, 0x00, 0x9d, 0x01, 0x2a]));
+        // S=1, PID=0, P=1
+        assert!(!is_keyframe("video/VP8", &[0x10, 0x01]));
+        // X=1, I=1 with 15-bit picture id, S=1
+        assert!(is_keyframe("video/VP8", &[0x90, 0x80, 0x81, 0x23, 0x00]));
+        // continuation packet
+        assert!(!is_keyframe("video/VP8", &[0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_h264_keyframe() {
+        assert!(is_keyframe("video/H264", &[0x65, 0x88]));
+        assert!(is_keyframe("video/H264", &[0x67, 0x42]));
+        assert!(!is_keyframe("video/H264", &[0x41, 0x9a]));
+        // STAP-A with SPS
+        assert!(is_keyframe("video/H264", &[0x78, 0x00, 0x02, 0x67, 0x42]));
+        // FU-A start of IDR
+        assert!(is_keyframe("video/H264", &[0x7c, 0x85, 0x88]));
+        // FU-A middle of IDR
+        assert!(!is_keyframe("video/H264", &[0x7c, 0x05, 0x88]));
+    }
+
+    #[test]
+    fn test_h265_keyframe() {
+        // IDR_W_RADL
+        assert!(is_keyframe("video/H265", &[0x26, 0x01]));
+        // TRAIL_R
+        assert!(!is_keyframe("video/H265", &[0x02, 0x01]));
+        // FU start of IDR_W_RADL
+        assert!(is_keyframe("video/H265", &[0x62, 0x01, 0x93]));
+    }
+
+    #[test]
+    fn test_vp9_av1_keyframe() {
+        assert!(is_keyframe("video/VP9", &[0x08]));
+        assert!(!is_keyframe("video/VP9", &[0x48]));
+        assert!(is_keyframe("video/AV1", &[0x18]));
+        assert!(!is_keyframe("video/AV1", &[0x10]));
+    }
+}
@@ -11,7 +11,7 @@ pub struct ForwardInfo {
     pub create_at: i64,
     pub publish_leave_at: i64,
     pub subscribe_leave_at: i64,
-    pub publish_session_info: Option<SessionInfo>,
+    pub publish_session_infos: Vec<SessionInfo>,
     pub subscribe_session_infos: Vec<SessionInfo>,
     pub codecs: Vec<Codec>,
 }
@@ -22,6 +22,33 @@ pub struct SessionInfo {
     pub state: RTCPeerConnectionState,
     pub cascade: Option<CascadeInfo>,
     pub has_data_channel: bool,
+    /// Only collected for the API and the SSE feed
+    pub stats: Option<SessionStats>,
+    /// Active problems of a publish session
+    pub health: Vec<HealthProblem>,
+}
+
+/// Transport stats of a session
+#[derive(Clone, Debug, Default)]
+pub struct SessionStats {
+    /// bits per second
+    pub bitrate_in: u64,
+    pub bitrate_out: u64,
+    pub packets_lost: i64,
+    /// In 1/256, as RTCP reception reports
+    pub fraction_lost: u8,
+    /// milliseconds
+    pub jitter: u32,
+    /// milliseconds
+    pub rtt: Option<u32>,
+    pub nack_count: u64,
+    pub pli_count: u64,
+    pub fir_count: u64,
+    /// Candidates of the selected pair, `{type} {network} {ip}:{port}`
+    pub local_candidate: Option<String>,
+    pub remote_candidate: Option<String>,
+    /// Simulcast layer a subscriber is bound to
+    pub layer: Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -54,4 +81,24 @@ pub enum ForwardEventType {
     SubscribeDown,
     ReforwardUp,
     ReforwardDown,
+    HealthWarning(HealthReport),
+    HealthRecovered(HealthReport),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthProblem {
+    Stalled,
+    NoKeyframe,
+    LowBitrate,
+    TimestampJump,
+    VideoMissing,
+}
+
+/// A problem of a publish track, or of the publisher for `VideoMissing`
+#[derive(Clone, Debug)]
+pub struct HealthReport {
+    pub problem: HealthProblem,
+    pub kind: String,
+    pub rid: String,
+    pub detail: String,
 }
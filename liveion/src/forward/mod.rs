@@ -1,8 +1,11 @@
+use std::future::Future;
 use std::io::Cursor;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use tokio::sync::{Mutex, broadcast};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::RTCPeerConnection;
@@ -11,8 +14,9 @@ use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
 use webrtc::sdp::SessionDescription;
 
-use libwish::Client;
+use libwish::{Client, sdpfrag};
 
+use crate::config::Simulcast;
 use crate::forward::internal::PeerForwardInternal;
 use crate::forward::message::{ForwardInfo, Layer};
 use crate::result::Result;
@@ -20,14 +24,27 @@ use crate::{AppError, constant};
 
 use self::media::MediaInfo;
 use self::message::{CascadeInfo, ForwardEvent};
+use self::nack::LocalStreamWriters;
+use self::network::Network;
 
+mod gop;
+mod health;
 mod internal;
+mod keyframe;
 mod media;
 pub mod message;
+mod nack;
+pub mod network;
 mod publish;
+mod room;
 pub mod rtcp;
+mod simulcast;
+mod stats;
 mod subscribe;
+mod timestamp;
 mod track;
+pub(crate) use self::track::OutputTrack;
+use chrono::Utc;
 use md5::{Digest, Md5};
 
 pub(crate) fn get_peer_id(peer: &Arc<RTCPeerConnection>) -> String {
@@ -37,6 +54,68 @@ pub(crate) fn get_peer_id(peer: &Arc<RTCPeerConnection>) -> String {
     format!("{digest:x}")
 }
 
+/// Limits a session is admitted with
+#[derive(Clone, Debug, Default)]
+pub struct Admitted {
+    /// Subscribers the stream accepts, set by a publisher for the following subscribers
+    pub max_subscribers: Option<usize>,
+    /// Unix timestamp in milliseconds the session is closed at
+    pub expires_at: Option<i64>,
+}
+
+/// A reader of the stream without a WebRTC peer, counted as a subscriber until dropped
+pub(crate) struct OutputSession {
+    internal: Weak<PeerForwardInternal>,
+    session: String,
+    expires_at: Option<i64>,
+}
+
+impl OutputSession {
+    pub(crate) fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Resolves once the session expires, never without `expires_at`
+    pub(crate) async fn expired(&self) {
+        match self.expires_at {
+            Some(expires_at) => {
+                let delay = (expires_at - Utc::now().timestamp_millis()).max(0) as u64;
+                tokio::time::sleep(Duration::from_millis(delay)).await
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now().timestamp_millis())
+    }
+}
+
+impl Drop for OutputSession {
+    fn drop(&mut self) {
+        if let Some(internal) = self.internal.upgrade() {
+            internal.remove_output_session(&self.session);
+        }
+    }
+}
+
+/// Decides on a new session by its id before the offer is answered
+pub type Admission =
+    Box<dyn FnOnce(String) -> Pin<Box<dyn Future<Output = Result<Admitted>> + Send>> + Send>;
+
+#[derive(Clone)]
+pub struct ForwardConfig {
+    pub ice_servers: Vec<RTCIceServer>,
+    pub simulcast: Simulcast,
+    /// Maximum packets of the GOP cache, `None` disables it
+    pub gop_cache: Option<usize>,
+    /// More than one makes the stream a room
+    pub max_publishers: usize,
+    pub health: crate::config::Health,
+    pub network: Network,
+}
+
 #[derive(Clone)]
 pub struct PeerForward {
     pub(crate) stream: String,
@@ -53,12 +132,39 @@ pub struct AudioTrackInfo {
     pub fmtp: String,
 }
 
+#[cfg(feature = "recorder")]
+impl AudioTrackInfo {
+    /// The codec the recorder writes, None for the ones it doesn't support
+    pub fn codec(&self) -> Option<crate::codec::AudioCodec> {
+        crate::codec::AudioCodec::from_mime(&self.codec_mime)
+    }
+}
+
 impl PeerForward {
-    pub fn new(stream: impl ToString, ice_server: Vec<RTCIceServer>) -> Self {
+    pub fn new(stream: impl ToString, config: ForwardConfig) -> Self {
+        let internal = Arc::new(PeerForwardInternal::new(stream.to_string(), config));
+        tokio::spawn(Self::health_watch(Arc::downgrade(&internal)));
         PeerForward {
             stream: stream.to_string(),
             publish_lock: Arc::new(Mutex::new(())),
-            internal: Arc::new(PeerForwardInternal::new(stream, ice_server)),
+            internal,
+        }
+    }
+
+    /// Watch the health of the publish tracks until the forward is dropped
+    async fn health_watch(internal: Weak<PeerForwardInternal>) {
+        let interval = match internal.upgrade() {
+            Some(internal) if internal.health.interval > 0 => {
+                Duration::from_millis(internal.health.interval)
+            }
+            _ => return,
+        };
+        loop {
+            tokio::time::sleep(interval).await;
+            match internal.upgrade() {
+                Some(internal) => internal.health_check().await,
+                None => break,
+            }
         }
     }
 
@@ -76,10 +182,58 @@ impl PeerForward {
             .await
     }
 
+    pub async fn ice_etag(&self, session: String) -> Result<String> {
+        self.internal.ice_etag(session).await
+    }
+
+    /// Restart ICE with the credentials and candidates of a trickle-ice-sdpfrag,
+    /// returns the trickle-ice-sdpfrag of the new local credentials and candidates
+    pub async fn ice_restart(&self, session: String, fragment: String) -> Result<String> {
+        let description = self.internal.ice_restart(session, fragment).await?;
+        Ok(sdpfrag::sdpfrag(&description.sdp)?)
+    }
+
     pub async fn remove_peer(&self, session: String) -> Result<bool> {
         self.internal.remove_peer(session).await
     }
 
+    /// Run the admission of a new peer, it is closed when not admitted
+    async fn admit(
+        &self,
+        peer: &Arc<RTCPeerConnection>,
+        admission: Option<Admission>,
+    ) -> Result<Admitted> {
+        let Some(admission) = admission else {
+            return Ok(Admitted::default());
+        };
+        match admission(get_peer_id(peer)).await {
+            Ok(admitted) => Ok(admitted),
+            Err(err) => {
+                let _ = peer.close().await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Close the session once it expires
+    fn expire(&self, session: String, expires_at: Option<i64>) {
+        let Some(expires_at) = expires_at else {
+            return;
+        };
+        let internal = self.internal.clone();
+        let delay = (expires_at - Utc::now().timestamp_millis()).max(0) as u64;
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            info!("[{}] [{}] session expired", internal.stream, session);
+            if let Err(err) = internal.remove_peer(session).await {
+                warn!(
+                    "[{}] expired session close error: {:?}",
+                    internal.stream, err
+                );
+            }
+        });
+    }
+
     pub async fn close(&self) -> Result<()> {
         self.internal.close().await?;
         Ok(())
@@ -88,53 +242,123 @@ impl PeerForward {
     pub async fn info(&self) -> ForwardInfo {
         self.internal.info().await
     }
+
+    /// Tracks of the publisher, read by the outputs without a WebRTC peer
+    pub(crate) async fn output_tracks(&self) -> Vec<OutputTrack> {
+        self.internal.output_tracks().await
+    }
+
+    /// Count an admitted reader without a WebRTC peer, as long as the session is kept
+    pub(crate) async fn add_output(
+        &self,
+        session: String,
+        admitted: Admitted,
+    ) -> Result<OutputSession> {
+        self.internal
+            .add_output_session(session.clone(), admitted.max_subscribers)
+            .await?;
+        Ok(OutputSession {
+            internal: Arc::downgrade(&self.internal),
+            session,
+            expires_at: admitted.expires_at,
+        })
+    }
+
+    /// `info` with the transport stats of every session
+    pub async fn info_with_stats(&self) -> ForwardInfo {
+        self.internal.info_with_stats().await
+    }
 }
 
 // publish
 impl PeerForward {
+    /// `loopback` for the inputs in the process, their peer has loopback candidates only
     pub async fn set_publish(
         &self,
         offer: RTCSessionDescription,
+        admission: Option<Admission>,
+        loopback: bool,
     ) -> Result<(RTCSessionDescription, String)> {
-        if self.internal.publish_is_some().await {
+        if self.internal.publish_is_full().await {
             return Err(AppError::stream_already_exists(
                 "A connection has already been established",
             ));
         }
         let _ = self.publish_lock.lock().await;
-        if self.internal.publish_is_some().await {
+        if self.internal.publish_is_full().await {
             return Err(AppError::stream_already_exists(
                 "A connection has already been established",
             ));
         }
         let peer = self
-            .new_publish_peer(MediaInfo::try_from(offer.unmarshal()?)?)
+            .new_publish_peer(MediaInfo::try_from(offer.unmarshal()?)?, false, loopback)
             .await?;
+        let admitted = self.admit(&peer, admission).await?;
         let description = peer_complete(offer, peer.clone()).await?;
         self.internal.set_publish(peer.clone(), None).await?;
+        self.internal
+            .set_max_subscribers(admitted.max_subscribers)
+            .await;
         let session = get_peer_id(&peer);
+        self.expire(session.clone(), admitted.expires_at);
+        Ok((description, session))
+    }
+
+    /// Register a hot standby, promoted when the publisher leaves.
+    /// Without a publisher it simply becomes the publisher.
+    pub async fn set_standby(
+        &self,
+        offer: RTCSessionDescription,
+        admission: Option<Admission>,
+    ) -> Result<(RTCSessionDescription, String)> {
+        if self.internal.is_room() {
+            return Err(AppError::throw("standby is not supported in room mode"));
+        }
+        if !self.internal.publish_is_full().await {
+            return self.set_publish(offer, admission, false).await;
+        }
+        if self.internal.has_standby().await {
+            return Err(AppError::stream_already_exists(
+                "A standby has already been established",
+            ));
+        }
+        let peer = self
+            .new_publish_peer(MediaInfo::try_from(offer.unmarshal()?)?, true, false)
+            .await?;
+        let admitted = self.admit(&peer, admission).await?;
+        let description = peer_complete(offer, peer.clone()).await?;
+        if let Err(err) = self.internal.set_standby(peer.clone()).await {
+            let _ = peer.close().await;
+            return Err(err);
+        }
+        let session = get_peer_id(&peer);
+        self.expire(session.clone(), admitted.expires_at);
         Ok((description, session))
     }
 
     pub async fn publish_pull(&self, src: String, token: Option<String>) -> Result<()> {
-        if self.internal.publish_is_some().await {
+        if self.internal.publish_is_full().await {
             return Err(AppError::stream_already_exists(
                 "A connection has already been established",
             ));
         }
         let _ = self.publish_lock.lock().await;
-        if self.internal.publish_is_some().await {
+        if self.internal.publish_is_full().await {
             return Err(AppError::stream_already_exists(
                 "A connection has already been established",
             ));
         }
         let peer = self
-            .new_publish_peer(MediaInfo {
-                _codec: vec![],
-                video_transceiver: (1, 0, false),
-                audio_transceiver: (1, 0),
-                has_data_channel: false,
-            })
+            .new_publish_peer(
+                MediaInfo {
+                    _codec: vec![],
+                    video_transceiver: (1, 0, false),
+                    audio_transceiver: (1, 0),
+                    has_data_channel: false,
+                },
+                false,
+                false,
+            )
             .await?;
         let offer = peer.create_offer(None).await?;
         let mut gather_complete = peer.gathering_complete_promise().await;
@@ -171,8 +395,13 @@ impl PeerForward {
         }
     }
 
-    async fn new_publish_peer(&self, media_info: MediaInfo) -> Result<Arc<RTCPeerConnection>> {
-        let peer = self.internal.new_publish_peer(media_info).await?;
+    async fn new_publish_peer(
+        &self,
+        media_info: MediaInfo,
+        standby: bool,
+        loopback: bool,
+    ) -> Result<Arc<RTCPeerConnection>> {
+        let peer = self.internal.new_publish_peer(media_info, loopback).await?;
         let internal = Arc::downgrade(&self.internal);
         let pc = Arc::downgrade(&peer);
         peer.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
@@ -202,7 +431,7 @@ impl PeerForward {
         peer.on_track(Box::new(move |track, _, _| {
             if let (Some(internal), Some(pc)) = (internal.upgrade(), pc.upgrade()) {
                 tokio::spawn(async move {
-                    let _ = internal.publish_track_up(pc, track).await;
+                    let _ = internal.publish_track_up(pc, track, standby).await;
                 });
             }
             Box::pin(async {})
@@ -294,17 +523,24 @@ impl PeerForward {
     pub async fn add_subscribe(
         &self,
         offer: RTCSessionDescription,
+        admission: Option<Admission>,
     ) -> Result<(RTCSessionDescription, String)> {
         let media_info = MediaInfo::try_from(offer.unmarshal()?)?;
-        let peer = self.new_subscription_peer(media_info.clone()).await?;
+        let (peer, writers) = self.new_subscription_peer(media_info.clone()).await?;
+        let admitted = self.admit(&peer, admission).await?;
         let (sdp, session) = (
             peer_complete(offer, peer.clone()).await?,
             get_peer_id(&peer),
         );
-        let _ = self
+        if let Err(err) = self
             .internal
-            .add_subscribe(peer.clone(), None, media_info)
-            .await;
+            .add_subscribe((peer.clone(), writers), None, media_info, Some(&admitted))
+            .await
+        {
+            let _ = peer.close().await;
+            return Err(err);
+        }
+        self.expire(session.clone(), admitted.expires_at);
         Ok((sdp, session))
     }
 
@@ -316,7 +552,7 @@ impl PeerForward {
             has_data_channel: false,
         };
 
-        let peer = self.new_subscription_peer(media_info.clone()).await?;
+        let (peer, writers) = self.new_subscription_peer(media_info.clone()).await?;
         let offer: RTCSessionDescription = peer.create_offer(None).await?;
         let mut gather_complete = peer.gathering_complete_promise().await;
         peer.set_local_description(offer).await?;
@@ -333,7 +569,7 @@ impl PeerForward {
             Ok((target_sdp, _)) => {
                 self.internal
                     .add_subscribe(
-                        peer.clone(),
+                        (peer.clone(), writers),
                         Some(CascadeInfo {
                             source_url: None,
                             target_url: Some(dst.clone()),
@@ -341,6 +577,7 @@ impl PeerForward {
                             session_url: client.session_url,
                         }),
                         media_info,
+                        None,
                     )
                     .await?;
                 let _ = peer.set_remote_description(target_sdp).await;
@@ -354,8 +591,11 @@ impl PeerForward {
         }
     }
 
-    async fn new_subscription_peer(&self, media_info: MediaInfo) -> Result<Arc<RTCPeerConnection>> {
-        let peer = self.internal.new_subscription_peer(media_info).await?;
+    async fn new_subscription_peer(
+        &self,
+        media_info: MediaInfo,
+    ) -> Result<(Arc<RTCPeerConnection>, LocalStreamWriters)> {
+        let (peer, writers) = self.internal.new_subscription_peer(media_info).await?;
         let internal = Arc::downgrade(&self.internal);
         let pc = Arc::downgrade(&peer);
         peer.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
@@ -391,12 +631,14 @@ impl PeerForward {
             }
             Box::pin(async {})
         }));
-        Ok(peer)
+        Ok((peer, writers))
     }
 
     pub async fn select_layer(&self, session: String, layer: Option<Layer>) -> Result<()> {
         let rid = if let Some(layer) = layer {
             layer.encoding_id
+        } else if self.internal.simulcast.auto_layer {
+            constant::RID_AUTO.to_string()
         } else {
             self.internal.publish_svc_rids().await?[0].clone()
         };
@@ -405,6 +647,22 @@ impl PeerForward {
             .await
     }
 
+    pub async fn renegotiate_subscribe(
+        &self,
+        session: String,
+        offer: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        self.internal.renegotiate_subscribe(session, offer).await
+    }
+
+    pub async fn select_publishers(
+        &self,
+        session: String,
+        publishers: Option<Vec<String>>,
+    ) -> Result<()> {
+        self.internal.select_publishers(session, publishers).await
+    }
+
     pub async fn change_resource(
         &self,
         session: String,
@@ -491,7 +749,9 @@ fn parse_ice_candidate(content: String) -> Result<Vec<RTCIceCandidateInit>> {
 
 #[cfg(test)]
 mod test {
-    use crate::forward::parse_ice_candidate;
+    use crate::config::{Health, Simulcast, WebRtc};
+    use crate::forward::network::Network;
+    use crate::forward::{Admitted, ForwardConfig, PeerForward, parse_ice_candidate};
 
     #[test]
     fn test_parse_ice_candidate() -> crate::result::Result<()> {
@@ -507,4 +767,40 @@ a=end-of-candidates";
         parse_ice_candidate(body.to_owned())?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_output_max_subscribers() {
+        let forward = PeerForward::new(
+            "output-max-subscribers",
+            ForwardConfig {
+                ice_servers: vec![],
+                simulcast: Simulcast::default(),
+                gop_cache: None,
+                max_publishers: 1,
+                health: Health::default(),
+                network: Network::new(&WebRtc::default()).await.unwrap(),
+            },
+        );
+        let admitted = Admitted {
+            max_subscribers: Some(2),
+            expires_at: None,
+        };
+        // Admitted at once, only two of them fit
+        let admissions: Vec<_> = (0..8)
+            .map(|i| {
+                let (forward, admitted) = (forward.clone(), admitted.clone());
+                tokio::spawn(async move { forward.add_output(format!("{i}"), admitted).await })
+            })
+            .collect();
+        let mut outputs = vec![];
+        for admission in admissions {
+            if let Ok(output) = admission.await.unwrap() {
+                outputs.push(output);
+            }
+        }
+        assert_eq!(outputs.len(), 2);
+
+        outputs.pop();
+        assert!(forward.add_output("8".to_string(), admitted).await.is_ok());
+    }
 }
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use webrtc::interceptor::stream_info::StreamInfo;
+use webrtc::interceptor::{
+    Attributes, Error, Interceptor, InterceptorBuilder, RTCPReader, RTCPWriter, RTPReader,
+    RTPWriter,
+};
+use webrtc::rtcp::packet::Packet as RtcpPacket;
+use webrtc::rtcp::transport_feedbacks::transport_layer_nack::TransportLayerNack;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+
+use super::track::ForwardData;
+
+/// Must divide 65536 so that sequence number wrap-around keeps the slots consistent
+const HISTORY_SIZE: usize = 512;
+
+const MIME_TYPE_RTX: &str = "video/rtx";
+
+/// Recently received packets of a publish track, keyed by their original sequence number
+pub(crate) struct PacketCache {
+    packets: Vec<Option<ForwardData>>,
+}
+
+impl PacketCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            packets: vec![None; HISTORY_SIZE],
+        }
+    }
+
+    pub(crate) fn push(&mut self, packet: ForwardData) {
+        let index = packet.header.sequence_number as usize % HISTORY_SIZE;
+        self.packets[index] = Some(packet);
+    }
+
+    pub(crate) fn get(&self, sequence_number: u16) -> Option<ForwardData> {
+        self.packets[sequence_number as usize % HISTORY_SIZE]
+            .as_ref()
+            .filter(|packet| packet.header.sequence_number == sequence_number)
+            .cloned()
+    }
+}
+
+struct SentPacket {
+    sequence_number: u16,
+    timestamp: u32,
+    original_sequence_number: u16,
+    cache: Arc<Mutex<PacketCache>>,
+}
+
+/// Maps the sequence numbers a subscriber received back to the publish track packets
+pub(crate) struct SentHistory {
+    packets: Vec<Option<SentPacket>>,
+}
+
+impl SentHistory {
+    pub(crate) fn new() -> Self {
+        Self {
+            packets: (0..HISTORY_SIZE).map(|_| None).collect(),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        (sequence_number, timestamp): (u16, u32),
+        original_sequence_number: u16,
+        cache: &Arc<Mutex<PacketCache>>,
+    ) {
+        self.packets[sequence_number as usize % HISTORY_SIZE] = Some(SentPacket {
+            sequence_number,
+            timestamp,
+            original_sequence_number,
+            cache: cache.clone(),
+        });
+    }
+
+    /// The publish track packet sent as `sequence_number`, if it is still cached,
+    /// with the sequence number and timestamp it was sent with
+    pub(crate) fn get(&self, sequence_number: u16) -> Option<Packet> {
+        let sent = self.packets[sequence_number as usize % HISTORY_SIZE]
+            .as_ref()
+            .filter(|sent| sent.sequence_number == sequence_number)?;
+        let mut packet = sent
+            .cache
+            .lock()
+            .unwrap()
+            .get(sent.original_sequence_number)?
+            .as_ref()
+            .clone();
+        packet.header.sequence_number = sent.sequence_number;
+        packet.header.timestamp = sent.timestamp;
+        Some(packet)
+    }
+}
+
+pub(crate) fn lost_sequence_numbers(packets: &[Box<dyn RtcpPacket + Send + Sync>]) -> Vec<u16> {
+    packets
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<TransportLayerNack>())
+        .flat_map(|nack| nack.nacks.iter().flat_map(|pair| pair.packet_list()))
+        .collect()
+}
+
+/// Interceptor that keeps the innermost RTP writer of every local stream,
+/// so that retransmissions can be sent on the RTX SSRC.
+#[derive(Clone, Default)]
+pub(crate) struct LocalStreamWriters {
+    writers: Arc<Mutex<HashMap<u32, Arc<dyn RTPWriter + Send + Sync>>>>,
+}
+
+impl LocalStreamWriters {
+    fn get(&self, ssrc: u32) -> Option<Arc<dyn RTPWriter + Send + Sync>> {
+        self.writers.lock().unwrap().get(&ssrc).cloned()
+    }
+}
+
+impl InterceptorBuilder for LocalStreamWriters {
+    fn build(&self, _id: &str) -> Result<Arc<dyn Interceptor + Send + Sync>, Error> {
+        Ok(Arc::new(self.clone()))
+    }
+}
+
+#[async_trait]
+impl Interceptor for LocalStreamWriters {
+    async fn bind_rtcp_reader(
+        &self,
+        reader: Arc<dyn RTCPReader + Send + Sync>,
+    ) -> Arc<dyn RTCPReader + Send + Sync> {
+        reader
+    }
+
+    async fn bind_rtcp_writer(
+        &self,
+        writer: Arc<dyn RTCPWriter + Send + Sync>,
+    ) -> Arc<dyn RTCPWriter + Send + Sync> {
+        writer
+    }
+
+    async fn bind_local_stream(
+        &self,
+        info: &StreamInfo,
+        writer: Arc<dyn RTPWriter + Send + Sync>,
+    ) -> Arc<dyn RTPWriter + Send + Sync> {
+        self.writers
+            .lock()
+            .unwrap()
+            .insert(info.ssrc, writer.clone());
+        writer
+    }
+
+    async fn unbind_local_stream(&self, info: &StreamInfo) {
+        self.writers.lock().unwrap().remove(&info.ssrc);
+    }
+
+    async fn bind_remote_stream(
+        &self,
+        _info: &StreamInfo,
+        reader: Arc<dyn RTPReader + Send + Sync>,
+    ) -> Arc<dyn RTPReader + Send + Sync> {
+        reader
+    }
+
+    async fn unbind_remote_stream(&self, _info: &StreamInfo) {}
+
+    async fn close(&self) -> Result<(), Error> {
+        self.writers.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// RFC 4588 retransmission stream negotiated for a sender
+pub(crate) struct RtxStream {
+    ssrc: u32,
+    payload_type: u8,
+    sequence_number: u16,
+    writer: Arc<dyn RTPWriter + Send + Sync>,
+}
+
+impl RtxStream {
+    pub(crate) async fn new(sender: &RTCRtpSender, writers: &LocalStreamWriters) -> Option<Self> {
+        let parameters = sender.get_parameters().await;
+        let encoding = parameters.encodings.first()?;
+        if encoding.rtx.ssrc == 0 {
+            return None;
+        }
+        let apt = format!("apt={}", encoding.payload_type);
+        let payload_type = parameters
+            .rtp_parameters
+            .codecs
+            .iter()
+            .find(|codec| {
+                codec
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(MIME_TYPE_RTX)
+                    && codec.capability.sdp_fmtp_line.split(';').any(|p| p == apt)
+            })?
+            .payload_type;
+        Some(Self {
+            ssrc: encoding.rtx.ssrc,
+            payload_type,
+            sequence_number: initial_sequence_number(encoding.rtx.ssrc),
+            writer: writers.get(encoding.ssrc)?,
+        })
+    }
+
+    /// `packet` must already carry the sequence number the subscriber asked for
+    pub(crate) async fn send(&mut self, packet: &Packet) -> Result<usize, Error> {
+        let mut payload = Vec::with_capacity(packet.payload.len() + 2);
+        payload.extend_from_slice(&packet.header.sequence_number.to_be_bytes());
+        payload.extend_from_slice(&packet.payload);
+        let mut rtx = Packet {
+            header: packet.header.clone(),
+            payload: payload.into(),
+        };
+        rtx.header.ssrc = self.ssrc;
+        rtx.header.payload_type = self.payload_type;
+        rtx.header.sequence_number = self.sequence_number;
+        rtx.header.padding = false;
+        rtx.header.extension = false;
+        rtx.header.extensions.clear();
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.writer.write(&rtx, &Attributes::new()).await
+    }
+}
+
+/// SSRCs are random already, derive the unpredictable initial sequence number from it
+fn initial_sequence_number(ssrc: u32) -> u16 {
+    (ssrc ^ (ssrc >> 16)) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use webrtc::rtp::packet::Packet;
+
+    use super::{HISTORY_SIZE, PacketCache, SentHistory};
+
+    fn packet(sequence_number: u16) -> Arc<Packet> {
+        let mut packet = Packet::default();
+        packet.header.sequence_number = sequence_number;
+        Arc::new(packet)
+    }
+
+    #[test]
+    fn test_packet_cache() {
+        let mut cache = PacketCache::new();
+        for seq in 65000u16..=65535 {
+            cache.push(packet(seq));
+        }
+        for seq in 0u16..100 {
+            cache.push(packet(seq));
+        }
+        assert!(cache.get(99).is_some());
+        assert!(cache.get(65535).is_some());
+        // evicted by wrap-around
+        assert!(cache.get(65000).is_none());
+        assert!(cache.get(100).is_none());
+    }
+
+    #[test]
+    fn test_sent_history_rewritten_sequence() {
+        let cache = Arc::new(Mutex::new(PacketCache::new()));
+        let mut history = SentHistory::new();
+        for (sent, original) in (0u16..10).zip(3000u16..) {
+            cache.lock().unwrap().push(packet(original));
+            history.record((sent, sent as u32 * 3000), original, &cache);
+        }
+        let packet = history.get(4).unwrap();
+        assert_eq!(packet.header.sequence_number, 4);
+        assert_eq!(packet.header.timestamp, 12000);
+        assert!(history.get(10).is_none());
+        assert!(history.get(4 + HISTORY_SIZE as u16).is_none());
+    }
+}
@@ -0,0 +1,69 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use tokio::net::UdpSocket;
+use tracing::info;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::ice::network_type::NetworkType;
+use webrtc::ice::udp_mux::{UDPMux, UDPMuxDefault, UDPMuxParams};
+use webrtc::ice::udp_network::{EphemeralUDP, UDPNetwork};
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+
+use crate::config::WebRtc;
+use crate::result::Result;
+
+/// ICE network settings shared by every peer connection
+#[derive(Clone)]
+pub struct Network {
+    udp_mux: Option<Arc<dyn UDPMux + Send + Sync>>,
+    port_range: Option<(u16, u16)>,
+    nat_1to1_ips: Vec<String>,
+}
+
+impl Network {
+    pub async fn new(cfg: &WebRtc) -> Result<Self> {
+        let udp_mux = match cfg.udp_mux_port {
+            Some(port) => {
+                let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
+                info!("webrtc udp mux listen: {}", socket.local_addr()?);
+                let udp_mux: Arc<dyn UDPMux + Send + Sync> =
+                    UDPMuxDefault::new(UDPMuxParams::new(socket));
+                Some(udp_mux)
+            }
+            None => None,
+        };
+        let port_range = (cfg.port_min != 0 || cfg.port_max != 0).then_some((
+            cfg.port_min,
+            if cfg.port_max == 0 {
+                u16::MAX
+            } else {
+                cfg.port_max
+            },
+        ));
+        Ok(Self {
+            udp_mux,
+            port_range,
+            nat_1to1_ips: cfg.nat_1to1_ips.clone(),
+        })
+    }
+
+    pub(crate) fn apply(&self, s: &mut SettingEngine) -> Result<()> {
+        if let Some(udp_mux) = &self.udp_mux {
+            s.set_udp_network(UDPNetwork::Muxed(udp_mux.clone()));
+        } else if let Some((min, max)) = self.port_range {
+            s.set_udp_network(UDPNetwork::Ephemeral(EphemeralUDP::new(min, max)?));
+        }
+        if !self.nat_1to1_ips.is_empty() {
+            s.set_nat_1to1_ips(self.nat_1to1_ips.clone(), RTCIceCandidateType::Host);
+        }
+        Ok(())
+    }
+
+    /// Host candidates of the loopback interface only, for the peers of the inputs in the
+    /// process, they connect whatever the UDP mux and NAT 1:1 settings are
+    pub(crate) fn apply_loopback(s: &mut SettingEngine) {
+        s.set_include_loopback_candidate(true);
+        s.set_ip_filter(Box::new(|ip: IpAddr| ip.is_loopback()));
+        s.set_network_types(vec![NetworkType::Udp4]);
+    }
+}
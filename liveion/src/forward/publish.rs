@@ -1,4 +1,4 @@
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, Weak};
 
 use anyhow::{Result, anyhow};
 use chrono::Utc;
@@ -6,12 +6,14 @@ use tokio::sync::broadcast;
 use tracing::debug;
 use webrtc::peer_connection::RTCPeerConnection;
 
-use crate::forward::message::SessionInfo;
+use crate::forward::message::{SessionInfo, SessionStats};
 use crate::forward::rtcp::RtcpMessage;
 
 use super::get_peer_id;
 use super::media::MediaInfo;
 use super::message::CascadeInfo;
+use super::stats::{BitrateSample, peer_stats};
+use super::track::PublishTrackRemote;
 
 pub(crate) struct PublishRTCPeerConnection {
     pub(crate) id: String,
@@ -19,6 +21,7 @@ pub(crate) struct PublishRTCPeerConnection {
     pub(crate) media_info: MediaInfo,
     pub(crate) create_at: i64,
     pub(crate) cascade: Option<CascadeInfo>,
+    bitrate_sample: Mutex<BitrateSample>,
 }
 
 impl PublishRTCPeerConnection {
@@ -43,6 +46,7 @@ impl PublishRTCPeerConnection {
             media_info,
             create_at: Utc::now().timestamp_millis(),
             cascade,
+            bitrate_sample: Mutex::new(BitrateSample::new()),
         })
     }
 
@@ -53,9 +57,22 @@ impl PublishRTCPeerConnection {
             state: self.peer.connection_state(),
             cascade: self.cascade.clone(),
             has_data_channel: self.media_info.has_data_channel,
+            stats: None,
+            health: vec![],
         }
     }
 
+    /// Transport stats, loss and jitter are measured on the received `tracks` of this session
+    pub(crate) async fn stats(&self, tracks: &[PublishTrackRemote]) -> SessionStats {
+        let mut stats = peer_stats(&self.peer, &self.bitrate_sample).await;
+        for track in tracks.iter().filter(|track| track.publish_id == self.id) {
+            let (lost, jitter) = track.reception();
+            stats.packets_lost += lost;
+            stats.jitter = stats.jitter.max(jitter);
+        }
+        stats
+    }
+
     async fn peer_send_rtcp(
         path: String,
         id: String,
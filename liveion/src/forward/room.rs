@@ -0,0 +1,60 @@
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+/// The publisher forwarded by the `index`-th sender of `kind`:
+/// publishers having a track of `kind` are taken in track order, optionally limited to `selected`.
+/// Outside room mode there is one publisher and one sender per kind, so index 0 picks it.
+pub(crate) fn slot_publisher<'a>(
+    tracks: impl IntoIterator<Item = (RTPCodecType, &'a String)>,
+    kind: RTPCodecType,
+    index: usize,
+    selected: Option<&Vec<String>>,
+) -> Option<String> {
+    let mut publishers: Vec<&String> = vec![];
+    for (track_kind, publisher) in tracks {
+        if track_kind == kind
+            && !publishers.contains(&publisher)
+            && selected.is_none_or(|selected| selected.contains(publisher))
+        {
+            publishers.push(publisher);
+        }
+    }
+    publishers.get(index).map(|publisher| publisher.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+    use super::slot_publisher;
+
+    #[test]
+    fn test_slot_publisher() {
+        let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+        let tracks = vec![
+            (RTPCodecType::Video, &a),
+            (RTPCodecType::Video, &a),
+            (RTPCodecType::Audio, &a),
+            (RTPCodecType::Audio, &b),
+            (RTPCodecType::Video, &c),
+            (RTPCodecType::Audio, &c),
+        ];
+        let video = |index| slot_publisher(tracks.clone(), RTPCodecType::Video, index, None);
+        assert_eq!(video(0), Some(a.clone()));
+        assert_eq!(video(1), Some(c.clone()));
+        assert_eq!(video(2), None);
+        assert_eq!(
+            slot_publisher(tracks.clone(), RTPCodecType::Audio, 1, None),
+            Some(b.clone())
+        );
+
+        let selected = vec![c.clone()];
+        assert_eq!(
+            slot_publisher(tracks.clone(), RTPCodecType::Audio, 0, Some(&selected)),
+            Some(c.clone())
+        );
+        assert_eq!(
+            slot_publisher(tracks, RTPCodecType::Audio, 1, Some(&selected)),
+            None
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use webrtc::rtcp::packet::Packet;
 use webrtc::rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
 use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
 use webrtc::rtcp::payload_feedbacks::slice_loss_indication::SliceLossIndication;
+use webrtc::rtcp::receiver_report::ReceiverReport;
+use webrtc::rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolTypeTcc, TransportLayerCc,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub enum RtcpMessage {
@@ -42,3 +47,68 @@ impl RtcpMessage {
         }
     }
 }
+
+/// Latest congestion feedback a subscriber reported for the stream we send to it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct BandwidthEstimate {
+    /// bits per second, from REMB
+    pub(crate) bitrate: Option<u64>,
+    /// 0.0 - 1.0, from receiver reports or transport-cc feedback
+    pub(crate) fraction_lost: f32,
+    /// Worst interarrival jitter from receiver reports, in timestamp units
+    pub(crate) jitter: u32,
+}
+
+impl BandwidthEstimate {
+    /// Returns true if the packet carried bandwidth or loss information
+    pub(crate) fn update(&mut self, packet: &(dyn Packet + Send + Sync)) -> bool {
+        let any = packet.as_any();
+        if let Some(remb) = any.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+            self.bitrate = Some(remb.bitrate as u64);
+            return true;
+        }
+        if let Some(rr) = any.downcast_ref::<ReceiverReport>() {
+            if let Some(worst) = rr.reports.iter().map(|r| r.fraction_lost).max() {
+                self.fraction_lost = worst as f32 / 256.0;
+                self.jitter = rr
+                    .reports
+                    .iter()
+                    .map(|r| r.jitter)
+                    .max()
+                    .unwrap_or_default();
+                return true;
+            }
+            return false;
+        }
+        if let Some(twcc) = any.downcast_ref::<TransportLayerCc>() {
+            if twcc.packet_status_count == 0 {
+                return false;
+            }
+            let mut lost = 0u32;
+            let mut total = 0u32;
+            for chunk in twcc.packet_chunks.iter() {
+                match chunk {
+                    PacketStatusChunk::RunLengthChunk(c) => {
+                        total += c.run_length as u32;
+                        if c.packet_status_symbol == SymbolTypeTcc::PacketNotReceived {
+                            lost += c.run_length as u32;
+                        }
+                    }
+                    PacketStatusChunk::StatusVectorChunk(c) => {
+                        total += c.symbol_list.len() as u32;
+                        lost += c
+                            .symbol_list
+                            .iter()
+                            .filter(|s| **s == SymbolTypeTcc::PacketNotReceived)
+                            .count() as u32;
+                    }
+                }
+            }
+            // The last chunk may be padded past packet_status_count
+            let total = total.min(twcc.packet_status_count as u32).max(1);
+            self.fraction_lost = lost.min(total) as f32 / total as f32;
+            return true;
+        }
+        false
+    }
+}
@@ -0,0 +1,178 @@
+use std::time::{Duration, Instant};
+
+use crate::config::Simulcast;
+
+use super::rtcp::BandwidthEstimate;
+
+/// The estimate must exceed the next layer's bitrate by this factor before switching up
+const UPGRADE_HEADROOM: f64 = 1.2;
+
+/// Decides when a subscriber should move between simulcast layers.
+/// Layers are indexed from the lowest to the highest bitrate.
+pub(crate) struct LayerController {
+    upgrade_hold: Duration,
+    downgrade_hold: Duration,
+    loss_threshold: f32,
+    upgrade_since: Option<Instant>,
+    downgrade_since: Option<Instant>,
+}
+
+impl LayerController {
+    pub(crate) fn new(cfg: &Simulcast) -> Self {
+        Self {
+            upgrade_hold: Duration::from_millis(cfg.upgrade_hold),
+            downgrade_hold: Duration::from_millis(cfg.downgrade_hold),
+            loss_threshold: cfg.loss_threshold,
+            upgrade_since: None,
+            downgrade_since: None,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.upgrade_since = None;
+        self.downgrade_since = None;
+    }
+
+    /// `bitrates` are the measured bitrates of the active layers, ascending.
+    /// Returns the index of the layer to switch to, if any.
+    pub(crate) fn decide(
+        &mut self,
+        now: Instant,
+        estimate: &BandwidthEstimate,
+        current: usize,
+        bitrates: &[u64],
+    ) -> Option<usize> {
+        let current_bitrate = *bitrates.get(current)?;
+        let lossy = estimate.fraction_lost > self.loss_threshold;
+        let congested = lossy || estimate.bitrate.is_some_and(|b| b < current_bitrate);
+        if congested && current > 0 {
+            self.upgrade_since = None;
+            let since = *self.downgrade_since.get_or_insert(now);
+            if now.duration_since(since) >= self.downgrade_hold {
+                self.reset();
+                return Some(current - 1);
+            }
+            return None;
+        }
+        self.downgrade_since = None;
+
+        let headroom = match (bitrates.get(current + 1), estimate.bitrate) {
+            (Some(next), Some(bitrate)) => {
+                !lossy
+                    && estimate.fraction_lost <= self.loss_threshold / 2.0
+                    && bitrate as f64 >= *next as f64 * UPGRADE_HEADROOM
+            }
+            _ => false,
+        };
+        if !headroom {
+            self.upgrade_since = None;
+            return None;
+        }
+        let since = *self.upgrade_since.get_or_insert(now);
+        if now.duration_since(since) >= self.upgrade_hold {
+            self.reset();
+            return Some(current + 1);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::LayerController;
+    use crate::config::Simulcast;
+    use crate::forward::rtcp::BandwidthEstimate;
+
+    const LAYERS: [u64; 3] = [150_000, 500_000, 1_500_000];
+
+    fn estimate(bitrate: u64, fraction_lost: f32) -> BandwidthEstimate {
+        BandwidthEstimate {
+            bitrate: Some(bitrate),
+            fraction_lost,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_upgrade_after_hold() {
+        let mut c = LayerController::new(&Simulcast::default());
+        let start = Instant::now();
+        let good = estimate(1_000_000, 0.0);
+        assert_eq!(c.decide(start, &good, 0, &LAYERS), None);
+        assert_eq!(
+            c.decide(start + Duration::from_secs(2), &good, 0, &LAYERS),
+            None
+        );
+        assert_eq!(
+            c.decide(start + Duration::from_secs(5), &good, 0, &LAYERS),
+            Some(1)
+        );
+        // not enough for the top layer
+        assert_eq!(
+            c.decide(start + Duration::from_secs(20), &good, 1, &LAYERS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_upgrade_interrupted() {
+        let mut c = LayerController::new(&Simulcast::default());
+        let start = Instant::now();
+        let good = estimate(1_000_000, 0.0);
+        c.decide(start, &good, 0, &LAYERS);
+        c.decide(
+            start + Duration::from_secs(3),
+            &estimate(400_000, 0.0),
+            0,
+            &LAYERS,
+        );
+        assert_eq!(
+            c.decide(start + Duration::from_secs(5), &good, 0, &LAYERS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_downgrade_on_loss() {
+        let mut c = LayerController::new(&Simulcast::default());
+        let start = Instant::now();
+        let lossy = estimate(5_000_000, 0.3);
+        assert_eq!(c.decide(start, &lossy, 2, &LAYERS), None);
+        assert_eq!(
+            c.decide(start + Duration::from_secs(1), &lossy, 2, &LAYERS),
+            Some(1)
+        );
+        // already at the bottom
+        assert_eq!(
+            c.decide(start + Duration::from_secs(10), &lossy, 0, &LAYERS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_downgrade_on_remb() {
+        let mut c = LayerController::new(&Simulcast::default());
+        let start = Instant::now();
+        let low = estimate(300_000, 0.0);
+        c.decide(start, &low, 1, &LAYERS);
+        assert_eq!(
+            c.decide(start + Duration::from_millis(1500), &low, 1, &LAYERS),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_no_estimate() {
+        let mut c = LayerController::new(&Simulcast::default());
+        let start = Instant::now();
+        let none = BandwidthEstimate::default();
+        for i in 0..10 {
+            assert_eq!(
+                c.decide(start + Duration::from_secs(i), &none, 1, &LAYERS),
+                None
+            );
+        }
+    }
+}
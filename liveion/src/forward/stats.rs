@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::{ICECandidateStats, StatsReportType};
+
+use super::message::SessionStats;
+
+/// RTP bytes counted at the previous stats collection, the bitrates are averaged since then
+pub(crate) struct BitrateSample {
+    at: Instant,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl BitrateSample {
+    pub(crate) fn new() -> Self {
+        Self {
+            at: Instant::now(),
+            bytes_in: 0,
+            bytes_out: 0,
+        }
+    }
+
+    /// Bits per second in and out since the previous sample
+    fn update(&mut self, now: Instant, bytes_in: u64, bytes_out: u64) -> (u64, u64) {
+        let elapsed = now.duration_since(self.at).as_millis().max(1) as u64;
+        let bitrate =
+            |bytes: u64, previous: u64| bytes.saturating_sub(previous) * 8 * 1000 / elapsed;
+        let bitrates = (
+            bitrate(bytes_in, self.bytes_in),
+            bitrate(bytes_out, self.bytes_out),
+        );
+        *self = Self {
+            at: now,
+            bytes_in,
+            bytes_out,
+        };
+        bitrates
+    }
+}
+
+/// Transport stats of a session from the stats report of its peer connection
+pub(crate) async fn peer_stats(
+    peer: &RTCPeerConnection,
+    sample: &Mutex<BitrateSample>,
+) -> SessionStats {
+    let report = peer.get_stats().await;
+    let mut stats = SessionStats::default();
+    let (mut bytes_in, mut bytes_out) = (0, 0);
+    let mut candidate_pair = None;
+    let mut candidates: HashMap<&String, &ICECandidateStats> = HashMap::new();
+    for report in report.reports.values() {
+        match report {
+            StatsReportType::InboundRTP(inbound) => {
+                bytes_in += inbound.header_bytes_received + inbound.bytes_received;
+                stats.nack_count += inbound.nack_count;
+                stats.pli_count += inbound.pli_count.unwrap_or_default();
+                stats.fir_count += inbound.fir_count.unwrap_or_default();
+            }
+            StatsReportType::OutboundRTP(outbound) => {
+                bytes_out += outbound.header_bytes_sent + outbound.bytes_sent;
+                stats.nack_count += outbound.nack_count;
+                stats.pli_count += outbound.pli_count.unwrap_or_default();
+                stats.fir_count += outbound.fir_count.unwrap_or_default();
+            }
+            // What the subscriber reports about the media we send
+            StatsReportType::RemoteInboundRTP(remote) => {
+                stats.packets_lost += remote.packets_lost;
+                stats.fraction_lost = stats
+                    .fraction_lost
+                    .max((remote.fraction_lost * 256.0).min(255.0) as u8);
+                if let Some(rtt) = remote.round_trip_time {
+                    stats.rtt = stats.rtt.max(Some((rtt * 1000.0) as u32));
+                }
+            }
+            StatsReportType::CandidatePair(pair) if pair.nominated => {
+                candidate_pair = Some(pair);
+            }
+            StatsReportType::LocalCandidate(candidate)
+            | StatsReportType::RemoteCandidate(candidate) => {
+                candidates.insert(&candidate.id, candidate);
+            }
+            _ => {}
+        }
+    }
+    if let Some(pair) = candidate_pair {
+        if stats.rtt.is_none() && pair.current_round_trip_time > 0.0 {
+            stats.rtt = Some((pair.current_round_trip_time * 1000.0) as u32);
+        }
+        let candidate = |id| {
+            candidates.get(id).map(|candidate| {
+                format!(
+                    "{} {} {}:{}",
+                    candidate.candidate_type, candidate.network_type, candidate.ip, candidate.port
+                )
+            })
+        };
+        stats.local_candidate = candidate(&pair.local_candidate_id);
+        stats.remote_candidate = candidate(&pair.remote_candidate_id);
+    }
+    (stats.bitrate_in, stats.bitrate_out) =
+        sample
+            .lock()
+            .unwrap()
+            .update(Instant::now(), bytes_in, bytes_out);
+    stats
+}
+
+/// Packet loss and interarrival jitter of a received RTP stream, as in RFC 3550 A.3 and A.8
+pub(crate) struct ReceptionStats {
+    clock_rate: u32,
+    start: Instant,
+    /// First and highest extended sequence numbers
+    base: Option<u64>,
+    highest: u64,
+    received: u64,
+    transit: Option<u32>,
+    /// In timestamp units
+    jitter: f64,
+}
+
+impl ReceptionStats {
+    pub(crate) fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate,
+            start: Instant::now(),
+            base: None,
+            highest: 0,
+            received: 0,
+            transit: None,
+            jitter: 0.0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, sequence_number: u16, timestamp: u32, arrival: Instant) {
+        match self.base {
+            None => {
+                self.highest = sequence_number as u64;
+                self.base = Some(self.highest);
+            }
+            Some(_) => {
+                let delta = sequence_number.wrapping_sub(self.highest as u16) as i16;
+                let extended = self.highest as i64 + delta as i64;
+                if extended > self.highest as i64 {
+                    self.highest = extended as u64;
+                }
+            }
+        }
+        self.received += 1;
+
+        let arrival = (arrival.duration_since(self.start).as_secs_f64() * self.clock_rate as f64)
+            as u64 as u32;
+        let transit = arrival.wrapping_sub(timestamp);
+        if let Some(previous) = self.transit {
+            let d = transit.wrapping_sub(previous) as i32;
+            self.jitter += (d.unsigned_abs() as f64 - self.jitter) / 16.0;
+        }
+        self.transit = Some(transit);
+    }
+
+    /// Packets expected but not received, negative with duplicates
+    pub(crate) fn lost(&self) -> i64 {
+        match self.base {
+            Some(base) => (self.highest - base + 1) as i64 - self.received as i64,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn jitter_ms(&self) -> u32 {
+        if self.clock_rate == 0 {
+            return 0;
+        }
+        (self.jitter * 1000.0 / self.clock_rate as f64) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{BitrateSample, ReceptionStats};
+
+    #[test]
+    fn test_reception_loss() {
+        let mut stats = ReceptionStats::new(90000);
+        let now = Instant::now();
+        for sequence_number in [65534u16, 65535, 1, 2, 2, 5] {
+            stats.update(sequence_number, 0, now);
+        }
+        // 65534..=5 expects 8 packets, 6 arrived with a duplicate
+        assert_eq!(stats.lost(), 2);
+        // reordered packets don't move the highest sequence number back
+        stats.update(0, 0, now);
+        assert_eq!(stats.lost(), 1);
+    }
+
+    #[test]
+    fn test_reception_jitter() {
+        let mut stats = ReceptionStats::new(90000);
+        let start = stats.start;
+        // 30 fps, evenly paced
+        for frame in 0..100u32 {
+            let arrival = start + Duration::from_micros(33_333 * frame as u64);
+            stats.update(frame as u16, 1000 + frame * 3000, arrival);
+        }
+        assert_eq!(stats.jitter_ms(), 0);
+
+        // every other frame 20ms late
+        for frame in 100..300u32 {
+            let late = if frame % 2 == 0 { 20_000 } else { 0 };
+            let arrival = start + Duration::from_micros(33_333 * frame as u64 + late);
+            stats.update(frame as u16, 1000 + frame * 3000, arrival);
+        }
+        assert!((15..=20).contains(&stats.jitter_ms()));
+    }
+
+    #[test]
+    fn test_bitrate_sample() {
+        let mut sample = BitrateSample::new();
+        let now = sample.at + Duration::from_secs(2);
+        assert_eq!(sample.update(now, 250_000, 500_000), (1_000_000, 2_000_000));
+        let now = now + Duration::from_millis(500);
+        assert_eq!(sample.update(now, 250_000, 562_500), (0, 1_000_000));
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use tokio::sync::{RwLock, broadcast};
+use tokio::sync::{RwLock, broadcast, mpsc, watch};
 use tracing::{debug, info, trace};
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
@@ -10,23 +11,68 @@ use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
 use webrtc::track::track_local::TrackLocalWriter;
 use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 
+use crate::config::Simulcast;
 use crate::error::AppError;
-use crate::forward::message::SessionInfo;
-use crate::forward::rtcp::RtcpMessage;
-use crate::new_broadcast_channel;
+use crate::forward::message::{SessionInfo, SessionStats};
+use crate::forward::rtcp::{BandwidthEstimate, RtcpMessage};
 use crate::{constant, result::Result};
+use crate::{metrics, new_broadcast_channel};
 
 use super::get_peer_id;
+use super::gop::replay_timestamps;
+use super::keyframe::is_keyframe;
 use super::media::MediaInfo;
 use super::message::CascadeInfo;
-use super::track::PublishTrackRemote;
+use super::nack::{LocalStreamWriters, PacketCache, RtxStream, SentHistory, lost_sequence_numbers};
+use super::room::slot_publisher;
+use super::simulcast::LayerController;
+use super::stats::{BitrateSample, peer_stats};
+use super::timestamp::TimestampRewriter;
+use super::track::{ForwardData, PublishTrackRemote};
 
 type SelectLayerBody = (RTPCodecType, String);
 
+const LAYER_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
 struct SubscribeForwardChannel {
     publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
     select_layer_recv: broadcast::Receiver<SelectLayerBody>,
     publish_track_change: broadcast::Receiver<()>,
+    bandwidth_estimate: watch::Receiver<BandwidthEstimate>,
+    nack_recv: mpsc::Receiver<Vec<u16>>,
+}
+
+/// A simulcast layer we switch to once its next keyframe arrives
+struct PendingLayer {
+    rid: String,
+    mime_type: String,
+    clock_rate: u32,
+    ssrc: u32,
+    recv: broadcast::Receiver<ForwardData>,
+    cache: Arc<Mutex<PacketCache>>,
+}
+
+/// Local track forwarding `publish_track`, room mode tags it with the publisher session as msid stream id
+fn new_local_track(room: bool, publish_track: &PublishTrackRemote) -> Arc<TrackLocalStaticRTP> {
+    let stream_id = if room {
+        publish_track.publish_id.clone()
+    } else {
+        format!("{}-{}", "webrtc", publish_track.kind)
+    };
+    Arc::new(TrackLocalStaticRTP::new(
+        publish_track.track.codec().capability,
+        "webrtc".to_string(),
+        stream_id,
+    ))
+}
+
+async fn recv_pending(
+    pending: &mut Option<PendingLayer>,
+) -> std::result::Result<ForwardData, broadcast::error::RecvError> {
+    match pending {
+        Some(layer) => layer.recv.recv().await,
+        None => std::future::pending().await,
+    }
 }
 
 pub(crate) struct SubscribeRTCPeerConnection {
@@ -34,63 +80,130 @@ pub(crate) struct SubscribeRTCPeerConnection {
     pub(crate) cascade: Option<CascadeInfo>,
     pub(crate) peer: Arc<RTCPeerConnection>,
     pub(crate) create_at: i64,
-    select_layer_sender: broadcast::Sender<SelectLayerBody>,
     pub(crate) media_info: MediaInfo,
+    context: SubscribeContext,
+    senders: Mutex<Vec<SenderState>>,
+    bitrate_sample: Mutex<BitrateSample>,
+}
+
+/// What the stats of a sender are read from
+#[derive(Clone)]
+struct SenderState {
+    kind: RTPCodecType,
+    sender: Arc<RTCRtpSender>,
+    track_binding_publish_rid: Arc<RwLock<HashMap<String, String>>>,
+    bandwidth_estimate: watch::Receiver<BandwidthEstimate>,
+}
+
+/// A sender forwarding the `index`-th publisher of its kind,
+/// only room mode has more than one sender per kind
+pub(crate) struct SenderSlot {
+    pub(crate) kind: RTPCodecType,
+    pub(crate) index: usize,
+    pub(crate) sender: Arc<RTCRtpSender>,
+}
+
+/// Shared by the forwarding tasks of all senders of a subscriber
+#[derive(Clone)]
+struct SubscribeContext {
+    stream: String,
+    id: String,
+    room: bool,
+    writers: LocalStreamWriters,
+    publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
+    publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
+    publish_track_change: broadcast::Sender<()>,
+    select_layer_sender: broadcast::Sender<SelectLayerBody>,
+    /// Publishers to receive in room mode, `None` for all of them
+    publishers: Arc<RwLock<Option<Vec<String>>>>,
+    simulcast: Simulcast,
 }
 
 impl SubscribeRTCPeerConnection {
     pub(crate) async fn new(
-        cascade: Option<CascadeInfo>,
+        (cascade, room): (Option<CascadeInfo>, bool),
         stream: String,
-        (peer, media_info): (Arc<RTCPeerConnection>, MediaInfo),
+        (peer, writers, media_info): (Arc<RTCPeerConnection>, LocalStreamWriters, MediaInfo),
         publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
         (publish_tracks, publish_track_change): (
             Arc<RwLock<Vec<PublishTrackRemote>>>,
             broadcast::Sender<()>, // use subscribe
         ),
-        (video_sender, audio_sender): (Option<Arc<RTCRtpSender>>, Option<Arc<RTCRtpSender>>),
+        slots: Vec<SenderSlot>,
+        simulcast: Simulcast,
     ) -> Self {
-        let select_layer_sender = new_broadcast_channel!(1);
         let id = get_peer_id(&peer);
-        let track_binding_publish_rid = Arc::new(RwLock::new(HashMap::new()));
-        for (sender, kind) in [
-            (video_sender, RTPCodecType::Video),
-            (audio_sender, RTPCodecType::Audio),
-        ] {
-            if sender.is_none() {
-                continue;
-            }
-            let sender = sender.unwrap();
+        let context = SubscribeContext {
+            stream,
+            id: id.clone(),
+            room,
+            writers,
+            publish_rtcp_sender,
+            publish_tracks,
+            publish_track_change,
+            select_layer_sender: new_broadcast_channel!(1),
+            publishers: Arc::new(RwLock::new(None)),
+            simulcast,
+        };
+        let s = Self {
+            id,
+            cascade,
+            peer,
+            create_at: Utc::now().timestamp_millis(),
+            media_info,
+            context,
+            senders: Mutex::new(vec![]),
+            bitrate_sample: Mutex::new(BitrateSample::new()),
+        };
+        s.add_slots(slots);
+        s
+    }
+
+    /// Start forwarding to more senders, e.g. after renegotiation
+    pub(crate) fn add_slots(&self, slots: Vec<SenderSlot>) {
+        for slot in slots {
+            let track_binding_publish_rid = Arc::new(RwLock::new(HashMap::new()));
+            let (publisher_sender, publisher) = watch::channel(None);
+            let (bandwidth_estimate_sender, bandwidth_estimate) =
+                watch::channel(BandwidthEstimate::default());
+            self.senders.lock().unwrap().push(SenderState {
+                kind: slot.kind,
+                sender: slot.sender.clone(),
+                track_binding_publish_rid: track_binding_publish_rid.clone(),
+                bandwidth_estimate: bandwidth_estimate.clone(),
+            });
+            let (nack_sender, nack_recv) = mpsc::channel(16);
             tokio::spawn(Self::sender_forward_rtcp(
-                kind,
-                sender.clone(),
-                publish_tracks.clone(),
-                track_binding_publish_rid.clone(),
-                publish_rtcp_sender.clone(),
+                (slot.kind, slot.sender.clone()),
+                self.context.publish_tracks.clone(),
+                (track_binding_publish_rid.clone(), publisher),
+                self.context.publish_rtcp_sender.clone(),
+                (bandwidth_estimate_sender, nack_sender),
             ));
+            let layer_controller = (slot.kind == RTPCodecType::Video
+                && self.context.simulcast.auto_layer)
+                .then(|| LayerController::new(&self.context.simulcast));
             tokio::spawn(Self::sender_forward_rtp(
-                stream.clone(),
-                id.clone(),
-                sender,
-                kind,
-                track_binding_publish_rid.clone(),
-                publish_tracks.clone(),
+                self.context.clone(),
+                slot,
+                (track_binding_publish_rid, publisher_sender),
                 SubscribeForwardChannel {
-                    publish_rtcp_sender: publish_rtcp_sender.clone(),
-                    select_layer_recv: select_layer_sender.subscribe(),
-                    publish_track_change: publish_track_change.subscribe(),
+                    publish_rtcp_sender: self.context.publish_rtcp_sender.clone(),
+                    select_layer_recv: self.context.select_layer_sender.subscribe(),
+                    publish_track_change: self.context.publish_track_change.subscribe(),
+                    bandwidth_estimate,
+                    nack_recv,
                 },
+                layer_controller,
             ));
         }
-        let _ = publish_track_change.send(());
-        Self {
-            id,
-            cascade,
-            peer,
-            create_at: Utc::now().timestamp_millis(),
-            select_layer_sender,
-            media_info,
-        }
+        let _ = self.context.publish_track_change.send(());
+    }
+
+    /// Limit the publishers received in room mode, `None` receives all of them
+    pub(crate) async fn select_publishers(&self, publishers: Option<Vec<String>>) {
+        *self.context.publishers.write().await = publishers;
+        let _ = self.context.publish_track_change.send(());
     }
 
     pub(crate) async fn info(&self) -> SessionInfo {
@@ -100,25 +213,82 @@ impl SubscribeRTCPeerConnection {
             state: self.peer.connection_state(),
             cascade: self.cascade.clone(),
             has_data_channel: self.media_info.has_data_channel,
+            stats: None,
+            health: vec![],
+        }
+    }
+
+    /// Transport stats, jitter is the worst the subscriber reported
+    pub(crate) async fn stats(&self) -> SessionStats {
+        let mut stats = peer_stats(&self.peer, &self.bitrate_sample).await;
+        let senders = self.senders.lock().unwrap().clone();
+        for state in senders {
+            let jitter = state.bandwidth_estimate.borrow().jitter;
+            let parameters = state.sender.get_parameters().await;
+            if let Some(codec) = parameters.rtp_parameters.codecs.first() {
+                let clock_rate = codec.capability.clock_rate.max(1) as u64;
+                stats.jitter = stats.jitter.max((jitter as u64 * 1000 / clock_rate) as u32);
+            }
+            if state.kind == RTPCodecType::Video && stats.layer.is_none() {
+                stats.layer = state
+                    .track_binding_publish_rid
+                    .read()
+                    .await
+                    .get(&state.kind.to_string())
+                    .filter(|rid| {
+                        !rid.is_empty()
+                            && *rid != constant::RID_DISABLE
+                            && *rid != constant::RID_ENABLE
+                    })
+                    .cloned();
+            }
         }
+        stats
     }
 
     async fn sender_forward_rtp(
-        stream: String,
-        id: String,
-        sender: Arc<RTCRtpSender>,
-        kind: RTPCodecType,
-        track_binding_publish_rid: Arc<RwLock<HashMap<String, String>>>,
-        publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
+        context: SubscribeContext,
+        SenderSlot {
+            kind,
+            index,
+            sender,
+        }: SenderSlot,
+        (track_binding_publish_rid, publisher_sender): (
+            Arc<RwLock<HashMap<String, String>>>,
+            watch::Sender<Option<String>>,
+        ),
         mut forward_channel: SubscribeForwardChannel,
+        mut layer_controller: Option<LayerController>,
     ) {
-        info!("[{}] [{}] {} up", stream, id, kind);
+        let SubscribeContext {
+            stream,
+            id,
+            room,
+            writers,
+            publish_tracks,
+            publishers,
+            ..
+        } = context;
+        info!("[{}] [{}] {} {} up", stream, id, kind, index);
+        // publisher of the bound track
+        let mut publisher: Option<String> = None;
         let mut pre_rid: Option<String> = None;
         // empty broadcast channel
         let virtual_sender = new_broadcast_channel!(1);
         let mut recv = virtual_sender.subscribe();
         let mut track = None;
         let mut sequence_number: u16 = 0;
+        let mut timestamps = TimestampRewriter::new();
+        // packet history of the publish track `recv` belongs to
+        let mut cache: Option<Arc<Mutex<PacketCache>>> = None;
+        let mut history = SentHistory::new();
+        let mut rtx: Option<RtxStream> = None;
+        let mut rtx_checked = false;
+        // Automatic layer selection until the client picks a layer itself
+        let mut auto_layer = layer_controller.is_some();
+        let mut pending: Option<PendingLayer> = None;
+        let mut layer_check = tokio::time::interval(LAYER_CHECK_INTERVAL);
+        let mut slot_metrics = metrics::SubscribeMetrics::new(&stream, &id, &kind.to_string());
         loop {
             tokio::select! {
                 publish_change = forward_channel.publish_track_change.recv() =>{
@@ -129,36 +299,71 @@ impl SubscribeRTCPeerConnection {
                       let mut track_binding_publish_rid = track_binding_publish_rid.write().await;
                         let publish_tracks = publish_tracks.read().await;
                         let current_rid = track_binding_publish_rid.get(&kind.clone().to_string());
-                        if publish_tracks.is_empty() {
+                        let target = slot_publisher(
+                            publish_tracks.iter().map(|t| (t.kind, &t.publish_id)),
+                            kind,
+                            index,
+                            publishers.read().await.as_ref(),
+                        );
+                        if publish_tracks.is_empty() || (target.is_none() && track.is_some()) {
                             debug!("{} {} publish track len 0 , probably offline",stream,id);
+                            pending = None;
                             recv = virtual_sender.subscribe();
+                            cache = None;
                             let _ = sender.replace_track(None).await;
                             track = None;
                             pre_rid = None;
+                            publisher = None;
+                            publisher_sender.send_replace(None);
                             if current_rid.is_some() && current_rid.cloned().unwrap() != constant::RID_DISABLE {
                                 track_binding_publish_rid.remove(&kind.clone().to_string());
                             };
                             continue;
                         }
-                        if track.is_some(){
+                        if track.is_some() && publisher == target {
                             continue;
                         }
                         if current_rid.is_some() && current_rid.cloned().unwrap() == constant::RID_DISABLE {
                            continue;
                         }
                         for publish_track in publish_tracks.iter() {
-                              if publish_track.kind != kind {
+                              if publish_track.kind != kind || Some(&publish_track.publish_id) != target.as_ref() {
                                 continue;
                             }
-                                    let new_track= Arc::new(
-                                        TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability,"webrtc".to_string(),format!("{}-{}","webrtc",kind))
-                                    );
+                                    let new_track = new_local_track(room, publish_track);
                                     match sender.replace_track(Some(new_track.clone())).await {
                                      Ok(_) => {
                                         debug!("[{}] [{}] {} track replace ok", stream, id,kind);
-                                        recv = publish_track.subscribe();
+                                        // A new track starts its own timeline, e.g. the publisher reconnected
+                                        // or the standby got promoted, keep the one sent to the subscriber going
+                                        timestamps.rebase(publish_track.track.codec().capability.clock_rate);
+                                        let (gop, gop_recv) = publish_track.subscribe_with_gop();
+                                        recv = gop_recv;
+                                        let publish_cache = publish_track.packet_cache();
+                                        // Start from the cached GOP, only ask for a keyframe without one
+                                        for (packet, timestamp) in gop.iter().zip(replay_timestamps(&gop)) {
+                                            let mut packet = packet.as_ref().clone();
+                                            let original_sequence_number = packet.header.sequence_number;
+                                            packet.header.sequence_number = sequence_number;
+                                            packet.header.timestamp = timestamps.rewrite(timestamp, Instant::now());
+                                            if let Err(err) = new_track.write_rtp(&packet).await {
+                                                debug!("[{}] [{}] {} gop replay err: {}", stream, id, kind, err);
+                                                break;
+                                            }
+                                            slot_metrics.sent(packet.payload.len());
+                                            history.record((sequence_number, packet.header.timestamp), original_sequence_number, &publish_cache);
+                                            sequence_number = sequence_number.wrapping_add(1);
+                                        }
+                                        if gop.is_empty() {
+                                            let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
+                                        } else {
+                                            debug!("[{}] [{}] {} replay gop {} packets", stream, id, kind, gop.len());
+                                        }
+                                        cache = Some(publish_cache);
                                         track = Some(new_track);
-                                        let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc()));
+                                        pending = None;
+                                        publisher = target.clone();
+                                        publisher_sender.send_replace(target.clone());
                                         track_binding_publish_rid.insert(kind.clone().to_string(), publish_track.rid.clone());
                                     }
                                      Err(e) => {
@@ -177,17 +382,30 @@ impl SubscribeRTCPeerConnection {
                                 }
                                 Some(ref track) => {
                                     let mut packet = packet.as_ref().clone();
+                                    let original_sequence_number = packet.header.sequence_number;
                                     packet.header.sequence_number = sequence_number;
+                                    packet.header.timestamp = timestamps.rewrite(packet.header.timestamp, Instant::now());
                                     if let Err(err) = track.write_rtp(&packet).await {
                                         debug!("[{}] [{}] {} track write err: {}", stream, id,kind, err);
                                         break;
                                     }
+                                    slot_metrics.sent(packet.payload.len());
+                                    if let Some(ref cache) = cache {
+                                        history.record((sequence_number, packet.header.timestamp), original_sequence_number, cache);
+                                    }
                                     sequence_number = sequence_number.wrapping_add(1);
                                 }
                             }
                         }
                         Err(err) => {
                             debug!("[{}] [{}] {} rtp receiver err: {}", stream, id, kind,err);
+                            if let broadcast::error::RecvError::Lagged(count) = err {
+                                slot_metrics.lagged(count);
+                            }
+                            // the publisher left, wait for the track change
+                            if err == broadcast::error::RecvError::Closed {
+                                recv = virtual_sender.subscribe();
+                            }
                         }
                     }
                 }
@@ -198,12 +416,34 @@ impl SubscribeRTCPeerConnection {
                                 continue;
                             };
                              let select_rid = select_layer_body.1;
+                             pending = None;
+                             if let Some(controller) = layer_controller.as_mut() {
+                                controller.reset();
+                                if select_rid == constant::RID_AUTO {
+                                    auto_layer = true;
+                                    continue;
+                                }
+                                if select_rid != constant::RID_ENABLE && select_rid != constant::RID_DISABLE {
+                                    auto_layer = false;
+                                }
+                             } else if select_rid == constant::RID_AUTO {
+                                continue;
+                             }
                              let mut track_binding_publish_rid = track_binding_publish_rid.write().await;
                              let publish_tracks =  publish_tracks.read().await;
                              let current_rid = track_binding_publish_rid.get(&kind.to_string()).cloned();
                              if current_rid == Some(select_rid.clone()){
                                 continue;
                              }
+                             let target = match publisher.clone() {
+                                Some(publisher) => Some(publisher),
+                                None => slot_publisher(
+                                    publish_tracks.iter().map(|t| (t.kind, &t.publish_id)),
+                                    kind,
+                                    index,
+                                    publishers.read().await.as_ref(),
+                                ),
+                             };
                             let new_rid = match &current_rid{
                                 None => {
                                     select_rid.clone()
@@ -213,7 +453,7 @@ impl SubscribeRTCPeerConnection {
                                         track_binding_publish_rid.remove(&kind.clone().to_string());
                                         match &pre_rid{
                                             None => {
-                                                let next_rid = publish_tracks.iter().filter(|t|t.kind==kind).map(|t|t.rid.clone()).next();
+                                                let next_rid = publish_tracks.iter().filter(|t|t.kind==kind && Some(&t.publish_id) == target.as_ref()).map(|t|t.rid.clone()).next();
                                                 if next_rid.is_none(){
                                                     continue;
                                                 }
@@ -231,6 +471,7 @@ impl SubscribeRTCPeerConnection {
                             if new_rid == constant::RID_DISABLE {
                                 if let Some(rid) = current_rid {
                                     recv = virtual_sender.subscribe();
+                                    cache = None;
                                     let _ = sender.replace_track(None).await;
                                     track = None;
                                     pre_rid = Some(rid);
@@ -239,15 +480,18 @@ impl SubscribeRTCPeerConnection {
                                 continue;
                             };
                             for  publish_track in publish_tracks.iter() {
-                                if publish_track.kind == RTPCodecType::Video && (publish_track.rid == new_rid || new_rid == constant::RID_ENABLE) {
-                                      let new_track= Arc::new(
-                                        TrackLocalStaticRTP::new(publish_track.track.clone().codec().capability,"webrtc".to_string(),format!("{}-{}","webrtc",kind))
-                                    );
+                                if publish_track.kind == RTPCodecType::Video && Some(&publish_track.publish_id) == target.as_ref() && (publish_track.rid == new_rid || new_rid == constant::RID_ENABLE) {
+                                    let new_track = new_local_track(room, publish_track);
                                     match sender.replace_track(Some(new_track.clone())).await {
                                      Ok(_) => {
                                         debug!("[{}] [{}] {} track replace ok", stream, id,kind);
+                                        // Every layer has its own timestamp base
+                                        timestamps.rebase(publish_track.track.codec().capability.clock_rate);
                                         recv = publish_track.subscribe();
+                                        cache = Some(publish_track.packet_cache());
                                         track = Some(new_track);
+                                        publisher = target.clone();
+                                        publisher_sender.send_replace(target.clone());
                                         let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, publish_track.track.ssrc())).unwrap();
                                         track_binding_publish_rid.insert(kind.clone().to_string(), new_rid.clone());
                                         info!("[{}] [{}] {} select layer to {}", stream, id, kind,new_rid);
@@ -265,13 +509,123 @@ impl SubscribeRTCPeerConnection {
                         }
                     }
                 }
+                pending_result = recv_pending(&mut pending) => {
+                    let packet = match pending_result {
+                        Ok(packet) => packet,
+                        Err(broadcast::error::RecvError::Lagged(count)) => {
+                            slot_metrics.lagged(count);
+                            continue;
+                        }
+                        Err(err) => {
+                            debug!("[{}] [{}] {} pending layer receiver err: {}", stream, id, kind, err);
+                            pending = None;
+                            continue;
+                        }
+                    };
+                    if !pending.as_ref().is_some_and(|layer| is_keyframe(&layer.mime_type, &packet.payload)) {
+                        continue;
+                    }
+                    let layer = pending.take().unwrap();
+                    // Every layer has its own timestamp base
+                    timestamps.rebase(layer.clock_rate);
+                    recv = layer.recv;
+                    cache = Some(layer.cache);
+                    track_binding_publish_rid.write().await.insert(kind.to_string(), layer.rid.clone());
+                    info!("[{}] [{}] {} auto select layer to {}", stream, id, kind, layer.rid);
+                    if let Some(ref track) = track {
+                        let mut packet = packet.as_ref().clone();
+                        let original_sequence_number = packet.header.sequence_number;
+                        packet.header.sequence_number = sequence_number;
+                        packet.header.timestamp = timestamps.rewrite(packet.header.timestamp, Instant::now());
+                        if let Err(err) = track.write_rtp(&packet).await {
+                            debug!("[{}] [{}] {} track write err: {}", stream, id, kind, err);
+                            break;
+                        }
+                        slot_metrics.sent(packet.payload.len());
+                        if let Some(ref cache) = cache {
+                            history.record((sequence_number, packet.header.timestamp), original_sequence_number, cache);
+                        }
+                        sequence_number = sequence_number.wrapping_add(1);
+                    }
+                }
+                Some(lost) = forward_channel.nack_recv.recv() => {
+                    let Some(ref track) = track else {
+                        continue;
+                    };
+                    slot_metrics.nacks(lost.len());
+                    if !rtx_checked {
+                        rtx_checked = true;
+                        rtx = RtxStream::new(&sender, &writers).await;
+                        debug!("[{}] [{}] {} rtx negotiated: {}", stream, id, kind, rtx.is_some());
+                    }
+                    for lost_sequence_number in lost {
+                        let Some(packet) = history.get(lost_sequence_number) else {
+                            trace!("[{}] [{}] {} nack {} not in history", stream, id, kind, lost_sequence_number);
+                            continue;
+                        };
+                        let result = match rtx.as_mut() {
+                            Some(rtx) => rtx.send(&packet).await.map(|_| ()).map_err(|e| e.to_string()),
+                            None => track.write_rtp(&packet).await.map(|_| ()).map_err(|e| e.to_string()),
+                        };
+                        if let Err(err) = result {
+                            debug!("[{}] [{}] {} retransmission err: {}", stream, id, kind, err);
+                            break;
+                        }
+                    }
+                }
+                _ = layer_check.tick(), if auto_layer => {
+                    if let Some(layer) = &pending {
+                        // keep asking until the keyframe shows up
+                        let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, layer.ssrc));
+                        continue;
+                    }
+                    if track.is_none() {
+                        continue;
+                    }
+                    let Some(controller) = layer_controller.as_mut() else {
+                        continue;
+                    };
+                    let current_rid = match track_binding_publish_rid.read().await.get(&kind.to_string()) {
+                        Some(rid) if rid != constant::RID_DISABLE => rid.clone(),
+                        _ => continue,
+                    };
+                    let publish_tracks = publish_tracks.read().await;
+                    let mut layers: Vec<&PublishTrackRemote> = publish_tracks
+                        .iter()
+                        .filter(|t| t.kind == kind && Some(&t.publish_id) == publisher.as_ref() && t.bitrate() > 0)
+                        .collect();
+                    if layers.len() < 2 {
+                        continue;
+                    }
+                    layers.sort_by_key(|t| t.bitrate());
+                    let bitrates: Vec<u64> = layers.iter().map(|t| t.bitrate()).collect();
+                    let estimate = *forward_channel.bandwidth_estimate.borrow();
+                    let target = match layers.iter().position(|t| t.rid == current_rid) {
+                        Some(current) => controller.decide(Instant::now(), &estimate, current, &bitrates),
+                        // the bound layer stopped sending
+                        None => Some(0),
+                    };
+                    if let Some(target) = target {
+                        let layer = layers[target];
+                        debug!("[{}] [{}] {} switching layer {} -> {}, estimate: {:?}", stream, id, kind, current_rid, layer.rid, estimate);
+                        let _ = forward_channel.publish_rtcp_sender.send((RtcpMessage::PictureLossIndication, layer.track.ssrc()));
+                        pending = Some(PendingLayer {
+                            rid: layer.rid.clone(),
+                            mime_type: layer.track.codec().capability.mime_type,
+                            clock_rate: layer.track.codec().capability.clock_rate,
+                            ssrc: layer.track.ssrc(),
+                            recv: layer.subscribe(),
+                            cache: layer.packet_cache(),
+                        });
+                    }
+                }
             }
         }
         info!("[{}] [{}] {} down", stream, id, kind);
     }
 
     pub(crate) fn select_kind_rid(&self, kind: RTPCodecType, rid: String) -> Result<()> {
-        if let Err(err) = self.select_layer_sender.send((kind, rid)) {
+        if let Err(err) = self.context.select_layer_sender.send((kind, rid)) {
             Err(AppError::throw(format!("select layer send err: {err}")))
         } else {
             Ok(())
@@ -279,15 +633,31 @@ impl SubscribeRTCPeerConnection {
     }
 
     async fn sender_forward_rtcp(
-        kind: RTPCodecType,
-        sender: Arc<RTCRtpSender>,
+        (kind, sender): (RTPCodecType, Arc<RTCRtpSender>),
         publish_tracks: Arc<RwLock<Vec<PublishTrackRemote>>>,
-        track_binding_publish_rid: Arc<RwLock<HashMap<String, String>>>,
+        (track_binding_publish_rid, publisher): (
+            Arc<RwLock<HashMap<String, String>>>,
+            watch::Receiver<Option<String>>,
+        ),
         publish_rtcp_sender: broadcast::Sender<(RtcpMessage, u32)>,
+        (bandwidth_estimate, nack_sender): (
+            watch::Sender<BandwidthEstimate>,
+            mpsc::Sender<Vec<u16>>,
+        ),
     ) {
+        let mut estimate = BandwidthEstimate::default();
         loop {
             match sender.read_rtcp().await {
                 Ok((packets, _)) => {
+                    if packets.iter().fold(false, |updated, packet| {
+                        estimate.update(packet.as_ref()) || updated
+                    }) {
+                        let _ = bandwidth_estimate.send(estimate);
+                    }
+                    let lost = lost_sequence_numbers(&packets);
+                    if !lost.is_empty() {
+                        let _ = nack_sender.try_send(lost);
+                    }
                     let track_binding_publish_rid = track_binding_publish_rid.read().await;
                     let publish_rid = match track_binding_publish_rid.get(&kind.clone().to_string())
                     {
@@ -296,11 +666,13 @@ impl SubscribeRTCPeerConnection {
                         }
                         Some(rid) => rid,
                     };
+                    let bound_publisher = publisher.borrow().clone();
                     for packet in packets {
                         if let Some(msg) = RtcpMessage::from_rtcp_packet(packet) {
                             let publish_tracks = publish_tracks.read().await;
                             for publish_track in publish_tracks.iter() {
                                 if publish_track.kind == kind
+                                    && Some(&publish_track.publish_id) == bound_publisher.as_ref()
                                     && &publish_track.rid == publish_rid
                                     && let Err(_err) =
                                         publish_rtcp_sender.send((msg, publish_track.track.ssrc()))
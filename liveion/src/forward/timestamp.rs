@@ -0,0 +1,126 @@
+use std::time::Instant;
+
+/// Keeps the RTP timestamps sent to a subscriber continuous
+/// when the source track changes, e.g. on publisher failover.
+pub(crate) struct TimestampRewriter {
+    clock_rate: u32,
+    offset: u32,
+    rebase: bool,
+    /// Last sent timestamp and when it was sent
+    last: Option<(u32, Instant)>,
+}
+
+impl TimestampRewriter {
+    pub(crate) fn new() -> Self {
+        Self {
+            clock_rate: 0,
+            offset: 0,
+            rebase: false,
+            last: None,
+        }
+    }
+
+    /// The next packet starts another timeline, recompute the offset from it
+    pub(crate) fn rebase(&mut self, clock_rate: u32) {
+        self.clock_rate = clock_rate;
+        self.rebase = true;
+    }
+
+    pub(crate) fn rewrite(&mut self, timestamp: u32, now: Instant) -> u32 {
+        if self.rebase {
+            self.rebase = false;
+            if let Some((last, at)) = self.last {
+                // Continue from the last sent timestamp, advanced by the wall clock time in between
+                let elapsed =
+                    now.duration_since(at).as_millis() as u64 * self.clock_rate as u64 / 1000;
+                self.offset = last
+                    .wrapping_add(elapsed.max(1) as u32)
+                    .wrapping_sub(timestamp);
+            }
+        }
+        let timestamp = timestamp.wrapping_add(self.offset);
+        self.last = Some((timestamp, now));
+        timestamp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::TimestampRewriter;
+
+    #[test]
+    fn test_passthrough_until_rebase() {
+        let mut rewriter = TimestampRewriter::new();
+        let now = Instant::now();
+        assert_eq!(rewriter.rewrite(1000, now), 1000);
+        assert_eq!(rewriter.rewrite(4000, now), 4000);
+
+        // nothing sent before, nothing to continue from
+        let mut rewriter = TimestampRewriter::new();
+        rewriter.rebase(90000);
+        assert_eq!(rewriter.rewrite(7000, now), 7000);
+    }
+
+    #[test]
+    fn test_rebase_continues_timeline() {
+        let mut rewriter = TimestampRewriter::new();
+        let start = Instant::now();
+        rewriter.rewrite(u32::MAX - 1000, start);
+        rewriter.rebase(90000);
+        // 100ms later a new source starts far away
+        let now = start + Duration::from_millis(100);
+        let first = rewriter.rewrite(123_456, now);
+        assert_eq!(first, (u32::MAX - 1000).wrapping_add(9000));
+        assert_eq!(
+            rewriter.rewrite(123_456 + 3000, now),
+            first.wrapping_add(3000)
+        );
+    }
+
+    #[test]
+    fn test_rebase_after_reconnect() {
+        let mut rewriter = TimestampRewriter::new();
+        let start = Instant::now();
+        rewriter.rewrite(90_000, start);
+        rewriter.rewrite(93_000, start + Duration::from_millis(33));
+        rewriter.rebase(90000);
+        // the publisher is back 2s later, restarting from a random timestamp
+        let now = start + Duration::from_millis(2033);
+        assert_eq!(rewriter.rewrite(7, now), 93_000 + 180_000);
+        assert_eq!(
+            rewriter.rewrite(3007, now + Duration::from_millis(33)),
+            93_000 + 183_000
+        );
+    }
+
+    #[test]
+    fn test_layer_switch_after_rebase() {
+        let mut rewriter = TimestampRewriter::new();
+        let start = Instant::now();
+        rewriter.rewrite(10_000, start);
+        // failover to a publisher with another timeline
+        rewriter.rebase(90000);
+        let failover = rewriter.rewrite(500_000, start + Duration::from_millis(100));
+        assert_eq!(failover, 10_000 + 9000);
+        // another simulcast layer of it, with its own random base
+        rewriter.rebase(90000);
+        let now = start + Duration::from_millis(200);
+        assert_eq!(rewriter.rewrite(3_000_000_000, now), failover + 9000);
+        assert_eq!(
+            rewriter.rewrite(3_000_003_000, now + Duration::from_millis(33)),
+            failover + 12_000
+        );
+    }
+
+    #[test]
+    fn test_rebase_immediately() {
+        let mut rewriter = TimestampRewriter::new();
+        let now = Instant::now();
+        rewriter.rewrite(5000, now);
+        rewriter.rebase(48000);
+        // never repeat the last timestamp
+        assert_eq!(rewriter.rewrite(0, now), 5001);
+    }
+}
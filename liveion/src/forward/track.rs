@@ -1,14 +1,21 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use tokio::sync::broadcast;
 use tracing::{debug, info, trace};
 use webrtc::rtp::packet::Packet;
-use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecParameters, RTPCodecType};
 use webrtc::track::track_remote::TrackRemote;
 
-use crate::new_broadcast_channel;
+use crate::{config, metrics, new_broadcast_channel};
 
-use super::message::Codec;
+use super::gop::GopCache;
+use super::health::{HealthChange, TrackHealth};
+use super::keyframe::is_keyframe;
+use super::message::{Codec, HealthProblem};
+use super::nack::PacketCache;
+use super::rtcp::RtcpMessage;
+use super::stats::ReceptionStats;
 
 fn codec_string(params: webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecParameters) -> String {
     format!(
@@ -19,30 +26,111 @@ fn codec_string(params: webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecParameter
 
 pub(crate) type ForwardData = Arc<Packet>;
 
+const BITRATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Payload bitrate over windows of `BITRATE_WINDOW`, closed by the packets
+struct BitrateMeter {
+    window_start: Instant,
+    window_bytes: u64,
+    bitrate: u64,
+}
+
+impl BitrateMeter {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            window_bytes: 0,
+            bitrate: 0,
+        }
+    }
+
+    fn packet(&mut self, len: usize, now: Instant) {
+        self.window_bytes += len as u64;
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= BITRATE_WINDOW {
+            self.bitrate = self.window_bytes * 8 * 1000 / elapsed.as_millis() as u64;
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+    }
+
+    /// 0 once the current window is overdue, the track stopped sending, e.g. a paused layer
+    fn bitrate(&self, now: Instant) -> u64 {
+        if now.duration_since(self.window_start) >= BITRATE_WINDOW * 2 {
+            0
+        } else {
+            self.bitrate
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct PublishTrackRemote {
+    /// Session of the publisher the track belongs to
+    pub(crate) publish_id: String,
     pub(crate) rid: String,
     pub(crate) kind: RTPCodecType,
     pub(crate) track: Arc<TrackRemote>,
     rtp_broadcast: Arc<broadcast::Sender<ForwardData>>,
+    bitrate: Arc<Mutex<BitrateMeter>>,
+    packet_cache: Arc<Mutex<PacketCache>>,
+    gop_cache: Option<Arc<Mutex<GopCache>>>,
+    reception: Arc<Mutex<ReceptionStats>>,
+    health: Arc<Mutex<TrackHealth>>,
 }
 
 impl PublishTrackRemote {
-    pub async fn new(stream: String, id: String, track: Arc<TrackRemote>) -> Self {
+    /// `gop_cache` is the maximum number of packets kept since the last keyframe, video only
+    pub async fn new(
+        stream: String,
+        id: String,
+        track: Arc<TrackRemote>,
+        gop_cache: Option<usize>,
+        health: config::Health,
+    ) -> Self {
         let rtp_sender = new_broadcast_channel!(128);
         let rid = track.rid().to_owned();
         let kind = track.kind();
+        let bitrate = Arc::new(Mutex::new(BitrateMeter::new(Instant::now())));
+        let packet_cache = Arc::new(Mutex::new(PacketCache::new()));
+        let reception = Arc::new(Mutex::new(ReceptionStats::new(
+            track.codec().capability.clock_rate,
+        )));
+        let health = Arc::new(Mutex::new(TrackHealth::new(
+            health,
+            kind,
+            track.codec().capability.clock_rate,
+        )));
+        let gop_cache = gop_cache
+            .filter(|_| kind == RTPCodecType::Video)
+            .map(|max_packets| {
+                Arc::new(Mutex::new(GopCache::new(
+                    track.codec().capability.mime_type,
+                    max_packets,
+                )))
+            });
         tokio::spawn(Self::track_forward(
             stream,
-            id,
+            id.clone(),
             track.clone(),
             rtp_sender.clone(),
+            bitrate.clone(),
+            packet_cache.clone(),
+            gop_cache.clone(),
+            reception.clone(),
+            health.clone(),
         ));
         Self {
+            publish_id: id,
             rid,
             kind,
             track,
             rtp_broadcast: Arc::new(rtp_sender),
+            bitrate,
+            packet_cache,
+            gop_cache,
+            reception,
+            health,
         }
     }
 
@@ -51,6 +139,11 @@ impl PublishTrackRemote {
         id: String,
         track: Arc<TrackRemote>,
         rtp_sender: broadcast::Sender<ForwardData>,
+        bitrate: Arc<Mutex<BitrateMeter>>,
+        packet_cache: Arc<Mutex<PacketCache>>,
+        gop_cache: Option<Arc<Mutex<GopCache>>>,
+        reception: Arc<Mutex<ReceptionStats>>,
+        health: Arc<Mutex<TrackHealth>>,
     ) {
         info!(
             "[{}] [{}] [track] kind: {:?}, rid: {}, ssrc: {}, codec: {} start forward",
@@ -63,16 +156,56 @@ impl PublishTrackRemote {
         );
         trace!("codec: {:?}", track.codec());
         let mut b = vec![0u8; 1500];
+        let mime_type = track.codec().capability.mime_type;
+        let mut track_metrics =
+            metrics::PublishTrackMetrics::new(&stream, &id, &track.kind().to_string());
         loop {
             match track.read(&mut b).await {
                 Ok((rtp_packet, _)) => {
+                    let now = Instant::now();
+                    let lost = {
+                        let mut reception = reception.lock().unwrap();
+                        let before = reception.lost();
+                        reception.update(
+                            rtp_packet.header.sequence_number,
+                            rtp_packet.header.timestamp,
+                            now,
+                        );
+                        reception.lost() - before
+                    };
+                    track_metrics.received(rtp_packet.payload.len(), lost.max(0) as u64);
+                    let keyframe = track.kind() == RTPCodecType::Video
+                        && is_keyframe(&mime_type, &rtp_packet.payload);
+                    if keyframe {
+                        track_metrics.keyframe(rtp_packet.header.timestamp, now);
+                    }
+                    health
+                        .lock()
+                        .unwrap()
+                        .packet(rtp_packet.header.timestamp, keyframe, now);
+                    bitrate
+                        .lock()
+                        .unwrap()
+                        .packet(rtp_packet.payload.len(), now);
                     trace!(
                         "RTP packet - SSRC: {}, SeqNum: {}, Timestamp: {}",
                         rtp_packet.header.ssrc,
                         rtp_packet.header.sequence_number,
                         rtp_packet.header.timestamp
                     );
-                    if let Err(err) = rtp_sender.send(Arc::new(rtp_packet)) {
+                    let rtp_packet = Arc::new(rtp_packet);
+                    packet_cache.lock().unwrap().push(rtp_packet.clone());
+                    let result = match gop_cache {
+                        // Hold the lock while sending, so subscribers joining
+                        // in `subscribe_with_gop` see no gap and no duplicate
+                        Some(ref gop_cache) => {
+                            let mut gop_cache = gop_cache.lock().unwrap();
+                            gop_cache.push(&rtp_packet);
+                            rtp_sender.send(rtp_packet)
+                        }
+                        None => rtp_sender.send(rtp_packet),
+                    };
+                    if let Err(err) = result {
                         debug!(
                             "[{}] [{}] [track] kind: {:?}, rid: {}, rtp broadcast error : {}",
                             stream,
@@ -97,6 +230,10 @@ impl PublishTrackRemote {
                 }
             }
         }
+        *bitrate.lock().unwrap() = BitrateMeter::new(Instant::now());
+        if let Some(gop_cache) = gop_cache {
+            gop_cache.lock().unwrap().clear();
+        }
         info!(
             "[{}] [{}] [track] kind: {:?}, rid :{}, ssrc: {} stop forward",
             stream,
@@ -111,6 +248,50 @@ impl PublishTrackRemote {
         self.rtp_broadcast.subscribe()
     }
 
+    /// Subscribe and get the packets since the last keyframe, if the GOP cache is enabled
+    pub(crate) fn subscribe_with_gop(
+        &self,
+    ) -> (Vec<ForwardData>, broadcast::Receiver<ForwardData>) {
+        match self.gop_cache {
+            Some(ref gop_cache) => {
+                let gop_cache = gop_cache.lock().unwrap();
+                (gop_cache.packets(), self.rtp_broadcast.subscribe())
+            }
+            None => (vec![], self.rtp_broadcast.subscribe()),
+        }
+    }
+
+    /// Payload bitrate over the last second, in bits per second, 0 once the track stalls
+    pub(crate) fn bitrate(&self) -> u64 {
+        self.bitrate.lock().unwrap().bitrate(Instant::now())
+    }
+
+    /// Packets lost and jitter in milliseconds, as received from the publisher
+    pub(crate) fn reception(&self) -> (i64, u32) {
+        let reception = self.reception.lock().unwrap();
+        (reception.lost(), reception.jitter_ms())
+    }
+
+    /// Problems raised and recovered since the previous check
+    pub(crate) fn health_check(&self, now: Instant) -> Vec<HealthChange> {
+        self.health.lock().unwrap().check(self.bitrate(), now)
+    }
+
+    pub(crate) fn health(&self) -> Vec<HealthProblem> {
+        self.health.lock().unwrap().problems()
+    }
+
+    /// Whether RTP is flowing, with when the track was added
+    pub(crate) fn health_receiving(&self) -> (bool, Instant) {
+        let health = self.health.lock().unwrap();
+        (!health.is_stalled(), health.start())
+    }
+
+    /// History used to answer subscriber NACKs
+    pub(crate) fn packet_cache(&self) -> Arc<Mutex<PacketCache>> {
+        self.packet_cache.clone()
+    }
+
     pub(crate) fn codec(&self) -> Codec {
         let codec = self.track.codec();
         let media: Vec<String> = codec
@@ -128,3 +309,67 @@ impl PublishTrackRemote {
         }
     }
 }
+
+/// A publish track read directly, without a WebRTC peer
+#[derive(Clone)]
+pub(crate) struct OutputTrack {
+    track: PublishTrackRemote,
+    publish_rtcp: broadcast::Sender<(RtcpMessage, u32)>,
+}
+
+impl OutputTrack {
+    pub(crate) fn new(
+        track: PublishTrackRemote,
+        publish_rtcp: broadcast::Sender<(RtcpMessage, u32)>,
+    ) -> Self {
+        Self {
+            track,
+            publish_rtcp,
+        }
+    }
+
+    pub(crate) fn kind(&self) -> RTPCodecType {
+        self.track.kind
+    }
+
+    pub(crate) fn codec(&self) -> RTCRtpCodecParameters {
+        self.track.track.codec()
+    }
+
+    /// Subscribe and get the packets since the last keyframe, if the GOP cache is enabled
+    pub(crate) fn subscribe(&self) -> (Vec<ForwardData>, broadcast::Receiver<ForwardData>) {
+        self.track.subscribe_with_gop()
+    }
+
+    /// Ask the publisher for a keyframe, so a new reader can start decoding
+    pub(crate) fn request_keyframe(&self) {
+        if self.track.kind == RTPCodecType::Video {
+            let _ = self
+                .publish_rtcp
+                .send((RtcpMessage::PictureLossIndication, self.track.track.ssrc()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::BitrateMeter;
+
+    #[test]
+    fn test_bitrate_of_stalled_track() {
+        let start = Instant::now();
+        let mut meter = BitrateMeter::new(start);
+        for i in 0..=10 {
+            meter.packet(1250, start + Duration::from_millis(i * 100));
+        }
+        let last = start + Duration::from_secs(1);
+        assert_eq!(meter.bitrate(last), 110_000);
+        assert_eq!(meter.bitrate(last + Duration::from_millis(1500)), 110_000);
+        // the layer is paused, no packet closes the window anymore
+        assert_eq!(meter.bitrate(last + Duration::from_secs(2)), 0);
+        meter.packet(1250, last + Duration::from_secs(3));
+        assert_eq!(meter.bitrate(last + Duration::from_secs(3)), 3333);
+    }
+}
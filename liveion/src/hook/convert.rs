@@ -32,22 +32,53 @@ impl From<message::ForwardEventType> for api::event::StreamEventType {
             message::ForwardEventType::SubscribeDown => api::event::StreamEventType::SubscribeDown,
             message::ForwardEventType::ReforwardUp => api::event::StreamEventType::ReforwardUp,
             message::ForwardEventType::ReforwardDown => api::event::StreamEventType::ReforwardDown,
+            message::ForwardEventType::HealthWarning(_) => {
+                api::event::StreamEventType::HealthWarning
+            }
+            message::ForwardEventType::HealthRecovered(_) => {
+                api::event::StreamEventType::HealthRecovered
+            }
+        }
+    }
+}
+
+impl From<message::HealthProblem> for api::event::HealthProblem {
+    fn from(value: message::HealthProblem) -> Self {
+        match value {
+            message::HealthProblem::Stalled => api::event::HealthProblem::Stalled,
+            message::HealthProblem::NoKeyframe => api::event::HealthProblem::NoKeyframe,
+            message::HealthProblem::LowBitrate => api::event::HealthProblem::LowBitrate,
+            message::HealthProblem::TimestampJump => api::event::HealthProblem::TimestampJump,
+            message::HealthProblem::VideoMissing => api::event::HealthProblem::VideoMissing,
+        }
+    }
+}
+
+impl From<message::HealthReport> for api::event::Health {
+    fn from(value: message::HealthReport) -> Self {
+        Self {
+            problem: value.problem.into(),
+            kind: value.kind,
+            rid: value.rid,
+            detail: value.detail,
         }
     }
 }
 
 impl From<message::ForwardEvent> for api::event::Event {
     fn from(value: message::ForwardEvent) -> Self {
+        let health = match &value.r#type {
+            message::ForwardEventType::HealthWarning(report)
+            | message::ForwardEventType::HealthRecovered(report) => Some(report.clone().into()),
+            _ => None,
+        };
         api::event::Event::Stream {
+            health,
             r#type: value.r#type.into(),
             stream: api::event::Stream {
                 stream: value.stream_info.id,
                 session: Some(value.session),
-                publish: if value.stream_info.publish_session_info.is_some() {
-                    1
-                } else {
-                    0
-                },
+                publish: value.stream_info.publish_session_infos.len() as u64,
                 subscribe: value.stream_info.subscribe_session_infos.len() as u64,
                 reforward: value
                     .stream_info
@@ -66,6 +97,7 @@ impl Event {
             Event::Stream(stream_evnet) => api::event::Event::Stream {
                 r#type: stream_evnet.r#type.into(),
                 stream: stream_evnet.stream.into(),
+                health: None,
             },
             Event::Forward(forward_event) => forward_event.into(),
         }
@@ -1,4 +1,5 @@
 pub mod convert;
+pub mod mqtt;
 pub mod webhook;
 use async_trait::async_trait;
 use tokio::sync::broadcast;
@@ -6,6 +7,7 @@ use tokio::sync::broadcast;
 use std::fmt::Debug;
 
 use crate::forward::message::ForwardEvent;
+use crate::metrics;
 
 #[derive(Clone, Debug)]
 pub enum Event {
@@ -38,3 +40,37 @@ pub struct Stream {
 pub trait EventHook: Debug {
     async fn hook(&self, mut event_receiver: broadcast::Receiver<Event>);
 }
+
+/// Whether the type of the event is one of `events`, empty for all of them
+pub(crate) fn accept_type(events: &[String], event: &api::event::Event) -> bool {
+    let api::event::Event::Stream { r#type, .. } = event;
+    let r#type = serde_json::to_value(r#type)
+        .ok()
+        .and_then(|value| value.as_str().map(|value| value.to_string()))
+        .unwrap_or_default();
+    events.is_empty() || events.contains(&r#type)
+}
+
+pub(crate) fn node_metrics() -> api::event::NodeMetrics {
+    api::event::NodeMetrics {
+        stream: metrics::STREAM.get() as u64,
+        publish: metrics::PUBLISH.get() as u64,
+        subscribe: metrics::SUBSCRIBE.get() as u64,
+        reforward: metrics::REFORWARD.get() as u64,
+    }
+}
+
+/// A stream event of `stream` without any session, for the tests of the hooks
+#[cfg(test)]
+pub(crate) fn stream_event(r#type: StreamEventType, stream: &str) -> Event {
+    Event::Stream(StreamEvent {
+        r#type,
+        stream: Stream {
+            stream: stream.to_string(),
+            session: None,
+            publish: 0,
+            subscribe: 0,
+            reforward: 0,
+        },
+    })
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::time::Duration;
+
+use api::event::EventBody;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event as MqttEvent, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use tokio::sync::{Mutex, broadcast};
+use tracing::{debug, warn};
+
+use super::{Event, EventHook, accept_type, node_metrics};
+use crate::config::MqttHookTarget;
+use crate::result::Result;
+
+const MQTT_BUFFER_CAPACITY: usize = 64;
+const MQTT_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MQTT_STATUS_ONLINE: &str = "online";
+const MQTT_STATUS_OFFLINE: &str = "offline";
+
+/// Publishes the events as `EventBody` JSON, and keeps a retained presence message per live stream.
+/// The presence of a node gone without closing its streams is left behind, the broker publishes
+/// the `offline` last will on the status topic of the node then, and consumers drop its presence.
+pub struct MqttHook {
+    target: MqttHookTarget,
+    client: AsyncClient,
+    eventloop: Mutex<EventLoop>,
+}
+
+impl fmt::Debug for MqttHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttHook")
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+impl MqttHook {
+    pub fn new(target: MqttHookTarget) -> Result<Self> {
+        let mut options = MqttOptions::parse_url(target.url.replace("{alias}", &target.alias))
+            .map_err(|err| anyhow::anyhow!("mqtt url error: {err:?}"))?;
+        if !target.status_topic.is_empty() {
+            options.set_last_will(LastWill::new(
+                target.status_topic.replace("{alias}", &target.alias),
+                MQTT_STATUS_OFFLINE,
+                qos(target.qos),
+                true,
+            ));
+        }
+        let (client, eventloop) = AsyncClient::new(options, MQTT_BUFFER_CAPACITY);
+        Ok(Self {
+            target,
+            client,
+            eventloop: Mutex::new(eventloop),
+        })
+    }
+
+    fn topic(&self, topic: &str, stream: &str) -> String {
+        topic
+            .replace("{alias}", &self.target.alias)
+            .replace("{stream}", stream)
+    }
+
+    fn qos(&self) -> QoS {
+        qos(self.target.qos)
+    }
+
+    async fn publish(&self, event: Event) -> Result<()> {
+        let event = event.convert_api_event();
+        let api::event::Event::Stream { r#type, stream, .. } = &event;
+        if !self.target.matches_stream(&stream.stream) {
+            return Ok(());
+        }
+        if !self.target.presence_topic.is_empty() {
+            let topic = self.topic(&self.target.presence_topic, &stream.stream);
+            // An empty retained message removes the presence of a closed stream
+            let payload = match r#type {
+                api::event::StreamEventType::StreamDown => vec![],
+                _ => serde_json::to_vec(stream)?,
+            };
+            self.client
+                .publish(topic, self.qos(), true, payload)
+                .await
+                .map_err(|err| anyhow::anyhow!(err))?;
+        }
+        if accept_type(&self.target.events, &event) {
+            let topic = self.topic(&self.target.topic, &stream.stream);
+            let payload = serde_json::to_vec(&EventBody {
+                metrics: node_metrics(),
+                event,
+            })?;
+            debug!(topic, "event mqtt publish");
+            self.client
+                .publish(topic, self.qos(), false, payload)
+                .await
+                .map_err(|err| anyhow::anyhow!(err))?;
+        }
+        Ok(())
+    }
+
+    /// Drives the connection, it reconnects on the next poll after an error.
+    /// Every connection marks the node `online`, replacing the last will of the previous one.
+    async fn connect(&self) {
+        let mut eventloop = self.eventloop.lock().await;
+        loop {
+            match eventloop.poll().await {
+                Ok(MqttEvent::Incoming(Packet::ConnAck(_)))
+                    if !self.target.status_topic.is_empty() =>
+                {
+                    let topic = self.topic(&self.target.status_topic, "");
+                    // Not awaited, the requests are only sent by this loop
+                    if let Err(err) =
+                        self.client
+                            .try_publish(topic, self.qos(), true, MQTT_STATUS_ONLINE)
+                    {
+                        warn!(url = self.target.url, ?err, "event mqtt status error");
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!(url = self.target.url, ?err, "event mqtt connection error");
+                    tokio::time::sleep(MQTT_RECONNECT_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+fn qos(qos: u8) -> QoS {
+    match qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
+}
+
+#[async_trait]
+impl EventHook for MqttHook {
+    async fn hook(&self, mut event_receiver: broadcast::Receiver<Event>) {
+        let receive = async {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(event) => {
+                        if let Err(err) = self.publish(event).await {
+                            warn!(url = self.target.url, ?err, "event mqtt publish error");
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!(url = self.target.url, count, "event mqtt lagged");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+        tokio::select! {
+            _ = receive => {}
+            _ = self.connect() => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use net4mqtt::broker;
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Publish, QoS};
+    use tokio::sync::broadcast;
+
+    use super::MqttHook;
+    use crate::config::MqttHookTarget;
+    use crate::hook::{EventHook, StreamEventType, stream_event};
+
+    /// The first publish received on `filter` by a new client
+    async fn receive(port: u16, id: &str, filter: &str) -> Publish {
+        let (client, mut eventloop) = AsyncClient::new(MqttOptions::new(id, "127.0.0.1", port), 16);
+        client.subscribe(filter, QoS::AtLeastOnce).await.unwrap();
+        loop {
+            if let Event::Incoming(Packet::Publish(publish)) = eventloop.poll().await.unwrap() {
+                return publish;
+            }
+        }
+    }
+
+    #[test]
+    fn test_topic() {
+        let hook = MqttHook::new(MqttHookTarget {
+            url: "mqtt://127.0.0.1:1883/?client_id={alias}".to_string(),
+            alias: "edge-0".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            hook.topic(&hook.target.topic, "camera"),
+            "live777/edge-0/events/camera"
+        );
+        assert!(
+            MqttHook::new(MqttHookTarget {
+                url: "mqtt://127.0.0.1:1883/".to_string(),
+                ..Default::default()
+            })
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish() {
+        let port = portpicker::pick_unused_port().expect("No ports free");
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        std::thread::spawn(move || broker::up_mqtt_broker(addr));
+        broker::wait_for_port_availabilty(addr).await;
+
+        let hook = Arc::new(
+            MqttHook::new(MqttHookTarget {
+                url: format!("mqtt://127.0.0.1:{port}/?client_id=live777-{{alias}}"),
+                events: vec!["publishUp".to_string(), "streamUp".to_string()],
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let (send, recv) = broadcast::channel(16);
+        tokio::spawn({
+            let hook = hook.clone();
+            async move { hook.hook(recv).await }
+        });
+
+        let event = tokio::spawn(receive(port, "event", "live777/live777/events/#"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        send.send(stream_event(StreamEventType::Down, "other"))
+            .unwrap();
+        send.send(stream_event(StreamEventType::Up, "camera"))
+            .unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), event)
+            .await
+            .unwrap()
+            .unwrap();
+        // streamDown is filtered out
+        assert_eq!(event.topic, "live777/live777/events/camera");
+        let body: api::event::EventBody = serde_json::from_slice(&event.payload).unwrap();
+        assert!(matches!(
+            body.event,
+            api::event::Event::Stream {
+                r#type: api::event::StreamEventType::StreamUp,
+                ..
+            }
+        ));
+
+        // a late subscriber gets the retained presence
+        let presence = tokio::time::timeout(
+            Duration::from_secs(5),
+            receive(port, "presence", "live777/live777/streams/camera"),
+        )
+        .await
+        .unwrap();
+        assert!(presence.retain);
+        let stream: api::event::Stream = serde_json::from_slice(&presence.payload).unwrap();
+        assert_eq!(stream.stream, "camera");
+
+        // the node is marked online, its last will marks it offline
+        let status = tokio::time::timeout(
+            Duration::from_secs(5),
+            receive(port, "status", "live777/live777/status"),
+        )
+        .await
+        .unwrap();
+        assert!(status.retain);
+        assert_eq!(&status.payload[..], b"online");
+    }
+}
@@ -1,56 +1,168 @@
-use std::{str::FromStr, time::Duration};
+use std::collections::VecDeque;
+use std::time::Duration;
 
-use api::event::{EventBody, NodeMetrics};
+use api::event::EventBody;
 use async_trait::async_trait;
-use reqwest::{Client, Method, header::HeaderMap};
-use tokio::sync::broadcast;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{Mutex, Notify, broadcast};
 use tracing::{debug, warn};
 
-use super::{Event, EventHook};
+use super::{Event, EventHook, accept_type, node_metrics};
+use crate::config::WebhookTarget;
 use crate::{error::AppError, metrics, result::Result};
 
-#[derive(Clone, Debug)]
+type HmacSha256 = Hmac<Sha256>;
+
+pub const EVENT_ID_HEADER: &str = "X-Live777-Event-Id";
+pub const SIGNATURE_HEADER: &str = "X-Live777-Signature";
+
+/// An event waiting for delivery, the body is built when the event happens
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct QueuedEvent {
+    id: String,
+    body: String,
+}
+
+#[derive(Debug)]
 pub struct WebHook {
-    url: String,
+    target: WebhookTarget,
     client: Client,
+    queue: Mutex<VecDeque<QueuedEvent>>,
+    notify: Notify,
 }
 
 impl WebHook {
-    pub fn new(url: String) -> Self {
+    /// Restores the events left undelivered in `queue_path`
+    pub async fn new(target: WebhookTarget) -> Self {
+        let timeout = Duration::from_millis(target.timeout);
+        let queue = match &target.queue_path {
+            Some(path) => load_queue(path).await,
+            None => VecDeque::new(),
+        };
+        if !queue.is_empty() {
+            debug!(
+                url = target.url,
+                len = queue.len(),
+                "webhook queue restored"
+            );
+        }
         WebHook {
-            url,
             client: reqwest::Client::builder()
-                .connect_timeout(Duration::from_millis(300))
-                .timeout(Duration::from_millis(500))
+                .connect_timeout(timeout)
+                .timeout(timeout)
                 .build()
                 .unwrap(),
+            queue: Mutex::new(queue),
+            notify: Notify::new(),
+            target,
         }
     }
 
-    async fn event_handler(&self, event: Event) -> Result<()> {
+    fn accept(&self, event: &api::event::Event) -> bool {
+        let api::event::Event::Stream { stream, .. } = event;
+        accept_type(&self.target.events, event) && self.target.matches_stream(&stream.stream)
+    }
+
+    async fn enqueue(&self, event: Event) -> Result<()> {
         let event = event.convert_api_event();
-        let event_body = EventBody {
+        if !self.accept(&event) {
+            return Ok(());
+        }
+        let body = serde_json::to_string(&EventBody {
             metrics: node_metrics(),
             event,
+        })?;
+        let mut queue = self.queue.lock().await;
+        if queue.len() >= self.target.queue_size.max(1) {
+            let dropped = queue.pop_front();
+            warn!(
+                url = self.target.url,
+                ?dropped,
+                "webhook queue full, drop the oldest event"
+            );
+        }
+        queue.push_back(QueuedEvent {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            body,
+        });
+        self.save(&queue).await;
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn save(&self, queue: &VecDeque<QueuedEvent>) {
+        let Some(path) = &self.target.queue_path else {
+            return;
         };
-        let req_body = serde_json::to_string(&event_body)?;
-        let mut headers = HeaderMap::new();
-        headers.append("Content-Type", "application/json".parse().unwrap());
-        match self
+        if let Err(err) = save_queue(path, queue).await {
+            warn!(
+                url = self.target.url,
+                path,
+                ?err,
+                "webhook queue save error"
+            );
+        }
+    }
+
+    /// Delivers the events in order, an event is retried before the ones after it
+    async fn deliver(&self) {
+        loop {
+            let Some(event) = self.queue.lock().await.front().cloned() else {
+                self.notify.notified().await;
+                continue;
+            };
+            let mut retries = 0;
+            while self.event_handler(&event).await.is_err() {
+                if retries >= self.target.max_retries {
+                    warn!(
+                        url = self.target.url,
+                        id = event.id,
+                        retries,
+                        "event webhook give up"
+                    );
+                    break;
+                }
+                tokio::time::sleep(backoff(
+                    self.target.backoff,
+                    self.target.max_backoff,
+                    retries,
+                ))
+                .await;
+                retries += 1;
+            }
+            let mut queue = self.queue.lock().await;
+            // The event may have been dropped from a full queue meanwhile
+            if queue.front() == Some(&event) {
+                queue.pop_front();
+            }
+            self.save(&queue).await;
+        }
+    }
+
+    async fn event_handler(&self, event: &QueuedEvent) -> Result<()> {
+        let mut request = self
             .client
-            .request(Method::from_str("POST")?, self.url.clone())
-            .headers(headers)
-            .body(req_body.clone())
-            .send()
-            .await
-        {
+            .post(self.target.url.clone())
+            .header("Content-Type", "application/json")
+            .header(EVENT_ID_HEADER, event.id.clone());
+        if !self.target.secret.is_empty() {
+            request = request.header(
+                SIGNATURE_HEADER,
+                signature(&self.target.secret, &event.body),
+            );
+        }
+        let req_body = event.body.clone();
+        match request.body(req_body.clone()).send().await {
             Ok(response) => {
                 let status = response.status();
                 let success = response.status().is_success();
                 let res_body = response.text().await?;
                 if success {
                     debug!(
-                        url = self.url,
+                        url = self.target.url,
                         ?status,
                         req_body,
                         res_body,
@@ -59,17 +171,23 @@ impl WebHook {
                     Ok(())
                 } else {
                     warn!(
-                        url = self.url,
+                        url = self.target.url,
                         ?status,
                         req_body,
                         res_body,
                         "event webhook error"
                     );
+                    metrics::WEBHOOK_FAILURES
+                        .with_label_values(&["status"])
+                        .inc();
                     Err(AppError::throw(res_body))
                 }
             }
             Err(err) => {
-                warn!(url = self.url, req_body, ?err, "event webhook error");
+                warn!(url = self.target.url, req_body, ?err, "event webhook error");
+                metrics::WEBHOOK_FAILURES
+                    .with_label_values(&["request"])
+                    .inc();
                 Err(err.into())
             }
         }
@@ -79,17 +197,138 @@ impl WebHook {
 #[async_trait]
 impl EventHook for WebHook {
     async fn hook(&self, mut event_receiver: broadcast::Receiver<Event>) {
-        while let Ok(event) = event_receiver.recv().await {
-            let _ = self.event_handler(event).await;
-        }
+        let receive = async {
+            loop {
+                match event_receiver.recv().await {
+                    Ok(event) => {
+                        if let Err(err) = self.enqueue(event).await {
+                            warn!(url = self.target.url, ?err, "event webhook enqueue error");
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!(url = self.target.url, count, "event webhook lagged");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+        tokio::join!(receive, self.deliver());
     }
 }
 
-fn node_metrics() -> NodeMetrics {
-    NodeMetrics {
-        stream: metrics::STREAM.get() as u64,
-        publish: metrics::PUBLISH.get() as u64,
-        subscribe: metrics::SUBSCRIBE.get() as u64,
-        reforward: metrics::REFORWARD.get() as u64,
+/// `sha256=` and the hex HMAC-SHA256 of the body
+fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+fn backoff(backoff: u64, max_backoff: u64, retries: u32) -> Duration {
+    Duration::from_millis(
+        backoff
+            .saturating_mul(1u64 << retries.min(32))
+            .min(max_backoff.max(backoff)),
+    )
+}
+
+async fn load_queue(path: &str) -> VecDeque<QueuedEvent> {
+    match tokio::fs::read(path).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+            warn!(path, ?err, "webhook queue is corrupted, start empty");
+            VecDeque::new()
+        }),
+        Err(_) => VecDeque::new(),
+    }
+}
+
+async fn save_queue(path: &str, queue: &VecDeque<QueuedEvent>) -> Result<()> {
+    let tmp = format!("{path}.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(queue)?).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use super::{QueuedEvent, WebHook, backoff, load_queue, save_queue, signature};
+    use crate::config::WebhookTarget;
+    use crate::hook::{StreamEventType, stream_event};
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(500, 30_000, 0), Duration::from_millis(500));
+        assert_eq!(backoff(500, 30_000, 3), Duration::from_millis(4000));
+        assert_eq!(backoff(500, 30_000, 10), Duration::from_millis(30_000));
+        assert_eq!(backoff(500, 30_000, 100), Duration::from_millis(30_000));
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let webhook = WebHook::new(WebhookTarget {
+            url: "http://127.0.0.1:1/webhook".to_string(),
+            events: vec!["streamUp".to_string()],
+            streams: vec!["camera-*".to_string()],
+            ..Default::default()
+        })
+        .await;
+        for event in [
+            stream_event(StreamEventType::Up, "camera-1"),
+            stream_event(StreamEventType::Down, "camera-1"),
+            stream_event(StreamEventType::Up, "screen-1"),
+        ] {
+            webhook.enqueue(event).await.unwrap();
+        }
+        let queue = webhook.queue.lock().await;
+        assert_eq!(queue.len(), 1);
+        assert!(queue[0].body.contains("camera-1"));
+    }
+
+    #[tokio::test]
+    async fn test_queue_limit_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("webhook.json")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let target = WebhookTarget {
+            url: "http://127.0.0.1:1/webhook".to_string(),
+            queue_size: 2,
+            queue_path: Some(path.clone()),
+            ..Default::default()
+        };
+        let webhook = WebHook::new(target.clone()).await;
+        for stream in ["a", "b", "c"] {
+            webhook
+                .enqueue(stream_event(StreamEventType::Up, stream))
+                .await
+                .unwrap();
+        }
+        let queue = load_queue(&path).await;
+        assert_eq!(queue, *webhook.queue.lock().await);
+        assert_eq!(queue.len(), 2);
+        assert!(queue[0].body.contains("\"b\""));
+
+        // restored by the next start
+        let restarted = WebHook::new(target).await;
+        assert_eq!(*restarted.queue.lock().await, queue);
+
+        save_queue(&path, &VecDeque::<QueuedEvent>::new())
+            .await
+            .unwrap();
+        assert!(load_queue(&path).await.is_empty());
     }
 }
@@ -0,0 +1,11 @@
+//! Inputs publishing to the streams from other protocols, through a WebRTC peer in the process
+
+mod packetizer;
+mod publisher;
+#[cfg(feature = "rtmp")]
+pub mod rtmp;
+#[cfg(feature = "srt")]
+pub mod srt;
+
+pub use packetizer::RtpPacketizer;
+pub use publisher::Publisher;
@@ -0,0 +1,206 @@
+use anyhow::Result;
+use bytes::Bytes;
+use cli::Codec;
+use livetwo::payload::{H264Processor, RTP_OUTBOUND_MTU};
+use webrtc::rtp::codecs::h264::H264Payloader;
+use webrtc::rtp::header::Header;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp::packetizer::Payloader;
+
+const RTP_HEADER_SIZE: usize = 12;
+const MAX_PAYLOAD_SIZE: usize = RTP_OUTBOUND_MTU - RTP_HEADER_SIZE;
+const H265_NAL_TYPE_FU: u8 = 49;
+
+/// Repacketizes Annex-B video frames and Opus packets to RTP.
+/// The H.264 parameter sets are added to the IDR frames without them,
+/// H.265 frames are expected to carry theirs.
+pub struct RtpPacketizer {
+    /// None for H.265
+    params: Option<H264Processor>,
+    h264: H264Payloader,
+    video_sequence: u16,
+    audio_sequence: u16,
+}
+
+impl RtpPacketizer {
+    /// H.264 or H.265 video
+    pub fn new(video: Codec) -> Self {
+        Self {
+            params: (video != Codec::H265).then(H264Processor::new),
+            h264: H264Payloader::default(),
+            video_sequence: 0,
+            audio_sequence: 0,
+        }
+    }
+
+    /// The parameter sets given out of band, e.g. by an AVCDecoderConfigurationRecord
+    pub fn set_h264_params(&mut self, sps: Vec<u8>, pps: Vec<u8>) {
+        if let Some(processor) = &mut self.params {
+            processor.set_params(sps, pps);
+        }
+    }
+
+    /// The packets of a frame at `timestamp`, in the 90 kHz clock
+    pub fn video(&mut self, frame: &[u8], timestamp: u32) -> Result<Vec<Packet>> {
+        let payloads = match &mut self.params {
+            Some(processor) => {
+                processor.extract_params(frame);
+                let frame = processor.inject_params(frame);
+                self.h264.payload(MAX_PAYLOAD_SIZE, &Bytes::from(frame))?
+            }
+            None => h265_payloads(frame),
+        };
+        let count = payloads.len();
+        Ok(payloads
+            .into_iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let sequence_number = self.video_sequence;
+                self.video_sequence = self.video_sequence.wrapping_add(1);
+                packet(sequence_number, timestamp, i + 1 == count, payload)
+            })
+            .collect())
+    }
+
+    /// One Opus packet at `timestamp`, in the 48 kHz clock
+    pub fn audio(&mut self, data: Bytes, timestamp: u32) -> Packet {
+        let sequence_number = self.audio_sequence;
+        self.audio_sequence = self.audio_sequence.wrapping_add(1);
+        packet(sequence_number, timestamp, true, data)
+    }
+}
+
+/// The NAL units of an Annex-B frame, without the start codes
+fn nal_units(frame: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= frame.len() {
+        if frame[i] == 0 && frame[i + 1] == 0 && frame[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|start| start - 3)
+        .chain(std::iter::once(frame.len()))
+        .collect();
+    starts
+        .into_iter()
+        .zip(ends)
+        .filter_map(move |(start, end)| {
+            // The zero byte of a 4 byte start code, and trailing zeros
+            let mut end = end;
+            while end > start && frame[end - 1] == 0 {
+                end -= 1;
+            }
+            (end > start).then(|| &frame[start..end])
+        })
+}
+
+/// Single NAL unit packets, and fragmentation units for the larger ones (RFC 7798)
+fn h265_payloads(frame: &[u8]) -> Vec<Bytes> {
+    let mut payloads = vec![];
+    for nalu in nal_units(frame) {
+        if nalu.len() <= MAX_PAYLOAD_SIZE {
+            payloads.push(Bytes::copy_from_slice(nalu));
+            continue;
+        }
+        if nalu.len() < 3 {
+            continue;
+        }
+        let nal_type = (nalu[0] >> 1) & 0x3f;
+        let header = [(nalu[0] & 0x81) | (H265_NAL_TYPE_FU << 1), nalu[1]];
+        let chunks: Vec<&[u8]> = nalu[2..].chunks(MAX_PAYLOAD_SIZE - 3).collect();
+        let last = chunks.len() - 1;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let mut fu_header = nal_type;
+            if i == 0 {
+                fu_header |= 0x80;
+            }
+            if i == last {
+                fu_header |= 0x40;
+            }
+            let mut payload = Vec::with_capacity(chunk.len() + 3);
+            payload.extend_from_slice(&header);
+            payload.push(fu_header);
+            payload.extend_from_slice(chunk);
+            payloads.push(Bytes::from(payload));
+        }
+    }
+    payloads
+}
+
+/// The payload type and SSRC are set by the local track
+fn packet(sequence_number: u16, timestamp: u32, marker: bool, payload: Bytes) -> Packet {
+    Packet {
+        header: Header {
+            version: 2,
+            marker,
+            sequence_number,
+            timestamp,
+            ..Default::default()
+        },
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use cli::Codec;
+
+    use super::{RtpPacketizer, nal_units};
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+    #[test]
+    fn test_h264() {
+        let mut packetizer = RtpPacketizer::new(Codec::H264);
+        packetizer.set_h264_params(SPS.to_vec(), PPS.to_vec());
+
+        let mut frame = vec![0, 0, 0, 1, 0x65];
+        frame.extend_from_slice(&[0x88; 3000]);
+        let packets = packetizer.video(&frame, 90000).unwrap();
+        // The SPS and PPS in a STAP-A, then the fragments of the IDR
+        assert_eq!(packets[0].payload[0] & 0x1f, 24);
+        assert_eq!(packets[1].payload[0] & 0x1f, 28);
+        assert_eq!(packets.len(), 4);
+        assert!(
+            packets
+                .iter()
+                .all(|packet| packet.header.timestamp == 90000)
+        );
+        assert!(packets.iter().all(|packet| packet.payload.len() <= 1188));
+        assert!(packets[3].header.marker && !packets[2].header.marker);
+        assert_eq!(packets[3].header.sequence_number, 3);
+
+        let packet = packetizer.audio(Bytes::from_static(&[0xfc]), 960);
+        assert_eq!(packet.header.timestamp, 960);
+        assert_eq!(packet.header.sequence_number, 0);
+    }
+
+    #[test]
+    fn test_h265() {
+        let mut packetizer = RtpPacketizer::new(Codec::H265);
+        // An AUD, then an IDR_W_RADL larger than a packet
+        let mut frame = vec![0, 0, 0, 1, 0x46, 0x01, 0x50, 0, 0, 1, 0x26, 0x01];
+        frame.extend_from_slice(&[0x88; 2000]);
+        assert_eq!(nal_units(&frame).count(), 2);
+
+        let packets = packetizer.video(&frame, 3000).unwrap();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(&packets[0].payload[..], &[0x46, 0x01, 0x50]);
+        assert_eq!(&packets[1].payload[..3], &[49 << 1, 0x01, 0x80 | 19]);
+        assert_eq!(&packets[2].payload[..3], &[49 << 1, 0x01, 0x40 | 19]);
+        assert_eq!(
+            packets[1].payload.len() + packets[2].payload.len(),
+            2000 + 6
+        );
+        assert!(packets[2].header.marker);
+    }
+}
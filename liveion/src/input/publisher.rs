@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use cli::Codec;
+use tokio::sync::Notify;
+use tracing::debug;
+use webrtc::api::APIBuilder;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+
+use crate::forward::network::Network;
+use crate::result::Result;
+use crate::stream::callout::ClientInfo;
+use crate::stream::manager::Manager;
+
+/// A WebRTC publisher of the stream, fed with the RTP packets of another protocol.
+/// The offer has a video and an Opus track, a track appears once its packets are written.
+/// Both peers only gather loopback candidates, the UDP mux and NAT 1:1 IPs are left out.
+pub struct Publisher {
+    manager: Arc<Manager>,
+    stream: String,
+    session: String,
+    peer: Arc<RTCPeerConnection>,
+    video: Arc<TrackLocalStaticRTP>,
+    audio: Arc<TrackLocalStaticRTP>,
+    closed: Arc<Notify>,
+}
+
+impl Publisher {
+    pub async fn new(
+        manager: Arc<Manager>,
+        stream: String,
+        video: Codec,
+        client: ClientInfo,
+    ) -> Result<Self> {
+        let mut m = MediaEngine::default();
+        m.register_default_codecs()?;
+        let mut registry = Registry::new();
+        registry = register_default_interceptors(registry, &mut m)?;
+        let mut s = SettingEngine::default();
+        s.set_ice_multicast_dns_mode(MulticastDnsMode::Disabled);
+        Network::apply_loopback(&mut s);
+        let api = APIBuilder::new()
+            .with_media_engine(m)
+            .with_interceptor_registry(registry)
+            .with_setting_engine(s)
+            .build();
+        let peer = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await?);
+
+        let video = Arc::new(TrackLocalStaticRTP::new(
+            video.into(),
+            "video".to_owned(),
+            stream.clone(),
+        ));
+        let audio = Arc::new(TrackLocalStaticRTP::new(
+            Codec::Opus.into(),
+            "audio".to_owned(),
+            stream.clone(),
+        ));
+        for track in [video.clone(), audio.clone()] {
+            let sender = peer
+                .add_transceiver_from_track(
+                    track as Arc<dyn TrackLocal + Send + Sync>,
+                    Some(RTCRtpTransceiverInit {
+                        direction: RTCRtpTransceiverDirection::Sendonly,
+                        send_encodings: Vec::new(),
+                    }),
+                )
+                .await?
+                .sender()
+                .await;
+            // Keyframe requests can't reach the source, RTCP is only read for the interceptors
+            tokio::spawn(async move { while sender.read_rtcp().await.is_ok() {} });
+        }
+
+        let closed = Arc::new(Notify::new());
+        let notify = closed.clone();
+        peer.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+            if matches!(
+                s,
+                RTCPeerConnectionState::Failed
+                    | RTCPeerConnectionState::Disconnected
+                    | RTCPeerConnectionState::Closed
+            ) {
+                notify.notify_one();
+            }
+            Box::pin(async {})
+        }));
+
+        let offer = peer.create_offer(None).await?;
+        let mut gather_complete = peer.gathering_complete_promise().await;
+        peer.set_local_description(offer).await?;
+        let _ = gather_complete.recv().await;
+        let offer = peer
+            .local_description()
+            .await
+            .ok_or(anyhow!("failed to get local description"))?;
+
+        let (answer, session) = match manager
+            .publish_loopback(stream.clone(), offer, client)
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                let _ = peer.close().await;
+                return Err(err);
+            }
+        };
+        let publisher = Self {
+            manager,
+            stream,
+            session,
+            peer,
+            video,
+            audio,
+            closed,
+        };
+        if let Err(err) = publisher.peer.set_remote_description(answer).await {
+            publisher.close().await;
+            return Err(err.into());
+        }
+        Ok(publisher)
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Packets written before the peer is connected are dropped
+    pub async fn write_video(&self, packet: &Packet) {
+        if let Err(err) = self.video.write_rtp(packet).await {
+            debug!(stream = self.stream, ?err, "write video error");
+        }
+    }
+
+    pub async fn write_audio(&self, packet: &Packet) {
+        if let Err(err) = self.audio.write_rtp(packet).await {
+            debug!(stream = self.stream, ?err, "write audio error");
+        }
+    }
+
+    /// Resolves once the peer is gone, e.g. the stream was deleted
+    pub async fn closed(&self) {
+        self.closed.notified().await
+    }
+
+    pub async fn close(self) {
+        let _ = self.peer.close().await;
+        if let Err(err) = self
+            .manager
+            .remove_stream_session(self.stream.clone(), self.session)
+            .await
+        {
+            debug!(stream = self.stream, ?err, "remove publish session error");
+        }
+    }
+}
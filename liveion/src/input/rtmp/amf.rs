@@ -0,0 +1,235 @@
+//! AMF0, the encoding of the RTMP commands and metadata
+
+use anyhow::{Result, anyhow};
+
+const NUMBER: u8 = 0x00;
+const BOOLEAN: u8 = 0x01;
+const STRING: u8 = 0x02;
+const OBJECT: u8 = 0x03;
+const NULL: u8 = 0x05;
+const UNDEFINED: u8 = 0x06;
+const ECMA_ARRAY: u8 = 0x08;
+const OBJECT_END: u8 = 0x09;
+const STRICT_ARRAY: u8 = 0x0a;
+const DATE: u8 = 0x0b;
+const LONG_STRING: u8 = 0x0c;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Amf0 {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Amf0)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, Amf0)>),
+    StrictArray(Vec<Amf0>),
+    Date(f64),
+}
+
+impl Amf0 {
+    pub fn object<const N: usize>(properties: [(&str, Amf0); N]) -> Self {
+        Amf0::Object(
+            properties
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn string(value: &str) -> Self {
+        Amf0::String(value.to_string())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Amf0::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Amf0::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The property `key` of an object or ECMA array
+    pub fn get(&self, key: &str) -> Option<&Amf0> {
+        match self {
+            Amf0::Object(properties) | Amf0::EcmaArray(properties) => properties
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Amf0::Number(value) => {
+                out.push(NUMBER);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Amf0::Boolean(value) => {
+                out.push(BOOLEAN);
+                out.push(*value as u8);
+            }
+            Amf0::String(value) if value.len() > u16::MAX as usize => {
+                out.push(LONG_STRING);
+                out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                out.extend_from_slice(value.as_bytes());
+            }
+            Amf0::String(value) => {
+                out.push(STRING);
+                encode_key(value, out);
+            }
+            Amf0::Object(properties) => {
+                out.push(OBJECT);
+                encode_properties(properties, out);
+            }
+            Amf0::Null => out.push(NULL),
+            Amf0::Undefined => out.push(UNDEFINED),
+            Amf0::EcmaArray(properties) => {
+                out.push(ECMA_ARRAY);
+                out.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+                encode_properties(properties, out);
+            }
+            Amf0::StrictArray(values) => {
+                out.push(STRICT_ARRAY);
+                out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+                for value in values {
+                    value.encode(out);
+                }
+            }
+            Amf0::Date(value) => {
+                out.push(DATE);
+                out.extend_from_slice(&value.to_be_bytes());
+                out.extend_from_slice(&0i16.to_be_bytes());
+            }
+        }
+    }
+
+    fn decode(data: &mut &[u8]) -> Result<Self> {
+        let marker = take(data, 1)?[0];
+        Ok(match marker {
+            NUMBER => Amf0::Number(f64::from_be_bytes(take(data, 8)?.try_into()?)),
+            BOOLEAN => Amf0::Boolean(take(data, 1)?[0] != 0),
+            STRING => Amf0::String(decode_key(data)?),
+            OBJECT => Amf0::Object(decode_properties(data)?),
+            NULL => Amf0::Null,
+            UNDEFINED => Amf0::Undefined,
+            ECMA_ARRAY => {
+                // The count is a hint, the properties end with the object end marker
+                take(data, 4)?;
+                Amf0::EcmaArray(decode_properties(data)?)
+            }
+            STRICT_ARRAY => {
+                let count = u32::from_be_bytes(take(data, 4)?.try_into()?);
+                let mut values = vec![];
+                for _ in 0..count {
+                    values.push(Amf0::decode(data)?);
+                }
+                Amf0::StrictArray(values)
+            }
+            DATE => {
+                let value = f64::from_be_bytes(take(data, 8)?.try_into()?);
+                take(data, 2)?;
+                Amf0::Date(value)
+            }
+            LONG_STRING => {
+                let len = u32::from_be_bytes(take(data, 4)?.try_into()?) as usize;
+                Amf0::String(String::from_utf8_lossy(take(data, len)?).into_owned())
+            }
+            marker => return Err(anyhow!("unsupported amf0 marker {marker:#04x}")),
+        })
+    }
+}
+
+/// The values of a command or data message, one after another
+pub fn decode_all(mut data: &[u8]) -> Result<Vec<Amf0>> {
+    let mut values = vec![];
+    while !data.is_empty() {
+        values.push(Amf0::decode(&mut data)?);
+    }
+    Ok(values)
+}
+
+pub fn encode_all(values: &[Amf0]) -> Vec<u8> {
+    let mut out = vec![];
+    for value in values {
+        value.encode(&mut out);
+    }
+    out
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(anyhow!("amf0 value is truncated"));
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+fn decode_key(data: &mut &[u8]) -> Result<String> {
+    let len = u16::from_be_bytes(take(data, 2)?.try_into()?) as usize;
+    Ok(String::from_utf8_lossy(take(data, len)?).into_owned())
+}
+
+fn encode_key(key: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    out.extend_from_slice(key.as_bytes());
+}
+
+fn decode_properties(data: &mut &[u8]) -> Result<Vec<(String, Amf0)>> {
+    let mut properties = vec![];
+    loop {
+        let key = decode_key(data)?;
+        if key.is_empty() && data.first() == Some(&OBJECT_END) {
+            take(data, 1)?;
+            return Ok(properties);
+        }
+        let value = Amf0::decode(data)?;
+        properties.push((key, value));
+    }
+}
+
+fn encode_properties(properties: &[(String, Amf0)], out: &mut Vec<u8>) {
+    for (key, value) in properties {
+        encode_key(key, out);
+        value.encode(out);
+    }
+    out.extend_from_slice(&[0, 0, OBJECT_END]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Amf0, decode_all, encode_all};
+
+    #[test]
+    fn test_amf0() {
+        let values = vec![
+            Amf0::string("connect"),
+            Amf0::Number(1.0),
+            Amf0::object([
+                ("app", Amf0::string("live")),
+                ("tcUrl", Amf0::string("rtmp://127.0.0.1/live")),
+                ("fpad", Amf0::Boolean(false)),
+                ("audioCodecs", Amf0::Number(3575.0)),
+            ]),
+            Amf0::Null,
+            Amf0::EcmaArray(vec![("width".to_string(), Amf0::Number(1280.0))]),
+            Amf0::StrictArray(vec![Amf0::Undefined]),
+        ];
+        let data = encode_all(&values);
+        assert_eq!(&data[..10], b"\x02\x00\x07connect");
+        assert_eq!(decode_all(&data).unwrap(), values);
+        assert_eq!(
+            values[2].get("app").and_then(|app| app.as_str()),
+            Some("live")
+        );
+        assert!(decode_all(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! RTMP chunk stream: messages are split into chunks of the negotiated size,
+//! the chunk headers only carry what changed from the previous message of the chunk stream
+
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const DEFAULT_CHUNK_SIZE: usize = 128;
+/// Larger messages are refused, a video frame above it is not expected
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+const EXTENDED_TIMESTAMP: u32 = 0xffffff;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub type_id: u8,
+    pub stream_id: u32,
+    /// Milliseconds
+    pub timestamp: u32,
+    pub payload: Bytes,
+}
+
+#[derive(Default)]
+struct ChunkStream {
+    timestamp: u32,
+    timestamp_delta: u32,
+    length: usize,
+    type_id: u8,
+    stream_id: u32,
+    extended: bool,
+    payload: BytesMut,
+}
+
+pub struct ChunkReader<R> {
+    reader: R,
+    chunk_size: usize,
+    streams: HashMap<u32, ChunkStream>,
+    /// Counted for the acknowledgements
+    pub bytes_read: u64,
+}
+
+impl<R: AsyncRead + Unpin> ChunkReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            streams: HashMap::new(),
+            bytes_read: 0,
+        }
+    }
+
+    /// Set by the Set Chunk Size message of the peer
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.clamp(1, MAX_MESSAGE_SIZE);
+    }
+
+    async fn read(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data).await?;
+        self.bytes_read += len as u64;
+        Ok(data)
+    }
+
+    async fn read_u24(&mut self) -> Result<u32> {
+        let data = self.read(3).await?;
+        Ok(u32::from_be_bytes([0, data[0], data[1], data[2]]))
+    }
+
+    async fn read_u32(&mut self) -> Result<u32> {
+        let data = self.read(4).await?;
+        Ok(u32::from_be_bytes(data.try_into().unwrap()))
+    }
+
+    /// Reads chunks until a message is complete, not cancel safe
+    pub async fn read_message(&mut self) -> Result<Message> {
+        loop {
+            let first = self.read(1).await?[0];
+            let fmt = first >> 6;
+            let csid = match first & 0x3f {
+                0 => 64 + self.read(1).await?[0] as u32,
+                1 => {
+                    let data = self.read(2).await?;
+                    64 + data[0] as u32 + data[1] as u32 * 256
+                }
+                csid => csid as u32,
+            };
+
+            let (timestamp, length, type_id, stream_id) = match fmt {
+                0 => {
+                    let timestamp = self.read_u24().await?;
+                    let length = self.read_u24().await?;
+                    let type_id = self.read(1).await?[0];
+                    let data = self.read(4).await?;
+                    let stream_id = u32::from_le_bytes(data.try_into().unwrap());
+                    (
+                        Some(timestamp),
+                        Some(length),
+                        Some(type_id),
+                        Some(stream_id),
+                    )
+                }
+                1 => {
+                    let delta = self.read_u24().await?;
+                    let length = self.read_u24().await?;
+                    let type_id = self.read(1).await?[0];
+                    (Some(delta), Some(length), Some(type_id), None)
+                }
+                2 => (Some(self.read_u24().await?), None, None, None),
+                _ => (None, None, None, None),
+            };
+            let extended = match timestamp {
+                Some(timestamp) => timestamp == EXTENDED_TIMESTAMP,
+                None => self
+                    .streams
+                    .get(&csid)
+                    .is_some_and(|stream| stream.extended),
+            };
+            let timestamp = match (timestamp, extended) {
+                (_, true) => Some(self.read_u32().await?),
+                (timestamp, false) => timestamp,
+            };
+
+            let chunk_size = self.chunk_size;
+            let stream = self.streams.entry(csid).or_default();
+            if fmt != 3 {
+                // A new message header drops an interrupted message
+                stream.payload.clear();
+                stream.extended = extended;
+            }
+            let new_message = stream.payload.is_empty();
+            if let Some(length) = length {
+                stream.length = length as usize;
+            }
+            if let Some(type_id) = type_id {
+                stream.type_id = type_id;
+            }
+            if let Some(stream_id) = stream_id {
+                stream.stream_id = stream_id;
+            }
+            match (fmt, timestamp) {
+                (0, Some(timestamp)) => {
+                    stream.timestamp = timestamp;
+                    stream.timestamp_delta = 0;
+                }
+                (1 | 2, Some(delta)) => {
+                    stream.timestamp_delta = delta;
+                    stream.timestamp = stream.timestamp.wrapping_add(delta);
+                }
+                _ if new_message => {
+                    stream.timestamp = stream.timestamp.wrapping_add(stream.timestamp_delta);
+                }
+                _ => {}
+            }
+            if stream.length > MAX_MESSAGE_SIZE {
+                return Err(anyhow!("message of {} bytes is too large", stream.length));
+            }
+
+            let len = chunk_size.min(stream.length - stream.payload.len());
+            let data = self.read(len).await?;
+            let stream = self.streams.get_mut(&csid).unwrap();
+            stream.payload.extend_from_slice(&data);
+            if stream.payload.len() == stream.length {
+                return Ok(Message {
+                    type_id: stream.type_id,
+                    stream_id: stream.stream_id,
+                    timestamp: stream.timestamp,
+                    payload: stream.payload.split().freeze(),
+                });
+            }
+        }
+    }
+}
+
+/// The chunks of `message` on the chunk stream `csid`, the first with a full header
+pub fn encode(chunk_size: usize, csid: u8, message: &Message) -> Vec<u8> {
+    let mut out = Vec::with_capacity(message.payload.len() + 16);
+    let extended = message.timestamp >= EXTENDED_TIMESTAMP;
+    out.push(csid & 0x3f);
+    out.extend_from_slice(&message.timestamp.min(EXTENDED_TIMESTAMP).to_be_bytes()[1..]);
+    out.extend_from_slice(&(message.payload.len() as u32).to_be_bytes()[1..]);
+    out.push(message.type_id);
+    out.extend_from_slice(&message.stream_id.to_le_bytes());
+    if extended {
+        out.extend_from_slice(&message.timestamp.to_be_bytes());
+    }
+    for (i, chunk) in message.payload.chunks(chunk_size.max(1)).enumerate() {
+        if i > 0 {
+            out.push(0xc0 | (csid & 0x3f));
+            if extended {
+                out.extend_from_slice(&message.timestamp.to_be_bytes());
+            }
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{ChunkReader, Message, encode};
+
+    #[tokio::test]
+    async fn test_chunks() {
+        let video = Message {
+            type_id: 9,
+            stream_id: 1,
+            timestamp: 40,
+            payload: Bytes::from(vec![7; 300]),
+        };
+        let mut data = encode(128, 6, &video);
+        assert_eq!(data.len(), 12 + 300 + 2);
+        // The next message of the chunk stream with a delta timestamp only
+        data.extend_from_slice(&[0x86, 0, 0, 40]);
+        data.extend_from_slice(&[7; 128]);
+        data.extend_from_slice(&[0xc6]);
+        data.extend_from_slice(&[7; 128]);
+        data.extend_from_slice(&[0xc6]);
+        data.extend_from_slice(&[7; 44]);
+        let late = Message {
+            timestamp: 0x01000000,
+            ..video.clone()
+        };
+        data.extend_from_slice(&encode(128, 6, &late));
+
+        let mut reader = ChunkReader::new(&data[..]);
+        assert_eq!(reader.read_message().await.unwrap(), video);
+        let next = reader.read_message().await.unwrap();
+        assert_eq!(next.timestamp, 80);
+        assert_eq!(next.payload, video.payload);
+        assert_eq!(reader.read_message().await.unwrap(), late);
+        assert_eq!(reader.bytes_read, data.len() as u64);
+        assert!(reader.read_message().await.is_err());
+    }
+}
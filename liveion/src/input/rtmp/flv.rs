@@ -0,0 +1,252 @@
+//! FLV audio and video tags of the RTMP messages, legacy and Enhanced RTMP
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+
+const CODEC_AVC: u8 = 7;
+const SOUND_FORMAT_AAC: u8 = 10;
+/// The sound format of the Enhanced RTMP audio tags
+const SOUND_FORMAT_EX_HEADER: u8 = 9;
+const FRAME_TYPE_KEY: u8 = 1;
+const FRAME_TYPE_COMMAND: u8 = 5;
+
+const PACKET_TYPE_SEQUENCE_START: u8 = 0;
+const PACKET_TYPE_CODED_FRAMES: u8 = 1;
+/// Enhanced RTMP coded video frames without the composition time
+const PACKET_TYPE_CODED_FRAMES_X: u8 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum VideoTag {
+    /// The AVCDecoderConfigurationRecord
+    AvcConfig(Bytes),
+    /// NAL units with length prefixes
+    AvcFrame {
+        keyframe: bool,
+        /// Milliseconds from the decode time to the presentation time
+        composition_time: i32,
+        data: Bytes,
+    },
+    Unsupported(String),
+    Ignored,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AudioTag {
+    Opus(Bytes),
+    Unsupported(&'static str),
+    Ignored,
+}
+
+pub fn parse_video(payload: &Bytes) -> Result<VideoTag> {
+    let Some(&first) = payload.first() else {
+        return Ok(VideoTag::Ignored);
+    };
+    let truncated = || anyhow!("video tag is truncated");
+
+    if first & 0x80 != 0 {
+        // Enhanced RTMP: frame type, packet type and the FourCC of the codec
+        let frame_type = (first >> 4) & 0x07;
+        let packet_type = first & 0x0f;
+        let fourcc = payload.get(1..5).ok_or_else(truncated)?;
+        if fourcc != b"avc1" {
+            return Ok(VideoTag::Unsupported(
+                String::from_utf8_lossy(fourcc).into_owned(),
+            ));
+        }
+        if frame_type == FRAME_TYPE_COMMAND {
+            return Ok(VideoTag::Ignored);
+        }
+        return Ok(match packet_type {
+            PACKET_TYPE_SEQUENCE_START => VideoTag::AvcConfig(payload.slice(5..)),
+            PACKET_TYPE_CODED_FRAMES => VideoTag::AvcFrame {
+                keyframe: frame_type == FRAME_TYPE_KEY,
+                composition_time: composition_time(payload.get(5..8).ok_or_else(truncated)?),
+                data: payload.slice(8..),
+            },
+            PACKET_TYPE_CODED_FRAMES_X => VideoTag::AvcFrame {
+                keyframe: frame_type == FRAME_TYPE_KEY,
+                composition_time: 0,
+                data: payload.slice(5..),
+            },
+            _ => VideoTag::Ignored,
+        });
+    }
+
+    let frame_type = first >> 4;
+    let codec = first & 0x0f;
+    if codec != CODEC_AVC {
+        return Ok(VideoTag::Unsupported(format!("codec id {codec}")));
+    }
+    if frame_type == FRAME_TYPE_COMMAND {
+        return Ok(VideoTag::Ignored);
+    }
+    let header = payload.get(1..5).ok_or_else(truncated)?;
+    Ok(match header[0] {
+        0 => VideoTag::AvcConfig(payload.slice(5..)),
+        1 => VideoTag::AvcFrame {
+            keyframe: frame_type == FRAME_TYPE_KEY,
+            composition_time: composition_time(&header[1..4]),
+            data: payload.slice(5..),
+        },
+        _ => VideoTag::Ignored,
+    })
+}
+
+pub fn parse_audio(payload: &Bytes) -> Result<AudioTag> {
+    let Some(&first) = payload.first() else {
+        return Ok(AudioTag::Ignored);
+    };
+    match first >> 4 {
+        SOUND_FORMAT_EX_HEADER => {
+            let fourcc = payload
+                .get(1..5)
+                .ok_or_else(|| anyhow!("audio tag is truncated"))?;
+            if fourcc != b"Opus" {
+                return Ok(AudioTag::Unsupported("Enhanced RTMP audio codec"));
+            }
+            // The sequence start is the OpusHead, not needed by RTP
+            Ok(match first & 0x0f {
+                PACKET_TYPE_CODED_FRAMES => AudioTag::Opus(payload.slice(5..)),
+                _ => AudioTag::Ignored,
+            })
+        }
+        SOUND_FORMAT_AAC => Ok(AudioTag::Unsupported("AAC")),
+        _ => Ok(AudioTag::Unsupported("legacy audio codec")),
+    }
+}
+
+/// SI24
+fn composition_time(data: &[u8]) -> i32 {
+    i32::from_be_bytes([data[0], data[1], data[2], 0]) >> 8
+}
+
+/// The parameter sets and the NAL unit length size of an AVCDecoderConfigurationRecord
+pub fn parse_avc_config(data: &[u8]) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>, usize)> {
+    let truncated = || anyhow!("AVCDecoderConfigurationRecord is truncated");
+    let header = data.get(..6).ok_or_else(truncated)?;
+    let length_size = (header[4] & 0x03) as usize + 1;
+    let mut offset = 6;
+    let read_sets = |count: usize, offset: &mut usize| -> Result<Vec<Vec<u8>>> {
+        let mut sets = vec![];
+        for _ in 0..count {
+            let len = data.get(*offset..*offset + 2).ok_or_else(truncated)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            *offset += 2;
+            sets.push(
+                data.get(*offset..*offset + len)
+                    .ok_or_else(truncated)?
+                    .to_vec(),
+            );
+            *offset += len;
+        }
+        Ok(sets)
+    };
+    let sps = read_sets((header[5] & 0x1f) as usize, &mut offset)?;
+    let pps_count = *data.get(offset).ok_or_else(truncated)? as usize;
+    offset += 1;
+    let pps = read_sets(pps_count, &mut offset)?;
+    Ok((sps, pps, length_size))
+}
+
+/// Length prefixed NAL units to Annex-B
+pub fn annex_b(data: &[u8], length_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    let mut data = data;
+    while !data.is_empty() {
+        let prefix = data
+            .get(..length_size)
+            .ok_or_else(|| anyhow!("NAL unit length is truncated"))?;
+        let len = prefix
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        let nalu = data
+            .get(length_size..length_size + len)
+            .ok_or_else(|| anyhow!("NAL unit is truncated"))?;
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(nalu);
+        data = &data[length_size + len..];
+    }
+    Ok(out)
+}
+
+/// An AVCDecoderConfigurationRecord of a single SPS and PPS
+#[cfg(test)]
+pub fn avc_config(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let mut config = vec![1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+    config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    config.extend_from_slice(sps);
+    config.extend_from_slice(&[1]);
+    config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    config.extend_from_slice(pps);
+    config
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{
+        AudioTag, VideoTag, annex_b, avc_config, parse_audio, parse_avc_config, parse_video,
+    };
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+    #[test]
+    fn test_video() {
+        let config = avc_config(SPS, PPS);
+        let mut tag = vec![0x17, 0, 0, 0, 0];
+        tag.extend_from_slice(&config);
+        assert_eq!(
+            parse_video(&Bytes::from(tag)).unwrap(),
+            VideoTag::AvcConfig(Bytes::from(config.clone()))
+        );
+
+        let (sps, pps, length_size) = parse_avc_config(&config).unwrap();
+        assert_eq!(
+            (sps, pps, length_size),
+            (vec![SPS.to_vec()], vec![PPS.to_vec()], 4)
+        );
+
+        // An IDR frame of 3000 bytes with a composition time of -40 ms, Enhanced RTMP
+        let mut tag = vec![0x91, b'a', b'v', b'c', b'1', 0xff, 0xff, 0xd8];
+        tag.extend_from_slice(&3001u32.to_be_bytes());
+        tag.push(0x65);
+        tag.extend_from_slice(&[0x88; 3000]);
+        let VideoTag::AvcFrame {
+            keyframe,
+            composition_time,
+            data,
+        } = parse_video(&Bytes::from(tag)).unwrap()
+        else {
+            panic!("not a frame");
+        };
+        assert!(keyframe);
+        assert_eq!(composition_time, -40);
+
+        let frame = annex_b(&data, length_size).unwrap();
+        assert_eq!(&frame[..5], &[0, 0, 0, 1, 0x65]);
+        assert_eq!(frame.len(), 4 + 3001);
+
+        assert_eq!(
+            parse_video(&Bytes::from_static(&[0x22, 0, 0, 0])).unwrap(),
+            VideoTag::Unsupported("codec id 2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_audio() {
+        assert_eq!(
+            parse_audio(&Bytes::from_static(&[0xaf, 1, 0x21])).unwrap(),
+            AudioTag::Unsupported("AAC")
+        );
+        assert_eq!(
+            parse_audio(&Bytes::from_static(b"\x91Opus\xfc\xff\xfe")).unwrap(),
+            AudioTag::Opus(Bytes::from_static(&[0xfc, 0xff, 0xfe]))
+        );
+        assert_eq!(
+            parse_audio(&Bytes::from_static(b"\x90OpusOpusHead")).unwrap(),
+            AudioTag::Ignored
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! RTMP server publishing to the streams, `rtmp://host/{app}/{stream}` from OBS or FFmpeg.
+//! H.264 video and Enhanced RTMP Opus audio are repacketized to RTP for a [`Publisher`].
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use auth::AuthState;
+use bytes::Bytes;
+use cli::Codec;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, trace, warn};
+
+use self::amf::Amf0;
+use self::chunk::{ChunkReader, DEFAULT_CHUNK_SIZE, Message};
+use self::flv::{AudioTag, VideoTag};
+use super::{Publisher, RtpPacketizer};
+use crate::config;
+use crate::stream::callout::ClientInfo;
+use crate::stream::manager::Manager;
+
+mod amf;
+mod chunk;
+mod flv;
+
+const HANDSHAKE_SIZE: usize = 1536;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const RTMP_VERSION: u8 = 3;
+/// Chunk size of the server messages
+const CHUNK_SIZE: usize = 4096;
+const WINDOW_ACK_SIZE: u32 = 2_500_000;
+/// Messages read ahead of the publisher
+const MESSAGE_QUEUE: usize = 64;
+
+const MSG_SET_CHUNK_SIZE: u8 = 1;
+const MSG_ACKNOWLEDGEMENT: u8 = 3;
+const MSG_USER_CONTROL: u8 = 4;
+const MSG_WINDOW_ACK_SIZE: u8 = 5;
+const MSG_SET_PEER_BANDWIDTH: u8 = 6;
+const MSG_AUDIO: u8 = 8;
+const MSG_VIDEO: u8 = 9;
+const MSG_DATA_AMF3: u8 = 15;
+const MSG_COMMAND_AMF3: u8 = 17;
+const MSG_DATA_AMF0: u8 = 18;
+const MSG_COMMAND_AMF0: u8 = 20;
+
+const CSID_CONTROL: u8 = 2;
+const CSID_COMMAND: u8 = 3;
+const CSID_STATUS: u8 = 5;
+
+/// The only message stream of a connection, returned by `createStream`
+const STREAM_ID: u32 = 1;
+const USER_CONTROL_STREAM_BEGIN: u16 = 0;
+
+pub async fn serve(
+    listener: TcpListener,
+    cfg: config::Rtmp,
+    manager: Arc<Manager>,
+    auth: AuthState,
+) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(?err, "rtmp accept error");
+                continue;
+            }
+        };
+        let cfg = cfg.clone();
+        let manager = manager.clone();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            debug!(%addr, "rtmp connection open");
+            if let Err(err) = run(socket, addr, cfg, manager, auth).await {
+                debug!(%addr, ?err, "rtmp connection error");
+            }
+            debug!(%addr, "rtmp connection closed");
+        });
+    }
+}
+
+async fn run(
+    mut socket: TcpStream,
+    addr: SocketAddr,
+    cfg: config::Rtmp,
+    manager: Arc<Manager>,
+    auth: AuthState,
+) -> Result<()> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&mut socket))
+        .await
+        .map_err(|_| anyhow!("handshake timeout"))??;
+    let _ = socket.set_nodelay(true);
+    let (reader, writer) = socket.into_split();
+
+    // Chunks are read in a task, reading a message can't be cancelled halfway
+    let (sender, mut messages) = mpsc::channel(MESSAGE_QUEUE);
+    let read_task = tokio::spawn(async move {
+        let mut reader = ChunkReader::new(BufReader::new(reader));
+        loop {
+            let result = reader.read_message().await;
+            if let Ok(message) = &result
+                && message.type_id == MSG_SET_CHUNK_SIZE
+                && let Some(size) = read_u32(&message.payload)
+            {
+                reader.set_chunk_size((size & 0x7fffffff) as usize);
+            }
+            let failed = result.is_err();
+            let result = result.map(|message| (message, reader.bytes_read));
+            if sender.send(result).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut connection = Connection {
+        addr,
+        manager,
+        auth,
+        writer,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        ack_window: 0,
+        acknowledged: 0,
+        tc_url: None,
+        flash_ver: None,
+        publisher: None,
+        packetizer: RtpPacketizer::new(Codec::H264),
+        length_size: 4,
+        unsupported: HashSet::new(),
+    };
+    let result = connection
+        .run(&mut messages, Duration::from_secs(cfg.timeout))
+        .await;
+
+    read_task.abort();
+    if let Some(publisher) = connection.publisher.take() {
+        info!(%addr, session = publisher.session(), "rtmp publish end");
+        publisher.close().await;
+    }
+    result
+}
+
+async fn handshake(socket: &mut TcpStream) -> Result<()> {
+    let mut c0c1 = vec![0; 1 + HANDSHAKE_SIZE];
+    socket.read_exact(&mut c0c1).await?;
+    if c0c1[0] != RTMP_VERSION {
+        return Err(anyhow!("unsupported rtmp version {}", c0c1[0]));
+    }
+
+    // The simple handshake: S1 has a zero version, and S2 echoes C1
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u32;
+    let mut s0s1s2 = Vec::with_capacity(1 + HANDSHAKE_SIZE * 2);
+    s0s1s2.push(RTMP_VERSION);
+    s0s1s2.extend_from_slice(&time.to_be_bytes());
+    s0s1s2.resize(1 + HANDSHAKE_SIZE, 0);
+    s0s1s2.extend_from_slice(&c0c1[1..]);
+    socket.write_all(&s0s1s2).await?;
+
+    let mut c2 = vec![0; HANDSHAKE_SIZE];
+    socket.read_exact(&mut c2).await?;
+    Ok(())
+}
+
+fn read_u32(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(payload.get(..4)?.try_into().ok()?))
+}
+
+async fn publisher_closed(publisher: &Option<Publisher>) {
+    match publisher {
+        Some(publisher) => publisher.closed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The `token` of a query, e.g. `token=...&a=b`
+fn query_token(query: &str) -> Option<String> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .map(|token| token.to_string())
+}
+
+/// The stream and the token of a publish name, `{stream}?token=...`
+fn stream_key(name: &str, tc_url: Option<&str>) -> (String, Option<String>) {
+    let (stream, query) = name.split_once('?').unwrap_or((name, ""));
+    let token = query_token(query).or_else(|| {
+        tc_url
+            .and_then(|url| url.split_once('?'))
+            .and_then(|(_, query)| query_token(query))
+    });
+    (stream.to_string(), token)
+}
+
+struct Connection {
+    addr: SocketAddr,
+    manager: Arc<Manager>,
+    auth: AuthState,
+    writer: OwnedWriteHalf,
+    chunk_size: usize,
+    /// Acknowledgements are sent after this many bytes, set by the client
+    ack_window: u32,
+    acknowledged: u64,
+    tc_url: Option<String>,
+    flash_ver: Option<String>,
+    publisher: Option<Publisher>,
+    packetizer: RtpPacketizer,
+    /// Bytes of the NAL unit lengths, from the AVCDecoderConfigurationRecord
+    length_size: usize,
+    /// Codecs already warned about
+    unsupported: HashSet<String>,
+}
+
+impl Connection {
+    async fn run(
+        &mut self,
+        messages: &mut mpsc::Receiver<Result<(Message, u64)>>,
+        timeout: Duration,
+    ) -> Result<()> {
+        loop {
+            let received = tokio::select! {
+                received = tokio::time::timeout(timeout, messages.recv()) => received,
+                _ = publisher_closed(&self.publisher) => {
+                    return Err(anyhow!("publisher is closed"));
+                }
+            };
+            let (message, bytes_read) = match received {
+                Ok(Some(result)) => result?,
+                Ok(None) => return Ok(()),
+                Err(_) => return Err(anyhow!("timeout")),
+            };
+            self.acknowledge(bytes_read).await?;
+            if !self.handle(message).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn send(
+        &mut self,
+        csid: u8,
+        type_id: u8,
+        stream_id: u32,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        let message = Message {
+            type_id,
+            stream_id,
+            timestamp: 0,
+            payload: Bytes::from(payload),
+        };
+        let data = chunk::encode(self.chunk_size, csid, &message);
+        self.writer.write_all(&data).await?;
+        Ok(())
+    }
+
+    async fn send_command(&mut self, csid: u8, stream_id: u32, values: &[Amf0]) -> Result<()> {
+        let payload = amf::encode_all(values);
+        self.send(csid, MSG_COMMAND_AMF0, stream_id, payload).await
+    }
+
+    async fn send_status(&mut self, level: &str, code: &str, description: &str) -> Result<()> {
+        let status = Amf0::object([
+            ("level", Amf0::string(level)),
+            ("code", Amf0::string(code)),
+            ("description", Amf0::string(description)),
+        ]);
+        let values = [
+            Amf0::string("onStatus"),
+            Amf0::Number(0.0),
+            Amf0::Null,
+            status,
+        ];
+        self.send_command(CSID_STATUS, STREAM_ID, &values).await
+    }
+
+    async fn acknowledge(&mut self, bytes_read: u64) -> Result<()> {
+        if self.ack_window > 0 && bytes_read - self.acknowledged >= self.ack_window as u64 {
+            self.acknowledged = bytes_read;
+            let sequence = (bytes_read as u32).to_be_bytes().to_vec();
+            self.send(CSID_CONTROL, MSG_ACKNOWLEDGEMENT, 0, sequence)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// False once the client stops publishing
+    async fn handle(&mut self, message: Message) -> Result<bool> {
+        match message.type_id {
+            MSG_WINDOW_ACK_SIZE => {
+                self.ack_window = read_u32(&message.payload).unwrap_or_default();
+            }
+            MSG_COMMAND_AMF0 => return self.command(amf::decode_all(&message.payload)?).await,
+            // An AMF0 command after a zero byte
+            MSG_COMMAND_AMF3 => {
+                let payload = message.payload.get(1..).unwrap_or_default();
+                return self.command(amf::decode_all(payload)?).await;
+            }
+            MSG_VIDEO => self.video(message).await?,
+            MSG_AUDIO => self.audio(message).await?,
+            // Set Chunk Size is applied by the reader, the metadata isn't needed
+            MSG_SET_CHUNK_SIZE
+            | MSG_ACKNOWLEDGEMENT
+            | MSG_USER_CONTROL
+            | MSG_SET_PEER_BANDWIDTH
+            | MSG_DATA_AMF0
+            | MSG_DATA_AMF3 => {}
+            type_id => trace!(addr = %self.addr, type_id, "rtmp message ignored"),
+        }
+        Ok(true)
+    }
+
+    async fn command(&mut self, values: Vec<Amf0>) -> Result<bool> {
+        let name = values.first().and_then(Amf0::as_str).unwrap_or_default();
+        let transaction = values.get(1).and_then(Amf0::as_number).unwrap_or_default();
+        trace!(addr = %self.addr, name, "rtmp command");
+        match name {
+            "connect" => {
+                let object = values.get(2);
+                let property = |key| {
+                    object
+                        .and_then(|object| object.get(key))
+                        .and_then(Amf0::as_str)
+                        .map(|value| value.to_string())
+                };
+                self.tc_url = property("tcUrl");
+                self.flash_ver = property("flashVer");
+                self.connect(transaction).await?;
+            }
+            "createStream" => {
+                let values = [
+                    Amf0::string("_result"),
+                    Amf0::Number(transaction),
+                    Amf0::Null,
+                    Amf0::Number(STREAM_ID as f64),
+                ];
+                self.send_command(CSID_COMMAND, 0, &values).await?;
+            }
+            "publish" => {
+                let name = values.get(3).and_then(Amf0::as_str).unwrap_or_default();
+                return self.publish(name.to_string()).await;
+            }
+            "FCUnpublish" | "deleteStream" | "closeStream" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    async fn connect(&mut self, transaction: f64) -> Result<()> {
+        self.send(
+            CSID_CONTROL,
+            MSG_WINDOW_ACK_SIZE,
+            0,
+            WINDOW_ACK_SIZE.to_be_bytes().to_vec(),
+        )
+        .await?;
+        // The dynamic limit type
+        let mut bandwidth = WINDOW_ACK_SIZE.to_be_bytes().to_vec();
+        bandwidth.push(2);
+        self.send(CSID_CONTROL, MSG_SET_PEER_BANDWIDTH, 0, bandwidth)
+            .await?;
+        self.send(
+            CSID_CONTROL,
+            MSG_SET_CHUNK_SIZE,
+            0,
+            (CHUNK_SIZE as u32).to_be_bytes().to_vec(),
+        )
+        .await?;
+        self.chunk_size = CHUNK_SIZE;
+
+        let values = [
+            Amf0::string("_result"),
+            Amf0::Number(transaction),
+            Amf0::object([
+                ("fmsVer", Amf0::string("FMS/3,0,1,123")),
+                ("capabilities", Amf0::Number(31.0)),
+                // The Enhanced RTMP codecs
+                (
+                    "fourCcList",
+                    Amf0::StrictArray(vec![Amf0::string("avc1"), Amf0::string("Opus")]),
+                ),
+            ]),
+            Amf0::object([
+                ("level", Amf0::string("status")),
+                ("code", Amf0::string("NetConnection.Connect.Success")),
+                ("description", Amf0::string("Connection succeeded.")),
+                ("objectEncoding", Amf0::Number(0.0)),
+            ]),
+        ];
+        self.send_command(CSID_COMMAND, 0, &values).await
+    }
+
+    async fn publish(&mut self, name: String) -> Result<bool> {
+        if self.publisher.is_some() {
+            self.send_status("error", "NetStream.Publish.BadName", "already publishing")
+                .await?;
+            return Ok(true);
+        }
+        let (stream, token) = stream_key(&name, self.tc_url.as_deref());
+        match self.auth.claims(token.as_deref()) {
+            Some(claims) if claims.can_publish(&stream) => {}
+            _ => {
+                self.send_status(
+                    "error",
+                    "NetStream.Publish.Unauthorized",
+                    "no publish permission",
+                )
+                .await?;
+                return Ok(false);
+            }
+        }
+
+        let client = ClientInfo {
+            ip: Some(self.addr.ip().to_string()),
+            token,
+            user_agent: self.flash_ver.clone(),
+        };
+        match Publisher::new(self.manager.clone(), stream.clone(), Codec::H264, client).await {
+            Ok(publisher) => {
+                info!(
+                    addr = %self.addr,
+                    stream,
+                    session = publisher.session(),
+                    "rtmp publish start"
+                );
+                self.publisher = Some(publisher);
+                let mut stream_begin = USER_CONTROL_STREAM_BEGIN.to_be_bytes().to_vec();
+                stream_begin.extend_from_slice(&STREAM_ID.to_be_bytes());
+                self.send(CSID_CONTROL, MSG_USER_CONTROL, 0, stream_begin)
+                    .await?;
+                self.send_status("status", "NetStream.Publish.Start", &stream)
+                    .await?;
+                Ok(true)
+            }
+            Err(err) => {
+                warn!(addr = %self.addr, stream, ?err, "rtmp publish error");
+                self.send_status("error", "NetStream.Publish.BadName", "publish failed")
+                    .await?;
+                Ok(false)
+            }
+        }
+    }
+
+    async fn video(&mut self, message: Message) -> Result<()> {
+        let Some(publisher) = &self.publisher else {
+            return Ok(());
+        };
+        match flv::parse_video(&message.payload)? {
+            VideoTag::AvcConfig(config) => {
+                let (sps, pps, length_size) = flv::parse_avc_config(&config)?;
+                if let (Some(sps), Some(pps)) = (sps.into_iter().next(), pps.into_iter().next()) {
+                    self.packetizer.set_h264_params(sps, pps);
+                }
+                self.length_size = length_size;
+            }
+            VideoTag::AvcFrame {
+                composition_time,
+                data,
+                ..
+            } => {
+                let pts = message.timestamp.wrapping_add_signed(composition_time);
+                let frame = flv::annex_b(&data, self.length_size)?;
+                for packet in self.packetizer.video(&frame, pts.wrapping_mul(90))? {
+                    publisher.write_video(&packet).await;
+                }
+            }
+            VideoTag::Unsupported(codec) => {
+                if self.unsupported.insert(codec.clone()) {
+                    warn!(addr = %self.addr, %codec, "rtmp video codec is not supported");
+                }
+            }
+            VideoTag::Ignored => {}
+        }
+        Ok(())
+    }
+
+    async fn audio(&mut self, message: Message) -> Result<()> {
+        let Some(publisher) = &self.publisher else {
+            return Ok(());
+        };
+        match flv::parse_audio(&message.payload)? {
+            AudioTag::Opus(data) => {
+                let packet = self
+                    .packetizer
+                    .audio(data, message.timestamp.wrapping_mul(48));
+                publisher.write_audio(&packet).await;
+            }
+            AudioTag::Unsupported(codec) => {
+                if self.unsupported.insert(codec.to_string()) {
+                    info!(addr = %self.addr, codec, "rtmp audio is dropped");
+                }
+            }
+            AudioTag::Ignored => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::stream_key;
+
+    #[test]
+    fn test_stream_key() {
+        assert_eq!(stream_key("live777", None), ("live777".to_string(), None));
+        assert_eq!(
+            stream_key("live777?token=abc&x=1", Some("rtmp://host/live?token=def")),
+            ("live777".to_string(), Some("abc".to_string()))
+        );
+        assert_eq!(
+            stream_key("live777", Some("rtmp://host/live?token=def")),
+            ("live777".to_string(), Some("def".to_string()))
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! SRT publishers, MPEG-TS demuxed and repacketized to RTP for a [`Publisher`].
+//! H.264 or H.265 video and Opus audio are published, the other streams are dropped.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use cli::Codec;
+use tracing::{info, warn};
+
+use super::{Publisher, RtpPacketizer};
+use crate::error::AppError;
+use crate::mpegts::{TsCodec, TsDemuxer, TsFrame};
+use crate::result::Result;
+use crate::srt::SrtSocket;
+use crate::stream::callout::ClientInfo;
+use crate::stream::manager::Manager;
+
+/// The PMT picks the video codec of the publisher
+const PMT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Publishes to `stream` until the caller or the stream is gone
+pub async fn publish(
+    mut socket: SrtSocket,
+    manager: Arc<Manager>,
+    stream: String,
+    client: ClientInfo,
+) -> Result<()> {
+    let mut demuxer = TsDemuxer::default();
+    let mut frames = vec![];
+    let streams = tokio::time::timeout(PMT_TIMEOUT, async {
+        while let Some(data) = socket.recv().await {
+            frames.extend(demuxer.push(&data));
+            if let Some(streams) = demuxer.streams() {
+                return Some(streams.to_vec());
+            }
+        }
+        None
+    })
+    .await
+    .map_err(|_| AppError::throw("srt publish without a PMT"))?;
+    let Some(streams) = streams else {
+        return Ok(());
+    };
+    for unsupported in streams.iter().filter(|stream| stream.codec.is_none()) {
+        warn!(
+            stream,
+            pid = unsupported.pid,
+            stream_type = unsupported.stream_type,
+            "srt stream type is not supported"
+        );
+    }
+    let video = match demuxer.video() {
+        Some(TsCodec::H265) => Codec::H265,
+        _ => Codec::H264,
+    };
+
+    let publisher = Publisher::new(manager, stream.clone(), video, client).await?;
+    info!(
+        peer = %socket.peer(),
+        stream,
+        session = publisher.session(),
+        "srt publish start"
+    );
+    let mut packetizer = RtpPacketizer::new(video);
+    let mut codec_changed = false;
+    loop {
+        for frame in frames.drain(..) {
+            write(
+                &publisher,
+                &mut packetizer,
+                video,
+                frame,
+                &mut codec_changed,
+            )
+            .await;
+        }
+        let data = tokio::select! {
+            data = socket.recv() => data,
+            _ = publisher.closed() => break,
+        };
+        let Some(data) = data else {
+            break;
+        };
+        frames = demuxer.push(&data);
+    }
+    info!(stream, session = publisher.session(), "srt publish end");
+    publisher.close().await;
+    Ok(())
+}
+
+async fn write(
+    publisher: &Publisher,
+    packetizer: &mut RtpPacketizer,
+    video: Codec,
+    frame: TsFrame,
+    codec_changed: &mut bool,
+) {
+    match frame.codec {
+        TsCodec::Opus => {
+            // 90 kHz to the 48 kHz of Opus
+            let timestamp = (frame.pts * 8 / 15) as u32;
+            let packet = packetizer.audio(frame.data, timestamp);
+            publisher.write_audio(&packet).await;
+        }
+        codec if (codec == TsCodec::H265) == (video == Codec::H265) => {
+            match packetizer.video(&frame.data, frame.pts as u32) {
+                Ok(packets) => {
+                    for packet in packets {
+                        publisher.write_video(&packet).await;
+                    }
+                }
+                Err(err) => warn!(?err, "srt video packetize error"),
+            }
+        }
+        _ => {
+            if !*codec_changed {
+                *codec_changed = true;
+                warn!("srt video codec changed, the frames are dropped");
+            }
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use std::{future::Future, sync::Arc};
+use std::{future::Future, net::SocketAddr, sync::Arc};
 
 use axum::{Router, extract::Request, middleware, response::IntoResponse, routing::get};
 use http::{StatusCode, Uri};
 use tokio::net::TcpListener;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tracing::{Level, error, info_span};
+use tracing::{Level, error, info, info_span};
 
 use crate::config::Config;
 use crate::route::{AppState, admin, session, whep, whip};
@@ -19,6 +19,8 @@ use stream::manager::Manager;
 #[folder = "../assets/liveion/"]
 struct Assets;
 
+#[cfg(any(feature = "recorder", feature = "mpegts"))]
+pub mod codec;
 pub mod config;
 
 mod constant;
@@ -26,10 +28,17 @@ mod convert;
 mod error;
 mod forward;
 mod hook;
+#[cfg(any(feature = "rtmp", feature = "srt"))]
+mod input;
 mod r#macro;
 mod metrics;
+#[cfg(feature = "mpegts")]
+pub mod mpegts;
+mod output;
 mod result;
 mod route;
+#[cfg(feature = "srt")]
+pub mod srt;
 mod stream;
 
 #[cfg(feature = "recorder")]
@@ -39,11 +48,76 @@ pub async fn serve<F>(cfg: Config, listener: TcpListener, signal: F)
 where
     F: Future<Output = ()> + Send + 'static,
 {
+    metrics::set_limits(&cfg.metrics);
+    let stream_manager = match Manager::new(cfg.clone()).await {
+        Ok(stream_manager) => stream_manager,
+        Err(err) => {
+            error!("WebRTC network setup error: {}", err);
+            return;
+        }
+    };
     let app_state = AppState {
-        stream_manager: Arc::new(Manager::new(cfg.clone()).await),
+        stream_manager: Arc::new(stream_manager),
         config: cfg.clone(),
+        #[cfg(feature = "recorder")]
+        live_streams: Default::default(),
     };
 
+    if let Some(listen) = cfg.rtsp.listen {
+        match TcpListener::bind(listen).await {
+            Ok(listener) => {
+                info!("RTSP server listening on {}", listen);
+                tokio::spawn(output::rtsp::serve(
+                    listener,
+                    cfg.rtsp.clone(),
+                    app_state.stream_manager.clone(),
+                    AuthState::new(cfg.auth.secret.clone(), cfg.auth.tokens.clone()),
+                ));
+            }
+            Err(err) => error!("RTSP server bind {} error: {}", listen, err),
+        }
+    }
+
+    #[cfg(feature = "rtmp")]
+    if let Some(listen) = cfg.rtmp.listen {
+        match TcpListener::bind(listen).await {
+            Ok(listener) => {
+                info!("RTMP server listening on {}", listen);
+                tokio::spawn(input::rtmp::serve(
+                    listener,
+                    cfg.rtmp.clone(),
+                    app_state.stream_manager.clone(),
+                    AuthState::new(cfg.auth.secret.clone(), cfg.auth.tokens.clone()),
+                ));
+            }
+            Err(err) => error!("RTMP server bind {} error: {}", listen, err),
+        }
+    }
+
+    #[cfg(feature = "srt")]
+    {
+        if let Some(listen) = cfg.srt.listen {
+            match srt::SrtListener::bind(listen, srt::options(&cfg.srt)).await {
+                Ok(listener) => {
+                    info!("SRT server listening on {}", listen);
+                    tokio::spawn(srt::serve(
+                        listener,
+                        app_state.stream_manager.clone(),
+                        AuthState::new(cfg.auth.secret.clone(), cfg.auth.tokens.clone()),
+                    ));
+                }
+                Err(err) => error!("SRT server bind {} error: {}", listen, err),
+            }
+        }
+        for caller in cfg.srt.callers.iter() {
+            tokio::spawn(srt::call(
+                caller.clone(),
+                cfg.srt.clone(),
+                app_state.stream_manager.clone(),
+            ));
+        }
+    }
+
     #[cfg(feature = "recorder")]
     {
         crate::recorder::init(app_state.stream_manager.clone(), cfg.recorder.clone()).await;
@@ -63,6 +137,12 @@ where
             )),
     );
 
+    #[cfg(feature = "recorder")]
+    let app = app
+        .merge(crate::route::hls::route())
+        .merge(crate::route::dash::route())
+        .merge(crate::route::ws::route());
+
     let app = app
         .route(path::METRICS, get(metrics))
         .with_state(app_state.clone())
@@ -131,10 +211,13 @@ where
         }
     }
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(signal)
-        .await
-        .unwrap_or_else(|e| error!("Application error: {e}"));
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(signal)
+    .await
+    .unwrap_or_else(|e| error!("Application error: {e}"));
 }
 
 #[cfg(feature = "webui")]
@@ -170,6 +253,9 @@ pub fn metrics_register() {
     metrics::REGISTRY
         .register(Box::new(metrics::REFORWARD.clone()))
         .unwrap();
+    for collector in metrics::labeled_collectors() {
+        metrics::REGISTRY.register(collector).unwrap();
+    }
 }
 
 async fn metrics() -> String {
@@ -1,12 +1,351 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
 use lazy_static::lazy_static;
-use prometheus::{Gauge, Registry, TextEncoder};
+use prometheus::core::Collector;
+use prometheus::{
+    Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::config;
+
+/// Label value of the streams and sessions beyond the cardinality limits
+pub const OVERFLOW: &str = "_overflow";
+
+const KINDS: [&str; 2] = ["audio", "video"];
+const DIRECTION_IN: &str = "in";
+const DIRECTION_OUT: &str = "out";
 
 lazy_static! {
     pub static ref STREAM: Gauge = Gauge::new("stream", "stream number").unwrap();
     pub static ref PUBLISH: Gauge = Gauge::new("publish", "publish number").unwrap();
     pub static ref SUBSCRIBE: Gauge = Gauge::new("subscribe", "subscribe number").unwrap();
     pub static ref REFORWARD: Gauge = Gauge::new("reforward", "reforward number").unwrap();
+    pub static ref BYTES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "bytes_total",
+            "RTP payload bytes received from publishers and sent to subscribers"
+        ),
+        &["stream", "kind", "direction"]
+    )
+    .unwrap();
+    pub static ref PACKETS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "packets_total",
+            "RTP packets received from publishers and sent to subscribers"
+        ),
+        &["stream", "kind", "direction"]
+    )
+    .unwrap();
+    pub static ref PACKETS_LOST: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "packets_lost_total",
+            "RTP packets lost on the way from publishers"
+        ),
+        &["stream", "kind"]
+    )
+    .unwrap();
+    pub static ref NACKS: IntCounterVec = IntCounterVec::new(
+        Opts::new("nacks_total", "RTP packets subscribers asked to retransmit"),
+        &["stream", "kind"]
+    )
+    .unwrap();
+    pub static ref KEYFRAME_INTERVAL: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "keyframe_interval_seconds",
+            "Time between keyframes of published video"
+        )
+        .buckets(vec![0.5, 1.0, 2.0, 3.0, 5.0, 10.0, 20.0, 30.0, 60.0]),
+        &["stream"]
+    )
+    .unwrap();
+    pub static ref FIRST_PACKET: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "subscribe_first_packet_seconds",
+            "Time from a subscriber starting to its first packet sent"
+        )
+        .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0]),
+        &["stream", "kind"]
+    )
+    .unwrap();
+    pub static ref LAGGED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "broadcast_lagged_total",
+            "RTP packets dropped by subscribers lagging behind the publisher"
+        ),
+        &["stream", "kind"]
+    )
+    .unwrap();
+    pub static ref SESSION_BYTES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "session_bytes_total",
+            "RTP payload bytes received from or sent to a session"
+        ),
+        &["stream", "session", "direction"]
+    )
+    .unwrap();
+    pub static ref SESSION_PACKETS_LOST: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "session_packets_lost_total",
+            "RTP packets lost on the way from a publisher session"
+        ),
+        &["stream", "session"]
+    )
+    .unwrap();
+    pub static ref SESSION_NACKS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "session_nacks_total",
+            "RTP packets a subscriber session asked to retransmit"
+        ),
+        &["stream", "session"]
+    )
+    .unwrap();
+    pub static ref WEBHOOK_FAILURES: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "webhook_failures_total",
+            "Webhook requests failed by an error status or in transport"
+        ),
+        &["reason"]
+    )
+    .unwrap();
     pub static ref REGISTRY: Registry =
         Registry::new_custom(Some("live777".to_string()), None).unwrap();
     pub static ref ENCODER: TextEncoder = TextEncoder::new();
+    static ref STREAMS: Cardinality = Cardinality::new(config::Metrics::default().max_streams);
+    static ref SESSIONS: Cardinality = Cardinality::new(config::Metrics::default().max_sessions);
+}
+
+/// The labeled metrics, registered besides the gauges
+pub fn labeled_collectors() -> Vec<Box<dyn Collector>> {
+    vec![
+        Box::new(BYTES.clone()),
+        Box::new(PACKETS.clone()),
+        Box::new(PACKETS_LOST.clone()),
+        Box::new(NACKS.clone()),
+        Box::new(KEYFRAME_INTERVAL.clone()),
+        Box::new(FIRST_PACKET.clone()),
+        Box::new(LAGGED.clone()),
+        Box::new(SESSION_BYTES.clone()),
+        Box::new(SESSION_PACKETS_LOST.clone()),
+        Box::new(SESSION_NACKS.clone()),
+        Box::new(WEBHOOK_FAILURES.clone()),
+    ]
+}
+
+pub fn set_limits(cfg: &config::Metrics) {
+    STREAMS.max.store(cfg.max_streams, Ordering::Relaxed);
+    SESSIONS.max.store(cfg.max_sessions, Ordering::Relaxed);
+}
+
+/// Label values in use, new values beyond `max` are reported as `OVERFLOW`
+struct Cardinality {
+    max: AtomicUsize,
+    values: Mutex<HashSet<String>>,
+}
+
+impl Cardinality {
+    fn new(max: usize) -> Self {
+        Self {
+            max: AtomicUsize::new(max),
+            values: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn label(&self, value: &str) -> String {
+        let mut values = self.values.lock().unwrap();
+        if values.contains(value) || values.len() < self.max.load(Ordering::Relaxed) {
+            values.insert(value.to_string());
+            value.to_string()
+        } else {
+            OVERFLOW.to_string()
+        }
+    }
+
+    /// Returns true if `value` was labeled by itself
+    fn release(&self, value: &str) -> bool {
+        self.values.lock().unwrap().remove(value)
+    }
+}
+
+fn session_label(stream: &str, session: &str) -> Option<String> {
+    if SESSIONS.max.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    let label = SESSIONS.label(&format!("{stream}/{session}"));
+    Some(if label == OVERFLOW {
+        label
+    } else {
+        session.to_string()
+    })
+}
+
+/// Drop the series of a closed stream, freeing its label for other streams
+pub fn release_stream(stream: &str) {
+    if !STREAMS.release(stream) {
+        return;
+    }
+    for kind in KINDS {
+        for direction in [DIRECTION_IN, DIRECTION_OUT] {
+            let _ = BYTES.remove_label_values(&[stream, kind, direction]);
+            let _ = PACKETS.remove_label_values(&[stream, kind, direction]);
+        }
+        let _ = PACKETS_LOST.remove_label_values(&[stream, kind]);
+        let _ = NACKS.remove_label_values(&[stream, kind]);
+        let _ = FIRST_PACKET.remove_label_values(&[stream, kind]);
+        let _ = LAGGED.remove_label_values(&[stream, kind]);
+    }
+    let _ = KEYFRAME_INTERVAL.remove_label_values(&[stream]);
+}
+
+/// Drop the series of a closed session
+pub fn release_session(stream: &str, session: &str) {
+    if !SESSIONS.release(&format!("{stream}/{session}")) {
+        return;
+    }
+    // The stream may be released already, session ids are unique in either label
+    for stream in [stream, OVERFLOW] {
+        for direction in [DIRECTION_IN, DIRECTION_OUT] {
+            let _ = SESSION_BYTES.remove_label_values(&[stream, session, direction]);
+        }
+        let _ = SESSION_PACKETS_LOST.remove_label_values(&[stream, session]);
+        let _ = SESSION_NACKS.remove_label_values(&[stream, session]);
+    }
+}
+
+/// Metrics of a track received from a publisher, labels resolved once
+pub(crate) struct PublishTrackMetrics {
+    bytes: IntCounter,
+    packets: IntCounter,
+    lost: IntCounter,
+    session_bytes: Option<IntCounter>,
+    session_lost: Option<IntCounter>,
+    keyframe_interval: Histogram,
+    /// Timestamp and arrival of the last keyframe
+    last_keyframe: Option<(u32, Instant)>,
+}
+
+impl PublishTrackMetrics {
+    pub(crate) fn new(stream: &str, session: &str, kind: &str) -> Self {
+        let stream_label = STREAMS.label(stream);
+        let session_label = session_label(stream, session);
+        Self {
+            bytes: BYTES.with_label_values(&[&stream_label, kind, DIRECTION_IN]),
+            packets: PACKETS.with_label_values(&[&stream_label, kind, DIRECTION_IN]),
+            lost: PACKETS_LOST.with_label_values(&[&stream_label, kind]),
+            session_bytes: session_label.as_ref().map(|session| {
+                SESSION_BYTES.with_label_values(&[&stream_label, session, DIRECTION_IN])
+            }),
+            session_lost: session_label
+                .as_ref()
+                .map(|session| SESSION_PACKETS_LOST.with_label_values(&[&stream_label, session])),
+            keyframe_interval: KEYFRAME_INTERVAL.with_label_values(&[&stream_label]),
+            last_keyframe: None,
+        }
+    }
+
+    pub(crate) fn received(&self, bytes: usize, lost: u64) {
+        self.bytes.inc_by(bytes as u64);
+        self.packets.inc();
+        if let Some(session_bytes) = &self.session_bytes {
+            session_bytes.inc_by(bytes as u64);
+        }
+        if lost > 0 {
+            self.lost.inc_by(lost);
+            if let Some(session_lost) = &self.session_lost {
+                session_lost.inc_by(lost);
+            }
+        }
+    }
+
+    /// A packet of a keyframe arrived, the packets after the first share its timestamp
+    pub(crate) fn keyframe(&mut self, timestamp: u32, now: Instant) {
+        match self.last_keyframe {
+            Some((last, _)) if last == timestamp => return,
+            Some((_, at)) => self
+                .keyframe_interval
+                .observe(now.duration_since(at).as_secs_f64()),
+            None => {}
+        }
+        self.last_keyframe = Some((timestamp, now));
+    }
+}
+
+/// Metrics of a sender forwarding to a subscriber, labels resolved once
+pub(crate) struct SubscribeMetrics {
+    bytes: IntCounter,
+    packets: IntCounter,
+    nacks: IntCounter,
+    lagged: IntCounter,
+    session_bytes: Option<IntCounter>,
+    session_nacks: Option<IntCounter>,
+    /// Until the first packet is sent
+    first_packet: Option<(Histogram, Instant)>,
+}
+
+impl SubscribeMetrics {
+    pub(crate) fn new(stream: &str, session: &str, kind: &str) -> Self {
+        let stream_label = STREAMS.label(stream);
+        let session_label = session_label(stream, session);
+        Self {
+            bytes: BYTES.with_label_values(&[&stream_label, kind, DIRECTION_OUT]),
+            packets: PACKETS.with_label_values(&[&stream_label, kind, DIRECTION_OUT]),
+            nacks: NACKS.with_label_values(&[&stream_label, kind]),
+            lagged: LAGGED.with_label_values(&[&stream_label, kind]),
+            session_bytes: session_label.as_ref().map(|session| {
+                SESSION_BYTES.with_label_values(&[&stream_label, session, DIRECTION_OUT])
+            }),
+            session_nacks: session_label
+                .as_ref()
+                .map(|session| SESSION_NACKS.with_label_values(&[&stream_label, session])),
+            first_packet: Some((
+                FIRST_PACKET.with_label_values(&[&stream_label, kind]),
+                Instant::now(),
+            )),
+        }
+    }
+
+    pub(crate) fn sent(&mut self, bytes: usize) {
+        self.bytes.inc_by(bytes as u64);
+        self.packets.inc();
+        if let Some(session_bytes) = &self.session_bytes {
+            session_bytes.inc_by(bytes as u64);
+        }
+        if let Some((first_packet, start)) = self.first_packet.take() {
+            first_packet.observe(start.elapsed().as_secs_f64());
+        }
+    }
+
+    pub(crate) fn nacks(&self, count: usize) {
+        self.nacks.inc_by(count as u64);
+        if let Some(session_nacks) = &self.session_nacks {
+            session_nacks.inc_by(count as u64);
+        }
+    }
+
+    pub(crate) fn lagged(&self, count: u64) {
+        self.lagged.inc_by(count);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cardinality, OVERFLOW};
+
+    #[test]
+    fn test_cardinality_limit() {
+        let cardinality = Cardinality::new(2);
+        assert_eq!(cardinality.label("a"), "a");
+        assert_eq!(cardinality.label("b"), "b");
+        assert_eq!(cardinality.label("c"), OVERFLOW);
+        // known values keep their label
+        assert_eq!(cardinality.label("a"), "a");
+
+        assert!(cardinality.release("a"));
+        assert!(!cardinality.release("c"));
+        assert_eq!(cardinality.label("c"), "c");
+        assert_eq!(cardinality.label("d"), OVERFLOW);
+    }
 }
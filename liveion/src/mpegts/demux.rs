@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use super::{
+    CLOCK_RATE, DESCRIPTOR_REGISTRATION, OPUS_REGISTRATION, PACKET_SIZE, PAT_PID, STREAM_TYPE_H264,
+    STREAM_TYPE_H265, STREAM_TYPE_PRIVATE, SYNC_BYTE, TsCodec, TsFrame, is_keyframe, opus_samples,
+};
+
+/// An elementary stream of the PMT, `codec` is None for the unsupported ones
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsStream {
+    pub pid: u16,
+    pub stream_type: u8,
+    pub codec: Option<TsCodec>,
+}
+
+/// Demuxes the first program of a transport stream.
+/// The PSI sections are expected to fit in a packet, as they do for a single program.
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// An incomplete packet
+    buffer: Vec<u8>,
+    pmt_pid: Option<u16>,
+    streams: Option<Vec<TsStream>>,
+    /// The PES in progress of every stream
+    pes: HashMap<u16, Vec<u8>>,
+}
+
+impl TsDemuxer {
+    /// None until the PMT is received
+    pub fn streams(&self) -> Option<&[TsStream]> {
+        self.streams.as_deref()
+    }
+
+    pub fn video(&self) -> Option<TsCodec> {
+        self.streams
+            .iter()
+            .flatten()
+            .find_map(|stream| stream.codec.filter(|codec| codec.is_video()))
+    }
+
+    /// The frames completed by `data`. A frame of unknown length, as video usually is,
+    /// completes with the start of the next one.
+    pub fn push(&mut self, data: &[u8]) -> Vec<TsFrame> {
+        let mut frames = vec![];
+        self.buffer.extend_from_slice(data);
+        let mut offset = 0;
+        while offset + PACKET_SIZE <= self.buffer.len() {
+            if self.buffer[offset] != SYNC_BYTE {
+                offset += 1;
+                continue;
+            }
+            let packet: [u8; PACKET_SIZE] = self.buffer[offset..offset + PACKET_SIZE]
+                .try_into()
+                .unwrap_or([0; PACKET_SIZE]);
+            self.packet(&packet, &mut frames);
+            offset += PACKET_SIZE;
+        }
+        self.buffer.drain(..offset);
+        frames
+    }
+
+    fn packet(&mut self, packet: &[u8; PACKET_SIZE], frames: &mut Vec<TsFrame>) {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let adaptation = (packet[3] >> 4) & 0x03;
+        if adaptation & 0x01 == 0 {
+            return;
+        }
+        let start = if adaptation & 0x02 != 0 {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        let Some(payload) = packet.get(start..) else {
+            return;
+        };
+
+        if pid == PAT_PID || Some(pid) == self.pmt_pid {
+            if unit_start && let Some(section) = section(payload) {
+                match section[0] {
+                    0x00 => self.pat(section),
+                    0x02 => self.pmt(section),
+                    _ => {}
+                }
+            }
+            return;
+        }
+
+        let Some(stream) = self
+            .streams
+            .iter()
+            .flatten()
+            .find(|stream| stream.pid == pid)
+        else {
+            return;
+        };
+        let Some(codec) = stream.codec else {
+            return;
+        };
+        if unit_start {
+            if let Some(pes) = self.pes.remove(&pid) {
+                frames.extend(parse_pes(codec, &pes));
+            }
+            self.pes.insert(pid, payload.to_vec());
+        } else if let Some(pes) = self.pes.get_mut(&pid) {
+            pes.extend_from_slice(payload);
+        } else {
+            // The middle of a PES, before its start was seen
+            return;
+        }
+        if let Some(pes) = self.pes.get(&pid)
+            && pes.len() >= 6
+        {
+            let length = u16::from_be_bytes([pes[4], pes[5]]) as usize;
+            if length > 0 && pes.len() >= 6 + length {
+                let pes = self.pes.remove(&pid).unwrap_or_default();
+                frames.extend(parse_pes(codec, &pes[..6 + length]));
+            }
+        }
+    }
+
+    fn pat(&mut self, section: &[u8]) {
+        self.pmt_pid = section[8..section.len() - 4]
+            .chunks_exact(4)
+            .find(|program| program[0] != 0 || program[1] != 0)
+            .map(|program| u16::from_be_bytes([program[2] & 0x1f, program[3]]));
+    }
+
+    fn pmt(&mut self, section: &[u8]) {
+        let info_length = u16::from_be_bytes([section[10] & 0x0f, section[11]]) as usize;
+        let end = section.len() - 4;
+        let mut offset = 12 + info_length;
+        let mut streams = vec![];
+        while offset + 5 <= end {
+            let stream_type = section[offset];
+            let pid = u16::from_be_bytes([section[offset + 1] & 0x1f, section[offset + 2]]);
+            let length =
+                u16::from_be_bytes([section[offset + 3] & 0x0f, section[offset + 4]]) as usize;
+            let descriptors = section
+                .get(offset + 5..(offset + 5 + length).min(end))
+                .unwrap_or_default();
+            let codec = match stream_type {
+                STREAM_TYPE_H264 => Some(TsCodec::H264),
+                STREAM_TYPE_H265 => Some(TsCodec::H265),
+                STREAM_TYPE_PRIVATE if is_opus(descriptors) => Some(TsCodec::Opus),
+                _ => None,
+            };
+            streams.push(TsStream {
+                pid,
+                stream_type,
+                codec,
+            });
+            offset += 5 + length;
+        }
+        if self.streams.as_ref() != Some(&streams) {
+            self.pes.clear();
+            self.streams = Some(streams);
+        }
+    }
+}
+
+/// The section after the pointer field, checked for its length
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let length = u16::from_be_bytes([*section.get(1)? & 0x0f, *section.get(2)?]) as usize;
+    // The PSI syntax and the CRC
+    if length < 9 {
+        return None;
+    }
+    section.get(..3 + length)
+}
+
+fn is_opus(descriptors: &[u8]) -> bool {
+    let mut descriptors = descriptors;
+    while descriptors.len() >= 2 {
+        let length = descriptors[1] as usize;
+        let Some(body) = descriptors.get(2..2 + length) else {
+            return false;
+        };
+        if descriptors[0] == DESCRIPTOR_REGISTRATION && body == OPUS_REGISTRATION {
+            return true;
+        }
+        descriptors = &descriptors[2 + length..];
+    }
+    false
+}
+
+fn read_timestamp(data: &[u8]) -> u64 {
+    ((((data[0] >> 1) & 0x07) as u64) << 30)
+        | (((u16::from_be_bytes([data[1], data[2]]) >> 1) as u64) << 15)
+        | ((u16::from_be_bytes([data[3], data[4]]) >> 1) as u64)
+}
+
+/// The frames of a PES, several for the Opus access units of one PES
+fn parse_pes(codec: TsCodec, pes: &[u8]) -> Vec<TsFrame> {
+    if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+        return vec![];
+    }
+    let flags = pes[7];
+    let header_end = 9 + pes[8] as usize;
+    let Some(payload) = pes.get(header_end..) else {
+        return vec![];
+    };
+    let Some(pts) = pes
+        .get(9..14)
+        .filter(|_| flags & 0x80 != 0)
+        .map(read_timestamp)
+    else {
+        return vec![];
+    };
+    let dts = pes
+        .get(14..19)
+        .filter(|_| flags & 0xc0 == 0xc0)
+        .map(read_timestamp)
+        .unwrap_or(pts);
+
+    if codec.is_video() {
+        return vec![TsFrame {
+            codec,
+            pts,
+            dts,
+            keyframe: is_keyframe(codec, payload),
+            data: Bytes::copy_from_slice(payload),
+        }];
+    }
+
+    let mut frames = vec![];
+    let mut pts = pts;
+    let mut data = payload;
+    while data.len() >= 2 && data[0] == 0x7f && data[1] & 0xe0 == 0xe0 {
+        let flags = data[1];
+        let mut offset = 2;
+        let mut size = 0;
+        while let Some(&byte) = data.get(offset) {
+            offset += 1;
+            size += byte as usize;
+            if byte != 0xff {
+                break;
+            }
+        }
+        // The trim fields, then the control extension
+        if flags & 0x10 != 0 {
+            offset += 2;
+        }
+        if flags & 0x08 != 0 {
+            offset += 2;
+        }
+        if flags & 0x04 != 0 {
+            offset += 1 + data.get(offset).copied().unwrap_or_default() as usize;
+        }
+        let Some(packet) = data.get(offset..offset + size) else {
+            break;
+        };
+        frames.push(TsFrame {
+            codec,
+            pts,
+            dts: pts,
+            keyframe: true,
+            data: Bytes::copy_from_slice(packet),
+        });
+        pts += opus_samples(packet) as u64 * CLOCK_RATE as u64 / 48000;
+        data = &data[offset + size..];
+    }
+    frames
+}
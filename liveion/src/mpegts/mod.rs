@@ -0,0 +1,306 @@
+//! MPEG-TS of a single program, H.264 or H.265 video and Opus audio.
+//!
+//! Opus follows the ETSI TS 102 366 style mapping of the Opus project: private stream type
+//! 0x06 with an `Opus` registration descriptor, and a control header before every packet.
+
+use std::time::Instant;
+
+use bytes::Bytes;
+use webrtc::rtp::packet::Packet;
+
+use crate::codec::{Depacketizer, VideoCodec};
+use crate::forward::OutputTrack;
+
+mod demux;
+mod mux;
+
+pub use demux::{TsDemuxer, TsStream};
+pub use mux::TsMuxer;
+
+pub const PACKET_SIZE: usize = 188;
+/// The clock of the timestamps
+pub const CLOCK_RATE: u32 = 90000;
+
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+const AUDIO_PID: u16 = 0x101;
+const PROGRAM_NUMBER: u16 = 1;
+
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_H265: u8 = 0x24;
+const STREAM_TYPE_PRIVATE: u8 = 0x06;
+
+const STREAM_ID_VIDEO: u8 = 0xe0;
+const STREAM_ID_PRIVATE: u8 = 0xbd;
+
+const DESCRIPTOR_REGISTRATION: u8 = 0x05;
+const DESCRIPTOR_EXTENSION: u8 = 0x7f;
+const OPUS_REGISTRATION: &[u8; 4] = b"Opus";
+/// The 11 bit prefix of the control header of an Opus access unit
+const OPUS_CONTROL_PREFIX: u16 = 0x7fe0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsCodec {
+    H264,
+    H265,
+    Opus,
+}
+
+impl TsCodec {
+    pub fn is_video(self) -> bool {
+        !matches!(self, Self::Opus)
+    }
+
+    fn stream_type(self) -> u8 {
+        match self {
+            Self::H264 => STREAM_TYPE_H264,
+            Self::H265 => STREAM_TYPE_H265,
+            Self::Opus => STREAM_TYPE_PRIVATE,
+        }
+    }
+}
+
+/// An access unit, Annex-B video or one Opus packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TsFrame {
+    pub codec: TsCodec,
+    /// 90 kHz, 33 bits
+    pub pts: u64,
+    pub dts: u64,
+    pub keyframe: bool,
+    pub data: Bytes,
+}
+
+/// Whether an Annex-B frame has an IDR, or an IRAP picture of H.265
+pub fn is_keyframe(codec: TsCodec, frame: &[u8]) -> bool {
+    let mut zeros = 0;
+    for (i, byte) in frame.iter().enumerate() {
+        if *byte == 1 && zeros >= 2 {
+            let Some(header) = frame.get(i + 1) else {
+                break;
+            };
+            let keyframe = match codec {
+                TsCodec::H264 => header & 0x1f == 5,
+                TsCodec::H265 => (16..=21).contains(&((header >> 1) & 0x3f)),
+                TsCodec::Opus => true,
+            };
+            if keyframe {
+                return true;
+            }
+        }
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+    }
+    codec == TsCodec::Opus
+}
+
+/// Samples at 48 kHz of an Opus packet, from its TOC byte (RFC 6716)
+pub fn opus_samples(packet: &[u8]) -> u32 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = (toc >> 3) as usize;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        12..=15 => [480, 960][config % 2],
+        _ => [120, 240, 480, 960][config % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map(|count| count & 0x3f).unwrap_or(0) as u32,
+    };
+    frame * frames
+}
+
+/// CRC-32/MPEG-2 of the PSI sections
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The frames of an output track with 90 kHz timestamps, from its RTP packets.
+/// The tracks sharing an `epoch` share the time base.
+pub struct TrackFrames {
+    codec: TsCodec,
+    depacketizer: Depacketizer,
+    clock_rate: u32,
+    epoch: Instant,
+    /// The last RTP timestamp, and the same time in ticks of the clock rate since the epoch
+    last: Option<(u32, u64)>,
+    /// Video frames are dropped until a keyframe after lost packets
+    resync: bool,
+}
+
+impl TrackFrames {
+    /// None for the codecs MPEG-TS doesn't carry here
+    pub fn new(track: &OutputTrack, epoch: Instant) -> Option<Self> {
+        let codec = track.codec();
+        let (depacketizer, video_codec) = Depacketizer::new(&codec.capability.mime_type)?;
+        let ts_codec = match video_codec {
+            Some(VideoCodec::H264) => TsCodec::H264,
+            Some(VideoCodec::H265) => TsCodec::H265,
+            Some(_) => return None,
+            None => TsCodec::Opus,
+        };
+        Some(Self {
+            codec: ts_codec,
+            depacketizer,
+            clock_rate: codec.capability.clock_rate.max(1),
+            epoch,
+            last: None,
+            resync: false,
+        })
+    }
+
+    pub fn codec(&self) -> TsCodec {
+        self.codec
+    }
+
+    /// Drops the video until the next keyframe, after lost packets
+    pub fn lagged(&mut self) {
+        self.resync = self.codec.is_video();
+    }
+
+    /// A frame once its last packet is pushed
+    pub fn push(&mut self, packet: &Packet) -> Option<TsFrame> {
+        let data = self.depacketizer.push(packet)?;
+        let timestamp = packet.header.timestamp;
+        let ticks = match self.last {
+            Some((last, ticks)) => {
+                let delta = timestamp.wrapping_sub(last) as i32 as i64;
+                (ticks as i64 + delta).max(0) as u64
+            }
+            None => (self.epoch.elapsed().as_secs_f64() * self.clock_rate as f64) as u64,
+        };
+        self.last = Some((timestamp, ticks));
+        let keyframe = is_keyframe(self.codec, &data);
+        if self.resync && !keyframe {
+            return None;
+        }
+        self.resync = false;
+        let pts = ticks * CLOCK_RATE as u64 / self.clock_rate as u64;
+        Some(TsFrame {
+            codec: self.codec,
+            pts,
+            dts: pts,
+            keyframe,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{TsCodec, TsDemuxer, TsFrame, TsMuxer, crc32, is_keyframe, opus_samples};
+
+    fn frame(codec: TsCodec, pts: u64, data: Vec<u8>) -> TsFrame {
+        TsFrame {
+            codec,
+            pts,
+            dts: pts,
+            keyframe: is_keyframe(codec, &data),
+            data: Bytes::from(data),
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0x0376e6e7);
+    }
+
+    #[test]
+    fn test_frame_info() {
+        assert!(is_keyframe(
+            TsCodec::H264,
+            &[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65]
+        ));
+        assert!(!is_keyframe(TsCodec::H264, &[0, 0, 0, 1, 0x41, 0x9a]));
+        assert!(is_keyframe(TsCodec::H265, &[0, 0, 1, 0x26, 0x01]));
+        assert!(!is_keyframe(TsCodec::H265, &[0, 0, 1, 0x02, 0x01]));
+
+        // CELT 20 ms, SILK 60 ms, and 3 frames of CELT 10 ms
+        assert_eq!(opus_samples(&[0xfc]), 960);
+        assert_eq!(opus_samples(&[0x18]), 2880);
+        assert_eq!(opus_samples(&[0xf3, 0x03]), 1440);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for codec in [TsCodec::H264, TsCodec::H265] {
+            let mut muxer = TsMuxer::new(Some(codec), true);
+            let mut demuxer = TsDemuxer::default();
+
+            let header = match codec {
+                TsCodec::H264 => vec![0x65],
+                _ => vec![0x26, 0x01],
+            };
+            let mut key = vec![0, 0, 0, 1];
+            key.extend_from_slice(&header);
+            key.extend((0..5000).map(|i| (i % 250 + 1) as u8));
+            let mut delta = vec![0, 0, 0, 1, 0x02, 0x01];
+            delta.extend_from_slice(&[0x88; 300]);
+
+            // Video before the first keyframe is dropped
+            let mut data = muxer.video(&frame(codec, 0, delta.clone()));
+            assert!(data.is_empty());
+            data.extend(muxer.video(&frame(codec, 9000, key.clone())));
+            data.extend(muxer.audio(&frame(TsCodec::Opus, 9000, vec![0xfc; 200])));
+            data.extend(muxer.audio(&frame(TsCodec::Opus, 10800, vec![0xfc; 300])));
+            data.extend(muxer.video(&frame(codec, 12000, delta.clone())));
+            data.extend(muxer.video(&frame(codec, 15000, key.clone())));
+            assert_eq!(data.len() % 188, 0);
+
+            // Split anywhere, the last frame waits for the next unit start
+            let mut frames = demuxer.push(&data[..1000]);
+            frames.extend(demuxer.push(&data[1000..]));
+            assert_eq!(demuxer.video(), Some(codec));
+            assert!(
+                demuxer
+                    .streams()
+                    .unwrap()
+                    .iter()
+                    .any(|stream| stream.codec == Some(TsCodec::Opus))
+            );
+
+            let video: Vec<_> = frames.iter().filter(|f| f.codec.is_video()).collect();
+            assert_eq!(video.len(), 2);
+            assert_eq!(video[0].pts, 9000);
+            assert!(video[0].keyframe);
+            assert!(video[0].data.ends_with(&key[4..]));
+            assert_eq!(video[1].pts, 12000);
+            assert!(!video[1].keyframe);
+            assert!(video[1].data.ends_with(&delta[4..]));
+
+            let audio: Vec<_> = frames.iter().filter(|f| !f.codec.is_video()).collect();
+            assert_eq!(audio.len(), 2);
+            assert_eq!((audio[0].pts, audio[0].data.len()), (9000, 200));
+            assert_eq!((audio[1].pts, audio[1].data.len()), (10800, 300));
+        }
+    }
+
+    #[test]
+    fn test_audio_only() {
+        let mut muxer = TsMuxer::new(None, true);
+        let mut demuxer = TsDemuxer::default();
+        let data = muxer.audio(&frame(TsCodec::Opus, 1800, vec![0xfc; 100]));
+        assert!(!data.is_empty());
+        let frames = demuxer.push(&data);
+        assert_eq!(demuxer.video(), None);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, Bytes::from(vec![0xfc; 100]));
+    }
+}
@@ -0,0 +1,282 @@
+use super::{
+    AUDIO_PID, CLOCK_RATE, DESCRIPTOR_EXTENSION, DESCRIPTOR_REGISTRATION, OPUS_CONTROL_PREFIX,
+    OPUS_REGISTRATION, PACKET_SIZE, PAT_PID, PMT_PID, PROGRAM_NUMBER, STREAM_ID_PRIVATE,
+    STREAM_ID_VIDEO, SYNC_BYTE, TsCodec, TsFrame, VIDEO_PID, crc32,
+};
+
+/// The PCR runs ahead of the DTS, the time a decoder has to receive a frame
+const PCR_DELAY: u64 = CLOCK_RATE as u64 / 10;
+/// The PAT and PMT are repeated at least this often without a video keyframe
+const PSI_INTERVAL: u64 = CLOCK_RATE as u64 / 2;
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+
+/// Muxes the frames of a stream into TS packets.
+/// The PAT and PMT go before every video keyframe, the video starts with one.
+pub struct TsMuxer {
+    video: Option<TsCodec>,
+    audio: bool,
+    /// Continuity counters of the PAT, the PMT, the video and the audio
+    continuity: [u8; 4],
+    /// DTS of the last PAT and PMT
+    psi_sent: Option<u64>,
+    keyframe_seen: bool,
+}
+
+impl TsMuxer {
+    /// H.264 or H.265 `video`, and Opus `audio`
+    pub fn new(video: Option<TsCodec>, audio: bool) -> Self {
+        Self {
+            video: video.filter(|codec| codec.is_video()),
+            audio,
+            continuity: [0; 4],
+            psi_sent: None,
+            keyframe_seen: false,
+        }
+    }
+
+    /// TS packets of a video frame, none before the first keyframe
+    pub fn video(&mut self, frame: &TsFrame) -> Vec<u8> {
+        let Some(codec) = self.video else {
+            return vec![];
+        };
+        if !frame.keyframe && !self.keyframe_seen {
+            return vec![];
+        }
+        self.keyframe_seen = true;
+
+        let mut out = self.psi(frame.dts, frame.keyframe);
+        // Decoders expect an access unit delimiter first
+        let delimiter: &[u8] = match codec {
+            TsCodec::H264 => &[0, 0, 0, 1, 0x09, 0xf0],
+            _ => &[0, 0, 0, 1, 0x46, 0x01, 0x50],
+        };
+        let mut payload = Vec::with_capacity(frame.data.len() + delimiter.len());
+        if !starts_with_delimiter(codec, &frame.data) {
+            payload.extend_from_slice(delimiter);
+        }
+        payload.extend_from_slice(&frame.data);
+        let pes = pes(STREAM_ID_VIDEO, frame.pts, frame.dts, &payload, false);
+        self.packets(&mut out, VIDEO_PID, &pes, Some(frame.dts), frame.keyframe);
+        out
+    }
+
+    /// TS packets of an Opus packet
+    pub fn audio(&mut self, frame: &TsFrame) -> Vec<u8> {
+        if !self.audio {
+            return vec![];
+        }
+        let mut out = self.psi(frame.dts, false);
+        let mut payload = Vec::with_capacity(frame.data.len() + 8);
+        payload.extend_from_slice(&OPUS_CONTROL_PREFIX.to_be_bytes());
+        let mut size = frame.data.len();
+        while size >= 0xff {
+            payload.push(0xff);
+            size -= 0xff;
+        }
+        payload.push(size as u8);
+        payload.extend_from_slice(&frame.data);
+        let pes = pes(STREAM_ID_PRIVATE, frame.pts, frame.dts, &payload, true);
+        // The audio carries the PCR of the programs without video
+        let pcr = self.video.is_none().then_some(frame.dts);
+        self.packets(&mut out, AUDIO_PID, &pes, pcr, true);
+        out
+    }
+
+    fn pcr_pid(&self) -> u16 {
+        if self.video.is_some() {
+            VIDEO_PID
+        } else {
+            AUDIO_PID
+        }
+    }
+
+    /// The PAT and PMT when due
+    fn psi(&mut self, dts: u64, keyframe: bool) -> Vec<u8> {
+        let due = keyframe
+            || self
+                .psi_sent
+                .is_none_or(|sent| dts.saturating_sub(sent) >= PSI_INTERVAL || dts < sent);
+        if !due {
+            return vec![];
+        }
+        self.psi_sent = Some(dts);
+
+        let mut pat = vec![];
+        pat.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+        pat.extend_from_slice(&(0xe000 | PMT_PID).to_be_bytes());
+
+        let mut pmt = vec![];
+        pmt.extend_from_slice(&(0xe000 | self.pcr_pid()).to_be_bytes());
+        // No program descriptors
+        pmt.extend_from_slice(&0xf000u16.to_be_bytes());
+        if let Some(codec) = self.video {
+            stream_entry(&mut pmt, codec.stream_type(), VIDEO_PID, &[]);
+        }
+        if self.audio {
+            let mut descriptors = vec![DESCRIPTOR_REGISTRATION, 4];
+            descriptors.extend_from_slice(OPUS_REGISTRATION);
+            // The channel config extension, 2 channels as in WebRTC
+            descriptors.extend_from_slice(&[DESCRIPTOR_EXTENSION, 2, 0x80, 2]);
+            stream_entry(
+                &mut pmt,
+                TsCodec::Opus.stream_type(),
+                AUDIO_PID,
+                &descriptors,
+            );
+        }
+
+        let mut out = Vec::with_capacity(PACKET_SIZE * 2);
+        let pat = section(0x00, 1, &pat);
+        let pmt = section(0x02, PROGRAM_NUMBER, &pmt);
+        for (index, pid, section) in [(0, PAT_PID, pat), (1, PMT_PID, pmt)] {
+            let counter = self.counter(index);
+            out.push(SYNC_BYTE);
+            out.extend_from_slice(&(0x4000 | pid).to_be_bytes());
+            out.push(0x10 | counter);
+            // The pointer field
+            out.push(0);
+            out.extend_from_slice(&section);
+            out.resize(out.len().next_multiple_of(PACKET_SIZE), 0xff);
+        }
+        out
+    }
+
+    fn counter(&mut self, index: usize) -> u8 {
+        let counter = self.continuity[index];
+        self.continuity[index] = (counter + 1) & 0x0f;
+        counter
+    }
+
+    /// Splits a PES into TS packets, with the PCR in the first one
+    fn packets(
+        &mut self,
+        out: &mut Vec<u8>,
+        pid: u16,
+        pes: &[u8],
+        pcr: Option<u64>,
+        random_access: bool,
+    ) {
+        let index = if pid == VIDEO_PID { 2 } else { 3 };
+        let mut remaining = pes;
+        let mut first = true;
+        while !remaining.is_empty() {
+            let mut adaptation = vec![];
+            if first && (pcr.is_some() || random_access) {
+                let mut flags = 0u8;
+                if random_access {
+                    flags |= 0x40;
+                }
+                adaptation.push(flags);
+                if let Some(dts) = pcr {
+                    adaptation[0] |= 0x10;
+                    let base = dts.wrapping_sub(PCR_DELAY) & TIMESTAMP_MASK;
+                    // 33 bits of base, 6 reserved bits and a zero extension
+                    adaptation.extend_from_slice(&((base << 15) | 0x7e00).to_be_bytes()[2..]);
+                }
+            }
+
+            // The adaptation field length byte, and the stuffing of the last packet
+            let header = 4;
+            let mut field = if adaptation.is_empty() {
+                0
+            } else {
+                1 + adaptation.len()
+            };
+            let room = PACKET_SIZE - header - field;
+            if remaining.len() < room {
+                let stuffing = room - remaining.len();
+                if field == 0 {
+                    field = stuffing;
+                    if stuffing > 1 {
+                        adaptation.push(0);
+                        adaptation.resize(stuffing - 1, 0xff);
+                    }
+                } else {
+                    field += stuffing;
+                    adaptation.resize(adaptation.len() + stuffing, 0xff);
+                }
+            }
+            let size = PACKET_SIZE - header - field;
+
+            let counter = self.counter(index);
+            out.push(SYNC_BYTE);
+            let start = if first { 0x4000 } else { 0 };
+            out.extend_from_slice(&(start | pid).to_be_bytes());
+            if field > 0 {
+                out.push(0x30 | counter);
+                out.push((field - 1) as u8);
+                out.extend_from_slice(&adaptation);
+            } else {
+                out.push(0x10 | counter);
+            }
+            out.extend_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+            first = false;
+        }
+    }
+}
+
+fn starts_with_delimiter(codec: TsCodec, frame: &[u8]) -> bool {
+    let header = match frame {
+        [0, 0, 0, 1, header, ..] | [0, 0, 1, header, ..] => *header,
+        _ => return false,
+    };
+    match codec {
+        TsCodec::H264 => header & 0x1f == 9,
+        _ => (header >> 1) & 0x3f == 35,
+    }
+}
+
+/// A PES packet, the length is only set when `bounded`, video may be larger than it allows
+fn pes(stream_id: u8, pts: u64, dts: u64, payload: &[u8], bounded: bool) -> Vec<u8> {
+    let with_dts = dts != pts;
+    let header_length = if with_dts { 10 } else { 5 };
+    let mut out = Vec::with_capacity(payload.len() + 9 + header_length);
+    out.extend_from_slice(&[0, 0, 1, stream_id]);
+    let length = 3 + header_length + payload.len();
+    let length = if bounded && length <= u16::MAX as usize {
+        length as u16
+    } else {
+        0
+    };
+    out.extend_from_slice(&length.to_be_bytes());
+    out.push(0x80);
+    out.push(if with_dts { 0xc0 } else { 0x80 });
+    out.push(header_length as u8);
+    if with_dts {
+        timestamp(&mut out, 0x3, pts);
+        timestamp(&mut out, 0x1, dts);
+    } else {
+        timestamp(&mut out, 0x2, pts);
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+fn timestamp(out: &mut Vec<u8>, prefix: u8, value: u64) {
+    let value = value & TIMESTAMP_MASK;
+    out.push((prefix << 4) | (((value >> 29) as u8) & 0x0e) | 1);
+    out.extend_from_slice(&((((value >> 14) as u16) & 0xfffe) | 1).to_be_bytes());
+    out.extend_from_slice(&((((value << 1) as u16) & 0xfffe) | 1).to_be_bytes());
+}
+
+fn stream_entry(out: &mut Vec<u8>, stream_type: u8, pid: u16, descriptors: &[u8]) {
+    out.push(stream_type);
+    out.extend_from_slice(&(0xe000 | pid).to_be_bytes());
+    out.extend_from_slice(&(0xf000 | descriptors.len() as u16).to_be_bytes());
+    out.extend_from_slice(descriptors);
+}
+
+/// A PSI section with the syntax, version 0 and the CRC
+fn section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+    let length = 5 + body.len() + 4;
+    let mut out = vec![table_id];
+    out.extend_from_slice(&(0xb000 | length as u16).to_be_bytes());
+    out.extend_from_slice(&id.to_be_bytes());
+    // Current, section 0 of 0
+    out.extend_from_slice(&[0xc1, 0, 0]);
+    out.extend_from_slice(body);
+    let crc = crc32(&out);
+    out.extend_from_slice(&crc.to_be_bytes());
+    out
+}
@@ -0,0 +1,11 @@
+//! Outputs playing the streams without a WebRTC peer, straight from the publish tracks
+
+#[cfg(feature = "mpegts")]
+pub mod mpegts;
+pub mod rtsp;
+#[cfg(feature = "srt")]
+pub mod srt;
+#[cfg(feature = "mpegts")]
+pub mod udp;
+#[cfg(feature = "recorder")]
+pub mod ws;
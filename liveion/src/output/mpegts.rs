@@ -0,0 +1,94 @@
+use std::time::Instant;
+
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinSet;
+use tracing::debug;
+
+use crate::forward::OutputTrack;
+use crate::mpegts::{TrackFrames, TsFrame, TsMuxer};
+
+/// Frames waiting for the muxer, the tracks lag behind it
+const FRAME_QUEUE: usize = 256;
+/// 7 TS packets, the payload of a UDP datagram or an SRT packet
+pub const CHUNK_SIZE: usize = 1316;
+
+/// The tracks MPEG-TS carries, sharing one time base
+pub fn frames(tracks: Vec<OutputTrack>) -> Vec<(OutputTrack, TrackFrames)> {
+    let epoch = Instant::now();
+    tracks
+        .into_iter()
+        .filter_map(|track| {
+            let frames = TrackFrames::new(&track, epoch)?;
+            Some((track, frames))
+        })
+        .collect()
+}
+
+/// A single program of the tracks, read until it is dropped
+pub struct TsOutput {
+    muxer: TsMuxer,
+    receiver: mpsc::Receiver<TsFrame>,
+    _reading: JoinSet<()>,
+}
+
+impl TsOutput {
+    pub fn new(tracks: Vec<(OutputTrack, TrackFrames)>) -> Self {
+        let video = tracks
+            .iter()
+            .map(|(_, frames)| frames.codec())
+            .find(|codec| codec.is_video());
+        let audio = tracks.iter().any(|(_, frames)| !frames.codec().is_video());
+        let (sender, receiver) = mpsc::channel(FRAME_QUEUE);
+        let mut reading = JoinSet::new();
+        for (track, frames) in tracks {
+            reading.spawn(read(track, frames, sender.clone()));
+        }
+        Self {
+            muxer: TsMuxer::new(video, audio),
+            receiver,
+            _reading: reading,
+        }
+    }
+
+    /// The next frame and its TS packets, None once the publisher is gone
+    pub async fn next(&mut self) -> Option<(TsFrame, Vec<u8>)> {
+        let frame = self.receiver.recv().await?;
+        let data = if frame.codec.is_video() {
+            self.muxer.video(&frame)
+        } else {
+            self.muxer.audio(&frame)
+        };
+        Some((frame, data))
+    }
+}
+
+async fn read(track: OutputTrack, mut frames: TrackFrames, sender: mpsc::Sender<TsFrame>) {
+    let (gop, mut receiver) = track.subscribe();
+    if gop.is_empty() {
+        track.request_keyframe();
+    }
+    for packet in gop {
+        if let Some(frame) = frames.push(&packet)
+            && sender.send(frame).await.is_err()
+        {
+            return;
+        }
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(packet) => {
+                if let Some(frame) = frames.push(&packet)
+                    && sender.send(frame).await.is_err()
+                {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                debug!(count, kind = %track.kind(), "mpegts output lagged");
+                frames.lagged();
+                track.request_keyframe();
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,981 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ::rtsp::constants::net;
+use ::rtsp::{Message, Method, ParseError, Request, Response, StatusCode, Version, headers};
+use anyhow::anyhow;
+use auth::AuthState;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{Notify, broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use webrtc::rtcp::sender_report::SenderReport;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::util::Marshal;
+
+use crate::config;
+use crate::error::AppError;
+use crate::forward::{OutputSession, OutputTrack};
+use crate::stream::callout::ClientInfo;
+use crate::stream::manager::Manager;
+
+const READ_BUFFER: usize = 4096;
+/// Responses and interleaved frames waiting for the socket, media is dropped beyond it
+const WRITE_QUEUE: usize = 1024;
+const SENDER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Seconds between 1900, the NTP epoch, and 1970
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+type Reply = std::result::Result<(Vec<(headers::HeaderName, String)>, Vec<u8>), StatusCode>;
+
+pub async fn serve(
+    listener: TcpListener,
+    cfg: config::Rtsp,
+    manager: Arc<Manager>,
+    auth: AuthState,
+) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(?err, "rtsp accept error");
+                continue;
+            }
+        };
+        let connection = Connection::new(addr, &cfg, manager.clone(), auth.clone());
+        tokio::spawn(async move {
+            debug!(%addr, session = connection.session, "rtsp connection open");
+            if let Err(err) = connection.run(socket).await {
+                debug!(%addr, ?err, "rtsp connection error");
+            }
+            debug!(%addr, "rtsp connection closed");
+        });
+    }
+}
+
+#[derive(Clone)]
+enum Transport {
+    Interleaved {
+        rtp: u8,
+        rtcp: u8,
+    },
+    Udp {
+        rtp: Arc<UdpSocket>,
+        rtcp: Arc<UdpSocket>,
+        rtp_target: SocketAddr,
+        rtcp_target: SocketAddr,
+    },
+}
+
+impl Transport {
+    /// False once the client is gone
+    async fn send(&self, rtcp: bool, data: &[u8], writer: &mpsc::Sender<Vec<u8>>) -> bool {
+        match self {
+            Transport::Interleaved {
+                rtp: rtp_channel,
+                rtcp: rtcp_channel,
+            } => {
+                let channel = if rtcp { *rtcp_channel } else { *rtp_channel };
+                let mut frame = Vec::with_capacity(data.len() + 4);
+                frame.push(b'$');
+                frame.push(channel);
+                frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                frame.extend_from_slice(data);
+                !matches!(
+                    writer.try_send(frame),
+                    Err(mpsc::error::TrySendError::Closed(_))
+                )
+            }
+            Transport::Udp {
+                rtp,
+                rtcp: rtcp_socket,
+                rtp_target,
+                rtcp_target,
+            } => {
+                let result = if rtcp {
+                    rtcp_socket.send_to(data, rtcp_target).await
+                } else {
+                    rtp.send_to(data, rtp_target).await
+                };
+                if let Err(err) = result {
+                    debug!(?err, "rtsp udp send error");
+                }
+                !writer.is_closed()
+            }
+        }
+    }
+}
+
+struct Connection {
+    addr: SocketAddr,
+    session: String,
+    session_timeout: Duration,
+    manager: Arc<Manager>,
+    auth: AuthState,
+    stream: Option<String>,
+    tracks: Vec<OutputTrack>,
+    /// Track index and transport of every SETUP
+    setups: Vec<(usize, Transport)>,
+    next_channel: u8,
+    play: bool,
+    forwards: Vec<JoinHandle<()>>,
+    closed: Arc<Notify>,
+    /// RTCP receiver reports of the UDP clients
+    reports: Arc<Notify>,
+    /// Admitted by DESCRIBE, the connection is closed once it expires
+    output: Option<OutputSession>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for forward in self.forwards.iter() {
+            forward.abort();
+        }
+    }
+}
+
+impl Connection {
+    fn new(addr: SocketAddr, cfg: &config::Rtsp, manager: Arc<Manager>, auth: AuthState) -> Self {
+        Self {
+            addr,
+            session: uuid::Uuid::new_v4().simple().to_string(),
+            session_timeout: Duration::from_secs(cfg.session_timeout),
+            manager,
+            auth,
+            stream: None,
+            tracks: vec![],
+            setups: vec![],
+            next_channel: 0,
+            play: false,
+            forwards: vec![],
+            closed: Arc::new(Notify::new()),
+            reports: Arc::new(Notify::new()),
+            output: None,
+        }
+    }
+
+    async fn run(mut self, socket: TcpStream) -> anyhow::Result<()> {
+        let (mut reader, mut writer) = socket.into_split();
+        let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(WRITE_QUEUE);
+        tokio::spawn(async move {
+            while let Some(data) = receiver.recv().await {
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut buffer = Vec::with_capacity(READ_BUFFER);
+        let mut chunk = vec![0u8; READ_BUFFER];
+        let mut deadline = tokio::time::Instant::now() + self.session_timeout;
+        loop {
+            while let Some((request, consumed)) = parse(&buffer)? {
+                buffer.drain(..consumed);
+                let Some(request) = request else {
+                    continue;
+                };
+                let teardown = matches!(request.method(), Method::Teardown);
+                let mut data = Vec::new();
+                self.handle(&request).await.write(&mut data)?;
+                sender.send(data).await?;
+                if teardown {
+                    return Ok(());
+                }
+                if std::mem::take(&mut self.play) {
+                    self.start(&sender);
+                }
+            }
+
+            // Requests and RTCP, interleaved or over UDP, keep the session alive
+            tokio::select! {
+                read = reader.read(&mut chunk) => {
+                    match read {
+                        Ok(0) => return Ok(()),
+                        Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                        Err(err) => return Err(err.into()),
+                    }
+                    deadline = tokio::time::Instant::now() + self.session_timeout;
+                }
+                _ = self.reports.notified() => {
+                    deadline = tokio::time::Instant::now() + self.session_timeout;
+                }
+                _ = tokio::time::sleep_until(deadline) => {
+                    info!(addr = %self.addr, session = self.session, "rtsp session timeout");
+                    return Ok(());
+                }
+                _ = self.closed.notified() => {
+                    info!(addr = %self.addr, session = self.session, "rtsp stream closed");
+                    return Ok(());
+                }
+                _ = expired(&self.output) => {
+                    info!(addr = %self.addr, session = self.session, "rtsp session expired");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+        debug!(addr = %self.addr, method = ?request.method(), uri = ?request.request_uri(), "rtsp request");
+        let reply = match request.method() {
+            Method::Options => Ok((
+                vec![(
+                    headers::PUBLIC,
+                    "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER".to_string(),
+                )],
+                vec![],
+            )),
+            Method::Describe => self.describe(request).await,
+            Method::Setup => self.setup(request).await,
+            Method::Play => self.play(),
+            Method::Teardown | Method::GetParameter => Ok((self.session_header(), vec![])),
+            _ => Err(StatusCode::MethodNotAllowed),
+        };
+
+        let (status, reply_headers, body) = match reply {
+            Ok((reply_headers, body)) => (StatusCode::Ok, reply_headers, body),
+            Err(status) => (status, vec![], vec![]),
+        };
+        let cseq = request
+            .header(&headers::CSEQ)
+            .map(|cseq| cseq.as_str().to_string())
+            .unwrap_or("0".to_string());
+        let mut builder = Response::builder(Version::V1_0, status).header(headers::CSEQ, cseq);
+        for (name, value) in reply_headers {
+            builder = builder.header(name, value);
+        }
+        if !body.is_empty() {
+            builder = builder.header(headers::CONTENT_LENGTH, body.len().to_string());
+        }
+        builder.build(body)
+    }
+
+    fn session_header(&self) -> Vec<(headers::HeaderName, String)> {
+        vec![(
+            headers::SESSION,
+            format!(
+                "{};timeout={}",
+                self.session,
+                self.session_timeout.as_secs()
+            ),
+        )]
+    }
+
+    async fn describe(&mut self, request: &Request<Vec<u8>>) -> Reply {
+        let url = request.request_uri().ok_or(StatusCode::BadRequest)?;
+        let stream = url.path().trim_matches('/').to_string();
+        if stream.is_empty() {
+            return Err(StatusCode::NotFound);
+        }
+
+        // Tokens as in the HTTP API, from the URL for the players without custom headers
+        let token = url
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .map(|(_, token)| token.to_string())
+            .or_else(|| {
+                request
+                    .header(&headers::AUTHORIZATION)
+                    .and_then(|value| value.as_str().strip_prefix("Bearer "))
+                    .map(|token| token.to_string())
+            });
+        let claims = self
+            .auth
+            .claims(token.as_deref())
+            .ok_or(StatusCode::Unauthorized)?;
+        if !claims.can_subscribe(&stream) {
+            return Err(StatusCode::Forbidden);
+        }
+
+        let client = ClientInfo {
+            ip: Some(self.addr.ip().to_string()),
+            token,
+            user_agent: request
+                .header(&headers::USER_AGENT)
+                .map(|value| value.as_str().to_string()),
+        };
+        let status = |err| match err {
+            AppError::StreamNotFound(_) => StatusCode::NotFound,
+            AppError::Forbidden(_) => StatusCode::Forbidden,
+            _ => StatusCode::InternalServerError,
+        };
+        let tracks = self.manager.output_tracks(&stream).await.map_err(status)?;
+        // Another DESCRIBE of the connection replaces the session
+        self.output = None;
+        self.output = Some(
+            self.manager
+                .admit_output(&stream, self.session.clone(), client)
+                .await
+                .map_err(status)?,
+        );
+        info!(addr = %self.addr, session = self.session, stream, "rtsp describe");
+        let sdp = session_description(&tracks);
+        self.stream = Some(stream);
+        self.tracks = tracks;
+        self.setups.clear();
+        Ok((
+            vec![(headers::CONTENT_TYPE, "application/sdp".to_string())],
+            sdp.into_bytes(),
+        ))
+    }
+
+    async fn setup(&mut self, request: &Request<Vec<u8>>) -> Reply {
+        if self.tracks.is_empty() || !self.forwards.is_empty() {
+            return Err(StatusCode::MethodNotValidInThisState);
+        }
+        let uri = request
+            .request_uri()
+            .map(|uri| uri.to_string())
+            .unwrap_or_default();
+        let index = match track_index(&uri) {
+            Some(index) => index,
+            None if self.tracks.len() == 1 => 0,
+            None => return Err(StatusCode::BadRequest),
+        };
+        if index >= self.tracks.len() {
+            return Err(StatusCode::NotFound);
+        }
+        let transport = request
+            .header(&headers::TRANSPORT)
+            .map(|transport| transport.as_str().to_string())
+            .ok_or(StatusCode::BadRequest)?;
+
+        let (transport, response_transport) =
+            if transport.contains("TCP") || transport.contains("interleaved") {
+                let (rtp, rtcp) = parse_pair::<u8>(&transport, "interleaved=")
+                    .unwrap_or((self.next_channel, self.next_channel.wrapping_add(1)));
+                self.next_channel = self.next_channel.max(rtcp.wrapping_add(1));
+                (
+                    Transport::Interleaved { rtp, rtcp },
+                    format!("RTP/AVP/TCP;unicast;interleaved={rtp}-{rtcp}"),
+                )
+            } else {
+                let (client_rtp, client_rtcp) = parse_pair::<u16>(&transport, "client_port=")
+                    .ok_or(StatusCode::UnsupportedTransport)?;
+                let bind = || UdpSocket::bind((net::unspecified_for(&self.addr), 0));
+                let (rtp, rtcp) = match (bind().await, bind().await) {
+                    (Ok(rtp), Ok(rtcp)) => (rtp, rtcp),
+                    _ => return Err(StatusCode::InternalServerError),
+                };
+                let server_rtp = rtp
+                    .local_addr()
+                    .map_err(|_| StatusCode::InternalServerError)?;
+                let server_rtcp = rtcp
+                    .local_addr()
+                    .map_err(|_| StatusCode::InternalServerError)?;
+                (
+                    Transport::Udp {
+                        rtp: Arc::new(rtp),
+                        rtcp: Arc::new(rtcp),
+                        rtp_target: SocketAddr::new(self.addr.ip(), client_rtp),
+                        rtcp_target: SocketAddr::new(self.addr.ip(), client_rtcp),
+                    },
+                    format!(
+                        "RTP/AVP;unicast;client_port={client_rtp}-{client_rtcp};server_port={}-{}",
+                        server_rtp.port(),
+                        server_rtcp.port()
+                    ),
+                )
+            };
+        self.setups.retain(|(setup, _)| *setup != index);
+        self.setups.push((index, transport));
+
+        let mut reply_headers = self.session_header();
+        reply_headers.push((headers::TRANSPORT, response_transport));
+        Ok((reply_headers, vec![]))
+    }
+
+    fn play(&mut self) -> Reply {
+        if self.setups.is_empty() {
+            return Err(StatusCode::MethodNotValidInThisState);
+        }
+        self.play = self.forwards.is_empty();
+        let mut reply_headers = self.session_header();
+        reply_headers.push((headers::RANGE, "npt=0.000-".to_string()));
+        Ok((reply_headers, vec![]))
+    }
+
+    /// Starts the media after the PLAY response is queued
+    fn start(&mut self, writer: &mpsc::Sender<Vec<u8>>) {
+        info!(
+            addr = %self.addr,
+            session = self.session,
+            stream = self.stream,
+            tracks = self.setups.len(),
+            "rtsp play"
+        );
+        for (index, transport) in self.setups.iter() {
+            self.forwards.push(tokio::spawn(forward(
+                self.tracks[*index].clone(),
+                transport.clone(),
+                writer.clone(),
+                self.closed.clone(),
+            )));
+            if let Transport::Udp { rtcp, .. } = transport {
+                self.forwards.push(tokio::spawn(receive_reports(
+                    rtcp.clone(),
+                    self.addr,
+                    self.reports.clone(),
+                )));
+            }
+        }
+    }
+}
+
+/// Resolves once the admitted session expires, never before DESCRIBE
+async fn expired(output: &Option<OutputSession>) {
+    match output {
+        Some(output) => output.expired().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Signals every RTCP packet the client sends to the server RTCP port
+async fn receive_reports(socket: Arc<UdpSocket>, client: SocketAddr, reports: Arc<Notify>) {
+    let mut buffer = vec![0u8; READ_BUFFER];
+    loop {
+        match socket.recv_from(&mut buffer).await {
+            Ok((_, from)) if from.ip() == client.ip() => reports.notify_one(),
+            Ok(_) => {}
+            Err(err) => {
+                debug!(?err, "rtsp udp receive error");
+                return;
+            }
+        }
+    }
+}
+
+/// Sends the track to the client until either of them is gone
+async fn forward(
+    track: OutputTrack,
+    transport: Transport,
+    writer: mpsc::Sender<Vec<u8>>,
+    closed: Arc<Notify>,
+) {
+    let (gop, mut receiver) = track.subscribe();
+    if gop.is_empty() {
+        track.request_keyframe();
+    }
+    let mut report = SenderReportState::default();
+    for packet in gop {
+        if !send_packet(&packet, &transport, &writer, &mut report).await {
+            return;
+        }
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(packet) => {
+                if !send_packet(&packet, &transport, &writer, &mut report).await {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                debug!(count, kind = %track.kind(), "rtsp forward lagged");
+                track.request_keyframe();
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    // The publisher is gone, the client reconnects to the next one
+    closed.notify_one();
+}
+
+#[derive(Default)]
+struct SenderReportState {
+    sent_at: Option<Instant>,
+    packets: u32,
+    octets: u32,
+}
+
+async fn send_packet(
+    packet: &Packet,
+    transport: &Transport,
+    writer: &mpsc::Sender<Vec<u8>>,
+    report: &mut SenderReportState,
+) -> bool {
+    // The WebRTC header extensions mean nothing to RTSP players
+    let mut packet = packet.clone();
+    packet.header.extension = false;
+    packet.header.extension_profile = 0;
+    packet.header.extensions.clear();
+    let Ok(data) = packet.marshal() else {
+        return true;
+    };
+    if !transport.send(false, &data, writer).await {
+        return false;
+    }
+
+    report.packets = report.packets.wrapping_add(1);
+    report.octets = report.octets.wrapping_add(packet.payload.len() as u32);
+    if report
+        .sent_at
+        .is_none_or(|sent_at| sent_at.elapsed() >= SENDER_REPORT_INTERVAL)
+    {
+        report.sent_at = Some(Instant::now());
+        // Players sync the tracks with the wall clock of the packet just received
+        let sender_report = SenderReport {
+            ssrc: packet.header.ssrc,
+            ntp_time: ntp_time(SystemTime::now()),
+            rtp_time: packet.header.timestamp,
+            packet_count: report.packets,
+            octet_count: report.octets,
+            ..Default::default()
+        };
+        if let Ok(data) = sender_report.marshal()
+            && !transport.send(true, &data, writer).await
+        {
+            return false;
+        }
+    }
+    true
+}
+
+fn ntp_time(time: SystemTime) -> u64 {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+/// A request with the bytes it used, `None` as request for interleaved data or responses,
+/// `Ok(None)` until a whole message is buffered
+type Parsed = Option<(Option<Request<Vec<u8>>>, usize)>;
+
+fn parse(buffer: &[u8]) -> anyhow::Result<Parsed> {
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+    // Interleaved RTCP receiver reports from TCP clients
+    if buffer[0] == b'$' {
+        if buffer.len() < 4 {
+            return Ok(None);
+        }
+        let length = 4 + u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+        return Ok((buffer.len() >= length).then_some((None, length)));
+    }
+    match Message::<Vec<u8>>::parse(&buffer) {
+        Ok((Message::Request(request), consumed)) => Ok(Some((Some(request), consumed))),
+        Ok((_, consumed)) => Ok(Some((None, consumed))),
+        Err(ParseError::Incomplete(_)) => Ok(None),
+        Err(err) => Err(anyhow!("rtsp parse error: {err:?}")),
+    }
+}
+
+/// The track of a SETUP URL, as listed by `a=control:trackID=N`
+fn track_index(uri: &str) -> Option<usize> {
+    let (_, index) = uri.rsplit_once("trackID=")?;
+    let digits = index
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(index.len());
+    index[..digits].parse().ok()
+}
+
+/// `a-b` after `key` in a Transport header
+fn parse_pair<T: std::str::FromStr + Copy>(transport: &str, key: &str) -> Option<(T, T)> {
+    let value = transport.split(key).nth(1)?.split(';').next()?;
+    match value.split_once('-') {
+        Some((first, second)) => Some((first.parse().ok()?, second.parse().ok()?)),
+        None => value.parse().ok().map(|first| (first, first)),
+    }
+}
+
+fn session_description(tracks: &[OutputTrack]) -> String {
+    let mut sdp = String::from(
+        "v=0\r\n\
+         o=- 0 0 IN IP4 127.0.0.1\r\n\
+         s=live777\r\n\
+         c=IN IP4 0.0.0.0\r\n\
+         t=0 0\r\n\
+         a=control:*\r\n",
+    );
+    for (index, track) in tracks.iter().enumerate() {
+        let codec = track.codec();
+        let media = match track.kind() {
+            RTPCodecType::Audio => "audio",
+            _ => "video",
+        };
+        let encoding = codec
+            .capability
+            .mime_type
+            .split_once('/')
+            .map(|(_, encoding)| encoding.to_string())
+            .unwrap_or(codec.capability.mime_type.clone());
+        let mut rtpmap = format!("{encoding}/{}", codec.capability.clock_rate);
+        if codec.capability.channels > 1 {
+            rtpmap.push_str(&format!("/{}", codec.capability.channels));
+        }
+        let pt = codec.payload_type;
+        sdp.push_str(&format!(
+            "m={media} 0 RTP/AVP {pt}\r\na=rtpmap:{pt} {rtpmap}\r\n"
+        ));
+        if !codec.capability.sdp_fmtp_line.is_empty() {
+            sdp.push_str(&format!(
+                "a=fmtp:{pt} {}\r\n",
+                codec.capability.sdp_fmtp_line
+            ));
+        }
+        sdp.push_str(&format!("a=control:trackID={index}\r\n"));
+    }
+    sdp
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ntp_time, parse, parse_pair, serve, track_index};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ::rtsp::{Message, ParseError, Response, StatusCode, headers};
+    use auth::AuthState;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream, UdpSocket};
+    use webrtc::api::APIBuilder;
+    use webrtc::api::media_engine::{MIME_TYPE_H264, MediaEngine};
+    use webrtc::api::setting_engine::SettingEngine;
+    use webrtc::ice::mdns::MulticastDnsMode;
+    use webrtc::peer_connection::RTCPeerConnection;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+    use webrtc::rtcp::receiver_report::ReceiverReport;
+    use webrtc::rtp::header::Header;
+    use webrtc::rtp::packet::Packet;
+    use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+    use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
+    use webrtc::track::track_local::{TrackLocal, TrackLocalWriter};
+    use webrtc::util::{Marshal, Unmarshal};
+
+    use crate::config::{self, Config};
+    use crate::forward::network::Network;
+    use crate::stream::callout::ClientInfo;
+    use crate::stream::manager::Manager;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+    const IDR: &[u8] = &[0x65, 0x88, 0x84, 0x00, 0x33, 0xff];
+
+    /// An H.264 publisher in the process, as the inputs publish
+    async fn publish(manager: &Manager, stream: &str) -> Arc<RTCPeerConnection> {
+        let mut m = MediaEngine::default();
+        m.register_default_codecs().unwrap();
+        let mut s = SettingEngine::default();
+        s.set_ice_multicast_dns_mode(MulticastDnsMode::Disabled);
+        Network::apply_loopback(&mut s);
+        let api = APIBuilder::new()
+            .with_media_engine(m)
+            .with_setting_engine(s)
+            .build();
+        let peer = Arc::new(
+            api.new_peer_connection(RTCConfiguration::default())
+                .await
+                .unwrap(),
+        );
+        let track = Arc::new(TrackLocalStaticRTP::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_H264.to_owned(),
+                clock_rate: 90000,
+                channels: 0,
+                sdp_fmtp_line:
+                    "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f"
+                        .to_owned(),
+                rtcp_feedback: vec![],
+            },
+            "video".to_owned(),
+            stream.to_owned(),
+        ));
+        peer.add_transceiver_from_track(
+            track.clone() as Arc<dyn TrackLocal + Send + Sync>,
+            Some(RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Sendonly,
+                send_encodings: vec![],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let offer = peer.create_offer(None).await.unwrap();
+        let mut gather_complete = peer.gathering_complete_promise().await;
+        peer.set_local_description(offer).await.unwrap();
+        let _ = gather_complete.recv().await;
+        let offer = peer.local_description().await.unwrap();
+        let (answer, _) = manager
+            .publish_loopback(stream.to_string(), offer, ClientInfo::default())
+            .await
+            .unwrap();
+        peer.set_remote_description(answer).await.unwrap();
+
+        // An IDR frame with its parameter sets every 33 ms
+        tokio::spawn(async move {
+            let mut sequence_number = 0u16;
+            for frame in 0u32.. {
+                for nalu in [SPS, PPS, IDR] {
+                    let packet = Packet {
+                        header: Header {
+                            version: 2,
+                            sequence_number,
+                            timestamp: frame.wrapping_mul(3000),
+                            marker: nalu == IDR,
+                            ..Default::default()
+                        },
+                        payload: nalu.to_vec().into(),
+                    };
+                    // Dropped until the peer is connected
+                    let _ = track.write_rtp(&packet).await;
+                    sequence_number = sequence_number.wrapping_add(1);
+                }
+                tokio::time::sleep(Duration::from_millis(33)).await;
+            }
+        });
+
+        let mut published = false;
+        for _ in 0..100 {
+            if manager.output_tracks(stream).await.is_ok() {
+                published = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(published);
+        peer
+    }
+
+    /// Sends the request, and reads until its response
+    async fn request(
+        client: &mut TcpStream,
+        buffer: &mut Vec<u8>,
+        request: String,
+    ) -> Response<Vec<u8>> {
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut chunk = [0u8; 4096];
+        loop {
+            match Message::<Vec<u8>>::parse(&buffer[..]) {
+                Ok((Message::Response(response), consumed)) => {
+                    buffer.drain(..consumed);
+                    return response;
+                }
+                Ok((message, _)) => panic!("unexpected rtsp message {message:?}"),
+                Err(ParseError::Incomplete(_)) => {
+                    let n = client.read(&mut chunk).await.unwrap();
+                    assert!(n > 0, "rtsp connection closed");
+                    buffer.extend_from_slice(&chunk[..n]);
+                }
+                Err(err) => panic!("rtsp parse error {err:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rtsp_loopback() {
+        let manager = Arc::new(Manager::new(Config::default()).await.unwrap());
+        let stream = "rtsp-loopback".to_string();
+        manager.stream_create(stream.clone()).await.unwrap();
+        let peer = publish(&manager, &stream).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            config::Rtsp::default(),
+            manager.clone(),
+            AuthState::new(String::new(), vec![]),
+        ));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buffer = vec![];
+        let url = format!("rtsp://{addr}/{stream}");
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!("DESCRIBE {url} RTSP/1.0\r\nCSeq: 1\r\n\r\n"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+        let sdp = String::from_utf8(response.body().clone()).unwrap();
+        assert!(sdp.contains("m=video 0 RTP/AVP"));
+        assert!(sdp.contains("H264/90000"));
+        assert!(sdp.contains("a=control:trackID=0"));
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!(
+                "SETUP {url}/trackID=0 RTSP/1.0\r\nCSeq: 2\r\nTransport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n\r\n"
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+        let transport = response.header(&headers::TRANSPORT).unwrap().as_str();
+        assert!(transport.contains("interleaved=0-1"));
+        let session = response.header(&headers::SESSION).unwrap().as_str();
+        let session = session.split(';').next().unwrap().to_string();
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!("PLAY {url} RTSP/1.0\r\nCSeq: 3\r\nSession: {session}\r\n\r\n"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        // RTP of the video track on the interleaved channel 0
+        let packet = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut chunk = [0u8; 4096];
+            loop {
+                if buffer.len() >= 4 {
+                    assert_eq!(buffer[0], b'$');
+                    let length = 4 + u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+                    if buffer.len() >= length {
+                        let frame: Vec<u8> = buffer.drain(..length).collect();
+                        if frame[1] == 0 {
+                            return Packet::unmarshal(&mut &frame[4..]).unwrap();
+                        }
+                        continue;
+                    }
+                }
+                let n = client.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "rtsp connection closed");
+                buffer.extend_from_slice(&chunk[..n]);
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(packet.header.version, 2);
+        assert!(!packet.payload.is_empty());
+
+        peer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rtsp_udp_receiver_reports() {
+        let manager = Arc::new(Manager::new(Config::default()).await.unwrap());
+        let stream = "rtsp-udp-reports".to_string();
+        manager.stream_create(stream.clone()).await.unwrap();
+        let peer = publish(&manager, &stream).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            config::Rtsp {
+                session_timeout: 1,
+                ..Default::default()
+            },
+            manager.clone(),
+            AuthState::new(String::new(), vec![]),
+        ));
+
+        let rtp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let rtcp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buffer = vec![];
+        let url = format!("rtsp://{addr}/{stream}");
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!("DESCRIBE {url} RTSP/1.0\r\nCSeq: 1\r\n\r\n"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!(
+                "SETUP {url}/trackID=0 RTSP/1.0\r\nCSeq: 2\r\nTransport: RTP/AVP;unicast;client_port={}-{}\r\n\r\n",
+                rtp.local_addr().unwrap().port(),
+                rtcp.local_addr().unwrap().port(),
+            ),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+        let transport = response.header(&headers::TRANSPORT).unwrap().as_str();
+        let (_, server_rtcp) = parse_pair::<u16>(transport, "server_port=").unwrap();
+        let session = response.header(&headers::SESSION).unwrap().as_str();
+        let session = session.split(';').next().unwrap().to_string();
+
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!("PLAY {url} RTSP/1.0\r\nCSeq: 3\r\nSession: {session}\r\n\r\n"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        // Receiver reports only, past the session timeout of a second
+        let report = ReceiverReport::default().marshal().unwrap();
+        for _ in 0..8 {
+            rtcp.send_to(&report, ("127.0.0.1", server_rtcp))
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+        }
+        let response = request(
+            &mut client,
+            &mut buffer,
+            format!("GET_PARAMETER {url} RTSP/1.0\r\nCSeq: 4\r\nSession: {session}\r\n\r\n"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::Ok);
+
+        // Silent clients time out
+        let mut chunk = [0u8; 4096];
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while client.read(&mut chunk).await.unwrap() > 0 {}
+        })
+        .await;
+        assert!(closed.is_ok());
+
+        peer.close().await.unwrap();
+    }
+
+    #[test]
+    fn test_parse_setup() {
+        assert_eq!(
+            track_index("rtsp://127.0.0.1:8554/camera/trackID=1"),
+            Some(1)
+        );
+        assert_eq!(
+            track_index("rtsp://127.0.0.1:8554/camera?token=abc/trackID=0"),
+            Some(0)
+        );
+        assert_eq!(track_index("rtsp://127.0.0.1:8554/camera"), None);
+
+        let transport = "RTP/AVP/TCP;unicast;interleaved=2-3";
+        assert_eq!(parse_pair::<u8>(transport, "interleaved="), Some((2, 3)));
+        let transport = "RTP/AVP;unicast;client_port=5000-5001";
+        assert_eq!(
+            parse_pair::<u16>(transport, "client_port="),
+            Some((5000, 5001))
+        );
+        assert_eq!(parse_pair::<u8>(transport, "interleaved="), None);
+    }
+
+    #[test]
+    fn test_parse_messages() {
+        let request = b"OPTIONS rtsp://127.0.0.1:8554/camera RTSP/1.0\r\nCSeq: 1\r\n\r\n";
+        let mut buffer = vec![b'$', 1, 0, 2, 0xaa, 0xbb];
+        buffer.extend_from_slice(request);
+
+        let (request, consumed) = parse(&buffer).unwrap().unwrap();
+        assert!(request.is_none());
+        assert_eq!(consumed, 6);
+        let (request, consumed) = parse(&buffer[consumed..]).unwrap().unwrap();
+        assert!(matches!(request.unwrap().method(), ::rtsp::Method::Options));
+        assert_eq!(consumed, buffer.len() - 6);
+
+        assert!(parse(&buffer[..3]).unwrap().is_none());
+        assert!(parse(&buffer[6..20]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ntp_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1500);
+        assert_eq!(ntp_time(time) >> 32, 2_208_988_801);
+        assert_eq!(ntp_time(time) as u32, 1 << 31);
+    }
+}
@@ -0,0 +1,44 @@
+use bytes::Bytes;
+use tracing::info;
+
+use super::mpegts::TsOutput;
+use crate::forward::{OutputSession, OutputTrack};
+use crate::mpegts::TrackFrames;
+use crate::srt::{MAX_PAYLOAD_SIZE, SrtSocket};
+
+/// Sends the tracks muxed to MPEG-TS until the player or the publisher is gone,
+/// or the session expires
+pub(crate) async fn play(
+    mut socket: SrtSocket,
+    stream: String,
+    output: OutputSession,
+    tracks: Vec<(OutputTrack, TrackFrames)>,
+) {
+    let session = output.session().to_string();
+    info!(stream, session, peer = %socket.peer(), "srt play start");
+    let mut muxer = TsOutput::new(tracks);
+    'play: loop {
+        tokio::select! {
+            data = muxer.next() => {
+                let Some((_, data)) = data else {
+                    // The publisher is gone, the player reconnects to the next one
+                    break;
+                };
+                // Whole TS packets in every message
+                for chunk in data.chunks(MAX_PAYLOAD_SIZE) {
+                    if socket.send(Bytes::copy_from_slice(chunk)).await.is_err() {
+                        break 'play;
+                    }
+                }
+            }
+            incoming = socket.recv() => if incoming.is_none() {
+                break;
+            },
+            _ = output.expired() => {
+                info!(stream, session, "srt session expired");
+                break;
+            }
+        }
+    }
+    info!(stream, session, "srt play end");
+}
@@ -0,0 +1,267 @@
+//! MPEG-TS over UDP for playout gear, a single program sent to a unicast or multicast address
+//! in datagrams of 7 TS packets
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::task::AbortHandle;
+use tokio::time::Instant;
+use tracing::{debug, info};
+use url::Url;
+
+use super::mpegts::{CHUNK_SIZE, TsOutput};
+use crate::error::AppError;
+use crate::forward::OutputTrack;
+use crate::mpegts::{CLOCK_RATE, TrackFrames, TsFrame};
+use crate::result::Result;
+
+/// Longer gaps between the frames are not spread, e.g. after lost packets
+const MAX_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+/// A destination, `udp://{host}:{port}?ttl={ttl}&localaddr={ip}`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UdpTarget {
+    pub addr: SocketAddr,
+    /// Hops of the multicast datagrams
+    pub ttl: Option<u32>,
+    /// The address of the interface multicast is sent from
+    pub local_addr: Option<IpAddr>,
+}
+
+impl UdpTarget {
+    pub async fn parse(target_url: &str) -> Result<Self> {
+        let url = Url::parse(target_url)
+            .map_err(|err| AppError::throw(format!("invalid target url: {err}")))?;
+        if url.scheme() != "udp" {
+            return Err(AppError::throw("target url must be udp://{host}:{port}"));
+        }
+        let (Some(host), Some(port)) = (url.host_str(), url.port()) else {
+            return Err(AppError::throw("target url must be udp://{host}:{port}"));
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addr = tokio::net::lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| AppError::throw(format!("can't resolve {host}")))?;
+        let mut target = Self {
+            addr,
+            ttl: None,
+            local_addr: None,
+        };
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "ttl" => {
+                    target.ttl = Some(
+                        value
+                            .parse()
+                            .map_err(|_| AppError::throw(format!("invalid ttl {value}")))?,
+                    )
+                }
+                "localaddr" => {
+                    target.local_addr = Some(
+                        value
+                            .parse()
+                            .map_err(|_| AppError::throw(format!("invalid localaddr {value}")))?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        Ok(target)
+    }
+
+    fn bind(&self) -> Result<UdpSocket> {
+        let local_addr = self.local_addr.unwrap_or(match self.addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        let local_addr = SocketAddr::new(local_addr, 0);
+        let socket = Socket::new(
+            Domain::for_address(local_addr),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        socket.set_nonblocking(true)?;
+        // Bound to the address of an interface, multicast leaves from that interface
+        socket.bind(&local_addr.into())?;
+        if let (Some(ttl), true) = (self.ttl, self.addr.ip().is_multicast()) {
+            match self.addr {
+                SocketAddr::V4(_) => socket.set_multicast_ttl_v4(ttl)?,
+                SocketAddr::V6(_) => socket.set_multicast_hops_v6(ttl)?,
+            }
+        }
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+}
+
+/// The running outputs of every stream
+#[derive(Clone, Default)]
+pub struct UdpOutputs {
+    outputs: Arc<Mutex<HashMap<String, Vec<(SocketAddr, AbortHandle)>>>>,
+}
+
+impl UdpOutputs {
+    /// Sends the tracks until the publisher is gone, an output to the same address is replaced
+    pub async fn start(
+        &self,
+        stream: String,
+        target: UdpTarget,
+        tracks: Vec<(OutputTrack, TrackFrames)>,
+    ) -> Result<()> {
+        let socket = target.bind()?;
+        let addr = target.addr;
+        let outputs = self.clone();
+        // Locked before the spawn, an output ending at once finds its entry
+        let mut map = self.outputs.lock().unwrap();
+        let task = tokio::spawn({
+            let stream = stream.clone();
+            async move {
+                info!(stream, %addr, "mpegts udp output start");
+                send(socket, addr, tracks).await;
+                info!(stream, %addr, "mpegts udp output end");
+                outputs.remove(&stream, tokio::task::id());
+            }
+        });
+        let outputs = map.entry(stream).or_default();
+        outputs.retain(|(target, output)| {
+            if *target == addr {
+                output.abort();
+            }
+            *target != addr
+        });
+        outputs.push((addr, task.abort_handle()));
+        Ok(())
+    }
+
+    /// Stops the outputs of the stream, false without any
+    pub fn stop(&self, stream: &str) -> bool {
+        let outputs = self.outputs.lock().unwrap().remove(stream);
+        let Some(outputs) = outputs else {
+            return false;
+        };
+        for (_, output) in outputs {
+            output.abort();
+        }
+        true
+    }
+
+    fn remove(&self, stream: &str, id: tokio::task::Id) {
+        let mut map = self.outputs.lock().unwrap();
+        if let Some(outputs) = map.get_mut(stream) {
+            outputs.retain(|(_, output)| output.id() != id);
+            if outputs.is_empty() {
+                map.remove(stream);
+            }
+        }
+    }
+}
+
+/// Spreads the datagrams of every frame over its duration, the receive buffers of the
+/// playout gear expect a steady rate rather than a burst per keyframe
+#[derive(Default)]
+struct Pacer {
+    /// The timestamp of the previous video and audio frame
+    last: [Option<u64>; 2],
+}
+
+impl Pacer {
+    /// The interval between the `datagrams` of `frame`, the time since the previous frame
+    /// of its track, 0 for the first one
+    fn interval(&mut self, frame: &TsFrame, datagrams: usize) -> Duration {
+        let last = self.last[frame.codec.is_video() as usize].replace(frame.pts);
+        let ticks = last.map_or(0, |last| frame.pts.saturating_sub(last));
+        let duration = Duration::from_micros(ticks * 1_000_000 / CLOCK_RATE as u64);
+        duration.min(MAX_FRAME_DURATION) / datagrams.max(1) as u32
+    }
+}
+
+async fn send(socket: UdpSocket, addr: SocketAddr, tracks: Vec<(OutputTrack, TrackFrames)>) {
+    let mut output = TsOutput::new(tracks);
+    let mut pacer = Pacer::default();
+    while let Some((frame, data)) = output.next().await {
+        let interval = pacer.interval(&frame, data.len().div_ceil(CHUNK_SIZE));
+        let mut at = Instant::now();
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            if index > 0 {
+                at += interval;
+                tokio::time::sleep_until(at).await;
+            }
+            // The receivers may not be there yet, as with any UDP stream
+            if let Err(err) = socket.send_to(chunk, addr).await {
+                debug!(%addr, ?err, "mpegts udp send error");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use super::{Pacer, UdpTarget};
+    use crate::mpegts::{TsCodec, TsFrame};
+
+    #[test]
+    fn test_pacer() {
+        let frame = |codec, pts| TsFrame {
+            codec,
+            pts,
+            dts: pts,
+            keyframe: false,
+            data: Bytes::new(),
+        };
+        let mut pacer = Pacer::default();
+        assert_eq!(pacer.interval(&frame(TsCodec::H264, 0), 10), Duration::ZERO);
+        // 20 ms of audio in between doesn't shorten the 40 ms of the video frame
+        assert_eq!(
+            pacer.interval(&frame(TsCodec::Opus, 1800), 1),
+            Duration::ZERO
+        );
+        assert_eq!(
+            pacer.interval(&frame(TsCodec::H264, 3600), 10),
+            Duration::from_millis(4)
+        );
+        assert_eq!(
+            pacer.interval(&frame(TsCodec::Opus, 3600), 2),
+            Duration::from_millis(10)
+        );
+        // a gap after lost packets
+        assert_eq!(
+            pacer.interval(&frame(TsCodec::H264, 90000), 2),
+            Duration::from_millis(50)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_target() {
+        let target = UdpTarget::parse("udp://239.0.0.1:1234?ttl=16&localaddr=192.168.1.2")
+            .await
+            .unwrap();
+        assert_eq!(
+            target,
+            UdpTarget {
+                addr: SocketAddr::from((Ipv4Addr::new(239, 0, 0, 1), 1234)),
+                ttl: Some(16),
+                local_addr: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))),
+            }
+        );
+        let target = UdpTarget::parse("udp://[::1]:5000").await.unwrap();
+        assert_eq!(target.addr, "[::1]:5000".parse().unwrap());
+        assert_eq!(target.ttl, None);
+
+        assert!(UdpTarget::parse("udp://127.0.0.1").await.is_err());
+        assert!(UdpTarget::parse("srt://127.0.0.1:5000").await.is_err());
+        assert!(
+            UdpTarget::parse("udp://127.0.0.1:5000?ttl=x")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::time::Instant;
+
+use axum::extract::ws::{Message, WebSocket};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+use crate::forward::{OutputSession, OutputTrack};
+use crate::recorder::mse::{FragmentMuxer, MseMessage};
+
+/// Fragments waiting for the socket, the tracks lag behind it
+const WRITE_QUEUE: usize = 256;
+
+/// The muxers of the tracks a player can decode, sharing one time base
+pub fn muxers(tracks: Vec<OutputTrack>) -> Vec<(OutputTrack, FragmentMuxer)> {
+    let epoch = Instant::now();
+    tracks
+        .into_iter()
+        .filter_map(|track| {
+            let muxer = FragmentMuxer::new(&track, epoch)?;
+            Some((track, muxer))
+        })
+        .collect()
+}
+
+/// Sends the fragments of the tracks until the player or the publisher is gone,
+/// or the session expires
+pub(crate) async fn serve(
+    mut socket: WebSocket,
+    stream: String,
+    output: OutputSession,
+    tracks: Vec<(OutputTrack, FragmentMuxer)>,
+) {
+    let session = output.session().to_string();
+    info!(stream, session, "websocket open");
+    let (sender, mut receiver) = mpsc::channel(WRITE_QUEUE);
+    let mut muxing = JoinSet::new();
+    for (track, muxer) in tracks {
+        muxing.spawn(mux(track, muxer, sender.clone()));
+    }
+    drop(sender);
+
+    loop {
+        tokio::select! {
+            message = receiver.recv() => {
+                let Some(message) = message else {
+                    // The publisher is gone, the player reconnects to the next one
+                    break;
+                };
+                let message = match message {
+                    MseMessage::Text(text) => Message::Text(text.into()),
+                    MseMessage::Binary(data) => Message::Binary(data),
+                };
+                if socket.send(message).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            _ = output.expired() => {
+                info!(stream, session, "websocket session expired");
+                break;
+            }
+        }
+    }
+    muxing.abort_all();
+    let _ = socket.close().await;
+    info!(stream, session, "websocket closed");
+}
+
+async fn mux(track: OutputTrack, mut muxer: FragmentMuxer, sender: mpsc::Sender<MseMessage>) {
+    let (gop, mut receiver) = track.subscribe();
+    if gop.is_empty() {
+        track.request_keyframe();
+    }
+    for packet in gop {
+        for message in muxer.push(&packet) {
+            if sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(packet) => {
+                for message in muxer.push(&packet) {
+                    if sender.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                debug!(count, kind = %track.kind(), "websocket mux lagged");
+                muxer.lagged();
+                track.request_keyframe();
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Dynamic low-latency DASH manifest of the live CMAF packaging
+
+use std::fmt::Write;
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::codec::TrackKind;
+use crate::recorder::hls::track_name;
+use crate::recorder::live::{LiveStream, Window};
+
+/// The MPD of the initialized tracks, the segment in progress is sent with chunked transfer.
+/// `query` is appended to every URI, e.g. `?token=...`, the MPD is reloaded from its `Location`
+pub fn manifest(live: &LiveStream, query: &str) -> Option<String> {
+    let query = query.replace('&', "&amp;");
+    let part_target = live.cfg.part_duration as f64 / 1000.0;
+    let segment_target = live.cfg.segment_duration as f64 / 1000.0;
+
+    let mut adaptation_sets = String::new();
+    let mut max_segment_duration = segment_target;
+    for (id, kind) in [TrackKind::Video, TrackKind::Audio].into_iter().enumerate() {
+        let Some(window) = live.window(kind) else {
+            continue;
+        };
+        let window = window.read().unwrap();
+        if window.init.is_none() {
+            continue;
+        }
+        max_segment_duration = window
+            .complete_segments()
+            .map(|segment| segment.duration())
+            .fold(max_segment_duration, f64::max);
+        adaptation_sets.push_str(&adaptation_set(
+            id,
+            kind,
+            &window,
+            live.cfg.segments,
+            segment_target - part_target,
+            &query,
+        ));
+    }
+    if adaptation_sets.is_empty() {
+        return None;
+    }
+
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    Some(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\"\n\
+     profiles=\"urn:mpeg:dash:profile:isoff-live:2011,urn:mpeg:dash:profile:cmaf:2019\"\n\
+     type=\"dynamic\"\n\
+     availabilityStartTime=\"{start}\"\n\
+     publishTime=\"{now}\"\n\
+     minimumUpdatePeriod=\"PT{segment_target:.3}S\"\n\
+     timeShiftBufferDepth=\"PT{depth:.3}S\"\n\
+     maxSegmentDuration=\"PT{max_segment_duration:.3}S\"\n\
+     minBufferTime=\"PT{part_target:.3}S\">\n    \
+<Location>manifest.mpd{query}</Location>\n    \
+<ServiceDescription id=\"0\">\n        \
+<Latency target=\"{latency}\" min=\"{min_latency}\" max=\"{max_latency}\"/>\n    \
+</ServiceDescription>\n    \
+<Period id=\"0\" start=\"PT0.0S\">\n{adaptation_sets}    </Period>\n    \
+<UTCTiming schemeIdUri=\"urn:mpeg:dash:utc:direct:2014\" value=\"{now}\"/>\n\
+</MPD>\n",
+        start = live.started.to_rfc3339_opts(SecondsFormat::Millis, true),
+        depth = segment_target * live.cfg.segments as f64,
+        latency = live.cfg.part_duration * 3,
+        min_latency = live.cfg.part_duration * 2,
+        max_latency = live.cfg.segment_duration * 3,
+    ))
+}
+
+fn adaptation_set(
+    id: usize,
+    kind: TrackKind,
+    window: &Window,
+    segments: usize,
+    availability_time_offset: f64,
+    query: &str,
+) -> String {
+    let track = track_name(kind);
+    let listed: Vec<_> = window.complete_segments().collect();
+    let listed = &listed[listed.len().saturating_sub(segments)..];
+    let start_number = listed
+        .first()
+        .map(|segment| segment.msn)
+        .unwrap_or_else(|| window.next_part().0);
+
+    let mut timeline = String::new();
+    for segment in listed {
+        let _ = writeln!(
+            timeline,
+            "                        <S t=\"{}\" d=\"{}\" />",
+            segment.time(),
+            (segment.duration() * window.timescale as f64).round() as u64
+        );
+    }
+    let representation = match kind {
+        TrackKind::Video => format!(
+            "<Representation id=\"{id}\" mimeType=\"video/mp4\" codecs=\"{}\" bandwidth=\"{}\" width=\"{}\" height=\"{}\" sar=\"1:1\">",
+            window.codec,
+            window.bandwidth().max(1),
+            window.width,
+            window.height
+        ),
+        TrackKind::Audio => format!(
+            "<Representation id=\"{id}\" mimeType=\"audio/mp4\" codecs=\"{}\" bandwidth=\"{}\" audioSamplingRate=\"{}\">",
+            window.codec,
+            window.bandwidth().max(1),
+            window.timescale
+        ),
+    };
+    format!(
+        "        <AdaptationSet id=\"{id}\" contentType=\"{track}\" startWithSAP=\"1\" segmentAlignment=\"true\">\n            {representation}\n                <SegmentTemplate timescale=\"{timescale}\" initialization=\"{track}/init.mp4{query}\" media=\"{track}/$Number$.m4s{query}\" startNumber=\"{start_number}\" availabilityTimeOffset=\"{availability_time_offset:.3}\" availabilityTimeComplete=\"false\">\n                    <SegmentTimeline>\n{timeline}                    </SegmentTimeline>\n                </SegmentTemplate>\n            </Representation>\n        </AdaptationSet>\n",
+        timescale = window.timescale,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::manifest;
+    use crate::codec::TrackKind;
+    use crate::config;
+    use crate::recorder::live::{LiveStream, Part};
+
+    #[test]
+    fn test_manifest() {
+        let live = LiveStream::new(
+            &config::Cmaf {
+                segments: 2,
+                ..Default::default()
+            },
+            &[TrackKind::Video, TrackKind::Audio],
+        );
+        assert!(manifest(&live, "").is_none());
+
+        live.publish(TrackKind::Audio, |window| {
+            window.init = Some(Bytes::from_static(b"init"));
+            window.codec = "opus".to_string();
+            window.timescale = 48000;
+            for msn in 0..3 {
+                window.push_part(
+                    msn,
+                    Part {
+                        time: 96000 * msn,
+                        duration: 2.0,
+                        independent: true,
+                        data: Bytes::from_static(b"part"),
+                    },
+                );
+                window.complete();
+            }
+        });
+
+        let mpd = manifest(&live, "?token=t&a=b").unwrap();
+        assert!(mpd.contains("type=\"dynamic\""));
+        assert!(mpd.contains("<UTCTiming schemeIdUri=\"urn:mpeg:dash:utc:direct:2014\""));
+        assert!(!mpd.contains("contentType=\"video\""));
+        assert!(mpd.contains("media=\"audio/$Number$.m4s?token=t&amp;a=b\" startNumber=\"1\""));
+        assert!(mpd.contains("<Location>manifest.mpd?token=t&amp;a=b</Location>"));
+        assert!(mpd.contains("availabilityTimeOffset=\"1.500\""));
+        assert!(mpd.contains("<S t=\"96000\" d=\"96000\" />\n"));
+        assert!(mpd.contains("<S t=\"192000\" d=\"96000\" />\n"));
+        assert!(!mpd.contains("<S t=\"0\""));
+    }
+}
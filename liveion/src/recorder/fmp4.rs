@@ -61,7 +61,7 @@ impl Fmp4Writer {
         }
     }
 
-    /// Create an *audio* track (Opus).
+    /// Create an *audio* track (Opus or G.711).
     pub fn new_audio(
         timescale: u32,
         track_id: u32,
@@ -223,6 +223,10 @@ impl Fmp4Writer {
             } else {
                 self.build_avc1_sample_entry()
             }
+        } else if self.codec_string.eq_ignore_ascii_case("ulaw") {
+            self.build_g711_sample_entry(b"ulaw")
+        } else if self.codec_string.eq_ignore_ascii_case("alaw") {
+            self.build_g711_sample_entry(b"alaw")
         } else {
             self.build_opus_sample_entry()
         };
@@ -493,6 +497,31 @@ impl Fmp4Writer {
         make_box(b"Opus", &payload)
     }
 
+    fn build_g711_sample_entry(&self, fourcc: &[u8; 4]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&[0u8; 6]); // reserved
+        payload.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+
+        // reserved
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&0u32.to_be_bytes());
+
+        // channelcount & samplesize, the size of a decoded sample
+        payload.extend_from_slice(&self.channels.to_be_bytes());
+        payload.extend_from_slice(&16u16.to_be_bytes());
+
+        // pre_defined & reserved
+        payload.extend_from_slice(&0u16.to_be_bytes());
+        payload.extend_from_slice(&0u16.to_be_bytes());
+
+        // samplerate 32-bit fixed (sampleRate<<16)
+        let fixed_rate = self.sample_rate << 16;
+        payload.extend_from_slice(&fixed_rate.to_be_bytes());
+
+        // No codec specific box, every byte is one companded sample
+        make_box(fourcc, &payload)
+    }
+
     /// Build a media fragment (styp+moof+mdat) for the given samples using this writer's track id.
     pub fn build_fragment(
         &self,
@@ -502,6 +531,13 @@ impl Fmp4Writer {
     ) -> Vec<u8> {
         _build_fragment_internal(self.track_id, seq_number, base_time, samples)
     }
+
+    /// Build a CMAF chunk (moof+mdat), which continues the fragment of a segment
+    pub fn build_chunk(&self, seq_number: u32, base_time: u64, samples: &[Mp4Sample]) -> Vec<u8> {
+        let mut chunk = self.build_fragment(seq_number, base_time, samples);
+        chunk.drain(..STYP_SIZE as usize);
+        chunk
+    }
 }
 
 // ======================= standalone box builders ===========================
@@ -701,6 +737,8 @@ fn make_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
     v
 }
 
+const STYP_SIZE: u32 = 24;
+
 /// Build a `styp` + `moof` + `mdat` fragment for the provided samples.
 ///
 /// * `track_id`     – ID of the track the samples belong to (usually 1)
@@ -719,7 +757,6 @@ fn _build_fragment_internal(
 
     // ========= styp =========
     let mut fragment: Vec<u8> = Vec::with_capacity(1024 + total_data);
-    const STYP_SIZE: u32 = 24;
     fragment.extend_from_slice(&STYP_SIZE.to_be_bytes());
     fragment.extend_from_slice(b"styp");
     fragment.extend_from_slice(b"msdh");
@@ -824,26 +861,10 @@ fn zeroes(buf: &mut Vec<u8>, n: usize) {
     buf.extend(std::iter::repeat_n(0u8, n));
 }
 
-/// Convert an Annex-B NALU (with or without start code) to a 4-byte-length-prefixed AVCC buffer.
-pub fn nalu_to_avcc(nalu: &Bytes) -> Vec<u8> {
-    // Determine where the raw payload starts (skip 3- or 4-byte start code if present)
-    let offset = if nalu.len() >= 4 && nalu[..4] == [0, 0, 0, 1][..] {
-        4
-    } else if nalu.len() >= 3 && nalu[..3] == [0, 0, 1][..] {
-        3
-    } else {
-        0
-    };
-    let payload = &nalu[offset..];
-    let mut out = Vec::with_capacity(4 + payload.len());
-    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
-    out.extend_from_slice(payload);
-    out
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::codec::nalu_to_avcc;
 
     #[test]
     fn test_build_init_segment_contains_ftyp_and_moov() {
@@ -866,6 +887,17 @@ mod tests {
         assert!(init_seg.windows(4).any(|w| w == b"moov"));
     }
 
+    #[test]
+    fn test_g711_init_segment_sample_entry() {
+        for (codec, fourcc) in [("ulaw", b"ulaw"), ("alaw", b"alaw")] {
+            let writer = Fmp4Writer::new_audio(8_000, 2, 1, 8_000, codec.to_string(), vec![]);
+
+            let init_seg = writer.build_init_segment();
+            assert!(init_seg.windows(4).any(|w| w == fourcc));
+            assert!(!init_seg.windows(4).any(|w| w == b"dOps"));
+        }
+    }
+
     #[test]
     fn test_av1_init_segment_contains_av1c_box() {
         let writer = Fmp4Writer::new(
@@ -0,0 +1,207 @@
+//! LL-HLS playlists of the live CMAF packaging
+
+use std::fmt::Write;
+
+use crate::codec::TrackKind;
+use crate::recorder::live::{LiveStream, Window};
+
+/// Segments of the last target durations list their parts
+const PART_SEGMENTS: usize = 3;
+
+pub fn track_name(kind: TrackKind) -> &'static str {
+    match kind {
+        TrackKind::Video => "video",
+        TrackKind::Audio => "audio",
+    }
+}
+
+pub fn track_kind(name: &str) -> Option<TrackKind> {
+    match name {
+        "video" => Some(TrackKind::Video),
+        "audio" => Some(TrackKind::Audio),
+        _ => None,
+    }
+}
+
+/// Lists the video playlist, with the audio playlist as its rendition.
+/// `query` is appended to every URI, e.g. `?token=...`
+pub fn multivariant_playlist(live: &LiveStream, query: &str) -> String {
+    let video = live.video.as_ref().map(|window| window.read().unwrap());
+    let audio = live.audio.as_ref().map(|window| window.read().unwrap());
+    let ready = |window: &Option<std::sync::RwLockReadGuard<'_, Window>>| {
+        window.as_ref().filter(|window| window.init.is_some())
+    };
+
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:9\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    let mut codecs = vec![];
+    let mut bandwidth = 0;
+    if let Some(audio) = ready(&audio) {
+        codecs.push(audio.codec.clone());
+        bandwidth += audio.bandwidth();
+    }
+    match ready(&video) {
+        Some(video) => {
+            codecs.insert(0, video.codec.clone());
+            bandwidth += video.bandwidth();
+            let audio_group = if ready(&audio).is_some() {
+                let _ = writeln!(
+                    playlist,
+                    "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"audio\",DEFAULT=YES,AUTOSELECT=YES,URI=\"audio.m3u8{query}\""
+                );
+                ",AUDIO=\"audio\""
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                playlist,
+                "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\",RESOLUTION={}x{}{audio_group}\nvideo.m3u8{query}",
+                bandwidth.max(1),
+                codecs.join(","),
+                video.width,
+                video.height
+            );
+        }
+        None => {
+            let _ = writeln!(
+                playlist,
+                "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"\naudio.m3u8{query}",
+                bandwidth.max(1),
+                codecs.join(",")
+            );
+        }
+    }
+    playlist
+}
+
+/// The media playlist of a track, with the parts of the latest segments and a preload hint
+pub fn media_playlist(live: &LiveStream, kind: TrackKind, query: &str) -> Option<String> {
+    let window = live.window(kind)?.read().unwrap();
+    window.init.as_ref()?;
+    let track = track_name(kind);
+    let part_target = live.cfg.part_duration as f64 / 1000.0;
+    let target_duration = window
+        .complete_segments()
+        .map(|segment| segment.duration())
+        .fold(live.cfg.segment_duration as f64 / 1000.0, f64::max)
+        .ceil() as u64;
+
+    let listed: Vec<_> = window
+        .segments
+        .iter()
+        .skip(
+            window
+                .complete_segments()
+                .count()
+                .saturating_sub(live.cfg.segments),
+        )
+        .collect();
+    let media_sequence = listed.first().map(|segment| segment.msn).unwrap_or(0);
+
+    let mut playlist = String::new();
+    let _ = write!(
+        playlist,
+        "#EXTM3U\n#EXT-X-VERSION:9\n#EXT-X-TARGETDURATION:{target_duration}\n\
+         #EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}\n\
+         #EXT-X-PART-INF:PART-TARGET={part_target:.3}\n\
+         #EXT-X-MEDIA-SEQUENCE:{media_sequence}\n#EXT-X-MAP:URI=\"{track}/init.mp4{query}\"\n",
+        part_target * 3.0,
+    );
+    let part_from = listed.len().saturating_sub(PART_SEGMENTS);
+    for (index, segment) in listed.iter().enumerate() {
+        let _ = writeln!(
+            playlist,
+            "#EXT-X-PROGRAM-DATE-TIME:{}",
+            segment
+                .start
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+        );
+        if index >= part_from {
+            for (part_index, part) in segment.parts.iter().enumerate() {
+                let _ = writeln!(
+                    playlist,
+                    "#EXT-X-PART:DURATION={:.5},URI=\"{track}/{}.{part_index}.m4s{query}\"{}",
+                    part.duration,
+                    segment.msn,
+                    if part.independent {
+                        ",INDEPENDENT=YES"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        if segment.complete {
+            let _ = writeln!(
+                playlist,
+                "#EXTINF:{:.5},\n{track}/{}.m4s{query}",
+                segment.duration(),
+                segment.msn
+            );
+        }
+    }
+    let (msn, part) = window.next_part();
+    let _ = writeln!(
+        playlist,
+        "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{track}/{msn}.{part}.m4s{query}\""
+    );
+    Some(playlist)
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{media_playlist, multivariant_playlist};
+    use crate::codec::TrackKind;
+    use crate::config;
+    use crate::recorder::live::{LiveStream, Part};
+
+    #[test]
+    fn test_playlists() {
+        let live = LiveStream::new(
+            &config::Cmaf {
+                segments: 2,
+                ..Default::default()
+            },
+            &[TrackKind::Video],
+        );
+        assert!(media_playlist(&live, TrackKind::Video, "").is_none());
+        assert!(media_playlist(&live, TrackKind::Audio, "").is_none());
+
+        let part = |independent| Part {
+            time: 0,
+            duration: 0.5,
+            independent,
+            data: Bytes::from_static(b"part"),
+        };
+        live.publish(TrackKind::Video, |window| {
+            window.init = Some(Bytes::from_static(b"init"));
+            window.codec = "avc1.42e01f".to_string();
+            window.width = 640;
+            window.height = 360;
+            for msn in 0..3 {
+                window.push_part(msn, part(true));
+                window.push_part(msn, part(false));
+                window.complete();
+            }
+            window.push_part(3, part(true));
+        });
+
+        let playlist = multivariant_playlist(&live, "?token=t");
+        assert!(playlist.contains("CODECS=\"avc1.42e01f\",RESOLUTION=640x360\nvideo.m3u8?token=t"));
+        assert!(!playlist.contains("EXT-X-MEDIA:TYPE=AUDIO"));
+
+        let playlist = media_playlist(&live, TrackKind::Video, "").unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:1\n"));
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:2\n"));
+        assert!(playlist.contains("#EXT-X-MAP:URI=\"video/init.mp4\""));
+        assert!(!playlist.contains("video/0.m4s"));
+        assert!(playlist.contains("#EXTINF:1.00000,\nvideo/2.m4s\n"));
+        assert!(
+            playlist
+                .contains("#EXT-X-PART:DURATION=0.50000,URI=\"video/3.0.m4s\",INDEPENDENT=YES\n")
+        );
+        assert!(playlist.contains("#EXT-X-PART:DURATION=0.50000,URI=\"video/2.1.m4s\"\n"));
+        assert!(playlist.ends_with("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"video/3.1.m4s\"\n"));
+    }
+}
@@ -0,0 +1,735 @@
+//! Live CMAF packaging: a rolling window of segments made of parts, kept in memory per stream.
+//! Shares the RTP parsers, codec adapters and the fMP4 writer with the recorder.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinSet;
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+use crate::codec::{CodecAdapter, Depacketizer, TrackKind, create_video_adapter};
+use crate::config;
+use crate::error::AppError;
+use crate::forward::{OutputSession, OutputTrack};
+use crate::recorder::fmp4::{Fmp4Writer, Mp4Sample};
+use crate::result::Result;
+use crate::stream::manager::Manager;
+
+pub(super) const VIDEO_TRACK_ID: u32 = 1;
+pub(super) const AUDIO_TRACK_ID: u32 = 2;
+/// Gaps longer than this are a restart of the publisher, not a frame duration
+const MAX_SAMPLE_DURATION: Duration = Duration::from_secs(10);
+/// Segments kept after they leave the playlists, for the players still loading them
+const EXTRA_SEGMENTS: usize = 2;
+
+/// The live packaging of the streams of a server, and their players
+#[derive(Clone, Default)]
+pub struct LiveStreams {
+    streams: Arc<tokio::sync::Mutex<HashMap<String, Arc<LiveStream>>>>,
+    /// The players of every stream by session, swept by one task per stream
+    players: Arc<Mutex<HashMap<String, HashMap<String, Player>>>>,
+}
+
+/// A player admitted by the HTTP callout, it counts as a subscriber while it is kept
+struct Player {
+    output: OutputSession,
+    last_access: Instant,
+}
+
+impl LiveStreams {
+    /// Keeps the player of `session`, false for an unknown one
+    pub fn touch_player(&self, stream: &str, session: &str) -> Result<bool> {
+        let mut players = self.players.lock().unwrap();
+        let Some(player) = players
+            .get_mut(stream)
+            .and_then(|players| players.get_mut(session))
+        else {
+            return Ok(false);
+        };
+        if player.output.is_expired() {
+            players.get_mut(stream).unwrap().remove(session);
+            return Err(AppError::forbidden("session expired"));
+        }
+        player.last_access = Instant::now();
+        Ok(true)
+    }
+
+    /// The player is dropped after `idle_timeout` without a request, or once it expires
+    pub fn add_player(&self, stream: &str, output: OutputSession, idle_timeout: Duration) {
+        let mut players = self.players.lock().unwrap();
+        if !players.contains_key(stream) {
+            tokio::spawn(self.clone().sweep_players(stream.to_string(), idle_timeout));
+        }
+        players.entry(stream.to_string()).or_default().insert(
+            output.session().to_string(),
+            Player {
+                output,
+                last_access: Instant::now(),
+            },
+        );
+    }
+
+    /// Drops the idle and expired players of `stream`, until it has none
+    async fn sweep_players(self, stream: String, idle_timeout: Duration) {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let mut players = self.players.lock().unwrap();
+            let Some(stream_players) = players.get_mut(&stream) else {
+                break;
+            };
+            stream_players.retain(|session, player| {
+                let keep =
+                    player.last_access.elapsed() < idle_timeout && !player.output.is_expired();
+                if !keep {
+                    tracing::info!(stream, session, "cmaf player session closed");
+                }
+                keep
+            });
+            if stream_players.is_empty() {
+                players.remove(&stream);
+                break;
+            }
+        }
+    }
+
+    /// The packaging of `stream`, started on the first call
+    pub async fn get(
+        &self,
+        manager: Arc<Manager>,
+        cfg: &config::Cmaf,
+        stream: &str,
+    ) -> Result<Arc<LiveStream>> {
+        let mut streams = self.streams.lock().await;
+        if let Some(live) = streams.get(stream) {
+            live.touch();
+            return Ok(live.clone());
+        }
+
+        let mut packagers = vec![];
+        for track in manager.output_tracks(stream).await? {
+            match TrackPackager::new(&track, cfg) {
+                Some(packager) => packagers.push((track, packager)),
+                None => tracing::warn!(
+                    "[live] {} codec {} is not supported",
+                    stream,
+                    track.codec().capability.mime_type
+                ),
+            }
+        }
+        let kinds: Vec<TrackKind> = packagers.iter().map(|(_, p)| p.kind).collect();
+        if kinds.is_empty() {
+            return Err(AppError::stream_not_found(format!(
+                "stream {stream} has no supported track"
+            )));
+        }
+
+        let live = Arc::new(LiveStream::new(cfg, &kinds));
+        streams.insert(stream.to_string(), live.clone());
+        tokio::spawn(package(
+            self.clone(),
+            stream.to_string(),
+            live.clone(),
+            packagers,
+        ));
+        tracing::info!("[live] {} packaging started", stream);
+        Ok(live)
+    }
+}
+
+/// A part of a segment, a CMAF chunk
+#[derive(Clone, Debug)]
+pub struct Part {
+    /// Decode time of the first sample, in the track timescale
+    pub time: u64,
+    pub duration: f64,
+    /// Starts with a keyframe
+    pub independent: bool,
+    pub data: Bytes,
+}
+
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// Media sequence number
+    pub msn: u64,
+    pub start: DateTime<Utc>,
+    pub parts: Vec<Part>,
+    /// No more parts are added
+    pub complete: bool,
+}
+
+impl Segment {
+    /// Decode time of the first sample, in the track timescale
+    pub fn time(&self) -> u64 {
+        self.parts.first().map(|part| part.time).unwrap_or_default()
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.parts.iter().map(|part| part.duration).sum()
+    }
+
+    pub fn data(&self) -> Bytes {
+        let mut data = Vec::with_capacity(self.parts.iter().map(|part| part.data.len()).sum());
+        for part in self.parts.iter() {
+            data.extend_from_slice(&part.data);
+        }
+        data.into()
+    }
+}
+
+/// The segments of one track
+#[derive(Debug, Default)]
+pub struct Window {
+    pub init: Option<Bytes>,
+    /// RFC 6381 codec, e.g. "avc1.42e01f"
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub timescale: u32,
+    pub segments: VecDeque<Segment>,
+    keep: usize,
+}
+
+impl Window {
+    fn new(keep: usize) -> Self {
+        Self {
+            keep: keep + EXTRA_SEGMENTS,
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn push_part(&mut self, msn: u64, part: Part) {
+        if self
+            .segments
+            .back()
+            .is_none_or(|segment| segment.msn != msn)
+        {
+            self.segments.push_back(Segment {
+                msn,
+                start: Utc::now(),
+                parts: vec![],
+                complete: false,
+            });
+        }
+        if let Some(segment) = self.segments.back_mut() {
+            segment.parts.push(part);
+        }
+    }
+
+    pub(super) fn complete(&mut self) {
+        if let Some(segment) = self.segments.back_mut() {
+            segment.complete = true;
+        }
+        while self.segments.len() > self.keep {
+            self.segments.pop_front();
+        }
+    }
+
+    /// The complete segments
+    pub fn complete_segments(&self) -> impl Iterator<Item = &Segment> {
+        self.segments.iter().filter(|segment| segment.complete)
+    }
+
+    /// The segment and part published next
+    pub fn next_part(&self) -> (u64, usize) {
+        match self.segments.back() {
+            Some(segment) if segment.complete => (segment.msn + 1, 0),
+            Some(segment) => (segment.msn, segment.parts.len()),
+            None => (0, 0),
+        }
+    }
+
+    /// Whether the segment `msn`, or its part `part`, is published
+    pub fn has(&self, msn: u64, part: Option<usize>) -> bool {
+        match self.segments.back() {
+            Some(last) if last.msn > msn => true,
+            Some(last) if last.msn == msn => {
+                last.complete || part.is_some_and(|part| part < last.parts.len())
+            }
+            _ => false,
+        }
+    }
+
+    fn segment(&self, msn: u64) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.msn == msn)
+    }
+
+    pub fn segment_data(&self, msn: u64) -> Option<Bytes> {
+        self.segment(msn)
+            .filter(|segment| segment.complete)
+            .map(|segment| segment.data())
+    }
+
+    pub fn part_data(&self, msn: u64, part: usize) -> Option<Bytes> {
+        self.segment(msn)
+            .and_then(|segment| segment.parts.get(part))
+            .map(|part| part.data.clone())
+    }
+
+    /// Average bits per second of the complete segments
+    pub fn bandwidth(&self) -> u64 {
+        let (bytes, duration) =
+            self.complete_segments()
+                .fold((0, 0.0), |(bytes, duration), segment| {
+                    (
+                        bytes + segment.parts.iter().map(|p| p.data.len()).sum::<usize>(),
+                        duration + segment.duration(),
+                    )
+                });
+        if duration > 0.0 {
+            (bytes as f64 * 8.0 / duration) as u64
+        } else {
+            0
+        }
+    }
+}
+
+/// The packaging of a stream, shared by the players
+pub struct LiveStream {
+    pub cfg: config::Cmaf,
+    pub video: Option<RwLock<Window>>,
+    pub audio: Option<RwLock<Window>>,
+    /// Wall clock of the decode time 0 of both tracks
+    pub started: DateTime<Utc>,
+    epoch: Instant,
+    /// Counts the published parts
+    changed: watch::Sender<u64>,
+    last_access: Mutex<Instant>,
+}
+
+impl LiveStream {
+    pub(super) fn new(cfg: &config::Cmaf, kinds: &[TrackKind]) -> Self {
+        let window = |kind| {
+            kinds
+                .contains(&kind)
+                .then(|| RwLock::new(Window::new(cfg.segments)))
+        };
+        Self {
+            cfg: cfg.clone(),
+            video: window(TrackKind::Video),
+            audio: window(TrackKind::Audio),
+            started: Utc::now(),
+            epoch: Instant::now(),
+            changed: watch::channel(0).0,
+            last_access: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn window(&self, kind: TrackKind) -> Option<&RwLock<Window>> {
+        match kind {
+            TrackKind::Video => self.video.as_ref(),
+            TrackKind::Audio => self.audio.as_ref(),
+        }
+    }
+
+    /// Every track has its init segment
+    pub fn initialized(&self) -> bool {
+        [&self.video, &self.audio]
+            .into_iter()
+            .flatten()
+            .all(|window| window.read().unwrap().init.is_some())
+    }
+
+    /// Keeps the packaging running
+    pub fn touch(&self) {
+        *self.last_access.lock().unwrap() = Instant::now();
+    }
+
+    fn idle(&self) -> bool {
+        self.last_access.lock().unwrap().elapsed() >= Duration::from_secs(self.cfg.idle_timeout)
+    }
+
+    /// Waits until `ready` or the timeout, false on timeout
+    pub async fn wait(&self, timeout: Duration, ready: impl Fn(&LiveStream) -> bool) -> bool {
+        let mut changed = self.changed.subscribe();
+        tokio::time::timeout(timeout, async {
+            while !ready(self) {
+                // The sender lives as long as self
+                let _ = changed.changed().await;
+            }
+        })
+        .await
+        .is_ok()
+    }
+
+    pub(super) fn publish(&self, kind: TrackKind, update: impl FnOnce(&mut Window)) {
+        if let Some(window) = self.window(kind) {
+            update(&mut window.write().unwrap());
+            self.changed.send_modify(|count| *count += 1);
+        }
+    }
+}
+
+async fn package(
+    streams: LiveStreams,
+    stream: String,
+    live: Arc<LiveStream>,
+    packagers: Vec<(OutputTrack, TrackPackager)>,
+) {
+    let mut tracks = JoinSet::new();
+    for (track, packager) in packagers {
+        tracks.spawn(package_track(track, packager, live.clone()));
+    }
+    let idle = async {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if live.idle() {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = idle => tracing::info!("[live] {} packaging idle", stream),
+        _ = async { while tracks.join_next().await.is_some() {} } => {
+            tracing::info!("[live] {} packaging ended with the publisher", stream)
+        }
+    }
+
+    let mut streams = streams.streams.lock().await;
+    if streams
+        .get(&stream)
+        .is_some_and(|current| Arc::ptr_eq(current, &live))
+    {
+        streams.remove(&stream);
+    }
+}
+
+async fn package_track(track: OutputTrack, mut packager: TrackPackager, live: Arc<LiveStream>) {
+    let (gop, mut receiver) = track.subscribe();
+    if gop.is_empty() {
+        track.request_keyframe();
+    }
+    for packet in gop {
+        packager.push(&packet, &live);
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(packet) => packager.push(&packet, &live),
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                tracing::debug!("[live] {:?} track lagged {} packets", packager.kind, count);
+                track.request_keyframe();
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// A frame waiting for the next one, which gives its duration
+pub(super) struct PendingSample {
+    pub timestamp: u32,
+    pub is_sync: bool,
+    pub bytes: Bytes,
+}
+
+/// Duration in `timescale` of a frame sent at RTP `timestamp`, followed by a frame at `next`.
+/// Falls back to `last_duration` on timestamp jumps.
+pub(super) fn sample_duration(
+    timestamp: u32,
+    next: u32,
+    clock_rate: u32,
+    timescale: u32,
+    last_duration: u32,
+) -> u32 {
+    let delta = next.wrapping_sub(timestamp) as u64;
+    let duration = (delta * timescale as u64 / clock_rate as u64) as u32;
+    if duration == 0 || duration as u64 > MAX_SAMPLE_DURATION.as_secs() * timescale as u64 {
+        last_duration.max(1)
+    } else {
+        duration
+    }
+}
+
+/// The fMP4 writer of a track, None until the video `adapter` has the codec config
+pub(super) fn new_writer(
+    adapter: Option<&dyn CodecAdapter>,
+    timescale: u32,
+    clock_rate: u32,
+    channels: u16,
+) -> Option<Fmp4Writer> {
+    let Some(adapter) = adapter else {
+        return Some(Fmp4Writer::new_audio(
+            timescale,
+            AUDIO_TRACK_ID,
+            channels,
+            clock_rate,
+            "opus".to_string(),
+            vec![],
+        ));
+    };
+    if !adapter.ready() {
+        return None;
+    }
+    let codec = adapter.codec_string().unwrap_or_default();
+    let lower_codec = codec.to_ascii_lowercase();
+    let codec_config = if ["avc1", "av01", "hev1", "hvc1"]
+        .iter()
+        .any(|prefix| lower_codec.starts_with(prefix))
+    {
+        adapter.codec_config().unwrap_or_default()
+    } else {
+        vec![]
+    };
+    let width = if adapter.width() == 0 {
+        1280
+    } else {
+        adapter.width()
+    };
+    let height = if adapter.height() == 0 {
+        720
+    } else {
+        adapter.height()
+    };
+    Some(Fmp4Writer::new(
+        timescale,
+        VIDEO_TRACK_ID,
+        width,
+        height,
+        codec,
+        codec_config,
+    ))
+}
+
+/// Cuts the frames of one track into parts and segments
+struct TrackPackager {
+    kind: TrackKind,
+    depacketizer: Depacketizer,
+    adapter: Option<Box<dyn CodecAdapter>>,
+    clock_rate: u32,
+    channels: u16,
+    timescale: u32,
+    part_ticks: u64,
+    segment_ticks: u64,
+    writer: Option<Fmp4Writer>,
+    pending: Option<PendingSample>,
+    last_duration: u32,
+    /// Decode time of the next sample
+    next_dts: u64,
+    msn: u64,
+    fragment_sequence: u32,
+    segment_start: u64,
+    segment_parts: usize,
+    part_start: u64,
+    part_samples: Vec<Mp4Sample>,
+}
+
+impl TrackPackager {
+    fn new(track: &OutputTrack, cfg: &config::Cmaf) -> Option<Self> {
+        let codec = track.codec();
+        let (depacketizer, video_codec) = Depacketizer::new(&codec.capability.mime_type)?;
+        let kind = match track.kind() {
+            RTPCodecType::Video if video_codec.is_some() => TrackKind::Video,
+            RTPCodecType::Audio if video_codec.is_none() => TrackKind::Audio,
+            _ => return None,
+        };
+        let adapter = video_codec.map(create_video_adapter);
+        let clock_rate = codec.capability.clock_rate.max(1);
+        let timescale = adapter
+            .as_ref()
+            .map(|adapter| adapter.timescale())
+            .unwrap_or(clock_rate);
+        Some(Self {
+            kind,
+            depacketizer,
+            adapter,
+            clock_rate,
+            channels: codec.capability.channels.max(1),
+            timescale,
+            part_ticks: cfg.part_duration * timescale as u64 / 1000,
+            segment_ticks: cfg.segment_duration * timescale as u64 / 1000,
+            writer: None,
+            pending: None,
+            last_duration: 0,
+            next_dts: 0,
+            msn: 0,
+            fragment_sequence: 1,
+            segment_start: 0,
+            segment_parts: 0,
+            part_start: 0,
+            part_samples: vec![],
+        })
+    }
+
+    fn push(&mut self, packet: &Packet, live: &LiveStream) {
+        let Some(frame) = self.depacketizer.push(packet) else {
+            return;
+        };
+        let (bytes, is_sync) = match self.adapter.as_mut() {
+            Some(adapter) => {
+                let (payload, is_sync, _) = adapter.convert_frame(&frame);
+                (Bytes::from(payload), is_sync)
+            }
+            None => (frame, true),
+        };
+        if self.writer.is_none() && !self.init(is_sync, live) {
+            return;
+        }
+
+        let timestamp = packet.header.timestamp;
+        if let Some(pending) = self.pending.take() {
+            let duration = sample_duration(
+                pending.timestamp,
+                timestamp,
+                self.clock_rate,
+                self.timescale,
+                self.last_duration,
+            );
+            self.last_duration = duration;
+            self.add_sample(
+                Mp4Sample {
+                    duration,
+                    is_sync: pending.is_sync,
+                    bytes: pending.bytes,
+                },
+                live,
+            );
+        }
+        self.pending = Some(PendingSample {
+            timestamp,
+            is_sync,
+            bytes,
+        });
+    }
+
+    /// Writes the init segment, video waits for the codec config and a keyframe
+    fn init(&mut self, is_sync: bool, live: &LiveStream) -> bool {
+        if !is_sync {
+            return false;
+        }
+        let Some(writer) = new_writer(
+            self.adapter.as_deref(),
+            self.timescale,
+            self.clock_rate,
+            self.channels,
+        ) else {
+            return false;
+        };
+
+        // Both tracks count from the start of the packaging, so players line them up
+        self.next_dts = (live.epoch.elapsed().as_secs_f64() * self.timescale as f64) as u64;
+        self.segment_start = self.next_dts;
+        self.part_start = self.next_dts;
+        let init = Bytes::from(writer.build_init_segment());
+        let (codec, width, height) = (writer.codec_string.clone(), writer.width, writer.height);
+        let timescale = self.timescale;
+        live.publish(self.kind, |window| {
+            window.init = Some(init);
+            window.codec = codec;
+            window.width = width;
+            window.height = height;
+            window.timescale = timescale;
+        });
+        self.writer = Some(writer);
+        true
+    }
+
+    fn add_sample(&mut self, sample: Mp4Sample, live: &LiveStream) {
+        let segment_elapsed = self.next_dts - self.segment_start;
+        let cut_segment = match self.kind {
+            TrackKind::Video => sample.is_sync,
+            TrackKind::Audio => true,
+        } && segment_elapsed >= self.segment_ticks;
+        if cut_segment {
+            self.close_part(live);
+            self.close_segment(live);
+        }
+
+        // Parts stay within the part target duration
+        if !self.part_samples.is_empty()
+            && self.next_dts - self.part_start + sample.duration as u64 > self.part_ticks
+        {
+            self.close_part(live);
+        }
+        if self.part_samples.is_empty() {
+            self.part_start = self.next_dts;
+        }
+        self.next_dts += sample.duration as u64;
+        self.part_samples.push(sample);
+    }
+
+    fn close_part(&mut self, live: &LiveStream) {
+        let Some(writer) = self.writer.as_ref() else {
+            return;
+        };
+        if self.part_samples.is_empty() {
+            return;
+        }
+        let data = if self.segment_parts == 0 {
+            writer.build_fragment(self.fragment_sequence, self.part_start, &self.part_samples)
+        } else {
+            writer.build_chunk(self.fragment_sequence, self.part_start, &self.part_samples)
+        };
+        self.fragment_sequence += 1;
+        let part = Part {
+            time: self.part_start,
+            duration: (self.next_dts - self.part_start) as f64 / self.timescale as f64,
+            independent: self.part_samples[0].is_sync,
+            data: data.into(),
+        };
+        self.part_samples.clear();
+        self.segment_parts += 1;
+        let msn = self.msn;
+        live.publish(self.kind, |window| window.push_part(msn, part));
+    }
+
+    fn close_segment(&mut self, live: &LiveStream) {
+        if self.segment_parts == 0 {
+            return;
+        }
+        live.publish(self.kind, |window| window.complete());
+        self.msn += 1;
+        self.segment_parts = 0;
+        self.segment_start = self.next_dts;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{Part, Window};
+
+    fn part(independent: bool) -> Part {
+        Part {
+            time: 0,
+            duration: 0.5,
+            independent,
+            data: Bytes::from_static(b"part"),
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let mut window = Window::new(2);
+        assert_eq!(window.next_part(), (0, 0));
+        assert!(!window.has(0, Some(0)));
+
+        window.push_part(0, part(true));
+        window.push_part(0, part(false));
+        assert_eq!(window.next_part(), (0, 2));
+        assert!(window.has(0, Some(1)));
+        assert!(!window.has(0, Some(2)));
+        assert!(!window.has(0, None));
+        assert!(window.segment_data(0).is_none());
+        assert_eq!(window.part_data(0, 1).unwrap(), "part");
+
+        window.complete();
+        assert_eq!(window.next_part(), (1, 0));
+        assert!(window.has(0, None));
+        assert_eq!(window.segment_data(0).unwrap(), "partpart");
+        assert_eq!(window.segments[0].duration(), 1.0);
+
+        // two segments listed, and two more kept
+        for msn in 1..6 {
+            window.push_part(msn, part(true));
+            window.complete();
+        }
+        assert_eq!(window.segments.len(), 4);
+        assert_eq!(window.segments[0].msn, 2);
+        assert!(window.has(3, Some(5)));
+        assert_eq!(window.bandwidth(), 64);
+    }
+}
@@ -26,9 +26,14 @@ mod index;
 mod pli_backoff;
 mod segmenter;
 mod task;
+#[cfg(test)]
+mod tests;
 use task::RecordingTask;
-pub mod codec;
+pub mod dash;
 mod fmp4;
+pub mod hls;
+pub mod live;
+pub mod mse;
 use index::{RecordingIndexEntry, RecordingsIndex};
 
 static TASKS: Lazy<RwLock<HashMap<String, RecordingTask>>> =
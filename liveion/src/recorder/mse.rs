@@ -0,0 +1,277 @@
+//! fMP4 fragments of one frame each, for Media Source Extensions players.
+//!
+//! Every track is its own fMP4 stream, sent as binary messages starting with the track id,
+//! 1 for video and 2 for audio. An init segment follows a text message announcing it:
+//! `{"type":"init","track":"video","id":1,"mime":"video/mp4; codecs=\"avc1.42e01f\""}`,
+//! the `mime` is the one of `MediaSource.addSourceBuffer`.
+
+use std::time::Instant;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use webrtc::rtp::packet::Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+use crate::codec::{CodecAdapter, CodecEvent, Depacketizer, TrackKind, create_video_adapter};
+use crate::forward::OutputTrack;
+use crate::recorder::fmp4::{Fmp4Writer, Mp4Sample};
+use crate::recorder::hls::track_name;
+use crate::recorder::live::{
+    AUDIO_TRACK_ID, PendingSample, VIDEO_TRACK_ID, new_writer, sample_duration,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MseMessage {
+    Text(String),
+    Binary(Bytes),
+}
+
+/// Muxes the frames of one track into fragments
+pub struct FragmentMuxer {
+    pub kind: TrackKind,
+    depacketizer: Depacketizer,
+    adapter: Option<Box<dyn CodecAdapter>>,
+    clock_rate: u32,
+    channels: u16,
+    timescale: u32,
+    /// Shared by the tracks of a player, so their decode times line up
+    epoch: Instant,
+    writer: Option<Fmp4Writer>,
+    /// The codec config changed, a new init segment is sent with the next keyframe
+    reinit: bool,
+    /// Frames were lost, the fragments restart with the next keyframe
+    resync: bool,
+    pending: Option<PendingSample>,
+    last_duration: u32,
+    next_dts: u64,
+    sequence: u32,
+}
+
+impl FragmentMuxer {
+    pub fn new(track: &OutputTrack, epoch: Instant) -> Option<Self> {
+        let codec = track.codec();
+        Self::with_codec(
+            track.kind(),
+            &codec.capability.mime_type,
+            codec.capability.clock_rate,
+            codec.capability.channels,
+            epoch,
+        )
+    }
+
+    fn with_codec(
+        codec_type: RTPCodecType,
+        mime_type: &str,
+        clock_rate: u32,
+        channels: u16,
+        epoch: Instant,
+    ) -> Option<Self> {
+        let (depacketizer, video_codec) = Depacketizer::new(mime_type)?;
+        let kind = match codec_type {
+            RTPCodecType::Video if video_codec.is_some() => TrackKind::Video,
+            RTPCodecType::Audio if video_codec.is_none() => TrackKind::Audio,
+            _ => return None,
+        };
+        let adapter = video_codec.map(create_video_adapter);
+        let clock_rate = clock_rate.max(1);
+        let timescale = adapter
+            .as_ref()
+            .map(|adapter| adapter.timescale())
+            .unwrap_or(clock_rate);
+        Some(Self {
+            kind,
+            depacketizer,
+            adapter,
+            clock_rate,
+            channels: channels.max(1),
+            timescale,
+            epoch,
+            writer: None,
+            reinit: false,
+            resync: false,
+            pending: None,
+            last_duration: 0,
+            next_dts: 0,
+            sequence: 1,
+        })
+    }
+
+    /// Drops the frame in progress after lost packets, until the next keyframe
+    pub fn lagged(&mut self) {
+        self.pending = None;
+        self.resync = true;
+    }
+
+    /// The messages ready once `packet` is pushed: the fragment of the previous frame,
+    /// then the init segment when the codec config is new
+    pub fn push(&mut self, packet: &Packet) -> Vec<MseMessage> {
+        let mut messages = vec![];
+        let Some(frame) = self.depacketizer.push(packet) else {
+            return messages;
+        };
+        let (bytes, is_sync, event) = match self.adapter.as_mut() {
+            Some(adapter) => {
+                let (payload, is_sync, config_updated) = adapter.convert_frame(&frame);
+                (
+                    Bytes::from(payload),
+                    is_sync,
+                    CodecEvent::of_frame(is_sync, config_updated),
+                )
+            }
+            None => (frame, true, CodecEvent::KeyFrame),
+        };
+        if matches!(event, CodecEvent::ConfigUpdated) && self.writer.is_some() {
+            self.reinit = true;
+        }
+
+        // The previous frame belongs to the previous init segment
+        let timestamp = packet.header.timestamp;
+        if let Some(pending) = self.pending.take() {
+            let duration = sample_duration(
+                pending.timestamp,
+                timestamp,
+                self.clock_rate,
+                self.timescale,
+                self.last_duration,
+            );
+            self.last_duration = duration;
+            messages.push(self.fragment(Mp4Sample {
+                duration,
+                is_sync: pending.is_sync,
+                bytes: pending.bytes,
+            }));
+        }
+
+        if self.writer.is_none() || self.reinit || self.resync {
+            if !is_sync {
+                return messages;
+            }
+            if self.writer.is_none() || self.reinit {
+                let Some(writer) = new_writer(
+                    self.adapter.as_deref(),
+                    self.timescale,
+                    self.clock_rate,
+                    self.channels,
+                ) else {
+                    return messages;
+                };
+                messages.extend(self.init(&writer));
+                self.writer = Some(writer);
+                self.reinit = false;
+            }
+            self.resync = false;
+            // Skips the time of the dropped frames, to stay in line with the other track
+            let now = (self.epoch.elapsed().as_secs_f64() * self.timescale as f64) as u64;
+            self.next_dts = self.next_dts.max(now);
+        }
+        self.pending = Some(PendingSample {
+            timestamp,
+            is_sync,
+            bytes,
+        });
+        messages
+    }
+
+    fn track_id(&self) -> u8 {
+        match self.kind {
+            TrackKind::Video => VIDEO_TRACK_ID as u8,
+            TrackKind::Audio => AUDIO_TRACK_ID as u8,
+        }
+    }
+
+    fn binary(&self, data: &[u8]) -> MseMessage {
+        let mut message = BytesMut::with_capacity(data.len() + 1);
+        message.put_u8(self.track_id());
+        message.put_slice(data);
+        MseMessage::Binary(message.freeze())
+    }
+
+    fn init(&self, writer: &Fmp4Writer) -> [MseMessage; 2] {
+        let mime = match self.kind {
+            TrackKind::Video => format!("video/mp4; codecs=\"{}\"", writer.codec_string),
+            TrackKind::Audio => format!("audio/mp4; codecs=\"{}\"", writer.codec_string),
+        };
+        let announce = serde_json::json!({
+            "type": "init",
+            "track": track_name(self.kind),
+            "id": self.track_id(),
+            "mime": mime,
+        });
+        [
+            MseMessage::Text(announce.to_string()),
+            self.binary(&writer.build_init_segment()),
+        ]
+    }
+
+    fn fragment(&mut self, sample: Mp4Sample) -> MseMessage {
+        let duration = sample.duration as u64;
+        let data = match self.writer.as_ref() {
+            Some(writer) => writer.build_chunk(self.sequence, self.next_dts, &[sample]),
+            None => vec![],
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+        self.next_dts += duration;
+        self.binary(&data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use bytes::Bytes;
+    use webrtc::api::media_engine::MIME_TYPE_OPUS;
+    use webrtc::rtp::packet::Packet;
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+
+    use super::{FragmentMuxer, MseMessage};
+
+    fn opus(timestamp: u32) -> Packet {
+        let mut packet = Packet::default();
+        packet.header.timestamp = timestamp;
+        packet.payload = Bytes::from_static(&[0xfc, 0xff, 0xfe]);
+        packet
+    }
+
+    #[test]
+    fn test_fragments() {
+        let mut muxer = FragmentMuxer::with_codec(
+            RTPCodecType::Audio,
+            MIME_TYPE_OPUS,
+            48000,
+            2,
+            Instant::now(),
+        )
+        .unwrap();
+
+        let messages = muxer.push(&opus(0));
+        assert_eq!(messages.len(), 2);
+        match &messages[0] {
+            MseMessage::Text(text) => {
+                assert!(text.contains("\"mime\":\"audio/mp4; codecs=\\\"opus\\\"\""));
+                assert!(text.contains("\"id\":2"));
+            }
+            message => panic!("unexpected {message:?}"),
+        }
+        match &messages[1] {
+            MseMessage::Binary(data) => {
+                assert_eq!(data[0], 2);
+                assert_eq!(&data[5..9], b"ftyp");
+            }
+            message => panic!("unexpected {message:?}"),
+        }
+
+        let messages = muxer.push(&opus(960));
+        assert_eq!(messages.len(), 1);
+        match &messages[0] {
+            MseMessage::Binary(data) => {
+                assert_eq!(data[0], 2);
+                assert_eq!(&data[5..9], b"moof");
+            }
+            message => panic!("unexpected {message:?}"),
+        }
+
+        muxer.lagged();
+        assert!(muxer.push(&opus(4800)).is_empty());
+        assert_eq!(muxer.push(&opus(5760)).len(), 1);
+    }
+}
@@ -1,15 +1,19 @@
-use crate::recorder::codec::{CodecAdapter, VideoCodec, create_video_adapter};
+use crate::codec::{AudioCodec, CodecAdapter, VideoCodec, create_video_adapter};
 use crate::recorder::fmp4::{Fmp4Writer, Mp4Sample};
 use crate::recorder::pli_backoff::PliBackoff;
 use anyhow::Result;
 use bytes::Bytes;
 use opendal::Operator;
+use std::fmt::Write;
 use tracing::info;
 
 /// Default duration of each segment in seconds
 const DEFAULT_SEG_DURATION: u64 = 10;
 
 const MANIFEST_FILENAME: &str = "manifest.mpd";
+const HLS_MASTER_FILENAME: &str = "master.m3u8";
+const HLS_INDEX_FILENAME: &str = "index.m3u8";
+const HLS_AUDIO_INDEX_FILENAME: &str = "a_index.m3u8";
 const VIDEO_INIT_FILENAME: &str = "v_init.m4s";
 const AUDIO_INIT_FILENAME: &str = "a_init.m4s";
 const VIDEO_SEGMENT_FILENAME_PREFIX: &str = "v_seg_";
@@ -20,7 +24,6 @@ const AUDIO_SEGMENT_TEMPLATE: &str = "a_seg_$Number%04d$.m4s";
 
 const DEFAULT_AUDIO_SAMPLE_RATE: u32 = 48_000;
 const DEFAULT_AUDIO_CHANNELS: u16 = 2;
-const DEFAULT_AUDIO_CODEC: &str = "opus";
 
 const VIDEO_TRACK_ID: u32 = 1;
 const AUDIO_TRACK_ID: u32 = 2;
@@ -46,7 +49,7 @@ pub struct Segmenter {
     video_seg_start_dts: u64,
     video_track_id: Option<u32>,
 
-    // Audio track id (Opus)
+    // Audio track id
     audio_track_id: Option<u32>,
 
     // All video samples buffered for the current segment (already converted to AVCC length prefix)
@@ -76,7 +79,7 @@ pub struct Segmenter {
     // encapsulated fmp4 writer once initialized
     fmp4_writer: Option<Fmp4Writer>,
 
-    // audio writer for Opus
+    // audio writer for Opus or G.711
     audio_writer: Option<Fmp4Writer>,
     audio_seg_index: u32,
     audio_seg_start_pts: u64,
@@ -86,7 +89,7 @@ pub struct Segmenter {
     // audio track metadata
     audio_sample_rate: u32,
     audio_channels: u16,
-    audio_codec: String,
+    audio_codec: AudioCodec,
 
     // audio bitrate stats
     audio_total_bytes: u64,
@@ -140,7 +143,7 @@ impl Segmenter {
 
             audio_sample_rate: DEFAULT_AUDIO_SAMPLE_RATE,
             audio_channels: DEFAULT_AUDIO_CHANNELS,
-            audio_codec: DEFAULT_AUDIO_CODEC.to_string(),
+            audio_codec: AudioCodec::Opus,
 
             audio_total_bytes: 0,
             audio_total_ticks: 0,
@@ -167,9 +170,9 @@ impl Segmenter {
             .await
     }
 
-    /// Feed Opus audio sample from RTP payload
-    /// `duration_ticks` – duration in the 48 kHz time base (i.e. RTP timestamp delta)
-    pub async fn push_opus(&mut self, payload: Bytes, duration_ticks: u32) -> Result<()> {
+    /// Feed one audio sample from RTP payload, in the codec of [`Self::configure_audio_track`]
+    /// `duration_ticks` – duration in the sample rate time base (i.e. RTP timestamp delta)
+    pub async fn push_audio(&mut self, payload: Bytes, duration_ticks: u32) -> Result<()> {
         // Initialize writer if not yet done
         if self.audio_track_id.is_none() {
             self.init_audio_writer().await?;
@@ -197,11 +200,12 @@ impl Segmenter {
 
     pub fn configure_audio_track(
         &mut self,
+        codec: AudioCodec,
         sample_rate: u32,
         channels: u16,
-        codec: impl Into<String>,
         fmtp: Option<&str>,
     ) {
+        self.audio_codec = codec;
         if sample_rate > 0 {
             self.audio_sample_rate = sample_rate;
         }
@@ -215,17 +219,16 @@ impl Segmenter {
 
         if derived_channels > 0 {
             self.audio_channels = derived_channels;
+        } else if matches!(codec, AudioCodec::Pcmu | AudioCodec::Pcma) {
+            // webrtc registers G.711 with 0 channels, it is mono (RFC 3551)
+            self.audio_channels = 1;
         }
+    }
 
-        let codec_input = codec.into();
-        if !codec_input.is_empty() {
-            let normalized = codec_input
-                .rsplit('/')
-                .next()
-                .unwrap_or(codec_input.as_str())
-                .to_ascii_lowercase();
-            self.audio_codec = normalized;
-        }
+    /// Feed one VP8 frame (already reassembled by RTP parser)
+    pub async fn push_vp8(&mut self, frame: Bytes, duration_ticks: u32) -> Result<()> {
+        self.push_video_frame(VideoCodec::Vp8, frame, None, duration_ticks)
+            .await
     }
 
     /// Feed one VP9 frame (already reassembled by RTP parser)
@@ -257,6 +260,16 @@ impl Segmenter {
             adapter.convert_frame(&frame)
         };
 
+        if config_ready && self.video_track_id.is_some() {
+            tracing::warn!(
+                "[segmenter] video config changed mid-stream for stream {}, keeping the init segment of {} {}x{}",
+                self.stream,
+                self.video_codec,
+                self.video_width,
+                self.video_height
+            );
+        }
+
         self.refresh_video_metadata();
         let adapter_ready = self.current_video_adapter_ready();
 
@@ -350,6 +363,11 @@ impl Segmenter {
     }
 
     fn refresh_video_metadata(&mut self) {
+        // Frozen once the init segment is written, the manifest and playlists
+        // must describe v_init.m4s
+        if self.video_track_id.is_some() {
+            return;
+        }
         if let Some(adapter) = self.video_adapter.as_ref() {
             let adapter = adapter.as_ref();
             let width = adapter.width();
@@ -485,25 +503,18 @@ impl Segmenter {
         } else {
             self.audio_sample_rate
         };
-        let codec_string = if self.audio_codec.is_empty() {
-            DEFAULT_AUDIO_CODEC.to_string()
-        } else {
-            self.audio_codec.clone()
-        };
-
         let writer = Fmp4Writer::new_audio(
             sample_rate,
             track_id,
             channels,
             sample_rate,
-            codec_string.clone(),
+            self.audio_codec.codec_string().to_string(),
             vec![],
         );
 
         let init_bytes = writer.build_init_segment();
         self.audio_sample_rate = sample_rate;
         self.audio_channels = channels;
-        self.audio_codec = codec_string.clone();
         self.store_file(AUDIO_INIT_FILENAME, init_bytes)
             .await
             .map_err(|e| {
@@ -690,14 +701,7 @@ impl Segmenter {
         let mut adaptation_sets = String::new();
 
         if video_track_ready {
-            let video_bandwidth = if self.total_ticks > 0 {
-                self.total_bytes
-                    .saturating_mul(8)
-                    .saturating_mul(self.timescale as u64)
-                    / self.total_ticks.max(1)
-            } else {
-                0
-            };
+            let video_bandwidth = self.video_bandwidth();
 
             let video_segment_timeline = self.generate_segment_timeline(&self.segments);
 
@@ -742,14 +746,7 @@ impl Segmenter {
 
         if audio_track_ready {
             let writer = self.audio_writer.as_ref().unwrap();
-            let audio_bandwidth = if self.audio_total_ticks > 0 {
-                self.audio_total_bytes
-                    .saturating_mul(8)
-                    .saturating_mul(writer.timescale as u64)
-                    / self.audio_total_ticks.max(1)
-            } else {
-                0
-            };
+            let audio_bandwidth = self.audio_bandwidth();
             let audio_segment_timeline = self.generate_segment_timeline(&self.audio_segments);
             let audio_adaptation_id = if video_track_ready { 1 } else { 0 };
             let audio_representation_id = if video_track_ready { 1 } else { 0 };
@@ -796,7 +793,114 @@ impl Segmenter {
                     e
                 );
                 e
-            })
+            })?;
+
+        self.write_hls_playlists().await
+    }
+
+    /// Write the VOD HLS playlists next to the MPD, `index.m3u8` of the video track (or the
+    /// audio track without video), `a_index.m3u8` of the audio rendition, and `master.m3u8`
+    async fn write_hls_playlists(&self) -> Result<()> {
+        let video = self.video_track_id.is_some().then(|| {
+            media_playlist(
+                VIDEO_INIT_FILENAME,
+                VIDEO_SEGMENT_FILENAME_PREFIX,
+                self.timescale,
+                &self.segments,
+            )
+        });
+        let audio = self.audio_writer.as_ref().map(|writer| {
+            media_playlist(
+                AUDIO_INIT_FILENAME,
+                AUDIO_SEGMENT_FILENAME_PREFIX,
+                writer.timescale,
+                &self.audio_segments,
+            )
+        });
+
+        let mut master = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+        let mut playlists = vec![];
+        match (video, audio) {
+            (Some(video), audio) => {
+                let mut codecs = vec![self.video_codec.clone()];
+                let mut bandwidth = self.video_bandwidth();
+                let mut audio_group = "";
+                if let (Some(audio), Some(writer)) = (audio, self.audio_writer.as_ref()) {
+                    codecs.push(writer.codec_string.clone());
+                    bandwidth += self.audio_bandwidth();
+                    audio_group = ",AUDIO=\"audio\"";
+                    let _ = writeln!(
+                        master,
+                        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"audio\",DEFAULT=YES,AUTOSELECT=YES,URI=\"{HLS_AUDIO_INDEX_FILENAME}\""
+                    );
+                    playlists.push((HLS_AUDIO_INDEX_FILENAME, audio));
+                }
+                let _ = writeln!(
+                    master,
+                    "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\",RESOLUTION={}x{}{audio_group}\n{HLS_INDEX_FILENAME}",
+                    bandwidth.max(1),
+                    codecs.join(","),
+                    self.video_width,
+                    self.video_height
+                );
+                playlists.push((HLS_INDEX_FILENAME, video));
+            }
+            (None, Some(audio)) => {
+                let codec = self
+                    .audio_writer
+                    .as_ref()
+                    .map(|writer| writer.codec_string.clone())
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    master,
+                    "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{codec}\"\n{HLS_INDEX_FILENAME}",
+                    self.audio_bandwidth().max(1)
+                );
+                playlists.push((HLS_INDEX_FILENAME, audio));
+            }
+            (None, None) => return Ok(()),
+        }
+        playlists.push((HLS_MASTER_FILENAME, master));
+
+        for (name, playlist) in playlists {
+            self.store_file(name, playlist.into_bytes())
+                .await
+                .map_err(|e| {
+                    tracing::error!(
+                        "[segmenter] failed to store {} for stream {}: {}",
+                        name,
+                        self.stream,
+                        e
+                    );
+                    e
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Average bits per second of the video samples so far
+    fn video_bandwidth(&self) -> u64 {
+        if self.total_ticks > 0 {
+            self.total_bytes
+                .saturating_mul(8)
+                .saturating_mul(self.timescale as u64)
+                / self.total_ticks.max(1)
+        } else {
+            0
+        }
+    }
+
+    /// Average bits per second of the audio samples so far
+    fn audio_bandwidth(&self) -> u64 {
+        match self.audio_writer.as_ref() {
+            Some(writer) if self.audio_total_ticks > 0 => {
+                self.audio_total_bytes
+                    .saturating_mul(8)
+                    .saturating_mul(writer.timescale as u64)
+                    / self.audio_total_ticks.max(1)
+            }
+            _ => 0,
+        }
     }
 
     /// Generate SegmentTimeline XML from segment info
@@ -861,6 +965,36 @@ impl Segmenter {
     }
 }
 
+/// A VOD media playlist of the segments of a track, numbered from 1 like the MPD template
+fn media_playlist(
+    init: &str,
+    segment_prefix: &str,
+    timescale: u32,
+    segments: &[SegmentInfo],
+) -> String {
+    let timescale = timescale.max(1) as f64;
+    let target_duration = segments
+        .iter()
+        .map(|segment| (segment.duration as f64 / timescale).ceil() as u64)
+        .max()
+        .unwrap_or(DEFAULT_SEG_DURATION)
+        .max(1);
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{target_duration}\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n#EXT-X-MAP:URI=\"{init}\"\n"
+    );
+    for (index, segment) in segments.iter().enumerate() {
+        let _ = writeln!(
+            playlist,
+            "#EXTINF:{:.3},\n{segment_prefix}{:04}{SEGMENT_FILE_EXTENSION}",
+            segment.duration as f64 / timescale,
+            index + 1
+        );
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
 fn parse_channels_from_fmtp(fmtp: &str) -> Option<u16> {
     fmtp.split(';').map(str::trim).find_map(|part| {
         if let Some(value) = part.strip_prefix("channels=") {
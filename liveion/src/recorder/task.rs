@@ -2,11 +2,14 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use super::RecordingInfo;
-use crate::recorder::codec::Av1RtpParser;
-use crate::recorder::codec::H265RtpParser;
-use crate::recorder::codec::h264::H264RtpParser;
-use crate::recorder::codec::opus::OpusRtpParser;
-use crate::recorder::codec::vp9::Vp9RtpParser;
+use crate::codec::AudioCodec;
+use crate::codec::Av1RtpParser;
+use crate::codec::H265RtpParser;
+use crate::codec::g711::G711RtpParser;
+use crate::codec::h264::H264RtpParser;
+use crate::codec::opus::OpusRtpParser;
+use crate::codec::vp8::Vp8RtpParser;
+use crate::codec::vp9::Vp9RtpParser;
 use crate::recorder::segmenter::Segmenter;
 use crate::stream::manager::Manager;
 use anyhow::{Result, anyhow};
@@ -15,7 +18,9 @@ use bytes::Bytes;
 use chrono::Utc;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
-use webrtc::api::media_engine::{MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_HEVC, MIME_TYPE_VP9};
+use webrtc::api::media_engine::{
+    MIME_TYPE_AV1, MIME_TYPE_H264, MIME_TYPE_HEVC, MIME_TYPE_VP8, MIME_TYPE_VP9,
+};
 
 pub struct RecordingTask {
     pub stream: String,
@@ -161,28 +166,41 @@ impl RecordingTask {
                 codec
             );
         } else {
-            tracing::info!("[recorder] stream {} is audio-only (Opus)", stream_name);
+            tracing::info!("[recorder] stream {} is audio-only", stream_name);
         }
 
         if audio_receiver_opt.is_some() {
             tracing::info!("[recorder] stream {} audio track detected", stream_name);
         }
 
+        let mut audio_codec = AudioCodec::Opus;
         if audio_receiver_opt.is_some()
             && let Some(info) = forward.first_audio_track_info().await
         {
-            let crate::forward::AudioTrackInfo {
-                clock_rate,
-                channels,
-                codec_mime,
-                fmtp,
-            } = info;
-            let fmtp_opt = if fmtp.trim().is_empty() {
-                None
-            } else {
-                Some(fmtp.as_str())
-            };
-            segmenter.configure_audio_track(clock_rate, channels, codec_mime, fmtp_opt);
+            match info.codec() {
+                Some(codec) => {
+                    let fmtp_opt = if info.fmtp.trim().is_empty() {
+                        None
+                    } else {
+                        Some(info.fmtp.as_str())
+                    };
+                    segmenter.configure_audio_track(
+                        codec,
+                        info.clock_rate,
+                        info.channels,
+                        fmtp_opt,
+                    );
+                    audio_codec = codec;
+                }
+                None => {
+                    tracing::warn!(
+                        "[recorder] stream {} audio codec {} is not supported, recording without audio",
+                        stream_name,
+                        info.codec_mime
+                    );
+                    audio_receiver_opt = None;
+                }
+            }
         }
 
         tracing::info!("[recorder] subscribed RTP for stream {}", stream_name);
@@ -200,10 +218,12 @@ impl RecordingTask {
             let mut parser_h264 = H264RtpParser::new();
             let mut parser_h265 = H265RtpParser::new();
             let mut parser_av1 = Av1RtpParser::new();
+            let mut parser_vp8 = Vp8RtpParser::new();
             let mut parser_vp9 = Vp9RtpParser::new();
             let mut prev_ts_video: Option<u32> = None;
 
             let mut parser_audio = OpusRtpParser::new();
+            let mut parser_g711 = G711RtpParser::new();
             let mut prev_ts_audio: Option<u32> = None;
 
             let mut frame_cnt_video: u64 = 0;
@@ -296,6 +316,15 @@ impl RecordingTask {
                                         tracing::warn!("[recorder] {} failed to process AV1 frame: {}", stream_name_cloned, e);
                                     }
                                     frame_cnt_video += 1;
+                                } else if codec_mime.eq_ignore_ascii_case(MIME_TYPE_VP8)
+                                    && let Ok(Some((frame, _))) = parser_vp8.push_packet(&packet)
+                                {
+                                    let duration_ticks: u32 = if let Some(prev) = prev_ts_video { pkt_ts.wrapping_sub(prev) } else { 3_000 };
+                                    prev_ts_video = Some(pkt_ts);
+                                    if let Err(e) = segmenter.push_vp8(frame.freeze(), duration_ticks).await {
+                                        tracing::warn!("[recorder] {} failed to process VP8 frame: {}", stream_name_cloned, e);
+                                    }
+                                    frame_cnt_video += 1;
                                 } else if codec_mime.eq_ignore_ascii_case(MIME_TYPE_VP9)
                                     && let Ok(Some(frame)) = parser_vp9.push_packet(&packet)
                                 {
@@ -322,18 +351,25 @@ impl RecordingTask {
                     }, if audio_rx_opt.is_some() => {
                         match result {
                             Some(packet) => {
-                                let (payload, pkt_ts) = match parser_audio.push_packet(&packet) {
+                                let parsed = match audio_codec {
+                                    AudioCodec::Opus => parser_audio.push_packet(&packet),
+                                    AudioCodec::Pcmu | AudioCodec::Pcma => parser_g711.push_packet(&packet),
+                                };
+                                let (payload, pkt_ts) = match parsed {
                                     Ok(v) => v,
                                     Err(_) => continue,
                                 };
                                 let duration_ticks: u32 = if let Some(prev) = prev_ts_audio {
                                     pkt_ts.wrapping_sub(prev)
-                                } else {
+                                } else if audio_codec == AudioCodec::Opus {
                                     960
+                                } else {
+                                    // One byte per sample of G.711
+                                    payload.len() as u32
                                 };
                                 prev_ts_audio = Some(pkt_ts);
-                                if let Err(e) = segmenter.push_opus(Bytes::from(payload), duration_ticks).await {
-                                    tracing::warn!("[recorder] {} failed to process Opus frame (storage error?): {}", stream_name_cloned, e);
+                                if let Err(e) = segmenter.push_audio(Bytes::from(payload), duration_ticks).await {
+                                    tracing::warn!("[recorder] {} failed to process {:?} frame (storage error?): {}", stream_name_cloned, audio_codec, e);
                                 }
                                 frame_cnt_audio += 1;
                             }
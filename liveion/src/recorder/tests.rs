@@ -1,12 +1,15 @@
 #[cfg(all(test, feature = "recorder"))]
 mod tests {
     use super::super::*;
+    use crate::codec::{AudioCodec, CodecAdapter, VideoCodec, Vp8RtpParser, create_video_adapter};
     use crate::recorder::segmenter::Segmenter;
     use bytes::Bytes;
     use opendal::Operator;
     use opendal::services::Fs;
     use tempfile::TempDir;
     use tokio::time::{Duration, sleep};
+    use webrtc::rtp::header::Header;
+    use webrtc::rtp::packet::Packet;
 
     // Helper to build a minimal H264 frame that includes SPS, PPS and an IDR slice
     fn make_h264_idr_frame() -> Bytes {
@@ -27,8 +30,7 @@ mod tests {
         let tmp = TempDir::new().expect("Failed to create temp dir");
         let tmp_path = tmp.path().to_str().unwrap().to_string();
 
-        let mut builder = Fs::default();
-        builder.root(&tmp_path);
+        let builder = Fs::default().root(&tmp_path);
         let op: Operator = Operator::new(builder).unwrap().finish();
 
         // Instantiate a Segmenter
@@ -46,14 +48,14 @@ mod tests {
         sleep(Duration::from_millis(200)).await;
 
         // The init segment and manifest should exist
-        let init_path = format!("{}/init.m4s", prefix);
+        let init_path = format!("{}/v_init.m4s", prefix);
         let manifest_path = format!("{}/manifest.mpd", prefix);
         assert!(
-            op.is_exist(&init_path).await.unwrap(),
-            "init.m4s not written"
+            op.exists(&init_path).await.unwrap(),
+            "v_init.m4s not written"
         );
         assert!(
-            op.is_exist(&manifest_path).await.unwrap(),
+            op.exists(&manifest_path).await.unwrap(),
             "manifest.mpd not written"
         );
     }
@@ -61,8 +63,293 @@ mod tests {
     #[test]
     fn test_should_record_glob() {
         let patterns = vec!["live/*".to_string(), "demo".to_string()];
-        assert!(super::should_record(&patterns, "live/abc"));
-        assert!(!super::should_record(&patterns, "other/stream"));
-        assert!(super::should_record(&patterns, "demo"));
+        assert!(should_record(&patterns, "live/abc"));
+        assert!(!should_record(&patterns, "other/stream"));
+        assert!(should_record(&patterns, "demo"));
+    }
+
+    // A VP8 keyframe: frame tag, start code, then 640x480
+    fn make_vp8_key_frame() -> Vec<u8> {
+        let mut buf = vec![0x10, 0x02, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0xe0, 0x01];
+        buf.extend_from_slice(&[0xAA; 32]);
+        buf
+    }
+
+    fn make_vp8_packet(sequence_number: u16, marker: bool, descriptor: u8, data: &[u8]) -> Packet {
+        let mut payload = vec![descriptor];
+        payload.extend_from_slice(data);
+        Packet {
+            header: Header {
+                sequence_number,
+                timestamp: 3000,
+                marker,
+                ..Default::default()
+            },
+            payload: Bytes::from(payload),
+        }
+    }
+
+    #[test]
+    fn test_vp8_rtp_parser() {
+        let frame = make_vp8_key_frame();
+        let (head, tail) = frame.split_at(16);
+        let mut parser = Vp8RtpParser::new();
+
+        // A continuation without the start of the frame is dropped
+        assert!(
+            parser
+                .push_packet(&make_vp8_packet(9, true, 0x00, tail))
+                .unwrap()
+                .is_none()
+        );
+
+        // S=1, PID=0 starts the frame, the marker bit ends it
+        assert!(
+            parser
+                .push_packet(&make_vp8_packet(10, false, 0x10, head))
+                .unwrap()
+                .is_none()
+        );
+        let (out, is_keyframe) = parser
+            .push_packet(&make_vp8_packet(11, true, 0x00, tail))
+            .unwrap()
+            .expect("frame should be complete");
+        assert_eq!(out.as_ref(), frame.as_slice());
+        assert!(is_keyframe);
+
+        // An inter frame, with a lost packet in the middle
+        let inter = [0x11, 0x00, 0x00, 0xBB, 0xBB];
+        let (out, is_keyframe) = parser
+            .push_packet(&make_vp8_packet(12, true, 0x10, &inter))
+            .unwrap()
+            .expect("single packet frame");
+        assert_eq!(out.as_ref(), inter.as_slice());
+        assert!(!is_keyframe);
+        assert!(
+            parser
+                .push_packet(&make_vp8_packet(13, false, 0x10, head))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            parser
+                .push_packet(&make_vp8_packet(15, true, 0x00, tail))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_vp8_adapter() {
+        let mut adapter = create_video_adapter(VideoCodec::Vp8);
+        assert!(!adapter.ready());
+
+        // Inter frames before a keyframe carry no dimensions
+        let (_, is_key, cfg_updated) = adapter.convert_frame(&Bytes::from_static(&[0x11, 0, 0]));
+        assert!(!is_key);
+        assert!(!cfg_updated);
+        assert!(!adapter.ready());
+
+        let frame = Bytes::from(make_vp8_key_frame());
+        let (payload, is_key, cfg_updated) = adapter.convert_frame(&frame);
+        assert_eq!(payload, frame.to_vec());
+        assert!(is_key);
+        assert!(cfg_updated);
+        assert!(adapter.ready());
+        assert_eq!((adapter.width(), adapter.height()), (640, 480));
+        assert_eq!(adapter.codec_string().as_deref(), Some("vp08.00.10.08"));
+
+        // The same keyframe again doesn't update the config
+        let (_, is_key, cfg_updated) = adapter.convert_frame(&frame);
+        assert!(is_key);
+        assert!(!cfg_updated);
+    }
+
+    #[tokio::test]
+    async fn test_segmenter_writes_vp8_init() {
+        let tmp = TempDir::new().expect("Failed to create temp dir");
+        let tmp_path = tmp.path().to_str().unwrap().to_string();
+
+        let builder = Fs::default().root(&tmp_path);
+        let op: Operator = Operator::new(builder).unwrap().finish();
+
+        let prefix = "vp8".to_string();
+        let mut seg = Segmenter::new(op.clone(), "test_vp8".to_string(), prefix.clone())
+            .await
+            .expect("Failed to create segmenter");
+        seg.push_vp8(Bytes::from(make_vp8_key_frame()), 3000)
+            .await
+            .expect("push failed");
+
+        sleep(Duration::from_millis(200)).await;
+
+        let init = op
+            .read(&format!("{}/v_init.m4s", prefix))
+            .await
+            .expect("v_init.m4s not written")
+            .to_vec();
+        let has_box = |fourcc: &[u8]| init.windows(4).any(|w| w == fourcc);
+        assert!(has_box(b"vp08"), "vp08 sample entry missing");
+        assert!(has_box(b"vpcC"), "vpcC box missing");
+    }
+
+    #[tokio::test]
+    async fn test_segmenter_keeps_video_metadata_of_init() {
+        let tmp = TempDir::new().expect("Failed to create temp dir");
+        let tmp_path = tmp.path().to_str().unwrap().to_string();
+
+        let builder = Fs::default().root(&tmp_path);
+        let op: Operator = Operator::new(builder).unwrap().finish();
+
+        let prefix = "vp8_resize".to_string();
+        let mut seg = Segmenter::new(op.clone(), "test_vp8_resize".to_string(), prefix.clone())
+            .await
+            .expect("Failed to create segmenter");
+        // A whole segment of 640x480, then a keyframe of 320x240 rolls the segment
+        seg.push_vp8(Bytes::from(make_vp8_key_frame()), 900_000)
+            .await
+            .expect("push failed");
+        let mut resized = make_vp8_key_frame();
+        resized[6..10].copy_from_slice(&[0x40, 0x01, 0xf0, 0x00]);
+        seg.push_vp8(Bytes::from(resized), 3000)
+            .await
+            .expect("push failed");
+
+        sleep(Duration::from_millis(200)).await;
+
+        let manifest = op
+            .read(&format!("{}/manifest.mpd", prefix))
+            .await
+            .expect("manifest.mpd not written")
+            .to_vec();
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert!(manifest.contains("width=\"640\" height=\"480\""));
+        let master = op
+            .read(&format!("{}/master.m3u8", prefix))
+            .await
+            .expect("master.m3u8 not written")
+            .to_vec();
+        let master = String::from_utf8(master).unwrap();
+        assert!(master.contains("RESOLUTION=640x480"));
+    }
+
+    #[tokio::test]
+    async fn test_segmenter_writes_pcmu_audio() {
+        let tmp = TempDir::new().expect("Failed to create temp dir");
+        let tmp_path = tmp.path().to_str().unwrap().to_string();
+
+        let builder = Fs::default().root(&tmp_path);
+        let op: Operator = Operator::new(builder).unwrap().finish();
+
+        let prefix = "pcmu".to_string();
+        let mut seg = Segmenter::new(op.clone(), "test_pcmu".to_string(), prefix.clone())
+            .await
+            .expect("Failed to create segmenter");
+        seg.configure_audio_track(AudioCodec::Pcmu, 8000, 1, None);
+        // 20 ms of G.711 at 8 kHz
+        seg.push_audio(Bytes::from_static(&[0xFF; 160]), 160)
+            .await
+            .expect("push failed");
+
+        sleep(Duration::from_millis(200)).await;
+
+        let init = op
+            .read(&format!("{}/a_init.m4s", prefix))
+            .await
+            .expect("a_init.m4s not written")
+            .to_vec();
+        assert!(
+            init.windows(4).any(|w| w == b"ulaw"),
+            "ulaw sample entry missing"
+        );
+        let manifest = op
+            .read(&format!("{}/manifest.mpd", prefix))
+            .await
+            .expect("manifest.mpd not written")
+            .to_vec();
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert!(manifest.contains("codecs=\"ulaw\""));
+        assert!(manifest.contains("audioSamplingRate=\"8000\""));
+    }
+
+    #[tokio::test]
+    async fn test_segmenter_pcma_without_channels_is_mono() {
+        let tmp = TempDir::new().expect("Failed to create temp dir");
+        let tmp_path = tmp.path().to_str().unwrap().to_string();
+
+        let builder = Fs::default().root(&tmp_path);
+        let op: Operator = Operator::new(builder).unwrap().finish();
+
+        let prefix = "pcma".to_string();
+        let mut seg = Segmenter::new(op.clone(), "test_pcma".to_string(), prefix.clone())
+            .await
+            .expect("Failed to create segmenter");
+        // webrtc registers G.711 with 0 channels
+        seg.configure_audio_track(AudioCodec::Pcma, 8000, 0, None);
+        seg.push_audio(Bytes::from_static(&[0xD5; 160]), 160)
+            .await
+            .expect("push failed");
+
+        sleep(Duration::from_millis(200)).await;
+
+        let init = op
+            .read(&format!("{}/a_init.m4s", prefix))
+            .await
+            .expect("a_init.m4s not written")
+            .to_vec();
+        let pos = init
+            .windows(4)
+            .position(|w| w == b"alaw")
+            .expect("alaw sample entry missing");
+        // channelcount is 16 bytes into the sample entry, after the fourcc
+        let channels = u16::from_be_bytes([init[pos + 20], init[pos + 21]]);
+        assert_eq!(channels, 1);
+    }
+
+    #[tokio::test]
+    async fn test_segmenter_writes_hls_playlists() {
+        let tmp = TempDir::new().expect("Failed to create temp dir");
+        let tmp_path = tmp.path().to_str().unwrap().to_string();
+
+        let builder = Fs::default().root(&tmp_path);
+        let op: Operator = Operator::new(builder).unwrap().finish();
+
+        let prefix = "hls".to_string();
+        let mut seg = Segmenter::new(op.clone(), "test_hls".to_string(), prefix.clone())
+            .await
+            .expect("Failed to create segmenter");
+        seg.configure_audio_track(AudioCodec::Pcmu, 8000, 1, None);
+        seg.push_vp8(Bytes::from(make_vp8_key_frame()), 3000)
+            .await
+            .expect("push video failed");
+        seg.push_audio(Bytes::from_static(&[0xFF; 160]), 160)
+            .await
+            .expect("push audio failed");
+        seg.flush().await.expect("flush failed");
+
+        sleep(Duration::from_millis(200)).await;
+
+        let read = |name: &str| {
+            let op = op.clone();
+            let path = format!("{}/{}", prefix, name);
+            async move {
+                let data = op.read(&path).await.expect("playlist not written");
+                String::from_utf8(data.to_vec()).unwrap()
+            }
+        };
+
+        let master = read("master.m3u8").await;
+        assert!(master.contains("URI=\"a_index.m3u8\""));
+        assert!(master.contains("CODECS=\"vp08.00.10.08,ulaw\",RESOLUTION=640x480"));
+        assert!(master.contains("\nindex.m3u8\n"));
+
+        let video = read("index.m3u8").await;
+        assert!(video.contains("#EXT-X-MAP:URI=\"v_init.m4s\""));
+        assert!(video.contains("#EXTINF:0.033,\nv_seg_0001.m4s\n"));
+        assert!(video.ends_with("#EXT-X-ENDLIST\n"));
+
+        let audio = read("a_index.m3u8").await;
+        assert!(audio.contains("#EXT-X-MAP:URI=\"a_init.m4s\""));
+        assert!(audio.contains("#EXTINF:0.020,\na_seg_0001.m4s\n"));
     }
 }
@@ -7,7 +7,13 @@ use crate::error::AppError;
 use crate::result::Result;
 
 pub fn route() -> Router<AppState> {
-    Router::new().route(&api::path::cascade("{stream}"), post(cascade))
+    let router = Router::new().route(&api::path::cascade("{stream}"), post(cascade));
+    #[cfg(feature = "mpegts")]
+    let router = router.route(
+        &api::path::mpegts("{stream}"),
+        post(mpegts).delete(mpegts_stop),
+    );
+    router
 }
 
 async fn cascade(
@@ -38,3 +44,22 @@ async fn cascade(
     }
     Ok("".to_string())
 }
+
+#[cfg(feature = "mpegts")]
+async fn mpegts(
+    State(state): State<AppState>,
+    Path(stream): Path<String>,
+    Json(body): Json<api::request::MpegtsOutput>,
+) -> Result<String> {
+    state
+        .stream_manager
+        .mpegts_push(stream, body.target_url)
+        .await?;
+    Ok("".to_string())
+}
+
+#[cfg(feature = "mpegts")]
+async fn mpegts_stop(State(state): State<AppState>, Path(stream): Path<String>) -> Result<String> {
+    state.stream_manager.mpegts_stop(stream).await?;
+    Ok("".to_string())
+}
@@ -0,0 +1,237 @@
+//! Shared by the LL-HLS and LL-DASH routes of the live CMAF packaging
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use auth::AuthState;
+use axum::body::Body;
+use axum::response::Response;
+use bytes::Bytes;
+use http::{HeaderMap, header};
+
+use crate::AppState;
+use crate::codec::TrackKind;
+use crate::error::AppError;
+use crate::recorder::hls::track_kind;
+use crate::recorder::live::LiveStream;
+use crate::result::Result;
+use crate::stream::callout::ClientInfo;
+
+pub const MEDIA_CONTENT_TYPE: &str = "video/mp4";
+
+/// The query parameter carrying the session of a player in the URIs of the manifests
+const SESSION_QUERY: &str = "session";
+
+/// The routes are outside of the auth middleware, players can only pass the token in the query
+pub fn authorize(
+    state: &AppState,
+    stream: &str,
+    query: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<()> {
+    let token = query.get("token").map(|token| token.as_str()).or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+    });
+    let auth = AuthState::new(
+        state.config.auth.secret.clone(),
+        state.config.auth.tokens.clone(),
+    );
+    match auth.claims(token) {
+        Some(claims) if claims.can_subscribe(stream) => Ok(()),
+        _ => Err(AppError::forbidden("no subscribe permission")),
+    }
+}
+
+/// The session of a player, admitted once by the HTTP callout.
+/// Manifest requests without a known session start a new one, the media requests
+/// must carry the session of their manifest. Sessions are dropped after `idle_timeout`
+/// seconds without a request, or once they expire
+pub async fn admit(
+    state: &AppState,
+    addr: SocketAddr,
+    stream: &str,
+    query: &HashMap<String, String>,
+    headers: &HeaderMap,
+    manifest: bool,
+) -> Result<String> {
+    if let Some(session) = query.get(SESSION_QUERY)
+        && state.live_streams.touch_player(stream, session)?
+    {
+        return Ok(session.clone());
+    }
+    if !manifest {
+        return Err(AppError::forbidden(
+            "unknown session, load the manifest first",
+        ));
+    }
+
+    let session = uuid::Uuid::new_v4().simple().to_string();
+    let mut client = ClientInfo::new(addr, headers);
+    client.token = client.token.or_else(|| query.get("token").cloned());
+    let output = state
+        .stream_manager
+        .admit_output(stream, session.clone(), client)
+        .await?;
+    state.live_streams.add_player(
+        stream,
+        output,
+        Duration::from_secs(state.config.cmaf.idle_timeout),
+    );
+    Ok(session)
+}
+
+/// The packaging of `stream`, when the output is `enabled`
+pub async fn live(state: &AppState, enabled: bool, stream: &str) -> Result<Arc<LiveStream>> {
+    if !enabled {
+        return Err(AppError::stream_not_found("output is disabled"));
+    }
+    state
+        .live_streams
+        .get(state.stream_manager.clone(), &state.config.cmaf, stream)
+        .await
+}
+
+/// Blocking requests wait up to three target durations
+pub fn block_timeout(live: &LiveStream) -> Duration {
+    Duration::from_millis(live.cfg.segment_duration * 3)
+}
+
+/// The query propagated to the manifest URIs, without the HLS delivery directives,
+/// with the session of the player
+pub fn uri_query(raw: Option<String>, session: &str) -> String {
+    let session = format!("{SESSION_QUERY}={session}");
+    let query: Vec<&str> = raw
+        .as_deref()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            !pair.is_empty()
+                && !pair.starts_with("_HLS_")
+                && !pair.starts_with(&format!("{SESSION_QUERY}="))
+        })
+        .chain([session.as_str()])
+        .collect();
+    format!("?{}", query.join("&"))
+}
+
+pub fn response(
+    content_type: &str,
+    cache_control: &str,
+    body: impl Into<Body>,
+) -> Result<Response> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(body.into())?)
+}
+
+/// `init.mp4`, `{msn}.m4s` segments and `{msn}.{part}.m4s` parts.
+/// The segment in progress is sent part by part with chunked transfer.
+pub async fn media(live: Arc<LiveStream>, track: &str, file: &str) -> Result<Response> {
+    let Some(kind) = track_kind(track) else {
+        return Err(AppError::stream_not_found("track not found"));
+    };
+    let Some(window) = live.window(kind) else {
+        return Err(AppError::stream_not_found("track not found"));
+    };
+
+    if file == "init.mp4" {
+        return match window.read().unwrap().init.clone() {
+            Some(init) => response(MEDIA_CONTENT_TYPE, "max-age=3600", init),
+            None => Err(AppError::stream_not_found("init segment not found")),
+        };
+    }
+
+    let Some(name) = file.strip_suffix(".m4s") else {
+        return Err(AppError::stream_not_found("file not found"));
+    };
+    let (msn, part) = match name.split_once('.') {
+        Some((msn, part)) => (msn.parse::<u64>(), part.parse::<usize>().map(Some)),
+        None => (name.parse::<u64>(), Ok(None)),
+    };
+    let (Ok(msn), Ok(part)) = (msn, part) else {
+        return Err(AppError::stream_not_found("file not found"));
+    };
+
+    let (next_msn, next_part) = window.read().unwrap().next_part();
+    match part {
+        Some(part) => {
+            // The next part, announced by the preload hint, is held until it is published
+            if msn == next_msn && part == next_part {
+                let ready = |live: &LiveStream| {
+                    live.window(kind)
+                        .unwrap()
+                        .read()
+                        .unwrap()
+                        .has(msn, Some(part))
+                };
+                live.wait(block_timeout(&live), ready).await;
+            }
+            match window.read().unwrap().part_data(msn, part) {
+                Some(data) => response(MEDIA_CONTENT_TYPE, "max-age=60", data),
+                None => Err(AppError::stream_not_found("part not found")),
+            }
+        }
+        None if msn == next_msn => response(
+            MEDIA_CONTENT_TYPE,
+            "no-cache",
+            Body::from_stream(segment_parts(live.clone(), kind, msn)),
+        ),
+        None => match window.read().unwrap().segment_data(msn) {
+            Some(data) => response(MEDIA_CONTENT_TYPE, "max-age=60", data),
+            None => Err(AppError::stream_not_found("segment not found")),
+        },
+    }
+}
+
+/// The parts of the segment `msn` as they are published, until it is complete
+fn segment_parts(
+    live: Arc<LiveStream>,
+    kind: TrackKind,
+    msn: u64,
+) -> impl tokio_stream::Stream<Item = std::result::Result<Bytes, Infallible>> {
+    async_stream::stream! {
+        let timeout = block_timeout(&live);
+        let mut part = 0;
+        loop {
+            let published = |live: &LiveStream| {
+                live.window(kind).unwrap().read().unwrap().has(msn, Some(part))
+            };
+            if !live.wait(timeout, published).await {
+                break;
+            }
+            // None once the segment is complete, or has left the window
+            let data = live.window(kind).unwrap().read().unwrap().part_data(msn, part);
+            match data {
+                Some(data) => {
+                    part += 1;
+                    yield Ok(data);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::uri_query;
+
+    #[test]
+    fn test_uri_query() {
+        assert_eq!(uri_query(None, "s1"), "?session=s1");
+        assert_eq!(
+            uri_query(
+                Some("token=t&_HLS_msn=3&session=old&_HLS_part=1".to_string()),
+                "s1"
+            ),
+            "?token=t&session=s1"
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::Router;
+use axum::extract::{ConnectInfo, Path, Query, RawQuery, State};
+use axum::response::Response;
+use axum::routing::get;
+use http::HeaderMap;
+
+use super::cmaf::{admit, authorize, block_timeout, live, response, uri_query};
+use crate::AppState;
+use crate::error::AppError;
+use crate::recorder::dash::manifest;
+use crate::recorder::live::LiveStream;
+use crate::result::Result;
+
+const MANIFEST_CONTENT_TYPE: &str = "application/dash+xml";
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route(
+            &format!("{}/manifest.mpd", api::path::dash("{stream}")),
+            get(mpd),
+        )
+        .route(
+            &format!("{}/{{track}}/{{file}}", api::path::dash("{stream}")),
+            get(media),
+        )
+}
+
+async fn mpd(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(stream): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    RawQuery(raw): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response> {
+    authorize(&state, &stream, &query, &headers)?;
+    let session = admit(&state, addr, &stream, &query, &headers, true).await?;
+    let live = live(&state, state.config.cmaf.dash, &stream).await?;
+    if !live
+        .wait(block_timeout(&live), LiveStream::initialized)
+        .await
+    {
+        return Err(AppError::stream_not_found("stream is not packaged yet"));
+    }
+    match manifest(&live, &uri_query(raw, &session)) {
+        Some(mpd) => response(MANIFEST_CONTENT_TYPE, "no-cache", mpd),
+        None => Err(AppError::stream_not_found("stream is not packaged yet")),
+    }
+}
+
+async fn media(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((stream, track, file)): Path<(String, String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    authorize(&state, &stream, &query, &headers)?;
+    admit(&state, addr, &stream, &query, &headers, false).await?;
+    let live = live(&state, state.config.cmaf.dash, &stream).await?;
+    super::cmaf::media(live, &track, &file).await
+}
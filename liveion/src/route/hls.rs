@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::Router;
+use axum::extract::{ConnectInfo, Path, Query, RawQuery, State};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use http::{HeaderMap, StatusCode};
+
+use super::cmaf::{admit, authorize, block_timeout, live, response, uri_query};
+use crate::AppState;
+use crate::error::AppError;
+use crate::recorder::hls::{media_playlist, multivariant_playlist, track_kind};
+use crate::recorder::live::LiveStream;
+use crate::result::Result;
+
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+pub fn route() -> Router<AppState> {
+    Router::new()
+        .route(
+            &format!("{}/{{playlist}}", api::path::hls("{stream}")),
+            get(playlist),
+        )
+        .route(
+            &format!("{}/{{track}}/{{file}}", api::path::hls("{stream}")),
+            get(media),
+        )
+}
+
+async fn playlist(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((stream, playlist)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    RawQuery(raw): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response> {
+    authorize(&state, &stream, &query, &headers)?;
+    let session = admit(&state, addr, &stream, &query, &headers, true).await?;
+    let live = live(&state, state.config.cmaf.hls, &stream).await?;
+    let uri_query = uri_query(raw, &session);
+
+    if playlist == "index.m3u8" {
+        if !live
+            .wait(block_timeout(&live), LiveStream::initialized)
+            .await
+        {
+            return Err(AppError::stream_not_found("stream is not packaged yet"));
+        }
+        return response(
+            PLAYLIST_CONTENT_TYPE,
+            "no-cache",
+            multivariant_playlist(&live, &uri_query),
+        );
+    }
+
+    let Some(kind) = playlist.strip_suffix(".m3u8").and_then(track_kind) else {
+        return Err(AppError::stream_not_found("playlist not found"));
+    };
+    if live.window(kind).is_none() {
+        return Err(AppError::stream_not_found("track not found"));
+    }
+
+    // Blocking playlist reload: hold the request until the segment or part is published
+    let msn = query
+        .get("_HLS_msn")
+        .and_then(|msn| msn.parse::<u64>().ok());
+    let part = query
+        .get("_HLS_part")
+        .and_then(|part| part.parse::<usize>().ok());
+    let ready = |live: &LiveStream| {
+        let window = live.window(kind).unwrap().read().unwrap();
+        window.init.is_some() && msn.is_none_or(|msn| window.has(msn, part))
+    };
+    if let Some(msn) = msn {
+        let (next, _) = live.window(kind).unwrap().read().unwrap().next_part();
+        if msn > next + 2 {
+            return Ok(
+                (StatusCode::BAD_REQUEST, "_HLS_msn is too far in the future").into_response(),
+            );
+        }
+    }
+    if !live.wait(block_timeout(&live), ready).await {
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, "playlist is not updated").into_response());
+    }
+    match media_playlist(&live, kind, &uri_query) {
+        Some(playlist) => response(PLAYLIST_CONTENT_TYPE, "no-cache", playlist),
+        None => Err(AppError::stream_not_found("stream is not packaged yet")),
+    }
+}
+
+async fn media(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((stream, track, file)): Path<(String, String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    authorize(&state, &stream, &query, &headers)?;
+    admit(&state, addr, &stream, &query, &headers, false).await?;
+    let live = live(&state, state.config.cmaf.hls, &stream).await?;
+    super::cmaf::media(live, &track, &file).await
+}
@@ -4,6 +4,12 @@ use crate::config::Config;
 use crate::stream::manager::Manager;
 
 pub mod admin;
+#[cfg(feature = "recorder")]
+mod cmaf;
+#[cfg(feature = "recorder")]
+pub mod dash;
+#[cfg(feature = "recorder")]
+pub mod hls;
 pub mod recorder;
 pub mod sdp;
 pub mod session;
@@ -11,9 +17,13 @@ pub mod strategy;
 pub mod stream;
 pub mod whep;
 pub mod whip;
+#[cfg(feature = "recorder")]
+pub mod ws;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Config,
     pub stream_manager: Arc<Manager>,
+    #[cfg(feature = "recorder")]
+    pub live_streams: crate::recorder::live::LiveStreams,
 }
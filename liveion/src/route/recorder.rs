@@ -36,6 +36,7 @@ async fn record_stream(
     .await?;
 
     let mpd_path = format!("{}/manifest.mpd", recording.record_dir);
+    let hls_path = format!("{}/master.m3u8", recording.record_dir);
     let record_id_str = if recording.record_id > 0 {
         recording.record_id.to_string()
     } else {
@@ -47,6 +48,7 @@ async fn record_stream(
         record_id: record_id_str,
         record_dir: recording.record_dir,
         mpd_path,
+        hls_path,
     };
     match serde_json::to_string(&resp) {
         Ok(json_body) => Ok(Response::builder().status(StatusCode::OK).body(json_body)?),
@@ -5,6 +5,7 @@ use axum::response::Response;
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use http::{HeaderMap, StatusCode, Uri, header};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
 use crate::error::AppError;
 use crate::route::AppState;
@@ -22,6 +23,10 @@ pub fn route() -> Router<AppState> {
             &api::path::session_layer("{stream}", "{session}"),
             get(get_layer).post(select_layer).delete(un_select_layer),
         )
+        .route(
+            &api::path::session_publishers("{stream}", "{session}"),
+            post(select_publishers),
+        )
 }
 async fn change_resource(
     State(state): State<AppState>,
@@ -44,9 +49,49 @@ async fn add_ice_candidate(
     let content_type = header
         .get(header::CONTENT_TYPE)
         .ok_or(AppError::from(anyhow::anyhow!("Content-Type is required")))?;
+    // A full offer renegotiates a room subscriber
+    if content_type.to_str()? == "application/sdp" {
+        let offer = RTCSessionDescription::offer(body)?;
+        let answer = state
+            .stream_manager
+            .renegotiate(stream, session, offer)
+            .await?;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/sdp")
+            .body(answer.sdp)?);
+    }
     if content_type.to_str()? != "application/trickle-ice-sdpfrag" {
         return Err(anyhow::anyhow!("Content-Type must be application/trickle-ice-sdpfrag").into());
     }
+    // `If-Match: *` asks for an ICE restart, otherwise it must match the current ICE session
+    let if_match = header
+        .get(header::IF_MATCH)
+        .map(|value| value.to_str())
+        .transpose()?;
+    if if_match == Some("*") {
+        let fragment = state
+            .stream_manager
+            .ice_restart(stream.clone(), session.clone(), body)
+            .await?;
+        let etag = state.stream_manager.ice_etag(stream, session).await?;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/trickle-ice-sdpfrag")
+            .header(header::ETAG, etag)
+            .body(fragment)?);
+    }
+    if let Some(if_match) = if_match {
+        let etag = state
+            .stream_manager
+            .ice_etag(stream.clone(), session.clone())
+            .await?;
+        if if_match != etag {
+            return Err(AppError::precondition_failed(
+                "If-Match does not match the ICE session",
+            ));
+        }
+    }
     state
         .stream_manager
         .add_ice_candidate(stream, session, body)
@@ -102,6 +147,18 @@ async fn select_layer(
     Ok("".to_string())
 }
 
+async fn select_publishers(
+    State(state): State<AppState>,
+    Path((stream, session)): Path<(String, String)>,
+    Json(req): Json<api::request::SelectPublishers>,
+) -> crate::result::Result<String> {
+    state
+        .stream_manager
+        .select_publishers(stream, session, req.publishers)
+        .await?;
+    Ok("".to_string())
+}
+
 async fn un_select_layer(
     State(state): State<AppState>,
     Path((stream, session)): Path<(String, String)>,
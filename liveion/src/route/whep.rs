@@ -1,5 +1,7 @@
 use axum::Router;
-use axum::extract::{Path, State};
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Path, State};
 use axum::response::Response;
 use axum::routing::post;
 use http::{HeaderMap, StatusCode, header};
@@ -10,12 +12,14 @@ use iceserver::link_header;
 
 use crate::AppState;
 use crate::route::sdp::maybe_filter_codecs;
+use crate::stream::callout::ClientInfo;
 
 pub fn route() -> Router<AppState> {
     Router::new().route(&api::path::whep("{stream}"), post(whep))
 }
 async fn whep(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Path(stream): Path<String>,
     header: HeaderMap,
     body: String,
@@ -29,9 +33,10 @@ async fn whep(
     let filtered_sdp = maybe_filter_codecs(&body, &state.config.sdp.disable_codecs)?;
     let offer = RTCSessionDescription::offer(filtered_sdp)?;
     debug!("offer: {}", offer.sdp);
+    let client = ClientInfo::new(addr, &header);
     let (answer, session) = state
         .stream_manager
-        .subscribe(stream.clone(), offer)
+        .subscribe(stream.clone(), offer, client)
         .await?;
     debug!("answer: {}", answer.sdp);
     let mut builder = Response::builder()
@@ -39,6 +44,9 @@ async fn whep(
         .header(header::CONTENT_TYPE, "application/sdp")
         .header("Accept-Patch", "application/trickle-ice-sdpfrag")
         .header(header::LOCATION, api::path::session(&stream, &session));
+    if let Some(etag) = libwish::sdpfrag::etag(&answer.sdp) {
+        builder = builder.header(header::ETAG, etag);
+    }
     for link in link_header(state.config.ice_servers.clone()) {
         builder = builder.header(header::LINK, link);
     }
@@ -1,5 +1,7 @@
 use axum::Router;
-use axum::extract::{Path, State};
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Path, Query, State};
 use axum::response::Response;
 use axum::routing::post;
 use http::{HeaderMap, StatusCode, header};
@@ -10,6 +12,7 @@ use iceserver::link_header;
 
 use crate::AppState;
 use crate::route::sdp::maybe_filter_codecs;
+use crate::stream::callout::ClientInfo;
 
 pub fn route() -> Router<AppState> {
     Router::new().route(&api::path::whip("{stream}"), post(whip))
@@ -17,7 +20,9 @@ pub fn route() -> Router<AppState> {
 
 async fn whip(
     State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Path(stream): Path<String>,
+    Query(query): Query<api::request::WhipQuery>,
     header: HeaderMap,
     body: String,
 ) -> crate::result::Result<Response<String>> {
@@ -30,13 +35,27 @@ async fn whip(
     let filtered_sdp = maybe_filter_codecs(&body, &state.config.sdp.disable_codecs)?;
     let offer = RTCSessionDescription::offer(filtered_sdp)?;
     debug!("offer: {}", offer.sdp);
-    let (answer, session) = state.stream_manager.publish(stream.clone(), offer).await?;
+    let client = ClientInfo::new(addr, &header);
+    let (answer, session) = if query.standby {
+        state
+            .stream_manager
+            .publish_standby(stream.clone(), offer, client)
+            .await?
+    } else {
+        state
+            .stream_manager
+            .publish(stream.clone(), offer, client)
+            .await?
+    };
     debug!("answer: {}", answer.sdp);
     let mut builder = Response::builder()
         .status(StatusCode::CREATED)
         .header(header::CONTENT_TYPE, "application/sdp")
         .header("Accept-Patch", "application/trickle-ice-sdpfrag")
         .header(header::LOCATION, api::path::session(&stream, &session));
+    if let Some(etag) = libwish::sdpfrag::etag(&answer.sdp) {
+        builder = builder.header(header::ETAG, etag);
+    }
     for link in link_header(state.config.ice_servers.clone()) {
         builder = builder.header(header::LINK, link);
     }
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::Router;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::response::Response;
+use axum::routing::get;
+use http::HeaderMap;
+
+use super::cmaf::authorize;
+use crate::AppState;
+use crate::error::AppError;
+use crate::output::ws;
+use crate::result::Result;
+use crate::stream::callout::ClientInfo;
+
+pub fn route() -> Router<AppState> {
+    Router::new().route(&api::path::ws("{stream}"), get(websocket))
+}
+
+async fn websocket(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(stream): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response> {
+    if !state.config.cmaf.ws {
+        return Err(AppError::stream_not_found("output is disabled"));
+    }
+    authorize(&state, &stream, &query, &headers)?;
+    let tracks = ws::muxers(state.stream_manager.output_tracks(&stream).await?);
+    if tracks.is_empty() {
+        return Err(AppError::stream_not_found("stream has no supported track"));
+    }
+
+    let session = uuid::Uuid::new_v4().simple().to_string();
+    let mut client = ClientInfo::new(addr, &headers);
+    client.token = client.token.or_else(|| query.get("token").cloned());
+    let output = state
+        .stream_manager
+        .admit_output(&stream, session, client)
+        .await?;
+    Ok(upgrade.on_upgrade(move |socket| ws::serve(socket, stream, output, tracks)))
+}
@@ -0,0 +1,198 @@
+//! The payload encryption of SRT: AES-CTR with a stream encrypting key, sent in the handshake
+//! wrapped by a key derived from the passphrase
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use aes::{Aes128, Aes192, Aes256};
+use aes_kw::{KekAes128, KekAes192, KekAes256};
+use anyhow::{Result, anyhow};
+use bytes::{BufMut, Bytes, BytesMut};
+use sha1::Sha1;
+
+const SALT_SIZE: usize = 16;
+/// The PBKDF2 salt is the end of the salt of the key material
+const PBKDF2_SALT_SIZE: usize = 8;
+const PBKDF2_ITERATIONS: u32 = 2048;
+/// The integrity check value of the AES key wrap
+const WRAP_OVERHEAD: usize = 8;
+
+/// Version 1, packet type 2 and the `HAI` sign
+const KM_HEADER: u32 = 0x12202900;
+const CIPHER_AES_CTR: u8 = 2;
+/// The SRT stream encapsulation
+const SE_SRT: u8 = 2;
+
+/// The even key of the key material, the one used to send as it is never refreshed
+pub const KEY_EVEN: u8 = 1;
+const KEY_BOTH: u8 = 3;
+
+/// The `encryption` of a handshake for a key length, 2, 3 and 4 for 16, 24 and 32 bytes
+pub fn key_length_code(key_length: usize) -> u16 {
+    (key_length / 8) as u16
+}
+
+/// The even and the odd key, a peer refreshing its key sends the next one with the current
+#[derive(Clone)]
+pub struct Cipher {
+    salt: [u8; SALT_SIZE],
+    key_length: usize,
+    keys: [Option<Vec<u8>>; 2],
+}
+
+impl Cipher {
+    /// A random key of 16, 24 or 32 bytes
+    pub fn generate(key_length: usize) -> Result<Self> {
+        if !matches!(key_length, 16 | 24 | 32) {
+            return Err(anyhow!("invalid srt key length {key_length}"));
+        }
+        let mut key = vec![0; key_length];
+        rand::fill(&mut key[..]);
+        Ok(Self {
+            salt: rand::random(),
+            key_length,
+            keys: [Some(key), None],
+        })
+    }
+
+    /// The KMREQ or KMRSP content, with the key wrapped for the passphrase
+    pub fn key_material(&self, passphrase: &str) -> Result<Bytes> {
+        let mut flags = 0;
+        let mut keys = Vec::with_capacity(self.key_length * 2);
+        for (i, key) in self.keys.iter().enumerate() {
+            if let Some(key) = key {
+                flags |= 1 << i;
+                keys.extend_from_slice(key);
+            }
+        }
+        let kek = kek(passphrase, &self.salt, self.key_length);
+        let wrapped = match self.key_length {
+            16 => KekAes128::try_from(&kek[..]).map(|kek| kek.wrap_vec(&keys)),
+            24 => KekAes192::try_from(&kek[..]).map(|kek| kek.wrap_vec(&keys)),
+            _ => KekAes256::try_from(&kek[..]).map(|kek| kek.wrap_vec(&keys)),
+        }
+        .map_err(|_| anyhow!("invalid srt key length"))?
+        .map_err(|_| anyhow!("srt key wrap error"))?;
+
+        let mut out = BytesMut::with_capacity(32 + wrapped.len());
+        out.put_u32(KM_HEADER | flags);
+        // KEKI, the key of the passphrase
+        out.put_u32(0);
+        out.put_slice(&[CIPHER_AES_CTR, 0, SE_SRT, 0]);
+        out.put_u16(0);
+        out.put_u8((SALT_SIZE / 4) as u8);
+        out.put_u8((self.key_length / 4) as u8);
+        out.put_slice(&self.salt);
+        out.put_slice(&wrapped);
+        Ok(out.freeze())
+    }
+
+    /// The keys of a KMREQ, an error when the passphrase doesn't unwrap them
+    pub fn from_key_material(content: &[u8], passphrase: &str) -> Result<Self> {
+        let truncated = || anyhow!("srt key material is truncated");
+        let header = content.get(..16).ok_or_else(truncated)?;
+        if u32::from_be_bytes([header[0], header[1], header[2], header[3]]) & !0x03 != KM_HEADER {
+            return Err(anyhow!("invalid srt key material"));
+        }
+        let flags = header[3] & KEY_BOTH;
+        if flags == 0 {
+            return Err(anyhow!("srt key material without a key"));
+        }
+        if header[8] != CIPHER_AES_CTR {
+            return Err(anyhow!("unsupported srt cipher {}", header[8]));
+        }
+        let salt_length = header[14] as usize * 4;
+        let key_length = header[15] as usize * 4;
+        if salt_length != SALT_SIZE || !matches!(key_length, 16 | 24 | 32) {
+            return Err(anyhow!("invalid srt key material"));
+        }
+        let salt: [u8; SALT_SIZE] = content.get(16..32).ok_or_else(truncated)?.try_into()?;
+        let count = flags.count_ones() as usize;
+        let wrapped = content
+            .get(32..32 + key_length * count + WRAP_OVERHEAD)
+            .ok_or_else(truncated)?;
+
+        let kek = kek(passphrase, &salt, key_length);
+        let unwrapped = match key_length {
+            16 => KekAes128::try_from(&kek[..]).map(|kek| kek.unwrap_vec(wrapped)),
+            24 => KekAes192::try_from(&kek[..]).map(|kek| kek.unwrap_vec(wrapped)),
+            _ => KekAes256::try_from(&kek[..]).map(|kek| kek.unwrap_vec(wrapped)),
+        }
+        .map_err(|_| anyhow!("invalid srt key length"))?
+        .map_err(|_| anyhow!("srt passphrase mismatch"))?;
+        let mut keys = unwrapped.chunks_exact(key_length).map(|key| key.to_vec());
+        let mut key = |bit: u8| if flags & bit != 0 { keys.next() } else { None };
+        Ok(Self {
+            salt,
+            key_length,
+            keys: [key(1), key(2)],
+        })
+    }
+
+    pub fn key_length(&self) -> usize {
+        self.key_length
+    }
+
+    /// Encrypts or decrypts the payload of the packet `seq` with the even or the odd `key`,
+    /// false without that key
+    pub fn apply(&self, key: u8, seq: u32, payload: &mut [u8]) -> bool {
+        let Some(key) = key
+            .checked_sub(1)
+            .and_then(|index| self.keys.get(index as usize))
+            .and_then(|key| key.as_deref())
+        else {
+            return false;
+        };
+        let mut iv = [0u8; 16];
+        iv[10..14].copy_from_slice(&seq.to_be_bytes());
+        for (byte, salt) in iv.iter_mut().zip(self.salt.iter()).take(14) {
+            *byte ^= salt;
+        }
+        match key.len() {
+            16 => ctr::Ctr128BE::<Aes128>::new_from_slices(key, &iv)
+                .map(|mut cipher| cipher.apply_keystream(payload)),
+            24 => ctr::Ctr128BE::<Aes192>::new_from_slices(key, &iv)
+                .map(|mut cipher| cipher.apply_keystream(payload)),
+            _ => ctr::Ctr128BE::<Aes256>::new_from_slices(key, &iv)
+                .map(|mut cipher| cipher.apply_keystream(payload)),
+        }
+        .is_ok()
+    }
+}
+
+/// The key encrypting key of a passphrase
+fn kek(passphrase: &str, salt: &[u8; SALT_SIZE], key_length: usize) -> Vec<u8> {
+    let mut kek = vec![0; key_length];
+    pbkdf2::pbkdf2_hmac::<Sha1>(
+        passphrase.as_bytes(),
+        &salt[SALT_SIZE - PBKDF2_SALT_SIZE..],
+        PBKDF2_ITERATIONS,
+        &mut kek,
+    );
+    kek
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cipher, KEY_EVEN};
+
+    #[test]
+    fn test_key_material() {
+        for key_length in [16, 24, 32] {
+            let cipher = Cipher::generate(key_length).unwrap();
+            let content = cipher.key_material("passphrase").unwrap();
+            assert_eq!(content.len(), 32 + key_length + 8);
+
+            let unwrapped = Cipher::from_key_material(&content, "passphrase").unwrap();
+            assert_eq!(unwrapped.key_length(), key_length);
+            assert!(Cipher::from_key_material(&content, "wrong passphrase").is_err());
+
+            let mut payload = b"mpeg-ts payload".to_vec();
+            assert!(cipher.apply(KEY_EVEN, 7, &mut payload));
+            assert_ne!(&payload[..], b"mpeg-ts payload");
+            assert!(unwrapped.apply(KEY_EVEN, 7, &mut payload));
+            assert_eq!(&payload[..], b"mpeg-ts payload");
+            // No odd key
+            assert!(!unwrapped.apply(2, 7, &mut payload));
+        }
+        assert!(Cipher::generate(20).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! The HSv5 handshake: an induction to get the cookie of the listener, then a conclusion
+//! with the SRT extensions, the latency, the key material and the stream id
+
+use anyhow::{Result, anyhow};
+use bytes::{BufMut, Bytes, BytesMut};
+
+pub const TYPE_DONE: u32 = 0xfffffffd;
+pub const TYPE_CONCLUSION: u32 = 0xffffffff;
+pub const TYPE_INDUCTION: u32 = 1;
+/// Rejections are this plus the reason
+pub const TYPE_REJECT: u32 = 1000;
+
+pub const REJECT_BACKLOG: u32 = 5;
+pub const REJECT_BADSECRET: u32 = 10;
+pub const REJECT_UNSECURE: u32 = 11;
+
+/// The extension field of the induction response
+pub const SRT_MAGIC: u16 = 0x4a17;
+/// The extension field of an HSv4 induction, UDT_DGRAM
+pub const UDT_DGRAM: u16 = 2;
+
+pub const EXT_FLAG_HSREQ: u16 = 0x1;
+pub const EXT_FLAG_KMREQ: u16 = 0x2;
+pub const EXT_FLAG_CONFIG: u16 = 0x4;
+
+pub const EXT_HSREQ: u16 = 1;
+pub const EXT_HSRSP: u16 = 2;
+pub const EXT_KMREQ: u16 = 3;
+pub const EXT_KMRSP: u16 = 4;
+pub const EXT_SID: u16 = 5;
+
+/// 1.5.0
+pub const SRT_VERSION: u32 = 0x010500;
+/// TSBPD on both sides, encryption, too late packet drop, periodic NAK and the
+/// retransmission flag
+pub const SRT_FLAGS: u32 = 0x01 | 0x02 | 0x04 | 0x08 | 0x10 | 0x20;
+
+pub const MTU: u32 = 1500;
+pub const FLOW_WINDOW: u32 = 8192;
+
+const SIZE: usize = 48;
+/// The stream id is at most 512 bytes
+const MAX_STREAM_ID: usize = 512;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Handshake {
+    pub version: u32,
+    /// The key size announced by the caller, 2, 3 or 4 for 16, 24 and 32 bytes
+    pub encryption: u16,
+    pub extension: u16,
+    pub initial_seq: u32,
+    pub mtu: u32,
+    pub flow_window: u32,
+    pub kind: u32,
+    pub socket_id: u32,
+    pub cookie: u32,
+    pub peer_ip: [u8; 16],
+    pub extensions: Vec<(u16, Bytes)>,
+}
+
+impl Handshake {
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        if body.len() < SIZE {
+            return Err(anyhow!("srt handshake is truncated"));
+        }
+        let word = |i: usize| u32::from_be_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]]);
+        let mut handshake = Self {
+            version: word(0),
+            encryption: u16::from_be_bytes([body[4], body[5]]),
+            extension: u16::from_be_bytes([body[6], body[7]]),
+            initial_seq: word(8),
+            mtu: word(12),
+            flow_window: word(16),
+            kind: word(20),
+            socket_id: word(24),
+            cookie: word(28),
+            peer_ip: body[32..48].try_into()?,
+            extensions: vec![],
+        };
+        let mut rest = &body[SIZE..];
+        while rest.len() >= 4 {
+            let kind = u16::from_be_bytes([rest[0], rest[1]]);
+            let length = u16::from_be_bytes([rest[2], rest[3]]) as usize * 4;
+            let content = rest
+                .get(4..4 + length)
+                .ok_or_else(|| anyhow!("srt handshake extension is truncated"))?;
+            handshake
+                .extensions
+                .push((kind, Bytes::copy_from_slice(content)));
+            rest = &rest[4 + length..];
+        }
+        Ok(handshake)
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = BytesMut::with_capacity(SIZE + 64);
+        out.put_u32(self.version);
+        out.put_u16(self.encryption);
+        out.put_u16(self.extension);
+        out.put_u32(self.initial_seq);
+        out.put_u32(self.mtu);
+        out.put_u32(self.flow_window);
+        out.put_u32(self.kind);
+        out.put_u32(self.socket_id);
+        out.put_u32(self.cookie);
+        out.put_slice(&self.peer_ip);
+        for (kind, content) in self.extensions.iter() {
+            out.put_u16(*kind);
+            out.put_u16((content.len() / 4) as u16);
+            out.put_slice(content);
+        }
+        out.freeze()
+    }
+
+    pub fn extension(&self, kind: u16) -> Option<&Bytes> {
+        self.extensions
+            .iter()
+            .find(|(extension, _)| *extension == kind)
+            .map(|(_, content)| content)
+    }
+
+    /// The reason of a rejection
+    pub fn rejection(&self) -> Option<u32> {
+        (TYPE_REJECT..TYPE_DONE)
+            .contains(&self.kind)
+            .then_some(self.kind.wrapping_sub(TYPE_REJECT))
+    }
+}
+
+/// The HSREQ and HSRSP content, with the receiver and the sender latency in milliseconds
+pub fn srt_extension(latency: u16) -> Bytes {
+    let mut out = BytesMut::with_capacity(12);
+    out.put_u32(SRT_VERSION);
+    out.put_u32(SRT_FLAGS);
+    out.put_u16(latency);
+    out.put_u16(latency);
+    out.freeze()
+}
+
+/// The receiver and the sender latency of the peer
+pub fn parse_srt_extension(content: &[u8]) -> Option<(u16, u16)> {
+    let delays = content.get(8..12)?;
+    Some((
+        u16::from_be_bytes([delays[0], delays[1]]),
+        u16::from_be_bytes([delays[2], delays[3]]),
+    ))
+}
+
+/// The stream id in 32 bit words of little endian order, padded with zeros
+pub fn stream_id_extension(stream_id: &str) -> Bytes {
+    let mut bytes = stream_id.as_bytes()[..stream_id.len().min(MAX_STREAM_ID)].to_vec();
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    for word in bytes.chunks_exact_mut(4) {
+        word.reverse();
+    }
+    Bytes::from(bytes)
+}
+
+pub fn parse_stream_id(content: &[u8]) -> String {
+    let mut bytes = content.to_vec();
+    for word in bytes.chunks_exact_mut(4) {
+        word.reverse();
+    }
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        EXT_HSREQ, EXT_SID, Handshake, TYPE_CONCLUSION, TYPE_REJECT, parse_srt_extension,
+        parse_stream_id, srt_extension, stream_id_extension,
+    };
+
+    #[test]
+    fn test_handshake() {
+        let handshake = Handshake {
+            version: 5,
+            encryption: 2,
+            extension: 0x5,
+            initial_seq: 1234,
+            mtu: 1500,
+            flow_window: 8192,
+            kind: TYPE_CONCLUSION,
+            socket_id: 99,
+            cookie: 0xdeadbeef,
+            peer_ip: [127, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            extensions: vec![
+                (EXT_HSREQ, srt_extension(120)),
+                (EXT_SID, stream_id_extension("#!::r=live777,m=publish")),
+            ],
+        };
+        let parsed = Handshake::parse(&handshake.to_bytes()).unwrap();
+        assert_eq!(parsed, handshake);
+        assert_eq!(
+            parse_srt_extension(parsed.extension(EXT_HSREQ).unwrap()),
+            Some((120, 120))
+        );
+        assert_eq!(
+            parse_stream_id(parsed.extension(EXT_SID).unwrap()),
+            "#!::r=live777,m=publish"
+        );
+        assert_eq!(parsed.rejection(), None);
+
+        // Each word of the stream id is reversed
+        assert_eq!(&stream_id_extension("abcde")[..], b"dcba\0\0\0e");
+
+        let rejected = Handshake {
+            kind: TYPE_REJECT + 10,
+            ..Default::default()
+        };
+        assert_eq!(rejected.rejection(), Some(10));
+    }
+}
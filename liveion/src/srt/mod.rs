@@ -0,0 +1,201 @@
+//! SRT in live mode carrying MPEG-TS, publishing to the streams and playing them.
+//!
+//! The stream id of a caller picks the stream, in the access control syntax
+//! `#!::r={stream},m=publish,s={token}` or as a plain stream name to play.
+//! Callers to other listeners are configured, they reconnect until the server stops.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use auth::AuthState;
+use tracing::{debug, info, warn};
+
+use crate::config::{self, SrtCaller, SrtMode};
+use crate::error::AppError;
+use crate::input;
+use crate::output;
+use crate::result::Result;
+use crate::stream::callout::ClientInfo;
+use crate::stream::manager::Manager;
+
+mod crypto;
+mod handshake;
+mod packet;
+mod socket;
+
+pub use socket::{MAX_PAYLOAD_SIZE, SrtListener, SrtOptions, SrtSocket};
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The options of the listener, and the defaults of the callers
+pub fn options(cfg: &config::Srt) -> SrtOptions {
+    SrtOptions {
+        passphrase: (!cfg.passphrase.is_empty()).then(|| cfg.passphrase.clone()),
+        key_length: cfg.pbkeylen,
+        latency: Duration::from_millis(cfg.latency),
+        stream_id: None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct StreamId {
+    stream: String,
+    publish: bool,
+    token: Option<String>,
+}
+
+impl StreamId {
+    fn parse(stream_id: &str) -> Option<Self> {
+        let Some(pairs) = stream_id.strip_prefix("#!::") else {
+            return (!stream_id.is_empty()).then(|| Self {
+                stream: stream_id.to_string(),
+                publish: false,
+                token: None,
+            });
+        };
+        let mut id = Self {
+            stream: String::new(),
+            publish: false,
+            token: None,
+        };
+        for pair in pairs.split(',') {
+            match pair.split_once('=') {
+                Some(("r", stream)) => id.stream = stream.to_string(),
+                Some(("m", mode)) => id.publish = mode == "publish",
+                Some(("s", token)) => id.token = Some(token.to_string()),
+                _ => {}
+            }
+        }
+        (!id.stream.is_empty()).then_some(id)
+    }
+}
+
+/// Accepts the callers publishing or playing the streams
+pub async fn serve(mut listener: SrtListener, manager: Arc<Manager>, auth: AuthState) {
+    while let Some(socket) = listener.accept().await {
+        let manager = manager.clone();
+        let auth = auth.clone();
+        tokio::spawn(async move {
+            let peer = socket.peer();
+            if let Err(err) = accept(socket, manager, auth).await {
+                debug!(%peer, ?err, "srt connection error");
+            }
+        });
+    }
+}
+
+async fn accept(socket: SrtSocket, manager: Arc<Manager>, auth: AuthState) -> Result<()> {
+    let id = StreamId::parse(socket.stream_id())
+        .ok_or_else(|| AppError::stream_not_found("srt stream id without a stream"))?;
+    let allowed = auth.claims(id.token.as_deref()).is_some_and(|claims| {
+        if id.publish {
+            claims.can_publish(&id.stream)
+        } else {
+            claims.can_subscribe(&id.stream)
+        }
+    });
+    if !allowed {
+        return Err(AppError::forbidden("srt stream id without permission"));
+    }
+    let client = ClientInfo {
+        ip: Some(socket.peer().ip().to_string()),
+        token: id.token,
+        user_agent: None,
+    };
+    if id.publish {
+        input::srt::publish(socket, manager, id.stream, client).await
+    } else {
+        play(socket, manager, id.stream, client).await
+    }
+}
+
+async fn play(
+    socket: SrtSocket,
+    manager: Arc<Manager>,
+    stream: String,
+    client: ClientInfo,
+) -> Result<()> {
+    let tracks = output::mpegts::frames(manager.output_tracks(&stream).await?);
+    if tracks.is_empty() {
+        return Err(AppError::stream_not_found("stream has no supported track"));
+    }
+    let session = uuid::Uuid::new_v4().simple().to_string();
+    let session = manager.admit_output(&stream, session, client).await?;
+    output::srt::play(socket, stream, session, tracks).await;
+    Ok(())
+}
+
+/// Connects to a listener again and again, to pull or push `caller.stream`
+pub async fn call(caller: SrtCaller, cfg: config::Srt, manager: Arc<Manager>) {
+    let options = SrtOptions {
+        passphrase: (!caller.passphrase.is_empty()).then(|| caller.passphrase.clone()),
+        stream_id: caller.streamid.clone(),
+        ..options(&cfg)
+    };
+    loop {
+        if let Err(err) = connect(&caller, options.clone(), manager.clone()).await {
+            warn!(
+                addr = caller.addr,
+                stream = caller.stream,
+                ?err,
+                "srt caller error"
+            );
+        }
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+async fn connect(caller: &SrtCaller, options: SrtOptions, manager: Arc<Manager>) -> Result<()> {
+    let addr = tokio::net::lookup_host(&caller.addr)
+        .await?
+        .next()
+        .ok_or_else(|| AppError::throw(format!("srt caller can't resolve {}", caller.addr)))?;
+    let socket = SrtSocket::connect(addr, options).await?;
+    info!(%addr, stream = caller.stream, mode = ?caller.mode, "srt caller connected");
+    let client = ClientInfo {
+        ip: Some(addr.ip().to_string()),
+        token: None,
+        user_agent: None,
+    };
+    match caller.mode {
+        SrtMode::Request => {
+            input::srt::publish(socket, manager, caller.stream.clone(), client).await
+        }
+        SrtMode::Publish => play(socket, manager, caller.stream.clone(), client).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StreamId;
+
+    #[test]
+    fn test_stream_id() {
+        assert_eq!(
+            StreamId::parse("#!::r=live777,m=publish,s=token"),
+            Some(StreamId {
+                stream: "live777".to_string(),
+                publish: true,
+                token: Some("token".to_string()),
+            })
+        );
+        assert_eq!(
+            StreamId::parse("#!::m=request,r=live777"),
+            Some(StreamId {
+                stream: "live777".to_string(),
+                publish: false,
+                token: None,
+            })
+        );
+        assert_eq!(
+            StreamId::parse("live777"),
+            Some(StreamId {
+                stream: "live777".to_string(),
+                publish: false,
+                token: None,
+            })
+        );
+        assert_eq!(StreamId::parse("#!::m=publish"), None);
+        assert_eq!(StreamId::parse(""), None);
+    }
+}
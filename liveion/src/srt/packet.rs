@@ -0,0 +1,231 @@
+//! The SRT packets, a 16 byte header and a data payload or a control body
+
+use anyhow::{Result, anyhow};
+use bytes::{BufMut, Bytes, BytesMut};
+
+pub const HEADER_SIZE: usize = 16;
+/// Sequence numbers have 31 bits
+pub const SEQUENCE_MASK: u32 = 0x7fffffff;
+const MESSAGE_NUMBER_MASK: u32 = 0x03ffffff;
+
+pub const CONTROL_HANDSHAKE: u16 = 0;
+pub const CONTROL_KEEPALIVE: u16 = 1;
+pub const CONTROL_ACK: u16 = 2;
+pub const CONTROL_NAK: u16 = 3;
+pub const CONTROL_SHUTDOWN: u16 = 5;
+pub const CONTROL_ACKACK: u16 = 6;
+/// The SRT extensions, e.g. the key material of a key refresh
+pub const CONTROL_USER: u16 = 0x7fff;
+
+/// `a + n` in the sequence number space
+pub fn seq_add(seq: u32, n: u32) -> u32 {
+    seq.wrapping_add(n) & SEQUENCE_MASK
+}
+
+/// `a - b` in the sequence number space, negative when `a` comes first
+pub fn seq_diff(a: u32, b: u32) -> i32 {
+    ((a.wrapping_sub(b) << 1) as i32) >> 1
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(DataPacket),
+    Control(ControlPacket),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataPacket {
+    pub seq: u32,
+    /// The encryption key of the payload, 0 when clear, 1 for the even key and 2 for the odd
+    pub key: u8,
+    pub retransmitted: bool,
+    pub message: u32,
+    /// Microseconds since the start of the connection
+    pub timestamp: u32,
+    pub dest: u32,
+    pub payload: Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlPacket {
+    pub kind: u16,
+    pub subtype: u16,
+    /// The type specific information, e.g. the ACK number
+    pub info: u32,
+    pub timestamp: u32,
+    pub dest: u32,
+    pub body: Bytes,
+}
+
+impl Packet {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE {
+            return Err(anyhow!("srt packet is truncated"));
+        }
+        let word = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let (first, second, timestamp, dest) = (word(0), word(4), word(8), word(12));
+        let body = Bytes::copy_from_slice(&data[HEADER_SIZE..]);
+        if first & 0x80000000 == 0 {
+            Ok(Packet::Data(DataPacket {
+                seq: first,
+                key: ((second >> 27) & 0x03) as u8,
+                retransmitted: second & 0x04000000 != 0,
+                message: second & MESSAGE_NUMBER_MASK,
+                timestamp,
+                dest,
+                payload: body,
+            }))
+        } else {
+            Ok(Packet::Control(ControlPacket {
+                kind: ((first >> 16) & 0x7fff) as u16,
+                subtype: first as u16,
+                info: second,
+                timestamp,
+                dest,
+                body,
+            }))
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        match self {
+            Packet::Data(packet) => {
+                let mut out = BytesMut::with_capacity(HEADER_SIZE + packet.payload.len());
+                out.put_u32(packet.seq & SEQUENCE_MASK);
+                // A whole message in a packet, out of order delivery
+                let mut second = 0xc0000000 | (packet.message & MESSAGE_NUMBER_MASK);
+                second |= ((packet.key & 0x03) as u32) << 27;
+                if packet.retransmitted {
+                    second |= 0x04000000;
+                }
+                out.put_u32(second);
+                out.put_u32(packet.timestamp);
+                out.put_u32(packet.dest);
+                out.put_slice(&packet.payload);
+                out.freeze()
+            }
+            Packet::Control(packet) => {
+                let mut out = BytesMut::with_capacity(HEADER_SIZE + packet.body.len());
+                out.put_u32(0x80000000 | ((packet.kind as u32) << 16) | packet.subtype as u32);
+                out.put_u32(packet.info);
+                out.put_u32(packet.timestamp);
+                out.put_u32(packet.dest);
+                out.put_slice(&packet.body);
+                out.freeze()
+            }
+        }
+    }
+}
+
+impl ControlPacket {
+    pub fn new(kind: u16, info: u32, body: Bytes) -> Self {
+        Self {
+            kind,
+            subtype: 0,
+            info,
+            timestamp: 0,
+            dest: 0,
+            body,
+        }
+    }
+}
+
+/// The body of a full ACK, with RTT and rates in the units of the spec
+pub fn ack_body(next_seq: u32, rtt: u32, rtt_var: u32, available: u32, rate: u32) -> Bytes {
+    let mut out = BytesMut::with_capacity(28);
+    out.put_u32(next_seq);
+    out.put_u32(rtt);
+    out.put_u32(rtt_var);
+    out.put_u32(available);
+    // Packets per second, link capacity and bytes per second
+    out.put_u32(rate);
+    out.put_u32(rate);
+    out.put_u32(rate.saturating_mul(1316));
+    out.freeze()
+}
+
+/// The sequence number acknowledged, every packet before it was received
+pub fn ack_seq(body: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(body.get(..4)?.try_into().ok()?) & SEQUENCE_MASK)
+}
+
+/// The loss list of a NAK, ranges have the high bit on their first number
+pub fn nak_body(ranges: &[(u32, u32)]) -> Bytes {
+    let mut out = BytesMut::with_capacity(ranges.len() * 8);
+    for (first, last) in ranges {
+        if first == last {
+            out.put_u32(*first);
+        } else {
+            out.put_u32(first | 0x80000000);
+            out.put_u32(*last);
+        }
+    }
+    out.freeze()
+}
+
+pub fn nak_ranges(body: &[u8]) -> Vec<(u32, u32)> {
+    let mut ranges = vec![];
+    let mut words = body
+        .chunks_exact(4)
+        .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]));
+    while let Some(word) = words.next() {
+        if word & 0x80000000 != 0 {
+            let Some(last) = words.next() else {
+                break;
+            };
+            ranges.push((word & SEQUENCE_MASK, last & SEQUENCE_MASK));
+        } else {
+            ranges.push((word, word));
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::{
+        CONTROL_NAK, ControlPacket, DataPacket, Packet, nak_body, nak_ranges, seq_add, seq_diff,
+    };
+
+    #[test]
+    fn test_packet() {
+        let data = Packet::Data(DataPacket {
+            seq: 0x7ffffffe,
+            key: 1,
+            retransmitted: true,
+            message: 42,
+            timestamp: 1_000_000,
+            dest: 0x12345678,
+            payload: Bytes::from_static(b"payload"),
+        });
+        let bytes = data.to_bytes();
+        assert_eq!(&bytes[4..8], &[0xcc, 0, 0, 42]);
+        assert_eq!(Packet::parse(&bytes).unwrap(), data);
+
+        let nak = Packet::Control(ControlPacket {
+            kind: CONTROL_NAK,
+            subtype: 0,
+            info: 0,
+            timestamp: 5,
+            dest: 7,
+            body: nak_body(&[(3, 3), (10, 12)]),
+        });
+        let bytes = nak.to_bytes();
+        assert_eq!(&bytes[..4], &[0x80, 0x03, 0, 0]);
+        let Packet::Control(parsed) = Packet::parse(&bytes).unwrap() else {
+            panic!("not a control packet");
+        };
+        assert_eq!(nak_ranges(&parsed.body), vec![(3, 3), (10, 12)]);
+        assert!(Packet::parse(&bytes[..15]).is_err());
+    }
+
+    #[test]
+    fn test_sequence() {
+        assert_eq!(seq_add(0x7fffffff, 2), 1);
+        assert_eq!(seq_diff(1, 0x7fffffff), 2);
+        assert_eq!(seq_diff(0x7fffffff, 1), -2);
+        assert_eq!(seq_diff(5, 5), 0);
+    }
+}
@@ -0,0 +1,1091 @@
+//! SRT connections in live mode, caller and listener.
+//!
+//! The receiver delivers the packets in order, each at its sender timestamp plus the latency
+//! (TSBPD), and reports the gaps with NAKs. A gap is given up at the play time of the packet
+//! after it.
+//! The sender keeps the packets until they are acknowledged or too late to be useful.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, trace};
+
+use super::crypto::{Cipher, KEY_EVEN, key_length_code};
+use super::handshake::{
+    EXT_FLAG_CONFIG, EXT_FLAG_HSREQ, EXT_FLAG_KMREQ, EXT_HSREQ, EXT_HSRSP, EXT_KMREQ, EXT_KMRSP,
+    EXT_SID, FLOW_WINDOW, Handshake, MTU, REJECT_BACKLOG, REJECT_BADSECRET, REJECT_UNSECURE,
+    SRT_MAGIC, TYPE_CONCLUSION, TYPE_INDUCTION, TYPE_REJECT, UDT_DGRAM, parse_srt_extension,
+    parse_stream_id, srt_extension, stream_id_extension,
+};
+use super::packet::{
+    CONTROL_ACK, CONTROL_ACKACK, CONTROL_HANDSHAKE, CONTROL_KEEPALIVE, CONTROL_NAK,
+    CONTROL_SHUTDOWN, CONTROL_USER, ControlPacket, DataPacket, Packet, SEQUENCE_MASK, ack_body,
+    ack_seq, nak_body, nak_ranges, seq_add, seq_diff,
+};
+
+/// The payload of a packet, 7 TS packets
+pub const MAX_PAYLOAD_SIZE: usize = 1316;
+
+const DATAGRAM_SIZE: usize = 1500;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const HANDSHAKE_RETRY: Duration = Duration::from_millis(250);
+const TICK: Duration = Duration::from_millis(10);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// A peer silent for this long is gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_NAK_INTERVAL: Duration = Duration::from_millis(20);
+/// The sender keeps a packet for the latency and this long
+const SEND_DROP_DELAY: Duration = Duration::from_secs(1);
+/// Packets of the send and the receive buffer
+const BUFFER_SIZE: usize = FLOW_WINDOW as usize;
+/// Datagrams and messages waiting for a connection or its user
+const QUEUE_SIZE: usize = 1024;
+/// Connections waiting to be accepted, the callers beyond are rejected
+const BACKLOG: usize = 16;
+/// Loss ranges of a NAK
+const MAX_NAK_RANGES: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct SrtOptions {
+    /// The payloads are encrypted with a passphrase, of 10 to 79 characters
+    pub passphrase: Option<String>,
+    /// 16, 24 or 32 bytes, the caller picks the key
+    pub key_length: usize,
+    pub latency: Duration,
+    /// Sent by the caller
+    pub stream_id: Option<String>,
+}
+
+impl Default for SrtOptions {
+    fn default() -> Self {
+        Self {
+            passphrase: None,
+            key_length: 16,
+            latency: Duration::from_millis(120),
+            stream_id: None,
+        }
+    }
+}
+
+/// A connection, messages of at most [`MAX_PAYLOAD_SIZE`] bytes both ways
+pub struct SrtSocket {
+    peer: SocketAddr,
+    stream_id: String,
+    sender: mpsc::Sender<Bytes>,
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl SrtSocket {
+    /// Calls a listener
+    pub async fn connect(addr: SocketAddr, options: SrtOptions) -> Result<Self> {
+        let bind = match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let udp = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+        udp.connect(addr).await?;
+        let udp = Arc::new(udp);
+
+        let local_id = socket_id();
+        let initial_seq = rand::random::<u32>() & SEQUENCE_MASK;
+        let cipher = match options.passphrase {
+            Some(_) => Some(Cipher::generate(options.key_length)?),
+            None => None,
+        };
+        let latency = latency_ms(options.latency);
+        let mut request = Handshake {
+            version: 4,
+            encryption: 0,
+            extension: UDT_DGRAM,
+            initial_seq,
+            mtu: MTU,
+            flow_window: FLOW_WINDOW,
+            kind: TYPE_INDUCTION,
+            socket_id: local_id,
+            cookie: 0,
+            peer_ip: peer_ip(addr.ip()),
+            extensions: vec![],
+        };
+
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let mut buffer = vec![0u8; DATAGRAM_SIZE];
+        let response = loop {
+            let packet = ControlPacket::new(CONTROL_HANDSHAKE, 0, request.to_bytes());
+            udp.send(&Packet::Control(packet).to_bytes()).await?;
+
+            let received = loop {
+                let Some(wait) = deadline.checked_duration_since(Instant::now()) else {
+                    return Err(anyhow!("srt connect timeout"));
+                };
+                let n = match tokio::time::timeout(wait.min(HANDSHAKE_RETRY), udp.recv(&mut buffer))
+                    .await
+                {
+                    Ok(n) => n?,
+                    Err(_) => break None,
+                };
+                if let Ok(Packet::Control(packet)) = Packet::parse(&buffer[..n])
+                    && packet.kind == CONTROL_HANDSHAKE
+                    && packet.dest == local_id
+                    && let Ok(handshake) = Handshake::parse(&packet.body)
+                {
+                    break Some(handshake);
+                }
+            };
+            let Some(handshake) = received else {
+                continue;
+            };
+            if let Some(reason) = handshake.rejection() {
+                return Err(anyhow!("srt connection rejected, reason {reason}"));
+            }
+            match handshake.kind {
+                TYPE_INDUCTION if request.kind == TYPE_INDUCTION => {
+                    if handshake.version < 5 || handshake.extension != SRT_MAGIC {
+                        return Err(anyhow!("srt peer without HSv5"));
+                    }
+                    let mut extension = EXT_FLAG_HSREQ;
+                    let mut extensions = vec![(EXT_HSREQ, srt_extension(latency))];
+                    if let (Some(cipher), Some(passphrase)) = (&cipher, &options.passphrase) {
+                        extension |= EXT_FLAG_KMREQ;
+                        extensions.push((EXT_KMREQ, cipher.key_material(passphrase)?));
+                    }
+                    if let Some(stream_id) = &options.stream_id {
+                        extension |= EXT_FLAG_CONFIG;
+                        extensions.push((EXT_SID, stream_id_extension(stream_id)));
+                    }
+                    request = Handshake {
+                        version: 5,
+                        encryption: cipher
+                            .as_ref()
+                            .map(|cipher| key_length_code(cipher.key_length()))
+                            .unwrap_or_default(),
+                        extension,
+                        kind: TYPE_CONCLUSION,
+                        cookie: handshake.cookie,
+                        extensions,
+                        ..request
+                    };
+                }
+                TYPE_CONCLUSION if request.kind == TYPE_CONCLUSION => break handshake,
+                _ => {}
+            }
+        };
+
+        if cipher.is_some() {
+            match response.extension(EXT_KMRSP) {
+                Some(content) if content.len() > 4 => {}
+                _ => return Err(anyhow!("srt peer has no passphrase or a different one")),
+            }
+        }
+        let peer_latency = response
+            .extension(EXT_HSRSP)
+            .and_then(|content| parse_srt_extension(content))
+            .map(|(receiver, sender)| receiver.max(sender))
+            .unwrap_or_default();
+
+        let (incoming, datagrams) = mpsc::channel(QUEUE_SIZE);
+        let reader = tokio::spawn({
+            let udp = udp.clone();
+            async move {
+                let mut buffer = vec![0u8; DATAGRAM_SIZE];
+                while let Ok(n) = udp.recv(&mut buffer).await {
+                    if incoming
+                        .send(Bytes::copy_from_slice(&buffer[..n]))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+        let connection = Connection::new(
+            udp,
+            addr,
+            response.socket_id,
+            initial_seq,
+            options
+                .latency
+                .max(Duration::from_millis(peer_latency as u64)),
+            cipher,
+            options.passphrase,
+            None,
+        );
+        Ok(connection.spawn(
+            options.stream_id.unwrap_or_default(),
+            datagrams,
+            Some(reader),
+        ))
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Sent by the caller, empty without one
+    pub fn stream_id(&self) -> &str {
+        &self.stream_id
+    }
+
+    /// Queues a message, an error once the connection is closed
+    pub async fn send(&self, data: Bytes) -> Result<()> {
+        if data.len() > MAX_PAYLOAD_SIZE {
+            return Err(anyhow!(
+                "srt message is larger than {MAX_PAYLOAD_SIZE} bytes"
+            ));
+        }
+        self.sender
+            .send(data)
+            .await
+            .map_err(|_| anyhow!("srt connection is closed"))
+    }
+
+    /// The next message, None once the connection is closed
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.receiver.recv().await
+    }
+}
+
+/// Accepts the callers, the connections share its socket and end with it
+pub struct SrtListener {
+    local_addr: SocketAddr,
+    accepted: mpsc::Receiver<SrtSocket>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for SrtListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl SrtListener {
+    pub async fn bind(addr: SocketAddr, options: SrtOptions) -> Result<Self> {
+        let udp = Arc::new(UdpSocket::bind(addr).await?);
+        let local_addr = udp.local_addr()?;
+        let (sender, accepted) = mpsc::channel(BACKLOG);
+        let listener = Listener {
+            udp,
+            options,
+            secret: rand::random(),
+            connections: HashMap::new(),
+            accepted: sender,
+        };
+        Ok(Self {
+            local_addr,
+            accepted,
+            task: tokio::spawn(listener.run()),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub async fn accept(&mut self) -> Option<SrtSocket> {
+        self.accepted.recv().await
+    }
+}
+
+struct Listener {
+    udp: Arc<UdpSocket>,
+    options: SrtOptions,
+    /// Of the cookies
+    secret: u64,
+    /// The datagrams of every caller go to its connection
+    connections: HashMap<SocketAddr, mpsc::Sender<Bytes>>,
+    accepted: mpsc::Sender<SrtSocket>,
+}
+
+impl Listener {
+    async fn run(mut self) {
+        let mut buffer = vec![0u8; DATAGRAM_SIZE];
+        loop {
+            let (n, peer) = match self.udp.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(err) => {
+                    debug!(?err, "srt receive error");
+                    continue;
+                }
+            };
+            let datagram = Bytes::copy_from_slice(&buffer[..n]);
+            if let Some(connection) = self.connections.get(&peer) {
+                match connection.try_send(datagram.clone()) {
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        self.connections.remove(&peer);
+                    }
+                    _ => continue,
+                }
+            }
+            if let Ok(Packet::Control(packet)) = Packet::parse(&datagram)
+                && packet.kind == CONTROL_HANDSHAKE
+                && packet.dest == 0
+                && let Ok(handshake) = Handshake::parse(&packet.body)
+                && let Err(err) = self.handshake(peer, handshake).await
+            {
+                debug!(%peer, ?err, "srt handshake error");
+            }
+        }
+    }
+
+    fn cookie(&self, peer: SocketAddr) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        (self.secret, peer).hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    async fn reply(&self, peer: SocketAddr, dest: u32, handshake: &Handshake) -> Result<Bytes> {
+        let mut packet = ControlPacket::new(CONTROL_HANDSHAKE, 0, handshake.to_bytes());
+        packet.dest = dest;
+        let data = Packet::Control(packet).to_bytes();
+        self.udp.send_to(&data, peer).await?;
+        Ok(data)
+    }
+
+    async fn reject(&self, peer: SocketAddr, request: &Handshake, reason: u32) -> Result<()> {
+        debug!(%peer, reason, "srt connection rejected");
+        let response = Handshake {
+            kind: TYPE_REJECT + reason,
+            extensions: vec![],
+            ..request.clone()
+        };
+        self.reply(peer, request.socket_id, &response).await?;
+        Ok(())
+    }
+
+    async fn handshake(&mut self, peer: SocketAddr, request: Handshake) -> Result<()> {
+        let cookie = self.cookie(peer);
+        match request.kind {
+            TYPE_INDUCTION => {
+                let response = Handshake {
+                    version: 5,
+                    encryption: 0,
+                    extension: SRT_MAGIC,
+                    kind: TYPE_INDUCTION,
+                    cookie,
+                    peer_ip: peer_ip(peer.ip()),
+                    extensions: vec![],
+                    ..request.clone()
+                };
+                self.reply(peer, request.socket_id, &response).await?;
+            }
+            TYPE_CONCLUSION if request.cookie == cookie => {
+                if request.version < 5 {
+                    return Err(anyhow!("srt caller without HSv5"));
+                }
+                let key_material = request.extension(EXT_KMREQ);
+                let cipher = match (&self.options.passphrase, key_material) {
+                    (None, None) => None,
+                    (Some(passphrase), Some(content)) => {
+                        match Cipher::from_key_material(content, passphrase) {
+                            Ok(cipher) => Some(cipher),
+                            Err(_) => return self.reject(peer, &request, REJECT_BADSECRET).await,
+                        }
+                    }
+                    _ => return self.reject(peer, &request, REJECT_UNSECURE).await,
+                };
+                let peer_latency = request
+                    .extension(EXT_HSREQ)
+                    .and_then(|content| parse_srt_extension(content))
+                    .map(|(receiver, sender)| receiver.max(sender))
+                    .unwrap_or_default();
+                let latency = self
+                    .options
+                    .latency
+                    .max(Duration::from_millis(peer_latency as u64));
+                let stream_id = request
+                    .extension(EXT_SID)
+                    .map(|content| parse_stream_id(content))
+                    .unwrap_or_default();
+
+                // Refused rather than waiting for the user, the other callers are handled here too
+                let permit = match self.accepted.try_reserve() {
+                    Ok(permit) => permit,
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        return self.reject(peer, &request, REJECT_BACKLOG).await;
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        return Err(anyhow!("srt listener is closed"));
+                    }
+                };
+
+                let local_id = socket_id();
+                let mut extension = EXT_FLAG_HSREQ;
+                let mut extensions = vec![(EXT_HSRSP, srt_extension(latency_ms(latency)))];
+                if let Some(content) = key_material {
+                    extension |= EXT_FLAG_KMREQ;
+                    extensions.push((EXT_KMRSP, content.clone()));
+                }
+                let response = Handshake {
+                    version: 5,
+                    encryption: 0,
+                    extension,
+                    kind: TYPE_CONCLUSION,
+                    socket_id: local_id,
+                    cookie: 0,
+                    peer_ip: peer_ip(peer.ip()),
+                    extensions,
+                    ..request.clone()
+                };
+                let conclusion = self.reply(peer, request.socket_id, &response).await?;
+
+                let (sender, datagrams) = mpsc::channel(QUEUE_SIZE);
+                let connection = Connection::new(
+                    self.udp.clone(),
+                    peer,
+                    request.socket_id,
+                    request.initial_seq,
+                    latency,
+                    cipher,
+                    self.options.passphrase.clone(),
+                    Some(conclusion),
+                );
+                self.connections.retain(|_, sender| !sender.is_closed());
+                self.connections.insert(peer, sender);
+                permit.send(connection.spawn(stream_id, datagrams, None));
+            }
+            kind => trace!(%peer, kind, "srt handshake ignored"),
+        }
+        Ok(())
+    }
+}
+
+fn socket_id() -> u32 {
+    (rand::random::<u32>() & 0x3fffffff).max(1)
+}
+
+fn latency_ms(latency: Duration) -> u16 {
+    latency.as_millis().min(u16::MAX as u128) as u16
+}
+
+fn peer_ip(ip: IpAddr) -> [u8; 16] {
+    let mut out = [0; 16];
+    match ip {
+        IpAddr::V4(ip) => out[..4].copy_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => out.copy_from_slice(&ip.octets()),
+    }
+    out
+}
+
+struct Connection {
+    udp: Arc<UdpSocket>,
+    peer: SocketAddr,
+    peer_id: u32,
+    start: Instant,
+    latency: Duration,
+    cipher: Option<Cipher>,
+    /// Unwraps the keys the peer refreshes
+    passphrase: Option<String>,
+    /// The conclusion response of a listener, repeated when the caller didn't get it
+    conclusion: Option<Bytes>,
+
+    next_seq: u32,
+    next_message: u32,
+    /// The packets sent and not acknowledged yet
+    sent: VecDeque<(Instant, DataPacket)>,
+    sent_at: Instant,
+
+    /// The packet to deliver next, the first of `received`
+    receive_seq: u32,
+    /// The sender timestamps and the payloads
+    received: VecDeque<Option<(u32, Bytes)>>,
+    /// A local time and the sender timestamp of the same time, moved with the delivered packets
+    tsbpd_base: Option<(Instant, u32)>,
+    received_at: Instant,
+    delivered: Option<mpsc::Sender<Bytes>>,
+    acked_seq: u32,
+    ack_number: u32,
+    /// The ACKs waiting for their ACKACK, which gives the round trip time
+    acks: VecDeque<(u32, Instant)>,
+    rtt: Duration,
+    rtt_var: Duration,
+    nak_at: Instant,
+}
+
+impl Connection {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        udp: Arc<UdpSocket>,
+        peer: SocketAddr,
+        peer_id: u32,
+        initial_seq: u32,
+        latency: Duration,
+        cipher: Option<Cipher>,
+        passphrase: Option<String>,
+        conclusion: Option<Bytes>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            udp,
+            peer,
+            peer_id,
+            start: now,
+            latency,
+            cipher,
+            passphrase,
+            conclusion,
+            next_seq: initial_seq,
+            next_message: 1,
+            sent: VecDeque::new(),
+            sent_at: now,
+            receive_seq: initial_seq,
+            received: VecDeque::new(),
+            tsbpd_base: None,
+            received_at: now,
+            delivered: None,
+            acked_seq: initial_seq,
+            ack_number: 0,
+            acks: VecDeque::new(),
+            rtt: Duration::from_millis(100),
+            rtt_var: Duration::from_millis(50),
+            nak_at: now,
+        }
+    }
+
+    fn spawn(
+        mut self,
+        stream_id: String,
+        datagrams: mpsc::Receiver<Bytes>,
+        reader: Option<JoinHandle<()>>,
+    ) -> SrtSocket {
+        let peer = self.peer;
+        let (sender, messages) = mpsc::channel(QUEUE_SIZE);
+        let (delivered, receiver) = mpsc::channel(QUEUE_SIZE);
+        self.delivered = Some(delivered);
+        tokio::spawn(async move {
+            debug!(%peer, latency = ?self.latency, encrypted = self.cipher.is_some(), "srt connected");
+            self.run(datagrams, messages).await;
+            if let Some(reader) = reader {
+                reader.abort();
+            }
+            debug!(%peer, "srt closed");
+        });
+        SrtSocket {
+            peer,
+            stream_id,
+            sender,
+            receiver,
+        }
+    }
+
+    async fn run(
+        &mut self,
+        mut datagrams: mpsc::Receiver<Bytes>,
+        mut messages: mpsc::Receiver<Bytes>,
+    ) {
+        let mut tick = tokio::time::interval(TICK);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                datagram = datagrams.recv() => {
+                    let Some(datagram) = datagram else {
+                        return;
+                    };
+                    if !self.handle(&datagram).await {
+                        return;
+                    }
+                }
+                message = messages.recv() => {
+                    let Some(message) = message else {
+                        // The user is gone
+                        self.control(CONTROL_SHUTDOWN, 0, 0, Bytes::from_static(&[0; 4])).await;
+                        return;
+                    };
+                    self.send_data(message).await;
+                }
+                _ = tick.tick() => {
+                    if !self.tick().await {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    fn timestamp(&self) -> u32 {
+        self.start.elapsed().as_micros() as u32
+    }
+
+    async fn send(&mut self, packet: Packet) {
+        self.sent_at = Instant::now();
+        if let Err(err) = self.udp.send_to(&packet.to_bytes(), self.peer).await {
+            debug!(peer = %self.peer, ?err, "srt send error");
+        }
+    }
+
+    async fn control(&mut self, kind: u16, subtype: u16, info: u32, body: Bytes) {
+        let packet = ControlPacket {
+            kind,
+            subtype,
+            info,
+            timestamp: self.timestamp(),
+            dest: self.peer_id,
+            body,
+        };
+        self.send(Packet::Control(packet)).await;
+    }
+
+    /// Encrypted with the even key only, this sender never refreshes it. AES-CTR counts with the
+    /// 31 bit sequence number, so the keystream repeats after 2^31 packets, about 2.8 TB of
+    /// payload: the encrypted connections carrying more should be reconnected.
+    async fn send_data(&mut self, message: Bytes) {
+        let seq = self.next_seq;
+        self.next_seq = seq_add(seq, 1);
+        let mut key = 0;
+        let payload = match &self.cipher {
+            Some(cipher) => {
+                let mut payload = message.to_vec();
+                if cipher.apply(KEY_EVEN, seq, &mut payload) {
+                    key = KEY_EVEN;
+                }
+                Bytes::from(payload)
+            }
+            None => message,
+        };
+        let packet = DataPacket {
+            seq,
+            key,
+            retransmitted: false,
+            message: self.next_message,
+            timestamp: self.timestamp(),
+            dest: self.peer_id,
+            payload,
+        };
+        self.next_message = (self.next_message + 1) & 0x03ffffff;
+        if self.sent.len() >= BUFFER_SIZE {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((Instant::now(), packet.clone()));
+        self.send(Packet::Data(packet)).await;
+    }
+
+    /// False once the peer shuts the connection down
+    async fn handle(&mut self, datagram: &[u8]) -> bool {
+        let packet = match Packet::parse(datagram) {
+            Ok(packet) => packet,
+            Err(err) => {
+                trace!(peer = %self.peer, ?err, "srt packet ignored");
+                return true;
+            }
+        };
+        self.received_at = Instant::now();
+        match packet {
+            Packet::Data(packet) => self.receive(packet).await,
+            Packet::Control(packet) => match packet.kind {
+                CONTROL_ACK => {
+                    if let Some(seq) = ack_seq(&packet.body) {
+                        while self
+                            .sent
+                            .front()
+                            .is_some_and(|(_, sent)| seq_diff(sent.seq, seq) < 0)
+                        {
+                            self.sent.pop_front();
+                        }
+                    }
+                    // Light ACKs have no ACKACK
+                    if packet.body.len() >= 16 {
+                        self.control(CONTROL_ACKACK, 0, packet.info, Bytes::from_static(&[0; 4]))
+                            .await;
+                    }
+                }
+                CONTROL_NAK => self.retransmit(&nak_ranges(&packet.body)).await,
+                CONTROL_ACKACK => {
+                    if let Some(index) = self.acks.iter().position(|(n, _)| *n == packet.info) {
+                        let sample = self.acks[index].1.elapsed();
+                        self.acks.drain(..=index);
+                        let deviation = self.rtt.abs_diff(sample);
+                        self.rtt = (self.rtt * 7 + sample) / 8;
+                        self.rtt_var = (self.rtt_var * 3 + deviation) / 4;
+                    }
+                }
+                CONTROL_SHUTDOWN => return false,
+                CONTROL_HANDSHAKE => {
+                    if let Some(conclusion) = self.conclusion.clone() {
+                        let _ = self.udp.send_to(&conclusion, self.peer).await;
+                    }
+                }
+                CONTROL_USER if packet.subtype == EXT_KMREQ => self.refresh_key(packet.body).await,
+                CONTROL_KEEPALIVE => {}
+                kind => trace!(peer = %self.peer, kind, "srt control ignored"),
+            },
+        }
+        true
+    }
+
+    async fn refresh_key(&mut self, content: Bytes) {
+        let Some(passphrase) = &self.passphrase else {
+            return;
+        };
+        match Cipher::from_key_material(&content, passphrase) {
+            Ok(cipher) => {
+                self.cipher = Some(cipher);
+                self.control(CONTROL_USER, EXT_KMRSP, 0, content).await;
+            }
+            Err(err) => debug!(peer = %self.peer, ?err, "srt key refresh error"),
+        }
+    }
+
+    async fn retransmit(&mut self, ranges: &[(u32, u32)]) {
+        let Some(first) = self.sent.front().map(|(_, packet)| packet.seq) else {
+            return;
+        };
+        let mut packets = vec![];
+        for (from, to) in ranges {
+            let start = seq_diff(*from, first).max(0) as usize;
+            let end = seq_diff(*to, first);
+            if end < 0 {
+                continue;
+            }
+            for index in start..=(end as usize).min(self.sent.len().saturating_sub(1)) {
+                let (_, packet) = &self.sent[index];
+                // The original timestamp, it gives the play time
+                packets.push(DataPacket {
+                    retransmitted: true,
+                    ..packet.clone()
+                });
+            }
+        }
+        for packet in packets {
+            self.send(Packet::Data(packet)).await;
+        }
+    }
+
+    async fn receive(&mut self, mut packet: DataPacket) {
+        let offset = seq_diff(packet.seq, self.receive_seq);
+        if offset < 0 {
+            return;
+        }
+        let mut offset = offset as usize;
+        if offset >= BUFFER_SIZE {
+            // Far ahead, the sender gave the packets in between up
+            while !self.received.is_empty() {
+                self.skip_head();
+            }
+            self.receive_seq = packet.seq;
+            offset = 0;
+        }
+
+        if packet.key != 0 {
+            let decrypted = self.cipher.as_ref().is_some_and(|cipher| {
+                let mut payload = packet.payload.to_vec();
+                let decrypted = cipher.apply(packet.key, packet.seq, &mut payload);
+                packet.payload = Bytes::from(payload);
+                decrypted
+            });
+            if !decrypted {
+                // Received all the same, there is nothing to retransmit
+                packet.payload = Bytes::new();
+            }
+        }
+
+        // The packets between the previous last one and this one are missing
+        let missing = (offset > self.received.len()).then(|| {
+            (
+                seq_add(self.receive_seq, self.received.len() as u32),
+                seq_add(packet.seq, SEQUENCE_MASK),
+            )
+        });
+        if offset >= self.received.len() {
+            self.received.resize(offset + 1, None);
+        }
+        if self.received[offset].is_some() {
+            return;
+        }
+        if self.tsbpd_base.is_none() {
+            self.tsbpd_base = Some((Instant::now(), packet.timestamp));
+        }
+        self.received[offset] = Some((packet.timestamp, packet.payload));
+        if let Some((first, last)) = missing {
+            self.control(CONTROL_NAK, 0, 0, nak_body(&[(first, last)]))
+                .await;
+        }
+        self.deliver();
+    }
+
+    /// Drops the first packet, or the gap
+    fn skip_head(&mut self) {
+        if let Some(Some((timestamp, payload))) = self.received.pop_front() {
+            // Follows the timestamps, which wrap every 71 minutes
+            if let Some((base, base_timestamp)) = self.tsbpd_base
+                && timestamp.wrapping_sub(base_timestamp) as i32 > 0
+            {
+                let elapsed = Duration::from_micros(timestamp.wrapping_sub(base_timestamp) as u64);
+                self.tsbpd_base = Some((base + elapsed, timestamp));
+            }
+            self.forward(payload);
+        }
+        self.receive_seq = seq_add(self.receive_seq, 1);
+    }
+
+    fn forward(&mut self, payload: Bytes) {
+        if payload.is_empty() {
+            return;
+        }
+        if let Some(delivered) = &self.delivered
+            && let Err(mpsc::error::TrySendError::Full(_)) = delivered.try_send(payload)
+        {
+            debug!(peer = %self.peer, "srt receive queue is full");
+        }
+    }
+
+    /// The local time to deliver a packet of the sender timestamp
+    fn play_time(&self, timestamp: u32) -> Instant {
+        let Some((base, base_timestamp)) = self.tsbpd_base else {
+            return Instant::now();
+        };
+        let offset = timestamp.wrapping_sub(base_timestamp) as i32;
+        let play = base + self.latency;
+        if offset >= 0 {
+            play + Duration::from_micros(offset as u64)
+        } else {
+            play.checked_sub(Duration::from_micros(offset.unsigned_abs() as u64))
+                .unwrap_or(base)
+        }
+    }
+
+    /// Delivers the packets in order at their play time, a gap is given up at the play time of
+    /// the packet after it
+    fn deliver(&mut self) {
+        let now = Instant::now();
+        loop {
+            match self.received.front() {
+                Some(Some((timestamp, _))) => {
+                    if self.play_time(*timestamp) > now {
+                        return;
+                    }
+                    // The user is behind, the packets wait in the buffer
+                    if self
+                        .delivered
+                        .as_ref()
+                        .is_some_and(|delivered| delivered.capacity() == 0)
+                    {
+                        return;
+                    }
+                    self.skip_head();
+                }
+                Some(None) => {
+                    let late = self
+                        .received
+                        .iter()
+                        .flatten()
+                        .next()
+                        .is_some_and(|(timestamp, _)| self.play_time(*timestamp) <= now);
+                    if !late {
+                        return;
+                    }
+                    trace!(peer = %self.peer, seq = self.receive_seq, "srt packet dropped");
+                    self.skip_head();
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// The gaps of the receive buffer
+    fn losses(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = vec![];
+        for (index, packet) in self.received.iter().enumerate() {
+            if packet.is_some() {
+                continue;
+            }
+            let seq = seq_add(self.receive_seq, index as u32);
+            match ranges.last_mut() {
+                Some((_, last)) if seq_add(*last, 1) == seq => *last = seq,
+                _ => {
+                    if ranges.len() >= MAX_NAK_RANGES {
+                        break;
+                    }
+                    ranges.push((seq, seq));
+                }
+            }
+        }
+        ranges
+    }
+
+    /// False once the peer is gone
+    async fn tick(&mut self) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.received_at) >= IDLE_TIMEOUT {
+            debug!(peer = %self.peer, "srt peer timeout");
+            return false;
+        }
+        self.deliver();
+
+        // The packets wait for their play time, the contiguous ones are acknowledged already
+        let received = self
+            .received
+            .iter()
+            .take_while(|packet| packet.is_some())
+            .count();
+        let ack_seq = seq_add(self.receive_seq, received as u32);
+        if ack_seq != self.acked_seq {
+            self.acked_seq = ack_seq;
+            self.ack_number = self.ack_number.wrapping_add(1);
+            self.acks.push_back((self.ack_number, now));
+            if self.acks.len() > 64 {
+                self.acks.pop_front();
+            }
+            let available = (BUFFER_SIZE - self.received.len().min(BUFFER_SIZE)) as u32;
+            let body = ack_body(
+                ack_seq,
+                self.rtt.as_micros() as u32,
+                self.rtt_var.as_micros() as u32,
+                available,
+                0,
+            );
+            self.control(CONTROL_ACK, 0, self.ack_number, body).await;
+        }
+
+        let nak_interval = ((self.rtt + self.rtt_var * 4) / 2).max(MIN_NAK_INTERVAL);
+        if now.duration_since(self.nak_at) >= nak_interval {
+            self.nak_at = now;
+            let losses = self.losses();
+            if !losses.is_empty() {
+                self.control(CONTROL_NAK, 0, 0, nak_body(&losses)).await;
+            }
+        }
+
+        // Too late to be played, the receiver gives them up too
+        let drop_after = self.latency + SEND_DROP_DELAY;
+        while self
+            .sent
+            .front()
+            .is_some_and(|(sent, _)| now.duration_since(*sent) >= drop_after)
+        {
+            self.sent.pop_front();
+        }
+
+        if now.duration_since(self.sent_at) >= KEEPALIVE_INTERVAL {
+            self.control(CONTROL_KEEPALIVE, 0, 0, Bytes::from_static(&[0; 4]))
+                .await;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use super::{BACKLOG, SrtListener, SrtOptions, SrtSocket};
+
+    async fn pair(
+        listener: SrtOptions,
+        caller: SrtOptions,
+    ) -> anyhow::Result<(SrtListener, SrtSocket, SrtSocket)> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let mut listener = SrtListener::bind(addr, listener).await?;
+        let caller = SrtSocket::connect(listener.local_addr(), caller).await?;
+        let accepted = tokio::time::timeout(Duration::from_secs(1), listener.accept())
+            .await?
+            .unwrap();
+        Ok((listener, caller, accepted))
+    }
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let passphrase = Some("a passphrase".to_string());
+        let (_listener, mut caller, mut accepted) = pair(
+            SrtOptions {
+                passphrase: passphrase.clone(),
+                ..Default::default()
+            },
+            SrtOptions {
+                passphrase,
+                key_length: 32,
+                stream_id: Some("#!::r=live777,m=publish".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(accepted.stream_id(), "#!::r=live777,m=publish");
+
+        for i in 0..100u8 {
+            caller.send(Bytes::from(vec![i; 1316])).await.unwrap();
+        }
+        for i in 0..100u8 {
+            let message = accepted.recv().await.unwrap();
+            assert_eq!(message, Bytes::from(vec![i; 1316]));
+        }
+        accepted.send(Bytes::from_static(b"back")).await.unwrap();
+        assert_eq!(caller.recv().await.unwrap(), Bytes::from_static(b"back"));
+        assert!(caller.send(Bytes::from(vec![0; 1317])).await.is_err());
+
+        // The shutdown reaches the other side
+        drop(caller);
+        let closed = tokio::time::timeout(Duration::from_secs(1), accepted.recv()).await;
+        assert_eq!(closed.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let latency = Duration::from_millis(300);
+        let options = SrtOptions {
+            latency,
+            ..Default::default()
+        };
+        let (_listener, caller, mut accepted) = pair(options.clone(), options).await.unwrap();
+
+        let sent = std::time::Instant::now();
+        caller.send(Bytes::from_static(b"frame")).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(1), accepted.recv()).await;
+        assert_eq!(message.unwrap().unwrap(), Bytes::from_static(b"frame"));
+        assert!(sent.elapsed() >= latency);
+    }
+
+    #[tokio::test]
+    async fn test_backlog() {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let listener = SrtListener::bind(addr, SrtOptions::default())
+            .await
+            .unwrap();
+        let mut callers = vec![];
+        for _ in 0..BACKLOG {
+            let caller = SrtSocket::connect(listener.local_addr(), SrtOptions::default()).await;
+            callers.push(caller.unwrap());
+        }
+        let rejected = SrtSocket::connect(listener.local_addr(), SrtOptions::default()).await;
+        assert!(rejected.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_passphrase() {
+        let with = |passphrase: &str| SrtOptions {
+            passphrase: Some(passphrase.to_string()),
+            ..Default::default()
+        };
+        assert!(
+            pair(with("a passphrase"), with("another passphrase"))
+                .await
+                .is_err()
+        );
+        assert!(
+            pair(with("a passphrase"), SrtOptions::default())
+                .await
+                .is_err()
+        );
+        assert!(
+            pair(SrtOptions::default(), with("a passphrase"))
+                .await
+                .is_err()
+        );
+        assert!(
+            pair(SrtOptions::default(), SrtOptions::default())
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use api::callout::{AuthAction, AuthRequest, AuthResponse};
+use http::{HeaderMap, header};
+use reqwest::Client;
+use tracing::{debug, warn};
+
+use crate::config;
+use crate::error::AppError;
+use crate::forward::{Admission, Admitted};
+use crate::result::Result;
+
+/// Who sent an offer, as told to the auth callout
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub token: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(addr: SocketAddr, headers: &HeaderMap) -> Self {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        Self {
+            ip: Some(addr.ip().to_string()),
+            token: header(header::AUTHORIZATION)
+                .and_then(|value| value.strip_prefix("Bearer ").map(|token| token.to_string())),
+            user_agent: header(header::USER_AGENT),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuthCallout {
+    cfg: config::AuthCallout,
+    client: Client,
+}
+
+impl AuthCallout {
+    pub fn new(cfg: config::AuthCallout) -> Self {
+        let timeout = Duration::from_millis(cfg.timeout);
+        Self {
+            client: Client::builder()
+                .connect_timeout(timeout)
+                .timeout(timeout)
+                .build()
+                .unwrap(),
+            cfg,
+        }
+    }
+
+    /// Admission of a new session of `stream`
+    pub fn admission(&self, action: AuthAction, stream: String, client: ClientInfo) -> Admission {
+        let callout = self.clone();
+        Box::new(move |session| {
+            Box::pin(async move {
+                callout
+                    .check(AuthRequest {
+                        action,
+                        stream,
+                        session,
+                        client_ip: client.ip,
+                        token: client.token,
+                        user_agent: client.user_agent,
+                    })
+                    .await
+            })
+        })
+    }
+
+    pub async fn check(&self, request: AuthRequest) -> Result<Admitted> {
+        match self.request(&request).await {
+            Ok(response) if response.allow => {
+                debug!(?request, ?response, "auth callout allow");
+                Ok(Admitted {
+                    max_subscribers: response.max_subscribers,
+                    expires_at: response.expires_at,
+                })
+            }
+            Ok(response) => {
+                debug!(?request, ?response, "auth callout deny");
+                Err(AppError::forbidden(
+                    response
+                        .reason
+                        .unwrap_or("denied by the auth callout".to_string()),
+                ))
+            }
+            Err(err) if self.cfg.allow_on_error => {
+                warn!(url = self.cfg.url, ?err, "auth callout error, allow");
+                Ok(Admitted::default())
+            }
+            Err(err) => {
+                warn!(url = self.cfg.url, ?err, "auth callout error, deny");
+                Err(AppError::forbidden("auth callout unavailable"))
+            }
+        }
+    }
+
+    async fn request(&self, request: &AuthRequest) -> anyhow::Result<AuthResponse> {
+        let response = self
+            .client
+            .post(self.cfg.url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(request)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use api::callout::{AuthAction, AuthRequest, AuthResponse};
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use http::{HeaderMap, HeaderValue, header};
+    use tokio::net::TcpListener;
+
+    use super::{AuthCallout, ClientInfo};
+    use crate::config;
+    use crate::error::AppError;
+
+    /// Allows the stream `allowed` until the given time, denies the others
+    async fn backend(Json(request): Json<AuthRequest>) -> Json<AuthResponse> {
+        Json(match request.stream.as_str() {
+            "allowed" => AuthResponse {
+                allow: true,
+                max_subscribers: Some(2),
+                expires_at: Some(1_700_000_000_000),
+                ..Default::default()
+            },
+            "slow" => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                AuthResponse {
+                    allow: true,
+                    ..Default::default()
+                }
+            }
+            _ => AuthResponse {
+                allow: false,
+                reason: Some(format!("{} is not entitled", request.token.unwrap())),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn serve() -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/auth", post(backend)))
+                .await
+                .unwrap();
+        });
+        format!("http://{addr}/auth")
+    }
+
+    fn request(stream: &str) -> AuthRequest {
+        AuthRequest {
+            action: AuthAction::Subscribe,
+            stream: stream.to_string(),
+            session: "session".to_string(),
+            client_ip: Some("192.0.2.1".to_string()),
+            token: Some("token".to_string()),
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn test_client_info() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("whipinto"));
+        let client = ClientInfo::new(SocketAddr::from(([192, 0, 2, 1], 5000)), &headers);
+        assert_eq!(client.ip.as_deref(), Some("192.0.2.1"));
+        assert_eq!(client.token.as_deref(), Some("abc"));
+        assert_eq!(client.user_agent.as_deref(), Some("whipinto"));
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_deny() {
+        let callout = AuthCallout::new(config::AuthCallout {
+            url: serve().await,
+            timeout: 500,
+            allow_on_error: false,
+        });
+        let admitted = callout.check(request("allowed")).await.unwrap();
+        assert_eq!(admitted.max_subscribers, Some(2));
+        assert_eq!(admitted.expires_at, Some(1_700_000_000_000));
+
+        match callout.check(request("other")).await {
+            Err(AppError::Forbidden(reason)) => assert_eq!(reason, "token is not entitled"),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_callout_error() {
+        let url = serve().await;
+        let callout = AuthCallout::new(config::AuthCallout {
+            url: url.clone(),
+            timeout: 100,
+            allow_on_error: false,
+        });
+        assert!(matches!(
+            callout.check(request("slow")).await,
+            Err(AppError::Forbidden(_))
+        ));
+
+        let callout = AuthCallout::new(config::AuthCallout {
+            url,
+            timeout: 100,
+            allow_on_error: true,
+        });
+        let admitted = callout.check(request("slow")).await.unwrap();
+        assert_eq!(admitted.max_subscribers, None);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::config::{Config, GopCache, Health, MqttHookTarget, Room, Simulcast, WebhookTarget};
 
 use webrtc::ice_transport::ice_server::RTCIceServer;
 
@@ -6,11 +6,16 @@ use webrtc::ice_transport::ice_server::RTCIceServer;
 pub struct ManagerConfig {
     pub ice_servers: Vec<RTCIceServer>,
     pub cascade_push_close_sub: bool,
-    pub webhooks: Vec<String>,
+    pub webhooks: Vec<WebhookTarget>,
+    pub mqtt_hooks: Vec<MqttHookTarget>,
     pub auto_create_pub: bool,
     pub auto_create_sub: bool,
     pub auto_delete_pub: i64,
     pub auto_delete_sub: i64,
+    pub simulcast: Simulcast,
+    pub gop_cache: GopCache,
+    pub room: Room,
+    pub health: Health,
 }
 
 impl ManagerConfig {
@@ -24,11 +29,16 @@ impl ManagerConfig {
         Self {
             ice_servers,
             cascade_push_close_sub: cfg.strategy.cascade_push_close_sub,
-            webhooks: cfg.webhook.webhooks.clone(),
+            webhooks: cfg.webhook.targets(),
+            mqtt_hooks: cfg.webhook.mqtt.clone(),
             auto_create_pub: cfg.strategy.auto_create_whip,
             auto_create_sub: cfg.strategy.auto_create_whep,
             auto_delete_pub: cfg.strategy.auto_delete_whip.0,
             auto_delete_sub: cfg.strategy.auto_delete_whep.0,
+            simulcast: cfg.simulcast.clone(),
+            gop_cache: cfg.gop_cache.clone(),
+            room: cfg.room.clone(),
+            health: cfg.health.clone(),
         }
     }
 }
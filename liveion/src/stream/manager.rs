@@ -1,11 +1,13 @@
 use crate::config::Config;
 use crate::forward::message::ForwardInfo;
 
+use crate::hook::mqtt::MqttHook;
 use crate::hook::webhook::WebHook;
 use crate::hook::{Event, EventHook, Stream, StreamEvent, StreamEventType};
 
 use crate::result::Result;
 
+use api::callout::AuthAction;
 use chrono::{DateTime, Utc};
 use std::time::Duration;
 
@@ -14,11 +16,15 @@ use tokio::sync::broadcast;
 use std::vec;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
-use tracing::{debug, info, trace};
+use tracing::{debug, error, info, trace, warn};
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
-use crate::forward::PeerForward;
 use crate::forward::message::Layer;
+use crate::forward::network::Network;
+use crate::forward::{Admission, Admitted, ForwardConfig, OutputSession, OutputTrack, PeerForward};
+#[cfg(feature = "mpegts")]
+use crate::output::udp::{UdpOutputs, UdpTarget};
+use crate::stream::callout::{AuthCallout, ClientInfo};
 use crate::stream::config::ManagerConfig;
 use crate::{AppError, metrics, new_broadcast_channel};
 
@@ -26,23 +32,43 @@ use crate::{AppError, metrics, new_broadcast_channel};
 pub struct Manager {
     stream_map: Arc<RwLock<HashMap<String, PeerForward>>>,
     config: ManagerConfig,
+    network: Network,
     event_sender: broadcast::Sender<Event>,
+    callout: Option<AuthCallout>,
+    #[cfg(feature = "mpegts")]
+    udp_outputs: UdpOutputs,
 }
 
 pub type Response = (RTCSessionDescription, String);
 
+const SSE_STATS_INTERVAL: Duration = Duration::from_secs(2);
+
 impl Manager {
-    pub async fn new(config: Config) -> Self {
+    /// Fails when the WebRTC network can't be set up, e.g. the UDP mux port is taken
+    pub async fn new(config: Config) -> Result<Self> {
         let cfg = ManagerConfig::from_config(config.clone());
+        let callout = config.auth.callout.clone().map(AuthCallout::new);
+        let network = Network::new(&config.webrtc).await?;
         let stream_map: Arc<RwLock<HashMap<String, PeerForward>>> = Default::default();
-        let send = new_broadcast_channel!(4);
-        for web_hook_url in cfg.webhooks.iter() {
-            let webhook = WebHook::new(web_hook_url.clone());
+        let send = new_broadcast_channel!(64);
+        for target in cfg.webhooks.iter() {
+            let webhook = WebHook::new(target.clone()).await;
             let recv = send.subscribe();
             tokio::spawn(async move {
                 webhook.hook(recv).await;
             });
         }
+        for target in cfg.mqtt_hooks.iter() {
+            match MqttHook::new(target.clone()) {
+                Ok(mqtt_hook) => {
+                    let recv = send.subscribe();
+                    tokio::spawn(async move {
+                        mqtt_hook.hook(recv).await;
+                    });
+                }
+                Err(err) => error!(url = target.url, ?err, "mqtt hook config error"),
+            }
+        }
 
         if cfg.auto_delete_pub >= 0 {
             tokio::spawn(Self::publish_check_tick(
@@ -60,11 +86,21 @@ impl Manager {
             ));
         }
 
-        Manager {
+        Ok(Manager {
             stream_map,
             config: cfg,
+            network,
             event_sender: send,
-        }
+            callout,
+            #[cfg(feature = "mpegts")]
+            udp_outputs: UdpOutputs::default(),
+        })
+    }
+
+    fn admission(&self, action: AuthAction, stream: &str, client: ClientInfo) -> Option<Admission> {
+        self.callout
+            .as_ref()
+            .map(|callout| callout.admission(action, stream.to_string(), client))
     }
 
     async fn publish_check_tick(
@@ -191,8 +227,17 @@ impl Manager {
         mut stream_event: broadcast::Receiver<crate::forward::message::ForwardEvent>,
         hook_event: broadcast::Sender<Event>,
     ) {
-        while let Ok(event) = stream_event.recv().await {
-            let _ = hook_event.send(Event::Forward(event));
+        loop {
+            match stream_event.recv().await {
+                Ok(event) => {
+                    let _ = hook_event.send(Event::Forward(event));
+                }
+                // A burst of health events must not end the forwarding
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("forward event lagged: {}", count);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
         }
     }
 
@@ -210,7 +255,25 @@ impl Manager {
     }
 
     async fn do_stream_create(&self, stream: String) -> PeerForward {
-        let forward = PeerForward::new(stream.clone(), self.config.ice_servers.clone());
+        let forward = PeerForward::new(
+            stream.clone(),
+            ForwardConfig {
+                ice_servers: self.config.ice_servers.clone(),
+                simulcast: self.config.simulcast.clone(),
+                gop_cache: self
+                    .config
+                    .gop_cache
+                    .enabled(&stream)
+                    .then_some(self.config.gop_cache.max_packets),
+                health: self.config.health.clone(),
+                max_publishers: if self.config.room.enabled(&stream) {
+                    self.config.room.max_publishers
+                } else {
+                    1
+                },
+                network: self.network.clone(),
+            },
+        );
         let subscribe_event = forward.subscribe_event();
         tokio::spawn(Self::forward_event_handler(
             subscribe_event,
@@ -261,7 +324,33 @@ impl Manager {
         }));
     }
 
-    pub async fn publish(&self, stream: String, offer: RTCSessionDescription) -> Result<Response> {
+    pub async fn publish(
+        &self,
+        stream: String,
+        offer: RTCSessionDescription,
+        client: ClientInfo,
+    ) -> Result<Response> {
+        self.do_publish(stream, offer, client, false).await
+    }
+
+    /// Publish from an input in the process, the peers connect over the loopback interface
+    #[cfg(any(feature = "rtmp", feature = "srt", test))]
+    pub(crate) async fn publish_loopback(
+        &self,
+        stream: String,
+        offer: RTCSessionDescription,
+        client: ClientInfo,
+    ) -> Result<Response> {
+        self.do_publish(stream, offer, client, true).await
+    }
+
+    async fn do_publish(
+        &self,
+        stream: String,
+        offer: RTCSessionDescription,
+        client: ClientInfo,
+        loopback: bool,
+    ) -> Result<Response> {
         trace!(
             "Publishing to stream: {}, offer type: {:?}",
             stream, offer.sdp_type
@@ -276,7 +365,31 @@ impl Manager {
         drop(stream_map);
 
         match forward {
-            Some(forward) => forward.set_publish(offer).await,
+            Some(forward) => {
+                let admission = self.admission(AuthAction::Publish, &stream, client);
+                forward.set_publish(offer, admission, loopback).await
+            }
+            None => Err(AppError::stream_not_found("stream not exists")),
+        }
+    }
+
+    /// Publish as the hot standby of the stream publisher
+    pub async fn publish_standby(
+        &self,
+        stream: String,
+        offer: RTCSessionDescription,
+        client: ClientInfo,
+    ) -> Result<Response> {
+        trace!(
+            "Publishing standby to stream: {}, offer type: {:?}",
+            stream, offer.sdp_type
+        );
+        let forward = self.stream_map.read().await.get(&stream).cloned();
+        match forward {
+            Some(forward) => {
+                let admission = self.admission(AuthAction::Publish, &stream, client);
+                forward.set_standby(offer, admission).await
+            }
             None => Err(AppError::stream_not_found("stream not exists")),
         }
     }
@@ -285,6 +398,7 @@ impl Manager {
         &self,
         stream: String,
         offer: RTCSessionDescription,
+        client: ClientInfo,
     ) -> Result<Response> {
         trace!(
             "Subscribing to stream: {}, offer SDP length: {}",
@@ -301,12 +415,46 @@ impl Manager {
         drop(stream_map);
 
         if let Some(forward) = forward {
-            Ok(forward.add_subscribe(offer).await?)
+            let admission = self.admission(AuthAction::Subscribe, &stream, client);
+            Ok(forward.add_subscribe(offer, admission).await?)
         } else {
             Err(AppError::stream_not_found("stream not exists"))
         }
     }
 
+    /// Tracks of the stream publisher, read by the outputs without a WebRTC peer
+    pub(crate) async fn output_tracks(&self, stream: &str) -> Result<Vec<OutputTrack>> {
+        let forward = self.stream_map.read().await.get(stream).cloned();
+        let Some(forward) = forward else {
+            return Err(AppError::stream_not_found("stream not exists"));
+        };
+        let tracks = forward.output_tracks().await;
+        if tracks.is_empty() {
+            return Err(AppError::stream_not_found("stream has no publisher"));
+        }
+        Ok(tracks)
+    }
+
+    /// Asks the auth callout about a reader without a WebRTC peer, `session` names the reader.
+    /// The reader counts against the max subscribers while the session is kept,
+    /// and is closed once it expires
+    pub(crate) async fn admit_output(
+        &self,
+        stream: &str,
+        session: String,
+        client: ClientInfo,
+    ) -> Result<OutputSession> {
+        let forward = self.stream_map.read().await.get(stream).cloned();
+        let Some(forward) = forward else {
+            return Err(AppError::stream_not_found("stream not exists"));
+        };
+        let admitted = match self.admission(AuthAction::Subscribe, stream, client) {
+            Some(admission) => admission(session.clone()).await?,
+            None => Admitted::default(),
+        };
+        forward.add_output(session, admitted).await
+    }
+
     pub async fn add_ice_candidate(
         &self,
         stream: String,
@@ -323,6 +471,33 @@ impl Manager {
         }
     }
 
+    pub async fn ice_etag(&self, stream: String, session: String) -> Result<String> {
+        let streams = self.stream_map.read().await;
+        let forward = streams.get(&stream).cloned();
+        drop(streams);
+        if let Some(forward) = forward {
+            forward.ice_etag(session).await
+        } else {
+            Err(AppError::session_not_found("session not exists"))
+        }
+    }
+
+    pub async fn ice_restart(
+        &self,
+        stream: String,
+        session: String,
+        fragment: String,
+    ) -> Result<String> {
+        let streams = self.stream_map.read().await;
+        let forward = streams.get(&stream).cloned();
+        drop(streams);
+        if let Some(forward) = forward {
+            forward.ice_restart(session, fragment).await
+        } else {
+            Err(AppError::session_not_found("session not exists"))
+        }
+    }
+
     pub async fn remove_stream_session(&self, stream: String, session: String) -> Result<()> {
         let streams = self.stream_map.read().await;
         let forward = streams.get(&stream).cloned();
@@ -365,6 +540,38 @@ impl Manager {
         }
     }
 
+    pub async fn renegotiate(
+        &self,
+        stream: String,
+        session: String,
+        offer: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.renegotiate_subscribe(session, offer).await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
+    pub async fn select_publishers(
+        &self,
+        stream: String,
+        session: String,
+        publishers: Option<Vec<String>>,
+    ) -> Result<()> {
+        let stream_map = self.stream_map.read().await;
+        let forward = stream_map.get(&stream).cloned();
+        drop(stream_map);
+        if let Some(forward) = forward {
+            forward.select_publishers(session, publishers).await
+        } else {
+            Err(AppError::stream_not_found("stream not exists"))
+        }
+    }
+
     pub async fn change_resource(
         &self,
         stream: String,
@@ -388,7 +595,7 @@ impl Manager {
         let stream_map = self.stream_map.read().await;
         for (stream, forward) in stream_map.iter() {
             if streams.is_empty() || streams.contains(stream) {
-                resp.push(forward.info().await);
+                resp.push(forward.info_with_stats().await);
             }
         }
         resp
@@ -439,6 +646,26 @@ impl Manager {
         }
     }
 
+    /// Sends the stream as MPEG-TS over UDP to `target_url` until the publisher is gone
+    #[cfg(feature = "mpegts")]
+    pub async fn mpegts_push(&self, stream: String, target_url: String) -> Result<()> {
+        let target = UdpTarget::parse(&target_url).await?;
+        let tracks = crate::output::mpegts::frames(self.output_tracks(&stream).await?);
+        if tracks.is_empty() {
+            return Err(AppError::stream_not_found("stream has no supported track"));
+        }
+        self.udp_outputs.start(stream, target, tracks).await
+    }
+
+    #[cfg(feature = "mpegts")]
+    pub async fn mpegts_stop(&self, stream: String) -> Result<()> {
+        if self.udp_outputs.stop(&stream) {
+            Ok(())
+        } else {
+            Err(AppError::stream_not_found("stream has no mpegts output"))
+        }
+    }
+
     pub async fn sse_handler(
         &self,
         streams: Vec<String>,
@@ -447,21 +674,31 @@ impl Manager {
         let mut evnet_recv = self.event_sender.subscribe();
         let stream_map = self.stream_map.clone();
         tokio::spawn(async move {
-            while let Ok(event) = evnet_recv.recv().await {
-                let stream = match event {
-                    Event::Stream(val) => val.stream.stream,
-                    Event::Forward(val) => val.stream_info.id,
+            // Also refresh periodically, for the live session stats
+            let mut ticker = tokio::time::interval(SSE_STATS_INTERVAL);
+            loop {
+                let stream = tokio::select! {
+                    event = evnet_recv.recv() => match event {
+                        Ok(Event::Stream(val)) => Some(val.stream.stream),
+                        Ok(Event::Forward(val)) => Some(val.stream_info.id),
+                        Err(_) => break,
+                    },
+                    _ = ticker.tick() => None,
                 };
-                if streams.is_empty() || streams.contains(&stream) {
-                    let stream_map = stream_map.read().await;
-                    let mut infos = vec![];
-                    for (_, forward) in stream_map.iter() {
-                        if !streams.is_empty() && !streams.contains(&forward.stream) {
-                            continue;
-                        }
-                        infos.push(forward.info().await);
+                if stream.is_some_and(|stream| !streams.is_empty() && !streams.contains(&stream)) {
+                    continue;
+                }
+                let stream_map = stream_map.read().await;
+                let mut infos = vec![];
+                for (_, forward) in stream_map.iter() {
+                    if !streams.is_empty() && !streams.contains(&forward.stream) {
+                        continue;
                     }
-                    let _ = send.send(infos).await;
+                    infos.push(forward.info_with_stats().await);
+                }
+                drop(stream_map);
+                if send.send(infos).await.is_err() {
+                    break;
                 }
             }
         });
@@ -1,2 +1,3 @@
+pub mod callout;
 pub mod config;
 pub mod manager;
@@ -39,6 +39,10 @@ pub fn route() -> Router<AppState> {
             &api::path::session_layer("{stream}", "{session}"),
             get(session).post(session).delete(session),
         )
+        .route(
+            &api::path::session_publishers("{stream}", "{session}"),
+            post(session),
+        )
         .route(
             &api::path::whip_with_node("{stream}", "{alias}"),
             post(api_whip),
@@ -27,11 +27,11 @@ async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) ->
     #[cfg(feature = "recorder")]
     {
         if let Some(ref operator) = state.file_storage {
-            // Always proxy MPD manifest itself to keep relative segment URLs under our domain
-            let is_mpd = path.ends_with(".mpd");
+            // Always proxy the MPD and HLS manifests themselves to keep relative segment URLs under our domain
+            let is_manifest = path.ends_with(".mpd") || path.ends_with(".m3u8");
 
-            // If enabled, try presigned redirect for non-MPD files (segments, init, etc.)
-            if !is_mpd && state.config.playback.signed_redirect {
+            // If enabled, try presigned redirect for non-manifest files (segments, init, etc.)
+            if !is_manifest && state.config.playback.signed_redirect {
                 use std::time::Duration as StdDuration;
 
                 let ttl = StdDuration::from_secs(state.config.playback.signed_ttl_seconds.max(1));
@@ -55,6 +55,8 @@ async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) ->
                     // Determine content type based on file extension
                     let content_type = if path.ends_with(".mpd") {
                         "application/dash+xml"
+                    } else if path.ends_with(".m3u8") {
+                        "application/vnd.apple.mpegurl"
                     } else if path.ends_with(".m4s") || path.ends_with(".mp4") {
                         // Heuristic: audio segments and init named with "audio_" prefix
                         if path.contains("audio_") {
@@ -101,6 +103,29 @@ async fn get_segment(State(state): State<AppState>, Path(path): Path<String>) ->
 struct RecordingIndexEntry {
     record: String,
     mpd_path: String,
+    hls_path: Option<String>,
+}
+
+/// The HLS master playlist the recorder writes next to the MPD, older recordings have none
+async fn hls_path(state: &AppState, mpd_path: &str) -> Option<String> {
+    #[cfg(feature = "recorder")]
+    if let Some(ref operator) = state.file_storage {
+        let path = match mpd_path.rsplit_once('/') {
+            Some((dir, _)) => format!("{dir}/master.m3u8"),
+            None => "master.m3u8".to_string(),
+        };
+        if operator.exists(&path).await.unwrap_or(false) {
+            return Some(path);
+        }
+    }
+
+    #[cfg(not(feature = "recorder"))]
+    {
+        // Avoid unused variable warnings
+        let _ = state;
+        let _ = mpd_path;
+    }
+    None
 }
 
 async fn list_index_streams(State(state): State<AppState>) -> Result<Json<Vec<String>>> {
@@ -128,13 +153,14 @@ async fn list_index_by_stream(
         .filter(recordings::Column::Stream.eq(stream))
         .all(db)
         .await?;
-    let entries = rows
-        .into_iter()
-        .map(|m| RecordingIndexEntry {
+    let mut entries = Vec::with_capacity(rows.len());
+    for m in rows {
+        entries.push(RecordingIndexEntry {
             record: m.record,
+            hls_path: hls_path(&state, &m.mpd_path).await,
             mpd_path: m.mpd_path,
-        })
-        .collect();
+        });
+    }
     Ok(Json(entries))
 }
 
@@ -149,6 +175,8 @@ struct StartRecordQuery {
 struct StartRecordResponse {
     started: bool,
     mpd_path: String,
+    /// None when the node records without HLS playlists
+    hls_path: Option<String>,
 }
 
 async fn start_record(
@@ -203,6 +231,7 @@ async fn start_record(
 
     let mut record_ts = String::new();
     let mut mpd_path = fallback_mpd_path;
+    let mut hls_path = None;
 
     if let Ok(v) = resp.json::<api::recorder::StartRecordResponse>().await {
         if !v.mpd_path.is_empty() {
@@ -211,6 +240,9 @@ async fn start_record(
         if !v.record_id.is_empty() {
             record_ts = v.record_id;
         }
+        if !v.hls_path.is_empty() {
+            hls_path = Some(v.hls_path);
+        }
     }
 
     if record_ts.is_empty() {
@@ -234,6 +266,7 @@ async fn start_record(
     Ok(Json(StartRecordResponse {
         started: true,
         mpd_path,
+        hls_path,
     }))
 }
 
@@ -4,7 +4,7 @@ mod repayload;
 
 pub use repayload::{Forward, RePayload, RePayloadCodec};
 
-pub(crate) use h264::H264Processor;
+pub use h264::H264Processor;
 pub(crate) use h265::H265Processor;
 
 /// RTP outbound MTU
@@ -0,0 +1,201 @@
+#![cfg(feature = "rtmp")]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+mod common;
+use common::shutdown_signal;
+
+const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8];
+const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+fn amf_string(out: &mut Vec<u8>, value: &str) {
+    out.push(0x02);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn amf_number(out: &mut Vec<u8>, value: f64) {
+    out.push(0x00);
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// A message in chunks of the default size, 128 bytes
+fn chunks(csid: u8, type_id: u8, stream_id: u32, timestamp: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![csid];
+    out.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(type_id);
+    out.extend_from_slice(&stream_id.to_le_bytes());
+    for (i, chunk) in payload.chunks(128).enumerate() {
+        if i > 0 {
+            out.push(0xc0 | csid);
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+fn command(name: &str, transaction: f64, args: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let mut payload = vec![];
+    amf_string(&mut payload, name);
+    amf_number(&mut payload, transaction);
+    args(&mut payload);
+    payload
+}
+
+/// Reads the server messages until `text` is seen
+async fn read_until(socket: &mut TcpStream, received: &mut Vec<u8>, text: &str) {
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !received
+            .windows(text.len())
+            .any(|window| window == text.as_bytes())
+        {
+            let mut buf = [0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before {text}");
+            received.extend_from_slice(&buf[..n]);
+        }
+    })
+    .await
+    .unwrap();
+}
+
+async fn publish_sessions(addr: SocketAddr, stream: &str) -> Option<Vec<api::response::Session>> {
+    let res = reqwest::get(format!("http://{addr}{}", api::path::streams("")))
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+    let body = res.json::<Vec<api::response::Stream>>().await.unwrap();
+    body.into_iter()
+        .find(|s| s.id == stream)
+        .map(|s| s.publish.sessions)
+}
+
+#[tokio::test]
+async fn test_liveion_rtmp_publish() {
+    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    // A free port for the RTMP server
+    let rtmp_listen = std::net::TcpListener::bind(SocketAddr::new(ip, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut cfg = liveion::config::Config::default();
+    cfg.rtmp.listen = Some(rtmp_listen);
+
+    let listener = TcpListener::bind(SocketAddr::new(ip, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(liveion::serve(cfg, listener, shutdown_signal()));
+
+    let stream = "rtmp-test";
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}{}", api::path::streams(stream)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::NO_CONTENT, res.status());
+
+    let mut socket = None;
+    for _ in 0..50 {
+        if let Ok(connected) = TcpStream::connect(rtmp_listen).await {
+            socket = Some(connected);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let mut socket = socket.expect("rtmp server is not listening");
+
+    // The simple handshake
+    let mut c0c1 = vec![3];
+    c0c1.resize(1 + 1536, 0);
+    socket.write_all(&c0c1).await.unwrap();
+    let mut s0s1s2 = vec![0; 1 + 1536 * 2];
+    socket.read_exact(&mut s0s1s2).await.unwrap();
+    assert_eq!(s0s1s2[0], 3);
+    socket.write_all(&s0s1s2[1..1 + 1536]).await.unwrap();
+
+    let mut received = vec![];
+    let connect = command("connect", 1.0, |out| {
+        out.push(0x03);
+        for (key, value) in [
+            ("app", "live"),
+            ("tcUrl", &format!("rtmp://{rtmp_listen}/live") as &str),
+        ] {
+            out.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            out.extend_from_slice(key.as_bytes());
+            amf_string(out, value);
+        }
+        out.extend_from_slice(&[0, 0, 0x09]);
+    });
+    socket
+        .write_all(&chunks(3, 20, 0, 0, &connect))
+        .await
+        .unwrap();
+    read_until(&mut socket, &mut received, "NetConnection.Connect.Success").await;
+
+    let create_stream = command("createStream", 2.0, |out| out.push(0x05));
+    socket
+        .write_all(&chunks(3, 20, 0, 0, &create_stream))
+        .await
+        .unwrap();
+    let publish = command("publish", 3.0, |out| {
+        out.push(0x05);
+        amf_string(out, stream);
+        amf_string(out, "live");
+    });
+    socket
+        .write_all(&chunks(8, 20, 1, 0, &publish))
+        .await
+        .unwrap();
+    read_until(&mut socket, &mut received, "NetStream.Publish.Start").await;
+
+    // The AVC sequence header, then an IDR frame every 100 ms
+    let mut config = vec![0x17, 0, 0, 0, 0, 1, SPS[1], SPS[2], SPS[3], 0xff, 0xe1];
+    config.extend_from_slice(&(SPS.len() as u16).to_be_bytes());
+    config.extend_from_slice(SPS);
+    config.push(1);
+    config.extend_from_slice(&(PPS.len() as u16).to_be_bytes());
+    config.extend_from_slice(PPS);
+    socket
+        .write_all(&chunks(6, 9, 1, 0, &config))
+        .await
+        .unwrap();
+
+    let mut frame = vec![0x17, 1, 0, 0, 0];
+    frame.extend_from_slice(&1001u32.to_be_bytes());
+    frame.push(0x65);
+    frame.extend_from_slice(&[0x88; 1000]);
+
+    let mut connected = false;
+    for i in 0..100 {
+        socket
+            .write_all(&chunks(6, 9, 1, i * 100, &frame))
+            .await
+            .unwrap();
+        if let Some(sessions) = publish_sessions(addr, stream).await
+            && sessions
+                .first()
+                .is_some_and(|s| s.state == api::response::RTCPeerConnectionState::Connected)
+        {
+            connected = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(connected);
+
+    // The stream goes away with the publisher
+    drop(socket);
+    let mut removed = false;
+    for _ in 0..50 {
+        if publish_sessions(addr, stream).await.is_none() {
+            removed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(removed);
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "srt")]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use bytes::Bytes;
+use liveion::mpegts::{TsCodec, TsDemuxer, TsFrame, TsMuxer};
+use liveion::srt::{MAX_PAYLOAD_SIZE, SrtOptions, SrtSocket};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+
+mod common;
+use common::shutdown_signal;
+
+const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40, 0x16, 0xe8];
+const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+async fn publish_sessions(addr: SocketAddr, stream: &str) -> Option<Vec<api::response::Session>> {
+    let res = reqwest::get(format!("http://{addr}{}", api::path::streams("")))
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::OK, res.status());
+    let body = res.json::<Vec<api::response::Stream>>().await.unwrap();
+    body.into_iter()
+        .find(|s| s.id == stream)
+        .map(|s| s.publish.sessions)
+}
+
+/// An IDR frame with its parameter sets every 100 ms, until `stop`
+async fn publish(socket: SrtSocket, mut stop: oneshot::Receiver<()>) {
+    let mut muxer = TsMuxer::new(Some(TsCodec::H264), false);
+    let mut data = vec![];
+    for nalu in [SPS, PPS, &[0x65; 1000][..]] {
+        data.extend_from_slice(&[0, 0, 0, 1]);
+        data.extend_from_slice(nalu);
+    }
+    for i in 0..300u64 {
+        let frame = TsFrame {
+            codec: TsCodec::H264,
+            pts: i * 9000,
+            dts: i * 9000,
+            keyframe: true,
+            data: Bytes::from(data.clone()),
+        };
+        for chunk in muxer.video(&frame).chunks(MAX_PAYLOAD_SIZE) {
+            socket.send(Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+            _ = &mut stop => return,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_liveion_srt() {
+    let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    // A free port for the SRT listener
+    let srt_listen = std::net::UdpSocket::bind(SocketAddr::new(ip, 0))
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut cfg = liveion::config::Config::default();
+    cfg.srt.listen = Some(srt_listen);
+    cfg.srt.passphrase = "srt passphrase".to_string();
+
+    let listener = TcpListener::bind(SocketAddr::new(ip, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(liveion::serve(cfg, listener, shutdown_signal()));
+
+    let stream = "srt-test";
+    let res = reqwest::Client::new()
+        .post(format!("http://{addr}{}", api::path::streams(stream)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(http::StatusCode::NO_CONTENT, res.status());
+
+    let options = |stream_id: String| SrtOptions {
+        passphrase: Some("srt passphrase".to_string()),
+        stream_id: Some(stream_id),
+        ..Default::default()
+    };
+    let socket = SrtSocket::connect(srt_listen, options(format!("#!::r={stream},m=publish")))
+        .await
+        .expect("srt server is not listening");
+    let (stop, stopped) = oneshot::channel();
+    let publishing = tokio::spawn(publish(socket, stopped));
+
+    let mut connected = false;
+    for _ in 0..100 {
+        if let Some(sessions) = publish_sessions(addr, stream).await
+            && sessions
+                .first()
+                .is_some_and(|s| s.state == api::response::RTCPeerConnectionState::Connected)
+        {
+            connected = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(connected);
+
+    // Played back as MPEG-TS, from the next keyframe
+    let mut player = SrtSocket::connect(srt_listen, options(stream.to_string()))
+        .await
+        .unwrap();
+    let mut demuxer = TsDemuxer::default();
+    let frame = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let data = player.recv().await.expect("srt player is closed");
+            if let Some(frame) = demuxer.push(&data).into_iter().next() {
+                return frame;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(frame.codec, TsCodec::H264);
+    assert!(frame.keyframe);
+    drop(player);
+
+    // The stream goes away with the publisher
+    stop.send(()).unwrap();
+    publishing.await.unwrap();
+    let mut removed = false;
+    for _ in 0..50 {
+        if publish_sessions(addr, stream).await.is_none() {
+            removed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(removed);
+}
@@ -114,6 +114,8 @@ export function getSegmentUrl(path: string) {
 export interface RecordingIndexEntry {
     record: string;
     mpd_path: string;
+    /** Absent for recordings without an HLS playlist */
+    hls_path: string | null;
 }
 
 export function getRecordingIndexStreams() {
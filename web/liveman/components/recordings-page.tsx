@@ -1,6 +1,6 @@
 import { useCallback, useContext, useEffect, useMemo, useState } from 'preact/hooks';
 import { Badge, Button, Card, Input, Loading, Select, Tooltip } from 'react-daisyui';
-import { RefreshCw, Calendar, Search, Play, Link2, Copy } from 'lucide-react';
+import { RefreshCw, Calendar, Search, Play, Link2, Copy, ListVideo } from 'lucide-react';
 import * as livemanApi from '../api';
 import { TokenContext } from '@/shared/context';
 
@@ -208,6 +208,13 @@ export function RecordingsPage() {
                                             <Copy className="w-4 h-4" />
                                         </Button>
                                     </Tooltip>
+                                    {e.hls_path && (
+                                        <Tooltip message="Copy HLS URL">
+                                            <Button size="sm" color="ghost" onClick={() => copyToClipboard(new URL(livemanApi.getSegmentUrl(e.hls_path!), location.origin).toString())}>
+                                                <ListVideo className="w-4 h-4" />
+                                            </Button>
+                                        </Tooltip>
+                                    )}
                                 </div>
                             </div>
                         ))}
@@ -51,6 +51,27 @@ export interface Session {
         sessionUrl: string;
     };
     reforward?: boolean;
+    stats?: SessionStats;
+    health?: HealthProblem[];
+}
+
+export type HealthProblem = 'stalled' | 'noKeyframe' | 'lowBitrate' | 'timestampJump' | 'videoMissing';
+
+export interface SessionStats {
+    bitrateIn: number;
+    bitrateOut: number;
+    packetsLost: number;
+    fractionLost: number;
+    jitter: number;
+    rtt?: number;
+    nackCount: number;
+    pliCount: number;
+    firCount: number;
+    candidatePair?: {
+        local: string;
+        remote: string;
+    };
+    layer?: string;
 }
 
 export interface Cascade {